@@ -0,0 +1,55 @@
+#ifndef TIME_TRACKER_H
+#define TIME_TRACKER_H
+
+/* Generated from src/ffi.rs by cbindgen; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define DEFAULT_WEEKS 8
+
+// Starts a session now, with the config's default project if `project` is NULL.
+// `note` may be NULL as well.
+//
+// # Safety
+//
+// `data_path`, `project` and `note` must each be NULL or a valid NUL-terminated
+// string.
+int32_t tt_start(const char *data_path, const char *project, const char *note);
+
+// Stops the running session.
+//
+// # Safety
+//
+// `data_path` must be NULL or a valid NUL-terminated string.
+int32_t tt_stop(const char *data_path);
+
+// The current status as a JSON object:
+//
+//   {"active": true, "since": "2026-10-17T07:30:00Z", "elapsed_seconds": 5400,
+//    "project": "acme", "tags": [], "note": null, "today_seconds": 12600}
+//
+// `since` and `elapsed_seconds` are null without a running session. Returns NULL
+// on failure; free the string with `tt_string_free`.
+//
+// # Safety
+//
+// `data_path` must be NULL or a valid NUL-terminated string.
+char *tt_status_json(const char *data_path);
+
+// Frees a string returned by this library. NULL is ignored.
+//
+// # Safety
+//
+// `text` must be NULL or a string returned by this library that was not freed
+// before.
+void tt_string_free(char *text);
+
+// Message of the last failure on this thread, or NULL if the last call succeeded.
+// The string belongs to the library and is valid until the next call.
+const char *tt_last_error(void);
+
+#endif  /* TIME_TRACKER_H */
@@ -0,0 +1,206 @@
+use crate::config::{Config, Contract};
+use crate::{Period, TimeSheet};
+use crate::zone::Zone;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io;
+use tracing::{info, instrument};
+
+// A day away from work. Absences are stored apart from the tracked periods and
+// are credited with the daily share of the weekly target.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Absence {
+    pub date: NaiveDate,
+    pub kind: AbsenceKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AbsenceKind {
+    Sick,
+    Vacation,
+}
+
+impl AbsenceKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AbsenceKind::Sick => "sick",
+            AbsenceKind::Vacation => "vacation",
+        }
+    }
+}
+
+// Parses a single day (`2024-05-03`) or an inclusive range (`2024-05-10..2024-05-12`).
+pub fn parse_days(text: &str) -> io::Result<Vec<NaiveDate>> {
+    let parse = |value: &str| {
+        NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid date '{}'. Expected YYYY-MM-DD or YYYY-MM-DD..YYYY-MM-DD.", text),
+            )
+        })
+    };
+    let (first, last) = match text.split_once("..") {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => (parse(text)?, parse(text)?),
+    };
+    if last < first {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The range {} ends before it starts.", text),
+        ));
+    }
+    Ok(first.iter_days().take_while(|day| *day <= last).collect())
+}
+
+// Handles `absence add <kind> <days>`. Days that already have an absence or lie in
+// a closed month are skipped. Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet))]
+pub fn add_absence(time_sheet: &mut TimeSheet, kind: AbsenceKind, days: &str, note: Option<String>) -> io::Result<bool> {
+    let mut added = 0;
+    for date in parse_days(days)? {
+        if let Some(existing) = time_sheet.absences.iter().find(|a| a.date == date) {
+            println!("{} is already recorded as {}, skipped.", date, existing.kind.name());
+            continue;
+        }
+        if let Some(day) = crate::logic::day_period(date, Zone::default())
+            && let Some(closed) = time_sheet.closed_month_at(day.start)
+        {
+            println!("{} is in the closed month {}, skipped.", date, closed.month);
+            continue;
+        }
+        time_sheet.absences.push(Absence { date, kind, note: note.clone() });
+        added += 1;
+    }
+    time_sheet.absences.sort_by_key(|a| a.date);
+
+    info!(added, kind = kind.name(), "recorded absences");
+    println!("Recorded {} day(s) of {}.", added, kind.name());
+    Ok(added > 0)
+}
+
+// Prints all absences.
+pub fn list_absences(time_sheet: &TimeSheet) {
+    if time_sheet.absences.is_empty() {
+        println!("No absences recorded.");
+    }
+    for absence in &time_sheet.absences {
+        match &absence.note {
+            Some(note) => println!("{} {}  {:<8}  {}", absence.date, absence.date.weekday(), absence.kind.name(), note),
+            None => println!("{} {}  {}", absence.date, absence.date.weekday(), absence.kind.name()),
+        }
+    }
+}
+
+// Whether the day is a working day: not a weekend day and not a holiday from the
+// schedule. Without a schedule, Saturday and Sunday are the weekend.
+pub fn is_workday(config: &Config, date: NaiveDate) -> bool {
+    match &config.schedule {
+        Some(schedule) => !schedule.weekend_days.contains(&date.weekday()) && !schedule.holidays.contains(&date),
+        None => !matches!(date.weekday(), Weekday::Sat | Weekday::Sun),
+    }
+}
+
+// The contract in effect on `date`. Where contracts overlap, the one that
+// started last wins.
+pub fn contract_on(config: &Config, date: NaiveDate) -> Option<&Contract> {
+    config.contracts.iter().filter(|contract| contract.covers(date)).max_by_key(|contract| contract.start)
+}
+
+// Whether any day can have a target: from `weekly_target_hours` or a contract.
+pub fn has_target(config: &Config) -> bool {
+    config.weekly_target_hours.is_some() || !config.contracts.is_empty()
+}
+
+// The weekly target on `date`: that of the contract in effect, otherwise
+// `weekly_target_hours`.
+pub fn weekly_target(config: &Config, date: NaiveDate) -> Option<f64> {
+    contract_on(config, date).map(|contract| contract.weekly_hours).or(config.weekly_target_hours)
+}
+
+// The weekly target on `date` spread evenly over the working days of a week.
+pub fn daily_target(config: &Config, date: NaiveDate) -> Option<Duration> {
+    let weekly = weekly_target(config, date)?;
+    let weekend_days = match &config.schedule {
+        Some(schedule) => schedule.weekend_days.len(),
+        None => 2,
+    };
+    let workdays = 7usize.saturating_sub(weekend_days).max(1);
+    Some(Duration::seconds((weekly * 3600.0 / workdays as f64) as i64))
+}
+
+// The first day with tracked time, a running session or an absence.
+pub fn tracking_start(time_sheet: &TimeSheet, zone: Zone) -> Option<NaiveDate> {
+    time_sheet
+        .periods
+        .iter()
+        .map(|p| zone.date(p.start))
+        .chain(time_sheet.active_period_start.map(|start| zone.date(start)))
+        .chain(time_sheet.absences.iter().map(|a| a.date))
+        .min()
+}
+
+// Calendar days of `zone` that start within the range.
+pub(crate) fn days_in(range: &Period, zone: Zone) -> impl Iterator<Item = NaiveDate> {
+    let first = zone.date(range.start);
+    let end = range.end;
+    first
+        .iter_days()
+        .take_while(move |day| crate::logic::day_period(*day, zone).is_some_and(|d| d.start < end))
+}
+
+// Absences on days within the range.
+pub fn absences_in<'a>(time_sheet: &'a TimeSheet, range: &Period, zone: Zone) -> Vec<&'a Absence> {
+    let days: Vec<NaiveDate> = days_in(range, zone).collect();
+    time_sheet.absences.iter().filter(|a| days.contains(&a.date)).collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Balance {
+    pub target: Duration,
+    pub credited: Duration,
+    pub balance: Duration,
+}
+
+// Target and balance for a reporting range, counting working days up to today,
+// each with the daily target of its contract. Days before the tracking history
+// starts have no target, so a first week or month that starts partway through
+// is prorated. Absences on working days count as worked for the daily target.
+// With a daily tolerance, days whose time is within it of their target add
+// nothing to the balance; days outside it add their full difference.
+pub fn balance(time_sheet: &TimeSheet, config: &Config, range: &Period, tracked: Duration, now: DateTime<Utc>) -> Option<Balance> {
+    if !has_target(config) {
+        return None;
+    }
+    let zone = config.zone();
+    let today = zone.date(now);
+    let first = tracking_start(time_sheet, zone);
+    let daily = |day: NaiveDate| {
+        let target = first.filter(|first| day >= *first).and_then(|_| daily_target(config, day));
+        target.unwrap_or_else(Duration::zero)
+    };
+    let target = days_in(range, zone).filter(|day| *day <= today && is_workday(config, *day)).map(daily).sum();
+    let absences = absences_in(time_sheet, range, zone);
+    let credited = absences.iter().filter(|a| a.date <= today && is_workday(config, a.date)).map(|a| daily(a.date)).sum();
+    let tolerance = Duration::minutes(config.daily_tolerance_minutes as i64);
+    if tolerance.is_zero() {
+        return Some(Balance { target, credited, balance: tracked + credited - target });
+    }
+    let balance = days_in(range, zone)
+        .filter(|day| *day <= today)
+        .filter_map(|day| {
+            let worked = crate::logic::calculate_tracked_time_in_period(time_sheet, &crate::logic::day_period(day, zone)?, now);
+            let (target, credited) = if is_workday(config, day) {
+                (daily(day), daily(day) * absences.iter().filter(|a| a.date == day).count() as i32)
+            } else {
+                (Duration::zero(), Duration::zero())
+            };
+            let difference = worked + credited - target;
+            Some(if difference.abs() <= tolerance { Duration::zero() } else { difference })
+        })
+        .sum();
+    Some(Balance { target, credited, balance })
+}
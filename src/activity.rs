@@ -0,0 +1,106 @@
+// Activity samples, so that `stop` can end a session when you actually left
+// instead of when you came back and noticed the timer.
+//
+// The tracker cannot watch the keyboard and mouse itself. An idle watcher, such as
+// swayidle, xidlehook or a shell prompt hook, runs `activity` while you are at
+// the computer. Only the latest sample is kept, in `<data file>.activity`, which
+// is replaced through a temporary file and a rename like the status summary.
+// Sampling is enabled by that file existing: without samples, `stop` is unchanged.
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::prompt;
+use crate::timefmt::TimeFormatter;
+use crate::TimeSheet;
+use chrono::{DateTime, Duration, Local, Utc};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument};
+
+// Used when `idle_after_minutes` is not set in the config.
+const DEFAULT_IDLE_AFTER_MINUTES: u64 = 15;
+
+pub fn activity_path(data_path: &Path) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(".activity");
+    PathBuf::from(name)
+}
+
+// Handles the "activity" command: records `now` as the last activity.
+#[instrument(level = "debug")]
+pub fn record(data_path: &Path, now: DateTime<Utc>) -> io::Result<()> {
+    let path = activity_path(data_path);
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+    fs::write(&temporary, now.to_rfc3339())?;
+    fs::rename(&temporary, &path)
+}
+
+// The latest sample, or none if nothing was sampled yet.
+pub fn last(data_path: &Path) -> io::Result<Option<DateTime<Utc>>> {
+    let path = activity_path(data_path);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let time = DateTime::parse_from_rfc3339(text.trim()).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid activity sample in {}: {}", path.display(), e))
+    })?;
+    Ok(Some(time.to_utc()))
+}
+
+// How long a session on `project` may go without activity before `stop` offers
+// to end it earlier: the project's entry in `idle_after_minutes_by_project`, or
+// else `idle_after_minutes`. Zero never offers.
+pub fn idle_after(config: &Config, project: Option<&str>) -> Duration {
+    let minutes = project
+        .and_then(|project| config.idle_after_minutes_by_project.get(project))
+        .copied()
+        .or(config.idle_after_minutes)
+        .unwrap_or(DEFAULT_IDLE_AFTER_MINUTES);
+    Duration::minutes(minutes as i64)
+}
+
+// The time `stop` should end the running session at, if not now. With
+// `at_last_activity` it is the last sample taken during the session, which must
+// exist. Otherwise, if the last sample is older than the idle threshold of the
+// session's project, it is offered on a terminal.
+pub fn stop_time(
+    data_path: &Path,
+    time_sheet: &TimeSheet,
+    at_last_activity: bool,
+    config: &Config,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<Option<DateTime<Utc>>> {
+    let Some(start) = time_sheet.active_period_start() else { return Ok(None) };
+    let now = clock.now();
+    let last = last(data_path)?.filter(|last| *last > start && *last < now);
+    debug!(?last, "last activity during the session");
+    if at_last_activity {
+        return match last {
+            Some(last) => Ok(Some(last)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No activity was sampled since the session started. Have an idle watcher run `activity` to record samples.",
+            )),
+        };
+    }
+    let idle_after = idle_after(config, time_sheet.active_metadata().project.as_deref());
+    debug!(idle_after = idle_after.num_minutes(), "idle threshold of the session");
+    let Some(last) = last.filter(|last| idle_after > Duration::zero() && now - *last >= idle_after) else {
+        return Ok(None);
+    };
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let question = format!(
+        "Your last activity was at {}, {} ago. End the session then instead of now?",
+        formatter.time(last.with_timezone(&Local)),
+        formatter.duration(now - last)
+    );
+    Ok(prompt::confirm(&question)?.then_some(last))
+}
@@ -0,0 +1,68 @@
+use crate::{Metadata, TimeSheet};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use std::collections::BTreeMap;
+use tracing::{debug, instrument};
+
+// Granularity that start times are rounded to.
+const START_GRANULARITY_MINUTES: i64 = 5;
+
+// A copy of the timesheet that can be shared: notes are removed, projects are
+// renamed to "Project A", "Project B", ... in the order they first occur, and
+// every start is rounded to five minutes with the duration and laps kept. Closed
+// months are left out, as their checksums no longer match.
+#[instrument(level = "debug", skip(time_sheet))]
+pub fn anonymize(time_sheet: &TimeSheet) -> TimeSheet {
+    let mut anonymized = time_sheet.clone();
+    anonymized.periods.sort_by_key(|p| p.start);
+    anonymized.closed_months.clear();
+
+    let mut names: BTreeMap<String, String> = BTreeMap::new();
+    let mut rename = |metadata: &mut Metadata| {
+        metadata.note = None;
+        if let Some(project) = metadata.project.take() {
+            let next = project_name(names.len());
+            metadata.project = Some(names.entry(project).or_insert(next).clone());
+        }
+    };
+    for period in &mut anonymized.periods {
+        let shift = fuzz(period.start) - period.start;
+        period.start += shift;
+        period.end += shift;
+        for lap in &mut period.laps {
+            lap.time += shift;
+            lap.note = None;
+        }
+        rename(&mut period.metadata);
+    }
+    if let Some(start) = anonymized.active_period_start {
+        let shift = fuzz(start) - start;
+        anonymized.active_period_start = Some(start + shift);
+        for lap in &mut anonymized.active_laps {
+            lap.time += shift;
+            lap.note = None;
+        }
+    }
+    rename(&mut anonymized.active_metadata);
+    for absence in &mut anonymized.absences {
+        absence.note = None;
+    }
+
+    debug!(projects = names.len(), "anonymized timesheet");
+    anonymized
+}
+
+// "Project A" to "Project Z", then "Project AA", "Project AB", ...
+fn project_name(index: usize) -> String {
+    let mut letters = Vec::new();
+    let mut n = index + 1;
+    while n > 0 {
+        n -= 1;
+        letters.push((b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    format!("Project {}", letters.iter().rev().collect::<String>())
+}
+
+fn fuzz(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_round(TimeDelta::minutes(START_GRANULARITY_MINUTES)).unwrap_or(time)
+}
@@ -0,0 +1,137 @@
+// The running overtime balance, week by week.
+//
+// Each week's balance is tracked time plus absence credit minus the target, as in
+// the reports, so days within `daily_tolerance_minutes` of their target add
+// nothing. What is left at the end of a week carries into the next one
+// according to `[carry_over]`: fully, capped at `cap_hours`, or only within a
+// month. A week belongs to the month of its first day. The first week only has
+// a target from the day tracking started. Days outside every contract have the
+// target of `weekly_target_hours`, or none without it.
+
+use crate::absence;
+use crate::clock::Clock;
+use crate::config::{CarryOverPolicy, Config};
+use crate::logic;
+use crate::timefmt::TimeFormatter;
+use crate::TimeSheet;
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use std::io;
+use tracing::{debug, instrument};
+
+pub struct WeekBalance {
+    pub first: NaiveDate,
+    pub tracked: Duration,
+    pub target: Duration,
+    pub credited: Duration,
+    /// The week's own balance, with the daily tolerance applied.
+    pub week: Duration,
+    /// Balance carried in from the weeks before.
+    pub carried: Duration,
+    /// Balance at the end of the week, after the policy is applied.
+    pub closing: Duration,
+}
+
+// Balances of every week from the first one with tracked time or an absence up to
+// the current week. Empty without a weekly target or any history.
+pub fn weekly_balances(time_sheet: &TimeSheet, config: &Config, clock: &dyn Clock) -> io::Result<Vec<WeekBalance>> {
+    let cap = match (config.carry_over.policy, config.carry_over.cap_hours) {
+        (CarryOverPolicy::Cap, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The carry-over policy \"cap\" needs cap_hours in the config.",
+            ));
+        }
+        (CarryOverPolicy::Cap, Some(hours)) => Some(Duration::seconds((hours.abs() * 3600.0) as i64)),
+        _ => None,
+    };
+    let now = clock.now();
+    let this_week = logic::get_week_period(config.week_start.0, clock, config.zone());
+    let this_week = this_week.start.with_timezone(&Local).date_naive();
+    let Some(earliest) = absence::tracking_start(time_sheet, config.zone()).filter(|_| absence::has_target(config)) else {
+        return Ok(Vec::new());
+    };
+
+    let mut first = earliest - Duration::days(earliest.weekday().days_since(config.week_start.0) as i64);
+    let mut carried = Duration::zero();
+    let mut previous_month = None;
+    let mut weeks = Vec::new();
+    while first <= this_week {
+        let month = (first.year(), first.month());
+        if config.carry_over.policy == CarryOverPolicy::ResetMonthly && previous_month.is_some_and(|m| m != month) {
+            carried = Duration::zero();
+        }
+        previous_month = Some(month);
+
+        let range = logic::week_starting(first, config.zone());
+        let tracked = logic::calculate_tracked_time_in_period(time_sheet, &range, now);
+        let Some(balance) = absence::balance(time_sheet, config, &range, tracked, now) else { break };
+        let mut closing = carried + balance.balance;
+        if let Some(cap) = cap {
+            closing = closing.clamp(-cap, cap);
+        }
+        weeks.push(WeekBalance {
+            first,
+            tracked,
+            target: balance.target,
+            credited: balance.credited,
+            week: balance.balance,
+            carried,
+            closing,
+        });
+        carried = closing;
+        first += Duration::weeks(1);
+    }
+    debug!(weeks = weeks.len(), "computed weekly balances");
+    Ok(weeks)
+}
+
+// Handles the "balance" command: the last `weeks` weeks and the current balance.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn show_balance(time_sheet: &TimeSheet, config: &Config, weeks: u32, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    if !absence::has_target(config) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "The balance needs weekly_target_hours or [[contracts]] in the config.",
+        ));
+    }
+    let balances = weekly_balances(time_sheet, config, clock)?;
+    let Some(current) = balances.last() else {
+        println!("Nothing tracked yet.");
+        return Ok(());
+    };
+    let shown = &balances[balances.len().saturating_sub(weeks as usize)..];
+    if !shown.is_empty() {
+        println!("Week of      {:>10} {:>10} {:>10} {:>11}", "Tracked", "Target", "Week", "Balance");
+    }
+    for week in shown {
+        let credited = if week.credited > Duration::zero() {
+            format!("  (+{} absent)", formatter.duration(week.credited))
+        } else {
+            String::new()
+        };
+        println!(
+            "{}  {:>10} {:>10} {:>10} {:>11}{}",
+            formatter.date(week.first),
+            formatter.duration(week.tracked),
+            formatter.duration(week.target),
+            signed(week.week, formatter),
+            signed(week.closing, formatter),
+            credited
+        );
+    }
+    if !shown.is_empty() {
+        println!();
+    }
+    let policy = match config.carry_over.policy {
+        CarryOverPolicy::Full => "carried over fully".to_string(),
+        CarryOverPolicy::Cap => format!("capped at {}h", config.carry_over.cap_hours.unwrap_or_default()),
+        CarryOverPolicy::ResetMonthly => "reset monthly".to_string(),
+    };
+    println!("Balance: {} ({}).", signed(current.closing, formatter), policy);
+    Ok(())
+}
+
+fn signed(duration: Duration, formatter: &TimeFormatter) -> String {
+    let sign = if duration < Duration::zero() { "-" } else { "+" };
+    format!("{}{}", sign, formatter.duration(duration.abs()))
+}
@@ -0,0 +1,102 @@
+use crate::absence;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::logic;
+use crate::timefmt::TimeFormatter;
+use crate::{Period, TimeSheet};
+use chrono::{DateTime, Duration, Local, Utc};
+use std::io;
+use tracing::{debug, instrument};
+
+// Characters of the trend bar that stand for 100%.
+const BAR_WIDTH: usize = 20;
+
+// Tracked time against the available working time of a range.
+pub struct Utilization {
+    pub tracked: Duration,
+    pub capacity: Duration,
+}
+
+impl Utilization {
+    // Tracked time as a percentage of capacity, or none without capacity.
+    pub fn percent(&self) -> Option<f64> {
+        (self.capacity > Duration::zero())
+            .then(|| self.tracked.num_seconds() as f64 * 100.0 / self.capacity.num_seconds() as f64)
+    }
+}
+
+// Capacity is the daily target for every working day of the range up to today,
+// except days with an absence: unlike the balance, where absences count as
+// worked, they reduce what could have been billed.
+pub fn utilization(time_sheet: &TimeSheet, config: &Config, range: &Period, now: DateTime<Utc>) -> Option<Utilization> {
+    if !absence::has_target(config) {
+        return None;
+    }
+    let today = now.with_timezone(&Local).date_naive();
+    let absent: Vec<_> = absence::absences_in(time_sheet, range, config.zone()).iter().map(|a| a.date).collect();
+    let capacity = absence::days_in(range, config.zone())
+        .filter(|day| *day <= today && absence::is_workday(config, *day) && !absent.contains(day))
+        .filter_map(|day| absence::daily_target(config, day))
+        .sum();
+    Some(Utilization { tracked: logic::calculate_tracked_time_in_period(time_sheet, range, now), capacity })
+}
+
+// Handles the "capacity" command: utilization of the current week and month and
+// a trend over the last `weeks` weeks.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn show_capacity(time_sheet: &TimeSheet, config: &Config, weeks: u32, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    if !absence::has_target(config) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "The capacity report needs weekly_target_hours or [[contracts]] in the config.",
+        ));
+    }
+    let now = clock.now();
+    let week = logic::get_week_period(config.week_start.0, clock, config.zone());
+    let month = logic::get_month_period(clock, config.zone());
+    for (name, range) in [("week", &week), ("month", &month)] {
+        if let Some(utilization) = utilization(time_sheet, config, range, now) {
+            println!(
+                "This {:<6} {} of {}  {}",
+                name,
+                formatter.duration(utilization.tracked),
+                formatter.duration(utilization.capacity),
+                percent_text(&utilization)
+            );
+        }
+    }
+
+    if weeks == 0 {
+        return Ok(());
+    }
+    println!();
+    println!("Last {} weeks:", weeks);
+    let this_week = week.start.with_timezone(&Local).date_naive();
+    for ago in (0..weeks as i64).rev() {
+        let first = this_week - Duration::weeks(ago);
+        let range = logic::week_starting(first, config.zone());
+        let Some(utilization) = utilization(time_sheet, config, &range, now) else { continue };
+        let bar = utilization
+            .percent()
+            .map(|percent| "#".repeat(((percent / 100.0 * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH * 2)))
+            .unwrap_or_default();
+        let line = format!(
+            "  {}  {:>10} / {:<10} {:>5}  {}",
+            formatter.date(first),
+            formatter.duration(utilization.tracked),
+            formatter.duration(utilization.capacity),
+            percent_text(&utilization),
+            bar
+        );
+        println!("{}", line.trim_end());
+    }
+    debug!(weeks, "printed capacity trend");
+    Ok(())
+}
+
+fn percent_text(utilization: &Utilization) -> String {
+    match utilization.percent() {
+        Some(percent) => format!("{:.0}%", percent),
+        None => "-".to_string(),
+    }
+}
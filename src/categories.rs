@@ -0,0 +1,109 @@
+use crate::config::{ScheduleConfig, TrackingWindow};
+use crate::zone::Zone;
+use crate::Period;
+use chrono::{DateTime, Datelike, Duration, DurationRound, FixedOffset, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Payroll categories of working time. When several apply, the first one in this
+// order wins: a night shift on a holiday counts as holiday.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Holiday,
+    Weekend,
+    Night,
+    Evening,
+    Regular,
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Holiday => "holiday",
+            Category::Weekend => "weekend",
+            Category::Night => "night",
+            Category::Evening => "evening",
+            Category::Regular => "regular",
+        }
+    }
+}
+
+// The category of a moment, by its local date and time.
+pub fn classify(schedule: &ScheduleConfig, time: DateTime<FixedOffset>) -> Category {
+    let date = time.date_naive();
+    if schedule.holidays.contains(&date) {
+        return Category::Holiday;
+    }
+    if schedule.weekend_days.contains(&date.weekday()) {
+        return Category::Weekend;
+    }
+    let clock = time.time();
+    if in_window(clock, schedule.night_start, schedule.night_end) {
+        return Category::Night;
+    }
+    if in_window(clock, schedule.evening_start, schedule.night_start) {
+        return Category::Evening;
+    }
+    Category::Regular
+}
+
+// Whether `time` lies in [start, end), where the window may wrap around midnight.
+pub(crate) fn in_window(time: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        time >= start || time < end
+    }
+}
+
+// Splits tracked time into categories, one minute at a time: of each period, the
+// part within the range paired with it. Categories without time are left out.
+pub fn breakdown<'a>(
+    schedule: &ScheduleConfig,
+    pieces: impl IntoIterator<Item = (&'a Period, &'a Period)>,
+    zone: Zone,
+) -> BTreeMap<Category, Duration> {
+    let mut totals: BTreeMap<Category, Duration> = BTreeMap::new();
+    for (period, range) in pieces {
+        for_each_minute_of(period, range, zone, &mut |time, length| {
+            *totals.entry(classify(schedule, time)).or_insert_with(Duration::zero) += length;
+        });
+    }
+    totals
+}
+
+// Splits the part of one period within `range` into categories, as `breakdown`
+// does for several.
+pub fn split(schedule: &ScheduleConfig, period: &Period, range: &Period, zone: Zone) -> BTreeMap<Category, Duration> {
+    breakdown(schedule, [(period, range)], zone)
+}
+
+// Tracked time that lies outside the tracking window, of each period the part
+// within the range paired with it.
+pub fn outside_window<'a>(window: &TrackingWindow, pieces: impl IntoIterator<Item = (&'a Period, &'a Period)>, zone: Zone) -> Duration {
+    let mut outside = Duration::zero();
+    for (period, range) in pieces {
+        for_each_minute_of(period, range, zone, &mut |time, length| {
+            if !window.contains(time.time()) {
+                outside += length;
+            }
+        });
+    }
+    outside
+}
+
+// Calls `f` with the start in `zone` and length of every piece of the period
+// within `range`, split at full minutes.
+fn for_each_minute_of(period: &Period, range: &Period, zone: Zone, f: &mut impl FnMut(DateTime<FixedOffset>, Duration)) {
+    let start = period.start.max(range.start);
+    let end = period.end.min(range.end);
+    let mut time = start;
+    while time < end {
+        // Step to the next full minute, so each minute is classified once.
+        let next = time.duration_trunc(Duration::minutes(1)).unwrap_or(time) + Duration::minutes(1);
+        let step_end = next.min(end);
+        f(zone.local_time(time), step_end - time);
+        time = step_end;
+    }
+}
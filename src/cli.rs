@@ -0,0 +1,824 @@
+use time_tracker::Metadata;
+use time_tracker::absence::AbsenceKind;
+use time_tracker::export::GridFormat;
+use time_tracker::presets;
+use time_tracker::report::Grouping;
+use time_tracker::retag::Filter;
+use time_tracker::sync::ConflictStrategy;
+use time_tracker::timefmt::{HoursFormat, Precision};
+use chrono::{DateTime, Utc};
+use clap::builder::PossibleValuesParser;
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// A simple command-line utility to track work time.
+#[derive(Parser, Debug)]
+#[command(name = "work_time_tracker", version, arg_required_else_help = true)]
+pub struct Cli {
+    /// Increase log verbosity (-v info, -vv debug, -vvv trace).
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Append log output to a file instead of stderr.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// How durations are shown: decimal, hms or verbose (default from config, else hms).
+    #[arg(long, value_name = "FORMAT", global = true)]
+    pub hours_format: Option<HoursFormat>,
+
+    /// How durations are rounded for display: seconds, minutes or quarter-hours
+    /// (default from config, else seconds). Stored times are not changed.
+    #[arg(long, value_name = "PRECISION", global = true)]
+    pub precision: Option<Precision>,
+
+    /// Act as if the current time were this RFC 3339 timestamp, e.g. 2024-05-03T17:00:00+02:00.
+    #[arg(long, value_name = "TIME", global = true, value_parser = parse_time)]
+    pub now: Option<DateTime<Utc>>,
+
+    /// Refuse to load a data file with unknown fields, unsorted or overlapping periods or times in
+    /// the future, instead of tidying it up (default from config, else off).
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// How to settle changes that conflict with ones made to the data file while
+    /// the command ran, e.g. by a sync client: ask (on a terminal, else fail),
+    /// fail, mine or theirs.
+    #[arg(long, value_enum, value_name = "STRATEGY", global = true, default_value = "ask")]
+    pub conflicts: ConflictStrategy,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+fn parse_time(text: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|time| time.to_utc())
+        .map_err(|e| format!("expected an RFC 3339 time such as 2024-05-03T17:00:00+02:00 ({})", e))
+}
+
+fn parse_field(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+        _ => Err("expected KEY=VALUE, e.g. ticket=ABC-1".to_string()),
+    }
+}
+
+fn parse_grouping(text: &str) -> Result<Grouping, String> {
+    Grouping::parse(text).map_err(|e| e.to_string())
+}
+
+fn parse_filter(text: &str) -> Result<Filter, String> {
+    Filter::parse(text).map_err(|e| e.to_string())
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Set up the config file interactively.
+    Init {
+        /// Do not ask; write the current or default settings.
+        #[arg(long)]
+        defaults: bool,
+    },
+    /// Start tracking a new time period.
+    Start {
+        /// Favorite to start, written as @name.
+        #[arg(value_name = "@FAVORITE")]
+        favorite: Option<String>,
+        /// Continue with the project, tags and note of the last stopped session.
+        /// Options given as well replace the copied values.
+        #[arg(long, conflicts_with = "favorite")]
+        resume: bool,
+        /// Work on this Taskwarrior task: its project and description are used, and
+        /// the session is tagged task:<uuid>.
+        #[arg(long, value_name = "ID")]
+        task: Option<String>,
+        /// Have 'remind' notify you once the session runs longer than this, e.g. 4h.
+        #[arg(long, value_name = "DURATION")]
+        remind_after: Option<String>,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+    /// Record a finished period after the fact.
+    Add {
+        /// When it started: HH:MM today, or "yesterday HH:MM" or "YYYY-MM-DD HH:MM".
+        #[arg(long, value_name = "TIME")]
+        start: String,
+        /// When it ended, in the same form as --start.
+        #[arg(long, value_name = "TIME", required_unless_present = "duration")]
+        end: Option<String>,
+        /// How long it took instead of the end, e.g. 2h30m, 45m, 1.5h or 2:30.
+        #[arg(long, value_name = "DURATION", conflicts_with = "end")]
+        duration: Option<String>,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+    /// Stop the currently tracked time period.
+    Stop {
+        /// Record the session even if it is shorter than the configured minimum.
+        #[arg(long)]
+        keep: bool,
+        /// End the session at the last `activity` sample instead of now.
+        #[arg(long)]
+        at_last_activity: bool,
+    },
+    /// Stop the running session and start the next one at the same instant, e.g.
+    /// when moving on to another project.
+    Switch {
+        /// Project to switch to, or a favorite written as @name.
+        #[arg(value_name = "PROJECT|@FAVORITE", conflicts_with = "project")]
+        target: Option<String>,
+        /// Record the stopped session even if it is shorter than the configured minimum.
+        #[arg(long)]
+        keep: bool,
+        /// End the stopped session at the last `activity` sample instead of now.
+        #[arg(long)]
+        at_last_activity: bool,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+    /// The sessions of a day as Markdown, with their notes, laps and commits.
+    Journal {
+        /// The day: today, yesterday or YYYY-MM-DD.
+        #[arg(long, default_value = "today")]
+        day: String,
+        /// Write to this file instead of printing.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Record that you are at the computer, for `stop`. Meant to be run by an idle
+    /// watcher such as swayidle, or from a shell prompt hook.
+    Activity,
+    /// One line on the running session and today's total, fast enough for a shell prompt.
+    Status {
+        /// Only read the summary the last change left behind, without looking at the data file.
+        /// Cheap enough to run every second, e.g. from tmux or i3blocks. Budgets are not shown.
+        #[arg(long)]
+        cached: bool,
+    },
+    /// List recent sessions.
+    Log {
+        /// How many sessions to show.
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Show running daily and weekly totals next to each session.
+        #[arg(long)]
+        cumulative: bool,
+        /// Show the laps of each session with their split times.
+        #[arg(long)]
+        details: bool,
+    },
+    /// Find sessions by project, tags, note, category or custom fields.
+    ///
+    /// Every word has to match a field, as a part of it or fuzzily with its letters in order, so
+    /// "mtg" finds "meeting". The best matches come first. Set `search_index = true` in the config
+    /// to keep an index that saves reading the whole history.
+    Search {
+        /// Words to look for.
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// How many sessions to show.
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Apply commands from standard input in one go, saving only if all succeed.
+    ///
+    /// Each line is a command. Words are split as in a shell, so quote values with spaces. Empty lines and
+    /// lines starting with '#' are skipped. The commands are:
+    ///
+    ///   add YYYY-MM-DD HH:MM HH:MM [PROJECT] [--tags ..] [--note ..] [--category ..] [--field ..]
+    ///   start YYYY-MM-DD HH:MM [PROJECT] [--tags ..] [--note ..] [--category ..] [--field ..]
+    ///   stop YYYY-MM-DD HH:MM [--keep]
+    ///   tag rename OLD NEW
+    ///   retag [--filter KEY=VALUE] [--add TAG] [--remove TAG] [--category NAME]
+    ///   absence add KIND DAYS [--note ..]
+    #[command(verbatim_doc_comment)]
+    Batch,
+    /// Mark an intermediate point in the running session.
+    Lap {
+        /// What was done since the previous lap.
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Write longer notes for periods in your editor.
+    Note {
+        #[command(subcommand)]
+        action: NoteAction,
+    },
+    /// Show tracked time for today.
+    Today,
+    /// Show tracked time for this week.
+    Week,
+    /// Show tracked time for this month.
+    Month,
+    /// Show a report with breakdowns, optionally rendered through a template.
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Report {
+        #[command(subcommand)]
+        action: Option<ReportAction>,
+        /// today, week, month, rolling or a name from [periods] in the config.
+        #[arg(required = true)]
+        period: Option<String>,
+        /// For rolling: how many days up to now the report covers, e.g. `report rolling 7`.
+        #[arg(value_name = "DAYS")]
+        days: Option<u32>,
+        /// TinyTemplate file to render the report with.
+        #[arg(long, value_name = "FILE")]
+        template: Option<PathBuf>,
+        /// Print the template context as JSON instead of rendering it.
+        #[arg(long, conflicts_with = "template")]
+        dump_context: bool,
+        /// Count days and show times in this IANA time zone, e.g. Europe/Berlin,
+        /// instead of the local one.
+        #[arg(long, value_name = "ZONE")]
+        tz: Option<String>,
+        /// Only count periods matching this, as for `retag --filter`. Can be
+        /// repeated; all must match.
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_filter)]
+        filter: Vec<Filter>,
+        /// Also show the time by project, tag, category, client, field:KEY or
+        /// weekday. Can be repeated; client is short for field:client.
+        #[arg(long, value_name = "AXIS", value_parser = parse_grouping)]
+        group_by: Vec<Grouping>,
+    },
+    /// Time by session category per week: focus, meetings and admin as shares of
+    /// the tracked time.
+    Focus {
+        /// How many weeks are listed, including the current one.
+        #[arg(long, default_value_t = 4)]
+        weeks: u32,
+    },
+    /// Tracked time as a percentage of contracted capacity, with a weekly trend.
+    Capacity {
+        /// How many weeks the trend covers, including the current one.
+        #[arg(long, default_value_t = 8)]
+        weeks: u32,
+    },
+    /// Check the data, config and environment for problems.
+    Doctor {
+        /// Also check file permissions, interrupted writes, the clock against NTP
+        /// and the timezone.
+        #[arg(long)]
+        full: bool,
+        /// Print the report as JSON, e.g. to attach to a bug report.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Overtime balance week by week, carried over as set in `[carry_over]`.
+    Balance {
+        /// How many of the most recent weeks are listed.
+        #[arg(long, default_value_t = 8)]
+        weeks: u32,
+    },
+    /// Invoice summary of a month from the hourly rates in `[rates]`.
+    Invoice {
+        /// The month to bill, as YYYY-MM. Defaults to the current month.
+        #[arg(long, value_name = "YYYY-MM")]
+        month: Option<String>,
+    },
+    /// Show statistics about tracked time.
+    Stats {
+        #[command(subcommand)]
+        action: StatsAction,
+    },
+    /// Manage timer templates for 'start @name'.
+    Favorite {
+        #[command(subcommand)]
+        action: FavoriteAction,
+    },
+    /// Store tokens for integrations in the OS keyring.
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+    /// Find untracked time and fill it in.
+    Suggest {
+        #[command(subcommand)]
+        action: SuggestAction,
+    },
+    /// Go through recent days to fix and confirm them.
+    Review {
+        #[command(subcommand)]
+        action: ReviewAction,
+    },
+    /// Plan hours per project for a week and compare them with the tracked time.
+    Plan {
+        #[command(subcommand)]
+        action: PlanAction,
+    },
+    /// Change tags across the timesheet.
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    /// Add and remove tags, or set the category, of every period matching the filters.
+    #[command(group(ArgGroup::new("change").required(true).multiple(true).args(["add", "remove", "category"])))]
+    Retag {
+        /// Only periods matching this: project=NAME (empty for none), tag=NAME,
+        /// note=TEXT, category=NAME (empty for none) or field.KEY=VALUE (empty for
+        /// none). Can be repeated; all must match.
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_filter)]
+        filter: Vec<Filter>,
+        /// Tag to add. Can be repeated.
+        #[arg(long, value_name = "TAG")]
+        add: Vec<String>,
+        /// Tag to remove. Can be repeated.
+        #[arg(long, value_name = "TAG")]
+        remove: Vec<String>,
+        /// Category to give the periods, replacing the one they have.
+        #[arg(long, value_name = "CATEGORY")]
+        category: Option<String>,
+        /// List the changes without saving them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Record sick leave and vacation.
+    Absence {
+        #[command(subcommand)]
+        action: AbsenceAction,
+    },
+    /// Close a month: validate, export and lock it.
+    Close {
+        #[command(subcommand)]
+        action: CloseAction,
+    },
+    /// Data protection: purge old identifying details under the retention policy.
+    Gdpr {
+        #[command(subcommand)]
+        action: GdprAction,
+    },
+    /// Import periods from other sources.
+    Import {
+        /// Show what would be imported, as a diff and a summary, without saving.
+        #[arg(long, global = true)]
+        dry_run: bool,
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Write the timesheet in another tool's format.
+    Export {
+        /// Remove notes, rename projects to "Project A", "Project B", ... and round
+        /// start times to 5 minutes, for sharing in bug reports.
+        #[arg(long, global = true)]
+        anonymize: bool,
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+    /// Publish sessions to other services, or back them up to a remote.
+    Sync {
+        #[command(subcommand)]
+        target: SyncTarget,
+    },
+    /// Send a desktop notification when the running session gets long, as set with
+    /// 'start --remind-after' or [reminders]. Keeps watching until interrupted.
+    Remind {
+        /// Check a single time and exit, e.g. when run from cron.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Run 'remind' in the background, one instance per data file.
+    Daemon {
+        #[command(subcommand)]
+        action: DaemonAction,
+    },
+    /// Tracked totals as Prometheus metrics.
+    Metrics {
+        #[command(subcommand)]
+        action: MetricsAction,
+    },
+    /// Serve a read-only dashboard on http://127.0.0.1, with Prometheus metrics on /metrics.
+    Serve {
+        /// Port to listen on.
+        #[arg(long, default_value_t = 7878)]
+        port: u16,
+        /// Address to listen on. Other machines only get the shared week report,
+        /// which needs the "share-token" secret.
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+    },
+    /// Rewrite the data file canonically: fold in the journal, sort periods, tidy
+    /// metadata and merge adjacent periods as set in [compaction].
+    Compact,
+    /// Convert a timesheet between JSON and MessagePack (.msgpack), by file extension.
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    /// Print a shell completion script.
+    Completions {
+        shell: Shell,
+    },
+    /// Print candidate values for dynamic shell completion, one per line.
+    #[command(hide = true)]
+    CompleteHelper {
+        kind: CompletionKind,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FavoriteAction {
+    /// Add or replace a favorite.
+    Add {
+        name: String,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+    /// Remove a favorite.
+    Remove { name: String },
+    /// List all favorites.
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NoteAction {
+    /// Open a period's note in $VISUAL or $EDITOR and save it when the editor
+    /// exits. Lines starting with '#' are dropped; an empty note removes it.
+    Edit {
+        /// The period's ID, as printed by 'add', or the start of it.
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PlanAction {
+    /// Set the hours planned per project for a week, replacing its plan, e.g.
+    /// `plan set week --project acme 20h --project internal 10h`.
+    Set {
+        /// The week: week, next-week, last-week or any of its days as YYYY-MM-DD.
+        week: String,
+        /// A project and the time planned for it. Can be repeated.
+        #[arg(long = "project", num_args = 2, value_names = ["PROJECT", "DURATION"], required = true)]
+        projects: Vec<String>,
+    },
+    /// Compare the plan of a week with the time tracked on each project.
+    Status {
+        /// The week, as for `plan set`.
+        #[arg(default_value = "week")]
+        week: String,
+    },
+    /// Remove the plan of a week.
+    Clear {
+        /// The week, as for `plan set`.
+        week: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReviewAction {
+    /// Walk through this week's days: set missing projects, merge fragments, fill
+    /// gaps and mark each day as reviewed.
+    Week {
+        /// Also go through days that were already reviewed.
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagAction {
+    /// Rename a tag on every period that has it.
+    Rename {
+        old: String,
+        new: String,
+        /// List the changes without saving them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SuggestAction {
+    /// List the untracked gaps of a workday and offer to fill them.
+    Gaps {
+        /// The day to check: today, yesterday or YYYY-MM-DD.
+        #[arg(long, default_value = "today")]
+        day: String,
+        /// Ignore gaps shorter than this many minutes.
+        #[arg(long, default_value_t = 15)]
+        min_minutes: i64,
+        /// Only list the gaps, without asking.
+        #[arg(long)]
+        list: bool,
+        /// Use the workday of `[schedule]` instead of the usual hours of the weekday.
+        #[arg(long)]
+        configured_hours: bool,
+    },
+}
+
+// Known names are "slack-token", "caldav-password", "share-token", "remote-password"
+// and "remote-passphrase".
+#[derive(Subcommand, Debug)]
+pub enum SecretAction {
+    /// Store a secret, read from standard input so it stays out of the shell history.
+    Set { name: String },
+    /// Print a stored secret.
+    Get { name: String },
+    /// Remove a stored secret.
+    Delete { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StatsAction {
+    /// Compare a day with the same date in previous years and weeks.
+    #[command(name = "onthisday")]
+    OnThisDay {
+        /// The day to compare as YYYY-MM-DD (default today).
+        #[arg(long)]
+        date: Option<String>,
+        /// How many previous years to show.
+        #[arg(long, default_value_t = 5)]
+        years: u32,
+        /// How many previous weeks to show.
+        #[arg(long, default_value_t = 4)]
+        weeks: u32,
+    },
+    /// Flag unusually long or short days and gaps in usually busy hours, to catch
+    /// forgotten stops and missing entries.
+    Anomalies {
+        /// How many days back to check, including today.
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+        /// How many standard deviations from the usual total make a day unusual.
+        #[arg(long, default_value_t = 2.0)]
+        sigma: f64,
+    },
+    /// Show the usual start, end and breaks of each weekday, learned from the
+    /// tracked history. `suggest gaps` looks for gaps within these hours.
+    Pattern {
+        /// How many weeks back to learn from.
+        #[arg(long, default_value_t = 8)]
+        weeks: u32,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AbsenceAction {
+    /// Record an absence for a day or an inclusive range of days.
+    Add {
+        #[arg(value_enum)]
+        kind: AbsenceKind,
+        /// YYYY-MM-DD or YYYY-MM-DD..YYYY-MM-DD.
+        days: String,
+        /// Free-form note.
+        #[arg(long, value_name = "TEXT")]
+        note: Option<String>,
+    },
+    /// List all recorded absences.
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DaemonAction {
+    /// Start it, unless it already runs. Its output goes to <data file>.daemon.log.
+    Start,
+    /// Tell whether it runs, and as which process.
+    Status,
+    /// Stop it, and remove a lock left by one that was killed.
+    Stop,
+    /// Stop it if it runs, then start it.
+    Restart,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MetricsAction {
+    /// Print the metrics, or write them for node_exporter's textfile collector.
+    Export {
+        /// Write to this file instead, e.g. /var/lib/node_exporter/tt.prom. It is
+        /// replaced in one go, so it can be written from cron.
+        #[arg(long, value_name = "PATH")]
+        textfile: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SyncTarget {
+    /// Upload finished sessions as events to the calendar in the [caldav] config section.
+    Caldav {
+        /// Show what would be uploaded or removed without contacting the server.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Upload an encrypted snapshot of the timesheet to the [remote] config section.
+    Remote,
+    /// Restore the timesheet from a snapshot uploaded with 'sync remote'.
+    Pull {
+        /// Restore the latest snapshot taken at or before this time: YYYY-MM-DD,
+        /// optionally followed by HH:MM. Defaults to the latest one.
+        #[arg(long)]
+        at: Option<String>,
+        /// List the snapshots instead of restoring one.
+        #[arg(long, conflicts_with_all = ["at", "yes"])]
+        list: bool,
+        /// Replace the timesheet without asking.
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GdprAction {
+    /// Anonymize or delete what is older than `[retention]` allows.
+    Enforce {
+        /// List what the policy applies to without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+        /// Apply the policy without asking.
+        #[arg(long, conflicts_with = "dry_run")]
+        yes: bool,
+        /// Write what is removed, as it was, to this JSON file first.
+        #[arg(long, value_name = "FILE")]
+        export: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CloseAction {
+    /// Validate, export and lock a finished month.
+    Month {
+        /// The month as YYYY-MM.
+        month: String,
+    },
+    /// List closed months with their checksums.
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImportSource {
+    /// Import past calendar events as worked time, tagged 'meeting' unless --tags is given.
+    Ics {
+        file: PathBuf,
+        /// Only import events whose summary contains one of these |-separated texts.
+        #[arg(long)]
+        filter: Option<String>,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+    /// Import intervals from 'timew export' JSON. A 'project:NAME' tag sets the project.
+    Timew {
+        file: PathBuf,
+        /// Used for intervals that have no project, tags or annotation of their own.
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportTarget {
+    /// Export as JSON for 'timew import'. The project becomes a 'project:NAME' tag.
+    Timew {
+        /// Write to this file instead of standard output.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Export a month as a day-by-day grid of start, end, break and total.
+    Grid {
+        /// The month to export, as YYYY-MM. Defaults to the current month.
+        #[arg(long, value_name = "YYYY-MM")]
+        month: Option<String>,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: GridFormat,
+        /// Write to this file instead of standard output.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Export all periods as a Parquet file for DuckDB, pandas or Polars. Needs a build with the
+    /// 'parquet' feature.
+    Parquet {
+        /// The file to write.
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Export daily hours per project in the layout of a payroll or timesheet system.
+    Preset {
+        #[arg(value_parser = PossibleValuesParser::new(presets::names()))]
+        name: String,
+        /// The period to export: today, week, month or a name from [periods].
+        #[arg(long, default_value = "week")]
+        period: String,
+        /// Write to this file instead of standard output.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReportAction {
+    /// List working days of a month without tracked time or an absence.
+    Missing {
+        /// The month as YYYY-MM. Defaults to the current month.
+        #[arg(long)]
+        month: Option<String>,
+    },
+    /// One row per ISO 8601 week with the tracked time and the target, across months and years.
+    Weeks {
+        /// The first week, e.g. 2024-W01.
+        #[arg(long, value_name = "YYYY-Www")]
+        from: String,
+        /// The last week. Defaults to the current week.
+        #[arg(long, value_name = "YYYY-Www")]
+        to: Option<String>,
+    },
+}
+
+// The kinds of names the completion scripts ask for.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum CompletionKind {
+    Projects,
+    Tags,
+    Favorites,
+    Periods,
+}
+
+// One line of `batch` input, split into words like a shell does.
+#[derive(Parser, Debug)]
+#[command(name = "batch", no_binary_name = true, disable_help_flag = true, disable_version_flag = true)]
+pub struct BatchLine {
+    #[command(subcommand)]
+    pub command: BatchCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BatchCommand {
+    Start {
+        day: String,
+        time: String,
+        #[arg(value_name = "PROJECT", conflicts_with = "project")]
+        project_name: Option<String>,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+    Stop {
+        day: String,
+        time: String,
+        #[arg(long)]
+        keep: bool,
+    },
+    // An end before the start is on the next day.
+    Add {
+        day: String,
+        start: String,
+        end: String,
+        #[arg(value_name = "PROJECT", conflicts_with = "project")]
+        project_name: Option<String>,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+    },
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    #[command(group(ArgGroup::new("change").required(true).multiple(true).args(["add", "remove", "category"])))]
+    Retag {
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_filter)]
+        filter: Vec<Filter>,
+        #[arg(long, value_name = "TAG")]
+        add: Vec<String>,
+        #[arg(long, value_name = "TAG")]
+        remove: Vec<String>,
+        #[arg(long, value_name = "CATEGORY")]
+        category: Option<String>,
+    },
+    Absence {
+        #[command(subcommand)]
+        action: AbsenceAction,
+    },
+}
+
+// The --project, --tags, --note, --category and --field options shared by commands that create periods.
+#[derive(Args, Debug, Default)]
+pub struct MetadataArgs {
+    /// Project the time is spent on.
+    #[arg(long, value_name = "PROJECT")]
+    pub project: Option<String>,
+
+    /// Tags for the period, comma-separated or given multiple times.
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    /// Free-form note.
+    #[arg(long, value_name = "TEXT")]
+    pub note: Option<String>,
+
+    /// Kind of work, one of the configured session categories (by default
+    /// focus, meeting or admin).
+    #[arg(long, value_name = "CATEGORY")]
+    pub category: Option<String>,
+
+    /// Custom field, e.g. ticket=ABC-1. Can be given multiple times.
+    #[arg(long = "field", value_name = "KEY=VALUE", value_parser = parse_field)]
+    pub fields: Vec<(String, String)>,
+}
+
+impl From<MetadataArgs> for Metadata {
+    fn from(args: MetadataArgs) -> Self {
+        Metadata {
+            project: args.project,
+            tags: args.tags.into_iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect(),
+            note: args.note,
+            category: args.category.map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty()),
+            fields: args.fields.into_iter().collect(),
+        }
+    }
+}
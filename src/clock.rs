@@ -0,0 +1,47 @@
+use crate::suspend::{self, SuspendMark};
+use chrono::{DateTime, Utc};
+
+// Source of the current time. Code that takes a clock instead of calling
+// `Utc::now()` can be driven by a different one, e.g. from embedding programs.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// How long the system has slept, for telling sleep apart from tracked time.
+    /// Clocks other than the system's have none.
+    fn suspend_mark(&self) -> Option<SuspendMark> {
+        None
+    }
+}
+
+// The system's wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn suspend_mark(&self) -> Option<SuspendMark> {
+        suspend::mark()
+    }
+}
+
+// A clock that always returns the same time, for reproducible runs and replaying
+// what a command would have done at a given moment.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock {
+    time: DateTime<Utc>,
+}
+
+impl FixedClock {
+    pub fn new(time: DateTime<Utc>) -> FixedClock {
+        FixedClock { time }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
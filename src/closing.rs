@@ -0,0 +1,232 @@
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::export::{self, ExportFormat};
+use crate::timefmt::TimeFormatter;
+use crate::timestamp;
+use crate::{Period, TimeSheet};
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tracing::{info, instrument};
+
+// Record of a month that went through `close month`. Periods in a closed month
+// can no longer be changed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClosedMonth {
+    /// The month as `YYYY-MM`.
+    pub month: String,
+    #[serde(deserialize_with = "timestamp::deserialize")]
+    pub closed_at: DateTime<Utc>,
+    /// SHA-256 over the month's periods at the time of closing, as hex.
+    pub checksum: String,
+}
+
+// Parses `YYYY-MM` into year and month.
+pub fn parse_month(text: &str) -> io::Result<(i32, u32)> {
+    NaiveDate::parse_from_str(&format!("{}-01", text), "%Y-%m-%d")
+        .map(|date| (chrono::Datelike::year(&date), chrono::Datelike::month(&date)))
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid month '{}'. Expected YYYY-MM, e.g. 2024-05.", text),
+            )
+        })
+}
+
+// The month given as `YYYY-MM`, or the current one.
+pub fn month_or_current(month: Option<&str>, clock: &dyn Clock) -> io::Result<(i32, u32)> {
+    match month {
+        Some(month) => parse_month(month),
+        None => {
+            let today = clock.now().with_timezone(&Local);
+            Ok((chrono::Datelike::year(&today), chrono::Datelike::month(&today)))
+        }
+    }
+}
+
+// The `YYYY-MM` key of the local month containing `time`.
+pub fn month_key(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("%Y-%m").to_string()
+}
+
+// Handles `close month YYYY-MM`: validate, export, checksum and lock.
+// Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn close_month(
+    time_sheet: &mut TimeSheet,
+    config: &Config,
+    month: &str,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let now = clock.now();
+    let (year, month_number) = parse_month(month)?;
+    let key = format!("{:04}-{:02}", year, month_number);
+    if let Some(closed) = time_sheet.closed_months.iter().find(|c| c.month == key) {
+        println!("Month {} was already closed at {}.", key, closed.closed_at.with_timezone(&Local));
+        return Ok(false);
+    }
+
+    let range = crate::logic::month_period(year, month_number, config.zone());
+    let problems = validate_month(time_sheet, config, &range, formatter, now);
+    if !problems.is_empty() {
+        println!("Month {} cannot be closed:", key);
+        for problem in &problems {
+            println!("  - {}", problem);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Validation of {} failed with {} problem(s).", key, problems.len()),
+        ));
+    }
+
+    let periods = periods_in_month(time_sheet, &range);
+    let export_dir = export_dir(config)?;
+    fs::create_dir_all(&export_dir)?;
+    for format in &config.closing.exports {
+        let path = export_dir.join(format!("timesheet-{}.{}", key, format.extension()));
+        export::export_periods(*format, &path, &format!("Timesheet {}", key), &periods, formatter)?;
+        println!("Exported {}.", path.display());
+    }
+
+    let checksum = checksum(&periods)?;
+    info!(month = %key, %checksum, "closed month");
+    println!("Closed {} with {} period(s). Checksum: {}", key, periods.len(), checksum);
+    time_sheet.closed_months.push(ClosedMonth { month: key, closed_at: now, checksum });
+    Ok(true)
+}
+
+// Prints all closed months.
+pub fn list_closed_months(time_sheet: &TimeSheet, formatter: &TimeFormatter) {
+    if time_sheet.closed_months.is_empty() {
+        println!("No months have been closed.");
+    }
+    for closed in &time_sheet.closed_months {
+        println!("{}  closed {}  {}", closed.month, formatter.date_time(closed.closed_at.with_timezone(&Local)), closed.checksum);
+    }
+}
+
+// Closed months whose periods no longer match the checksum taken at closing.
+pub(crate) fn modified_months(time_sheet: &TimeSheet, config: &Config) -> io::Result<Vec<String>> {
+    let mut modified = Vec::new();
+    for closed in &time_sheet.closed_months {
+        let (year, month) = parse_month(&closed.month)?;
+        let range = crate::logic::month_period(year, month, config.zone());
+        if checksum(&periods_in_month(time_sheet, &range))? != closed.checksum {
+            modified.push(closed.month.clone());
+        }
+    }
+    Ok(modified)
+}
+
+// Takes the checksum of a closed month again, after a change that had to be made
+// to it anyway, such as purging data under the retention policy.
+pub(crate) fn reseal(time_sheet: &mut TimeSheet, month: &str, config: &Config) -> io::Result<()> {
+    let (year, number) = parse_month(month)?;
+    let range = crate::logic::month_period(year, number, config.zone());
+    let checksum = checksum(&periods_in_month(time_sheet, &range))?;
+    if let Some(closed) = time_sheet.closed_months.iter_mut().find(|c| c.month == month) {
+        info!(%month, %checksum, "resealed closed month");
+        closed.checksum = checksum;
+    }
+    Ok(())
+}
+
+// Checks everything that must hold before a month is locked. Returns a list of problems.
+fn validate_month(time_sheet: &TimeSheet, config: &Config, range: &Period, formatter: &TimeFormatter, now: DateTime<Utc>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if range.end > now {
+        problems.push("The month has not ended yet.".to_string());
+    }
+    if let Some(start) = time_sheet.active_period_start.filter(|start| *start < range.end) {
+        problems.push(format!("A session started at {} is still open.", start.with_timezone(&Local)));
+    }
+
+    let periods = periods_in_month(time_sheet, range);
+    for period in &periods {
+        if period.end <= period.start {
+            problems.push(format!("Period {} ends before it starts.", period.id));
+        }
+        if period.end > now {
+            problems.push(format!("Period {} ends in the future.", period.id));
+        }
+    }
+    for pair in periods.windows(2) {
+        if pair[0].end > pair[1].start {
+            problems.push(format!("Periods {} and {} overlap.", pair[0].id, pair[1].id));
+        }
+    }
+
+    problems.extend(check_compliance(config, &periods, formatter));
+    problems
+}
+
+// Compliance rules from the config. Currently the maximum tracked time per day.
+fn check_compliance(config: &Config, periods: &[&Period], formatter: &TimeFormatter) -> Vec<String> {
+    let Some(max_hours) = config.max_daily_hours else {
+        return Vec::new();
+    };
+    let limit = Duration::seconds((max_hours * 3600.0) as i64);
+
+    let mut daily: BTreeMap<NaiveDate, Duration> = BTreeMap::new();
+    for period in periods {
+        let mut day = period.start.with_timezone(&Local).date_naive();
+        while let Some(day_range) = crate::logic::day_period(day, config.zone()).filter(|d| d.start < period.end) {
+            *daily.entry(day).or_insert_with(Duration::zero) += period.overlap(&day_range);
+            day = day.succ_opt().unwrap();
+        }
+    }
+
+    daily
+        .into_iter()
+        .filter(|(_, total)| *total > limit)
+        .map(|(day, total)| {
+            format!(
+                "On {} {} was tracked, more than the maximum of {}.",
+                day,
+                formatter.duration(total),
+                formatter.duration(limit)
+            )
+        })
+        .collect()
+}
+
+// Periods that overlap the month, sorted by start time.
+fn periods_in_month<'a>(time_sheet: &'a TimeSheet, range: &Period) -> Vec<&'a Period> {
+    time_sheet.periods_in(range).collect()
+}
+
+// SHA-256 over the canonical JSON of the periods.
+fn checksum(periods: &[&Period]) -> io::Result<String> {
+    let json = serde_json::to_vec(periods).map_err(io::Error::other)?;
+    let digest = Sha256::digest(&json);
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Where closing exports are written: the configured directory, or the app's data directory.
+fn export_dir(config: &Config) -> io::Result<PathBuf> {
+    if let Some(dir) = &config.closing.export_dir {
+        return Ok(dir.clone());
+    }
+    match dirs::data_dir() {
+        Some(mut path) => {
+            path.push("work_time_tracker");
+            path.push("exports");
+            Ok(path)
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Could not find data directory. Set closing.export_dir in the config.",
+        )),
+    }
+}
+
+// Used by the exports list in the config.
+pub fn default_exports() -> Vec<ExportFormat> {
+    vec![ExportFormat::Csv]
+}
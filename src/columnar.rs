@@ -0,0 +1,87 @@
+// `export parquet`: all finished periods as one Parquet file, one row each, for
+// analysis in DuckDB, pandas or Polars, which read it much faster than the JSON
+// data file and with the column types intact.
+//
+// The columns are id, start and end (UTC timestamps in microseconds), duration
+// in seconds, the local date the period started on, project, tags (a list),
+// note, category and the custom fields (a map). Missing values are nulls. The
+// file is Snappy-compressed.
+//
+// Writing Parquet pulls in the Arrow libraries, so it needs the 'parquet'
+// feature.
+
+use crate::zone::Zone;
+use crate::Period;
+use std::io;
+use std::path::Path;
+#[cfg(feature = "parquet")]
+use {
+    arrow_array::builder::{ListBuilder, MapBuilder, StringBuilder},
+    arrow_array::{ArrayRef, Date32Array, Int64Array, RecordBatch, StringArray, TimestampMicrosecondArray},
+    chrono::NaiveDate,
+    parquet::arrow::ArrowWriter,
+    parquet::basic::Compression,
+    parquet::file::properties::WriterProperties,
+    std::fs::File,
+    std::sync::Arc,
+    tracing::debug,
+};
+
+// Writes the periods to `path`, replacing it.
+#[cfg(feature = "parquet")]
+pub fn write(path: &Path, periods: &[&Period], zone: Zone) -> io::Result<()> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    let mut tags = ListBuilder::new(StringBuilder::new());
+    let mut fields = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for period in periods {
+        for tag in &period.metadata.tags {
+            tags.values().append_value(tag);
+        }
+        tags.append(true);
+        for (key, value) in &period.metadata.fields {
+            fields.keys().append_value(key);
+            fields.values().append_value(value);
+        }
+        fields.append(true).map_err(io::Error::other)?;
+    }
+    let text = |value: fn(&Period) -> Option<&String>| -> ArrayRef {
+        Arc::new(periods.iter().map(|p| value(p)).collect::<StringArray>())
+    };
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        ("id", Arc::new(periods.iter().map(|p| Some(p.id.to_string())).collect::<StringArray>())),
+        (
+            "start",
+            Arc::new(TimestampMicrosecondArray::from_iter_values(periods.iter().map(|p| p.start.timestamp_micros())).with_timezone_utc()),
+        ),
+        (
+            "end",
+            Arc::new(TimestampMicrosecondArray::from_iter_values(periods.iter().map(|p| p.end.timestamp_micros())).with_timezone_utc()),
+        ),
+        ("duration_seconds", Arc::new(Int64Array::from_iter_values(periods.iter().map(|p| p.duration().num_seconds())))),
+        (
+            "date",
+            Arc::new(Date32Array::from_iter_values(periods.iter().map(|p| (zone.date(p.start) - epoch).num_days() as i32))),
+        ),
+        ("project", text(|p| p.metadata.project.as_ref())),
+        ("tags", Arc::new(tags.finish())),
+        ("note", text(|p| p.metadata.note.as_ref())),
+        ("category", text(|p| p.metadata.category.as_ref())),
+        ("fields", Arc::new(fields.finish())),
+    ];
+    let batch = RecordBatch::try_from_iter(columns).map_err(io::Error::other)?;
+
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), Some(properties)).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+    debug!(path = %path.display(), rows = periods.len(), "wrote parquet file");
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+pub fn write(_path: &Path, _periods: &[&Period], _zone: Zone) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Parquet export needs a build with the 'parquet' feature: cargo build --release --features parquet",
+    ))
+}
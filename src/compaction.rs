@@ -0,0 +1,104 @@
+use crate::config::{CompactionConfig, MergePolicy};
+use crate::{Metadata, Period, TimeSheet};
+use chrono::Duration;
+use tracing::{debug, instrument};
+
+// What `compact` changed in the timesheet.
+#[derive(Debug, Default)]
+pub struct Compaction {
+    pub merged: usize,
+    pub cleaned: usize,
+    pub reordered: bool,
+}
+
+impl Compaction {
+    pub fn changed(&self) -> bool {
+        self.merged > 0 || self.cleaned > 0 || self.reordered
+    }
+}
+
+// Brings the timesheet into canonical form: periods sorted by start, blank
+// project and note values removed, tags trimmed and deduplicated, metadata of a
+// session that is no longer running dropped, and, with `merge = "same-metadata"`,
+// periods that follow each other within `max_gap_seconds` with the same metadata
+// joined into one. Periods in closed months are only sorted, never changed, so
+// their checksums stay valid.
+#[instrument(level = "debug", skip(time_sheet))]
+pub fn canonicalize(time_sheet: &mut TimeSheet, config: &CompactionConfig) -> Compaction {
+    let mut result = Compaction::default();
+
+    if time_sheet.periods.windows(2).any(|pair| pair[0].start > pair[1].start) {
+        time_sheet.periods.sort_by_key(|p| p.start);
+        result.reordered = true;
+    }
+
+    if time_sheet.active_period_start.is_none()
+        && (!time_sheet.active_metadata.is_empty()
+            || !time_sheet.active_laps.is_empty()
+            || time_sheet.active_remind_after.is_some()
+            || time_sheet.active_suspend.is_some())
+    {
+        time_sheet.active_metadata = Metadata::default();
+        time_sheet.active_laps.clear();
+        time_sheet.active_remind_after = None;
+        time_sheet.active_suspend = None;
+        result.cleaned += 1;
+    }
+
+    let locked: Vec<bool> = time_sheet.periods.iter().map(|p| time_sheet.is_locked(p)).collect();
+    for (period, locked) in time_sheet.periods.iter_mut().zip(&locked) {
+        if !locked && clean(&mut period.metadata) {
+            result.cleaned += 1;
+        }
+    }
+
+    if config.merge == MergePolicy::SameMetadata {
+        let max_gap = Duration::seconds(config.max_gap_seconds as i64);
+        let mut merged: Vec<Period> = Vec::with_capacity(time_sheet.periods.len());
+        let mut merged_locked: Vec<bool> = Vec::with_capacity(locked.len());
+        for (period, locked) in time_sheet.periods.drain(..).zip(locked) {
+            if let Some(last) = merged.last_mut()
+                && !locked
+                && !merged_locked.last().copied().unwrap_or(true)
+                && last.metadata == period.metadata
+                && period.start >= last.end
+                && period.start - last.end <= max_gap
+            {
+                debug!(into = %last.id, from = %period.id, "merging adjacent periods");
+                last.end = period.end;
+                last.laps.extend(period.laps);
+                result.merged += 1;
+                continue;
+            }
+            merged.push(period);
+            merged_locked.push(locked);
+        }
+        time_sheet.periods = merged;
+    }
+
+    debug!(?result, "canonicalized timesheet");
+    result
+}
+
+// Normalizes the metadata. Returns whether anything changed.
+fn clean(metadata: &mut Metadata) -> bool {
+    let before = metadata.clone();
+    let blank = |value: &mut Option<String>| {
+        if let Some(text) = value {
+            *text = text.trim().to_string();
+        }
+        if value.as_deref() == Some("") {
+            *value = None;
+        }
+    };
+    blank(&mut metadata.project);
+    blank(&mut metadata.note);
+    let mut tags: Vec<String> = Vec::with_capacity(metadata.tags.len());
+    for tag in metadata.tags.iter().map(|t| t.trim()) {
+        if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    metadata.tags = tags;
+    *metadata != before
+}
@@ -0,0 +1,138 @@
+use crate::cli::{Cli, CompletionKind};
+use time_tracker::config::Config;
+use time_tracker::TimeSheet;
+use clap::CommandFactory;
+use clap_complete::Shell;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+// Writes the completion script for `shell` to `out`.
+//
+// The static part comes from clap_complete. On top of that, the values for
+// `--project`, `--tags`, `@favorite` and the period of `report` are completed
+// dynamically by calling the hidden `complete-helper` subcommand, so names from
+// the timesheet and config show up.
+pub fn generate(shell: Shell, bin_name: &str, out: &mut dyn Write) -> io::Result<()> {
+    let mut command = Cli::command();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, bin_name, &mut script);
+    let script = String::from_utf8(script).map_err(io::Error::other)?;
+
+    match shell {
+        Shell::Bash => {
+            out.write_all(script.as_bytes())?;
+            out.write_all(bash_dynamic(bin_name).as_bytes())
+        }
+        Shell::Zsh => out.write_all(zsh_dynamic(&script, bin_name).as_bytes()),
+        Shell::Fish => {
+            out.write_all(script.as_bytes())?;
+            out.write_all(fish_dynamic(bin_name).as_bytes())
+        }
+        // Other shells only get the static completions.
+        _ => out.write_all(script.as_bytes()),
+    }
+}
+
+// Collects the names the completion scripts ask for: projects and tags used in the
+// timesheet or in favorites, the favorite names themselves, or the reporting
+// periods including the aliases from `[periods]`.
+pub fn candidates(kind: CompletionKind, time_sheet: &TimeSheet, config: &Config) -> BTreeSet<String> {
+    let metadata = time_sheet
+        .periods()
+        .iter()
+        .map(|p| p.metadata())
+        .chain(std::iter::once(time_sheet.active_metadata()))
+        .chain(config.favorites.values());
+
+    match kind {
+        CompletionKind::Projects => metadata.filter_map(|m| m.project.clone()).collect(),
+        CompletionKind::Tags => metadata.flat_map(|m| m.tags.iter().cloned()).collect(),
+        CompletionKind::Favorites => config.favorites.keys().map(|name| format!("@{}", name)).collect(),
+        CompletionKind::Periods => ["today", "week", "month"].into_iter().map(String::from).chain(config.periods.keys().cloned()).collect(),
+    }
+}
+
+// Shell function names cannot contain every character a binary name can.
+fn function_name(bin_name: &str) -> String {
+    bin_name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+// Wraps the generated bash completion function and handles dynamic values first.
+fn bash_dynamic(bin_name: &str) -> String {
+    let func = function_name(bin_name);
+    // This is how clap_complete names the generated function.
+    let generated = bin_name.replace('-', "__");
+    format!(
+        r#"
+_{func}_dynamic() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local kind=""
+    case "$prev" in
+        --project) kind=projects ;;
+        --tags) kind=tags ;;
+        report|--period) kind=periods ;;
+        *) [[ "$cur" == @* ]] && kind=favorites ;;
+    esac
+    if [[ -n "$kind" ]]; then
+        local IFS=$'\n'
+        COMPREPLY=($(compgen -W "$({bin_name} complete-helper "$kind" 2>/dev/null)" -- "$cur"))
+        return 0
+    fi
+    _{generated} "$@"
+}}
+complete -F _{func}_dynamic -o nosort -o bashdefault -o default {bin_name}
+"#
+    )
+}
+
+// Replaces the default value completion for --project and --tags in the generated
+// zsh script with helper functions that call back into the binary.
+fn zsh_dynamic(script: &str, bin_name: &str) -> String {
+    let func = function_name(bin_name);
+    let script = script
+        .replace(":PROJECT:_default", &format!(":PROJECT:_{func}_projects"))
+        .replace(":TAGS:_default", &format!(":TAGS:_{func}_tags"))
+        .replace(":@FAVORITE:_default", &format!(":@FAVORITE:_{func}_favorites"))
+        .replace(":PERIOD:_default", &format!(":PERIOD:_{func}_periods"));
+    // The period of `report` is positional, named by its ID rather than value name.
+    let script: String = script
+        .lines()
+        .map(|line| match line.strip_suffix(":_default' \\") {
+            Some(start) if line.starts_with("':period -- ") => format!("{}:_{func}_periods' \\\n", start),
+            _ => format!("{}\n", line),
+        })
+        .collect();
+
+    let helpers: String = ["projects", "tags", "favorites", "periods"]
+        .iter()
+        .map(|kind| {
+            format!(
+                "_{func}_{kind}() {{\n    local -a values\n    values=(${{(f)\"$({bin_name} complete-helper {kind} 2>/dev/null)\"}})\n    _describe '{kind}' values\n}}\n\n"
+            )
+        })
+        .collect();
+
+    // Helpers must be defined before the compdef call at the end of the script runs.
+    insert_after_first_line(&script, &helpers)
+}
+
+fn insert_after_first_line(script: &str, text: &str) -> String {
+    match script.split_once('\n') {
+        Some((first, rest)) => format!("{}\n\n{}{}", first, text, rest),
+        None => format!("{}\n{}", script, text),
+    }
+}
+
+// Fish merges multiple `complete` definitions, so the dynamic ones are appended.
+fn fish_dynamic(bin_name: &str) -> String {
+    format!(
+        r#"
+complete -c {bin_name} -n "__fish_seen_subcommand_from start add" -l project -f -r -a "({bin_name} complete-helper projects 2>/dev/null)"
+complete -c {bin_name} -n "__fish_seen_subcommand_from start add" -l tags -f -r -a "({bin_name} complete-helper tags 2>/dev/null)"
+complete -c {bin_name} -n "__fish_seen_subcommand_from start" -f -a "({bin_name} complete-helper favorites 2>/dev/null)"
+complete -c {bin_name} -n "__fish_seen_subcommand_from report" -f -a "({bin_name} complete-helper periods 2>/dev/null)"
+complete -c {bin_name} -n "__fish_seen_subcommand_from preset" -l period -f -r -a "({bin_name} complete-helper periods 2>/dev/null)"
+"#
+    )
+}
@@ -0,0 +1,927 @@
+use crate::Metadata;
+use crate::categories::Category;
+use crate::export::ExportFormat;
+use crate::storage::LoadOptions;
+use crate::timefmt::{ClockFormat, DateFormat, HoursFormat, Precision};
+use crate::zone::Zone;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument, warn};
+
+// User configuration, read from `config.toml` in the platform config directory.
+// Every field has a default so a missing or partial file is fine.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Other config files merged under this one, as paths or https URLs, e.g. a
+    /// team's shared projects and rates. Later files override earlier ones; this
+    /// file overrides them all. Included files can only set shared data, not
+    /// commands, hooks or paths.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// The settings that came from included files, left out by `save_config`.
+    #[serde(skip)]
+    pub(crate) included: toml::Table,
+    /// Where the timesheet is stored. Defaults to `~/.work_time_tracker.json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_file: Option<PathBuf>,
+    /// First day of the week for weekly reports and totals.
+    pub week_start: WeekStart,
+    /// Contracted hours per week.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weekly_target_hours: Option<f64>,
+    /// Contracts in effect for a span of dates, when the hours or the rate
+    /// changed over time.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contracts: Vec<Contract>,
+    /// How over- and undertime of a week carries into the following weeks.
+    pub carry_over: CarryOverConfig,
+    /// Minutes by which a day's tracked time may fall short of or exceed the daily
+    /// target without counting as under- or overtime. Zero counts every minute.
+    pub daily_tolerance_minutes: u32,
+    /// Categories a period can have with `--category`, shown by `focus`. Defaults
+    /// to ["focus", "meeting", "admin"].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_categories: Option<Vec<String>>,
+    /// Project used by `start` when none is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_project: Option<String>,
+    /// Default format for durations when `--hours-format` is not given.
+    pub hours_format: HoursFormat,
+    /// Default rounding of displayed durations when `--precision` is not given.
+    pub precision: Precision,
+    /// How reports show dates, as a strftime pattern. Defaults to "%Y-%m-%d".
+    pub date_format: DateFormat,
+    /// Whether reports show times of day as "24h" (the default) or "12h".
+    pub clock_format: ClockFormat,
+    /// Timer templates, started with `start @name`.
+    pub favorites: BTreeMap<String, Metadata>,
+    /// Hour budgets per project, shown by `status`.
+    pub budgets: BTreeMap<String, Budget>,
+    /// Hourly rates per project, billed by `invoice`.
+    pub rates: BTreeMap<String, Rate>,
+    /// Currency and exchange rates of `invoice`.
+    pub invoice: InvoiceConfig,
+    /// Sessions shorter than this many seconds are handled by `short_session_policy`.
+    /// Zero disables the check.
+    pub min_session_seconds: u64,
+    /// What `stop` does with a session shorter than `min_session_seconds`.
+    pub short_session_policy: ShortSessionPolicy,
+    /// What `stop` does with time the computer slept during the session.
+    pub suspend_policy: SuspendPolicy,
+    /// Once the last `activity` sample is this many minutes old, `stop` offers to
+    /// end the session at it. Zero never offers. Defaults to 15.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_after_minutes: Option<u64>,
+    /// `idle_after_minutes` for sessions on a project, e.g. "reading-papers" = 30
+    /// for work away from the keyboard, "coding" = 5.
+    pub idle_after_minutes_by_project: BTreeMap<String, u64>,
+    /// Compliance rule: most hours that may be tracked on a single day.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_daily_hours: Option<f64>,
+    /// Settings for `close month`.
+    pub closing: ClosingConfig,
+    /// Employee and cost center details for `export preset`.
+    pub presets: PresetsConfig,
+    /// `start` and `stop` append to a journal next to the data file; once it has
+    /// more entries than this, the data file is rewritten. Zero always rewrites.
+    /// Defaults to 100.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compact_after_entries: Option<usize>,
+    /// How `compact` tidies up the timesheet.
+    pub compaction: CompactionConfig,
+    /// `monthly` keeps one file per month in a data directory instead of a single
+    /// data file.
+    pub storage_layout: StorageLayout,
+    /// Keep the fields `search` looks at in a file next to the data file, so a
+    /// search does not read the whole history.
+    pub search_index: bool,
+    /// Refuse to load a data file with unknown fields, unsorted or overlapping
+    /// periods or times in the future, as `--strict` does.
+    pub strict: bool,
+    /// How local times that occur twice, when clocks go back, are read.
+    pub dst_policy: DstPolicy,
+    /// The zone reports are made in instead of the local one, from `report --tz`.
+    /// Not read from the file.
+    #[serde(skip)]
+    pub time_zone: Option<Tz>,
+    /// Reporting periods by name, usable like `week` or `month`, e.g.
+    /// payperiod = "26th..25th" or sprint = "2-week cycles anchored 2024-01-03".
+    pub periods: BTreeMap<String, String>,
+    /// Where reports and invoices count a session that runs into the next month.
+    pub month_attribution: MonthAttribution,
+    /// Hours in which tracking is expected. `start` outside them asks first, and
+    /// reports show the time tracked outside separately.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracking_window: Option<TrackingWindow>,
+    /// Integrations run by `start` and `stop`.
+    pub hooks: HooksConfig,
+    /// When `remind` reports long-running sessions.
+    pub reminders: RemindersConfig,
+    /// Used by `start --task`.
+    pub taskwarrior: TaskwarriorConfig,
+    /// Clean-up of imported intervals, per import source.
+    pub import: ImportConfig,
+    /// Calendar that `sync caldav` publishes finished sessions to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caldav: Option<CaldavConfig>,
+    /// WebDAV or S3 remote that `sync remote` uploads encrypted snapshots to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<RemoteConfig>,
+    /// How long identifying details are kept, enforced by `gdpr enforce`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionConfig>,
+    /// Working hours policy for splitting time into regular, evening, night,
+    /// weekend and holiday hours. Reports only show the split when this is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleConfig>,
+}
+
+impl Config {
+    /// The zone local times are read and shown in, with `dst_policy`.
+    pub fn zone(&self) -> Zone {
+        Zone::new(self.time_zone, self.dst_policy)
+    }
+
+    /// How the data file is loaded: strictly with `strict`, as of `now`.
+    pub fn load_options(&self, now: DateTime<Utc>) -> LoadOptions {
+        if self.strict {
+            LoadOptions::strict(now)
+        } else {
+            LoadOptions::default()
+        }
+    }
+}
+
+// The `[closing]` section.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ClosingConfig {
+    /// Exports generated when a month is closed.
+    pub exports: Vec<ExportFormat>,
+    /// Directory for the exports. Defaults to the app's data directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub export_dir: Option<PathBuf>,
+}
+
+impl Default for ClosingConfig {
+    fn default() -> Self {
+        ClosingConfig { exports: crate::closing::default_exports(), export_dir: None }
+    }
+}
+
+// The `[presets]` section.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PresetsConfig {
+    /// Personnel number in the payroll or timesheet system.
+    pub employee_id: String,
+    /// DATEV wage type (Lohnart) for worked hours.
+    pub datev_wage_type: String,
+    /// SAP attendance type (AWART) for worked hours.
+    pub sap_attendance_type: String,
+    /// Cost center per project. Projects without one are exported without.
+    pub cost_centers: BTreeMap<String, String>,
+}
+
+impl Default for PresetsConfig {
+    fn default() -> Self {
+        PresetsConfig {
+            employee_id: String::new(),
+            datev_wage_type: "100".to_string(),
+            sap_attendance_type: "0800".to_string(),
+            cost_centers: BTreeMap::new(),
+        }
+    }
+}
+
+// The `[schedule]` section. Times are local, written as "HH:MM".
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Start of evening hours, which last until `night_start`.
+    pub evening_start: NaiveTime,
+    /// Start of night hours. Night may wrap around midnight.
+    pub night_start: NaiveTime,
+    /// End of night hours.
+    pub night_end: NaiveTime,
+    /// Days counted as weekend, e.g. ["sat", "sun"].
+    pub weekend_days: Vec<Weekday>,
+    /// Public holidays as YYYY-MM-DD.
+    pub holidays: Vec<NaiveDate>,
+    /// Usual working hours, searched for untracked time by `suggest gaps`.
+    pub workday_start: NaiveTime,
+    pub workday_end: NaiveTime,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig {
+            evening_start: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            night_start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            night_end: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            weekend_days: vec![Weekday::Sat, Weekday::Sun],
+            holidays: Vec::new(),
+            workday_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            workday_end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        }
+    }
+}
+
+// The `[tracking_window]` section, e.g. start = "06:00", end = "22:00". The
+// window may wrap around midnight.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct TrackingWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TrackingWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        crate::categories::in_window(time, self.start, self.end)
+    }
+}
+
+// The `[hooks]` section. Every hook is off unless its section is present.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Show the running session as the Slack status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slack: Option<SlackHookConfig>,
+    /// On `stop`, annotate the Taskwarrior task of a `start --task` session with
+    /// the time worked.
+    pub annotate_tasks: bool,
+    /// On `stop`, add the git commits made during the session to its note and tags.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitHookConfig>,
+}
+
+// The `[reminders]` section.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RemindersConfig {
+    /// Sessions started without `--remind-after` are reported after this many
+    /// hours. Without it, only those started with `--remind-after` are.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after_hours: Option<f64>,
+    /// Minutes until the reminder for the same session is repeated. Zero reminds once.
+    pub repeat_minutes: u64,
+    /// Also notify once today's tracked time reaches the daily target, and again
+    /// when it reaches `max_daily_hours`.
+    pub daily_limits: bool,
+    /// Shell command run with every notification. TT_EVENT holds "long_session",
+    /// "target", "max_daily_hours" or "budget", TT_TRACKED_SECONDS the time tracked
+    /// in the session, today or against the budget, and TT_MESSAGE the text of the
+    /// notification. Budget alerts also set TT_PROJECT and TT_ALERT_PERCENT.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+impl Default for RemindersConfig {
+    fn default() -> Self {
+        RemindersConfig { after_hours: None, repeat_minutes: 30, daily_limits: false, command: None }
+    }
+}
+
+// The `[taskwarrior]` section.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct TaskwarriorConfig {
+    /// The Taskwarrior program.
+    pub command: String,
+}
+
+impl Default for TaskwarriorConfig {
+    fn default() -> Self {
+        TaskwarriorConfig { command: "task".to_string() }
+    }
+}
+
+// The `[import]` section, with one subsection per source, e.g. `[import.timew]`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ImportConfig {
+    pub ics: NormalizeConfig,
+    pub timew: NormalizeConfig,
+}
+
+// How `logic::normalize` cleans up the intervals of an import. Zero turns a rule off.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(default)]
+pub struct NormalizeConfig {
+    /// Intervals with the same project, tags and note that are less than this many
+    /// seconds apart are merged into one.
+    pub merge_gap_seconds: u64,
+    /// Intervals shorter than this many seconds, after merging, are dropped.
+    pub min_interval_seconds: u64,
+}
+
+// The `[hooks.slack]` section.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SlackHookConfig {
+    /// Turns the hook off without removing its settings.
+    pub enabled: bool,
+    /// Status while tracking; "{project}" is replaced with the session's project.
+    pub status_text: String,
+    pub status_emoji: String,
+}
+
+impl Default for SlackHookConfig {
+    fn default() -> Self {
+        SlackHookConfig {
+            enabled: true,
+            status_text: "Working on {project}".to_string(),
+            status_emoji: ":computer:".to_string(),
+        }
+    }
+}
+
+// The `[hooks.git]` section.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GitHookConfig {
+    /// Turns the hook off without removing its settings.
+    pub enabled: bool,
+    /// Repositories to look for commits in. Defaults to the one `stop` runs in.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<PathBuf>,
+    /// Add the subjects of the commits to the note, one per line.
+    pub subjects: bool,
+    /// Tag the session with the issue keys the commits mention, e.g. "issue:PROJ-123".
+    pub issue_tags: bool,
+    /// Also take the commits of other authors, not only those of `user.email`.
+    pub all_authors: bool,
+}
+
+impl Default for GitHookConfig {
+    fn default() -> Self {
+        GitHookConfig { enabled: true, repositories: Vec::new(), subjects: true, issue_tags: true, all_authors: false }
+    }
+}
+
+// The `[caldav]` section.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaldavConfig {
+    /// URL of the calendar collection, e.g. "https://dav.example.com/calendars/me/work/".
+    pub url: String,
+    pub username: String,
+    /// Password or app token. Better kept out of the config with
+    /// `secret set caldav-password`, or given as WORK_TIME_TRACKER_CALDAV_PASSWORD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+// The `[retention]` section, e.g. after_years = 3.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RetentionConfig {
+    /// Periods and absences from before this many years ago fall under the policy.
+    pub after_years: u32,
+    /// What happens to them.
+    #[serde(default)]
+    pub action: RetentionAction,
+}
+
+// What the retention policy does with old periods and absences.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    /// Remove projects, tags, notes and custom fields but keep the times, so
+    /// totals and balances stay as they were.
+    #[default]
+    Anonymize,
+    /// Remove the periods and absences entirely.
+    Delete,
+}
+
+// The `[remote]` section.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteConfig {
+    /// A WebDAV collection, e.g. "https://dav.example.com/backups/tracker/" or one
+    /// served by `rclone serve webdav`, or a bucket as "s3://bucket/prefix".
+    pub url: String,
+    /// The WebDAV user name, or the S3 access key ID.
+    pub username: String,
+    /// The WebDAV password or S3 secret key. Better kept out of the config with
+    /// `secret set remote-password`, or given as WORK_TIME_TRACKER_REMOTE_PASSWORD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// The S3 endpoint, for S3-compatible servers such as MinIO. Defaults to AWS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// The S3 region.
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// How many snapshots to keep; older ones are removed after each upload.
+    /// Keeps all when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+// The first day of the week, written as e.g. "monday" or "sun" in the config.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct WeekStart(pub Weekday);
+
+impl Default for WeekStart {
+    fn default() -> Self {
+        WeekStart(Weekday::Mon)
+    }
+}
+
+// What to do with sessions below the minimum length, e.g. an accidental start/stop.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShortSessionPolicy {
+    /// Do not record the session at all.
+    #[default]
+    Reject,
+    /// Extend the previous period to cover the session if it ended less than the
+    /// minimum length before the session started. Otherwise the session is rejected.
+    Merge,
+    /// Ask whether to keep the session, as a debounce against a `stop` that quickly
+    /// follows `start` by mistake. Without a terminal to ask on, the session is rejected.
+    Prompt,
+}
+
+// What to do with time the computer slept, in suspend or hibernation, while a
+// session was running.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SuspendPolicy {
+    /// Leave it out by ending the session earlier by that much.
+    #[default]
+    Subtract,
+    /// Ask whether to leave it out. Without a terminal to ask on, it is left out.
+    Prompt,
+    /// Count it as tracked time.
+    Keep,
+}
+
+// The `[compaction]` section.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CompactionConfig {
+    /// Whether `compact` joins periods that follow each other.
+    pub merge: MergePolicy,
+    /// Largest gap between two periods that are still joined.
+    pub max_gap_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergePolicy {
+    /// Periods are never joined.
+    #[default]
+    Never,
+    /// Periods with the same project, tags and note are joined.
+    SameMetadata,
+}
+
+// A `[budgets.<project>]` entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Budget {
+    pub hours: f64,
+    /// Only time from this day on counts against the budget.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<NaiveDate>,
+    /// Percentages of the budget to alert at, e.g. [50, 80, 100]. `stop` and
+    /// `remind` send a notification, and run `[reminders] command`, once each is
+    /// crossed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<u32>,
+}
+
+// A `[rates.<project>]` entry, e.g. hourly = 95.0, currency = "USD".
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rate {
+    pub hourly: f64,
+    /// Defaults to the invoice currency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// VAT charged to this client, in percent. Defaults to the invoice VAT.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vat_percent: Option<f64>,
+    /// Surcharges of this client, instead of those in `[invoice.surcharges]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub surcharges: Option<BTreeMap<Category, f64>>,
+}
+
+// A `[[contracts]]` entry. On the days it covers, its weekly hours replace
+// `weekly_target_hours`, and its rate bills the projects that have no rate of
+// their own in `[rates]`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Contract {
+    /// First day of the contract.
+    pub start: NaiveDate,
+    /// Last day of the contract, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<NaiveDate>,
+    /// Contracted hours per week.
+    pub weekly_hours: f64,
+    /// Hourly rate, in the invoice currency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+}
+
+impl Contract {
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        self.start <= date && self.end.is_none_or(|end| date <= end)
+    }
+}
+
+// The `[invoice]` section.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct InvoiceConfig {
+    /// Currency of the grand total, and of rates without their own.
+    pub currency: String,
+    /// Value of one unit of another currency in `currency`, e.g. USD = 0.92.
+    pub exchange_rates: BTreeMap<String, f64>,
+    /// VAT in percent for rates without their own.
+    pub vat_percent: f64,
+    /// Rate multipliers for time in a `[schedule]` category, e.g. evening = 1.5
+    /// with evening_start = "20:00". Other time is billed at the plain rate.
+    pub surcharges: BTreeMap<Category, f64>,
+}
+
+impl Default for InvoiceConfig {
+    fn default() -> Self {
+        InvoiceConfig {
+            currency: "EUR".to_string(),
+            exchange_rates: BTreeMap::new(),
+            vat_percent: 0.0,
+            surcharges: BTreeMap::new(),
+        }
+    }
+}
+
+// The `[carry_over]` section.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CarryOverConfig {
+    pub policy: CarryOverPolicy,
+    /// Largest balance, in either direction, kept by the `cap` policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cap_hours: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CarryOverPolicy {
+    /// The whole balance carries over.
+    #[default]
+    Full,
+    /// The balance carries over up to `cap_hours`; anything beyond is dropped.
+    Cap,
+    /// The balance carries over within a month and starts at zero with the first
+    /// week of the next.
+    ResetMonthly,
+}
+
+// How the timesheet is split into files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageLayout {
+    /// Everything in one file.
+    #[default]
+    Single,
+    /// A directory with a file per month; see `shards`.
+    Monthly,
+}
+
+// Which of the two instants an ambiguous local time means, e.g. 02:30 on the
+// night clocks go back.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DstPolicy {
+    /// The first occurrence, still on summer time.
+    #[default]
+    Earliest,
+    /// The second occurrence, already on standard time.
+    Latest,
+}
+
+// How a session that crosses midnight at the end of a month is attributed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MonthAttribution {
+    /// Each month gets the part of the session that falls in it.
+    #[default]
+    Split,
+    /// The whole session counts in the month it started in.
+    Start,
+}
+
+// Gets the path to the configuration file.
+pub fn get_config_file_path() -> io::Result<PathBuf> {
+    match dirs::config_dir() {
+        Some(mut path) => {
+            path.push("work_time_tracker");
+            path.push("config.toml");
+            Ok(path)
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Could not find config directory.",
+        )),
+    }
+}
+
+// Expands a leading `~/` to the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+// Loads the configuration, falling back to defaults if the file does not exist.
+#[instrument(level = "debug")]
+pub fn load_config() -> io::Result<Config> {
+    let path = get_config_file_path()?;
+    if !path.exists() {
+        debug!(path = %path.display(), "config file does not exist, using defaults");
+        return Ok(Config::default());
+    }
+
+    let contents = fs::read_to_string(&path)?;
+    let invalid = |e: toml::de::Error| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid config file {}: {}", path.display(), e),
+        )
+    };
+    let table: toml::Table = toml::from_str(&contents).map_err(invalid)?;
+    let mut chain = vec![fs::canonicalize(&path)?.display().to_string()];
+    let included = load_includes(&table, path.parent().unwrap_or(Path::new(".")), &mut chain)?;
+    if let Some(key) = non_finite(&table, "").or_else(|| non_finite(&included, "")) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid config file {}: {} must be a finite number, not nan or inf.", path.display(), key),
+        ));
+    }
+    let config = if included.is_empty() {
+        toml::from_str(&contents).map_err(invalid)?
+    } else {
+        let mut merged = included.clone();
+        merge_tables(&mut merged, table);
+        let mut config: Config = toml::Value::Table(merged).try_into().map_err(invalid)?;
+        config.included = included;
+        config
+    };
+    debug!(path = %path.display(), ?config, "loaded config");
+    Ok(config)
+}
+
+// The first key holding nan or inf, as a dotted path. Hours, rates and VAT are
+// all read as numbers that have to add up.
+fn non_finite(table: &toml::Table, prefix: &str) -> Option<String> {
+    fn check(value: &toml::Value, path: String) -> Option<String> {
+        match value {
+            toml::Value::Float(number) if !number.is_finite() => Some(path),
+            toml::Value::Table(table) => non_finite(table, &path),
+            toml::Value::Array(items) => items.iter().enumerate().find_map(|(index, item)| check(item, format!("{}[{}]", path, index))),
+            _ => None,
+        }
+    }
+    table.iter().find_map(|(key, value)| check(value, if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) }))
+}
+
+// Writes the configuration back to disk, creating the config directory if needed.
+// Settings as they came from included files are left out. Note that comments in a
+// hand-written config file are not preserved.
+#[instrument(level = "debug", skip(config))]
+pub fn save_config(config: &Config) -> io::Result<()> {
+    let path = get_config_file_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let toml::Value::Table(mut table) = toml::Value::try_from(config).map_err(io::Error::other)? else {
+        return Err(io::Error::other("The config is not a table."));
+    };
+    strip_included(&mut table, &config.included);
+    let contents = toml::to_string_pretty(&table).map_err(io::Error::other)?;
+    fs::write(&path, contents)?;
+    debug!(path = %path.display(), "saved config");
+    Ok(())
+}
+
+// The top-level settings an included file may set: the shared data a team hands
+// out. Anything that runs commands, names files or changes how the data file is
+// stored stays with the own config, so an include cannot take over the tracker.
+const SHARED_KEYS: &[&str] = &[
+    "include",
+    "week_start",
+    "weekly_target_hours",
+    "contracts",
+    "carry_over",
+    "daily_tolerance_minutes",
+    "session_categories",
+    "default_project",
+    "hours_format",
+    "precision",
+    "date_format",
+    "clock_format",
+    "favorites",
+    "budgets",
+    "rates",
+    "invoice",
+    "min_session_seconds",
+    "short_session_policy",
+    "max_daily_hours",
+    "presets",
+    "periods",
+    "month_attribution",
+    "schedule",
+];
+
+// The files included by `table`, each merged over its own includes, merged in
+// order. Relative paths are relative to `dir`, the directory of the including
+// file. `chain` holds the canonical paths and URLs being included, to catch
+// cycles. Settings outside `SHARED_KEYS` are ignored with a warning.
+fn load_includes(table: &toml::Table, dir: &Path, chain: &mut Vec<String>) -> io::Result<toml::Table> {
+    let Some(includes) = table.get("include") else {
+        return Ok(toml::Table::new());
+    };
+    let includes: Vec<String> = includes.clone().try_into().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "\"include\" in the config must be a list of paths or URLs.")
+    })?;
+    let mut merged = toml::Table::new();
+    for include in includes {
+        let (source, contents, dir) = if include.starts_with("https://") {
+            (include.clone(), fetch_include(&include)?, dir.to_path_buf())
+        } else if include.starts_with("http://") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Included config {} must be fetched over https.", include),
+            ));
+        } else {
+            let path = dir.join(expand_home(Path::new(&include)));
+            let unreadable =
+                |e: io::Error| io::Error::new(e.kind(), format!("Could not read included config {}: {}", path.display(), e));
+            let path = fs::canonicalize(&path).map_err(unreadable)?;
+            let contents = fs::read_to_string(&path).map_err(unreadable)?;
+            let dir = path.parent().unwrap_or(dir).to_path_buf();
+            (path.display().to_string(), contents, dir)
+        };
+        if chain.contains(&source) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Config {} includes itself.", source)));
+        }
+        let mut table: toml::Table = toml::from_str(&contents).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid included config {}: {}", source, e))
+        })?;
+        let ignored: Vec<String> = table.keys().filter(|key| !SHARED_KEYS.contains(&key.as_str())).cloned().collect();
+        if !ignored.is_empty() {
+            eprintln!(
+                "Warning: Ignoring {} from the included config {}; only the own config may set these.",
+                ignored.join(", "),
+                source
+            );
+            table.retain(|key, _| SHARED_KEYS.contains(&key));
+        }
+        debug!(source, "including config");
+        chain.push(source);
+        let mut own = load_includes(&table, &dir, chain)?;
+        chain.pop();
+        table.remove("include");
+        merge_tables(&mut own, table);
+        merge_tables(&mut merged, own);
+    }
+    Ok(merged)
+}
+
+// Merges `overrides` into `base`: tables key by key, anything else replaced.
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => merge_tables(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// Removes the settings that are the same as in `included`, and tables left empty.
+fn strip_included(table: &mut toml::Table, included: &toml::Table) {
+    table.retain(|key, value| match (value, included.get(key)) {
+        (toml::Value::Table(own), Some(toml::Value::Table(included))) => {
+            strip_included(own, included);
+            !own.is_empty()
+        }
+        (value, Some(included)) => value != included,
+        (_, None) => true,
+    });
+}
+
+// How long a downloaded include is used before it is fetched again.
+#[cfg(not(target_arch = "wasm32"))]
+const INCLUDE_REFRESH: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+// Downloads an included config. A copy is kept in the cache directory and used
+// instead for `INCLUDE_REFRESH`, so commands do not wait on the network, and
+// when the download fails, so the tracker keeps working offline.
+#[cfg(not(target_arch = "wasm32"))]
+fn fetch_include(url: &str) -> io::Result<String> {
+    use sha2::{Digest, Sha256};
+    let name: String = Sha256::digest(url.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    let cache = dirs::cache_dir().map(|dir| dir.join("work_time_tracker").join("includes").join(format!("{}.toml", name)));
+    let fresh = cache.as_ref().and_then(|cache| {
+        let age = fs::metadata(cache).and_then(|m| m.modified()).ok()?.elapsed().ok()?;
+        (age < INCLUDE_REFRESH).then(|| fs::read_to_string(cache).ok()).flatten()
+    });
+    if let Some(contents) = fresh {
+        debug!(url, "using the cached included config");
+        return Ok(contents);
+    }
+    let agent = ureq::AgentBuilder::new().timeout(std::time::Duration::from_secs(10)).build();
+    match agent.get(url).call() {
+        Ok(response) => {
+            let contents = response.into_string()?;
+            if let Some(cache) = &cache {
+                let saved = cache.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(cache, &contents));
+                if let Err(e) = saved {
+                    warn!(url, error = %e, "could not cache the included config");
+                }
+            }
+            Ok(contents)
+        }
+        Err(e) => match cache.and_then(|cache| fs::read_to_string(cache).ok()) {
+            Some(contents) => {
+                warn!(url, error = %e, "could not fetch the included config, using the cached copy");
+                Ok(contents)
+            }
+            None => Err(io::Error::other(format!("Could not fetch included config {}: {}", url, e))),
+        },
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn fetch_include(url: &str) -> io::Result<String> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, format!("Cannot include {} in the browser.", url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_finds_nan_and_inf_anywhere() {
+        let table = |text: &str| toml::from_str::<toml::Table>(text).unwrap();
+        assert_eq!(non_finite(&table("weekly_target_hours = 40.0\n[invoice]\nvat_percent = 19.0"), ""), None);
+        assert_eq!(non_finite(&table("[rates.a]\nhourly = nan"), ""), Some("rates.a.hourly".to_string()));
+        assert_eq!(non_finite(&table("[invoice]\nvat_percent = -inf"), ""), Some("invoice.vat_percent".to_string()));
+        let contracts = table("[[contracts]]\nstart = 2024-01-01\nweekly_hours = 40.0\n[[contracts]]\nstart = 2025-01-01\nweekly_hours = inf");
+        assert_eq!(non_finite(&contracts, ""), Some("contracts[1].weekly_hours".to_string()));
+    }
+
+    // A fresh directory under the temp dir for the files of one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("time-tracker-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn includes_only_set_shared_settings() {
+        let dir = scratch("shared");
+        fs::write(
+            dir.join("team.toml"),
+            "data_file = \"/tmp/elsewhere.json\"\n[rates.acme]\nhourly = 90.0\n[reminders]\ncommand = \"rm -rf ~\"\n",
+        )
+        .unwrap();
+        let table: toml::Table = toml::from_str("include = [\"team.toml\"]").unwrap();
+        let included = load_includes(&table, &dir, &mut Vec::new()).unwrap();
+        assert_eq!(included.keys().collect::<Vec<_>>(), ["rates"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn includes_reject_plain_http() {
+        let table: toml::Table = toml::from_str("include = [\"http://example.com/team.toml\"]").unwrap();
+        assert!(load_includes(&table, Path::new("."), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn includes_catch_cycles_through_other_paths() {
+        let dir = scratch("cycle");
+        fs::create_dir(dir.join("team")).unwrap();
+        fs::write(dir.join("team").join("team.toml"), "include = [\"../team/team.toml\"]").unwrap();
+        let table: toml::Table = toml::from_str("include = [\"team/team.toml\"]").unwrap();
+        let error = load_includes(&table, &dir, &mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("includes itself"), "{}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
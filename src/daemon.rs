@@ -0,0 +1,308 @@
+// `daemon start|status|stop|restart`: runs `remind` in the background, with its
+// output appended to `<data file>.daemon.log`.
+//
+// Only one watcher may run per data file, or reminders and budget alerts would be
+// sent twice and their state written by two processes at once. A watching
+// `remind`, whether started by `daemon start` or by hand, holds an advisory lock
+// (flock, or LockFileEx on Windows) on `<data file>.daemon.lock` for as long as it
+// runs. The system releases it when the process ends, however it ends, so there
+// are no stale locks to take over, and the lock file itself is never removed.
+// The holder then writes its process ID to `<data file>.daemon.pid`, through a
+// temporary file renamed into place so that it is never seen half-written. The
+// ID is only believed while the lock is held.
+//
+// Before `daemon stop` kills the process with that ID, it checks that it is a
+// watcher of this program: the ID of one that ended may have been given to
+// another process. The command line is read from /proc on Linux, and asked of
+// `ps` on other Unix systems and of `tasklist` on Windows. Stopping it goes
+// through `kill` or `taskkill`.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, instrument, warn};
+
+// How long `stop` waits for the daemon to exit, and `start` for it to take the lock.
+const WAIT: Duration = Duration::from_secs(5);
+const POLL: Duration = Duration::from_millis(100);
+
+pub fn lock_path(data_path: &Path) -> PathBuf {
+    sidecar(data_path, ".daemon.lock")
+}
+
+pub fn pid_path(data_path: &Path) -> PathBuf {
+    sidecar(data_path, ".daemon.pid")
+}
+
+pub fn log_path(data_path: &Path) -> PathBuf {
+    sidecar(data_path, ".daemon.log")
+}
+
+fn sidecar(data_path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// The lock of the running watcher, released when dropped.
+#[derive(Debug)]
+pub struct InstanceLock {
+    // Holds the lock; closing it releases it.
+    _file: File,
+    pid_path: PathBuf,
+}
+
+impl InstanceLock {
+    // Takes the lock for `data_path`. Fails if another watcher holds it.
+    #[instrument(level = "debug")]
+    pub fn acquire(data_path: &Path) -> io::Result<InstanceLock> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(lock_path(data_path))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = match read_pid(&pid_path(data_path))? {
+                    Some(pid) => format!(" (process {})", pid),
+                    None => String::new(),
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Another watcher runs for {}{}; see 'daemon status'.", data_path.display(), holder),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        let pid_path = pid_path(data_path);
+        write_pid(&pid_path, std::process::id())?;
+        debug!(path = %pid_path.display(), "took the daemon lock");
+        Ok(InstanceLock { _file: file, pid_path })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Still under the lock, which is released after this when the file closes.
+        if let Err(e) = remove(&self.pid_path) {
+            warn!(error = %e, "could not remove the daemon's process ID");
+        }
+    }
+}
+
+// Whether a watcher holds the lock for `data_path`.
+fn is_held(data_path: &Path) -> io::Result<bool> {
+    let file = match OpenOptions::new().read(true).write(true).open(lock_path(data_path)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    match file.try_lock() {
+        // Released again when the file closes.
+        Ok(()) => Ok(false),
+        Err(TryLockError::WouldBlock) => Ok(true),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+fn write_pid(path: &Path, pid: u32) -> io::Result<()> {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    let temporary = PathBuf::from(name);
+    let mut file = File::create(&temporary)?;
+    writeln!(file, "{}", pid)?;
+    file.sync_data()?;
+    fs::rename(&temporary, path)
+}
+
+// The process ID in the pid file, if there is one holding a number.
+fn read_pid(path: &Path) -> io::Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text.trim().parse().ok()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Whether process `pid` runs `remind` of this program.
+fn is_watcher(pid: u32) -> bool {
+    let Some(name) = env::current_exe().ok().and_then(|exe| exe.file_stem().map(OsString::from)) else {
+        return false;
+    };
+    let words = command_line(pid);
+    debug!(pid, ?words, "checking the daemon's process");
+    words.iter().any(|word| Path::new(word).file_stem() == Some(name.as_os_str()))
+        // tasklist only tells the program.
+        && (cfg!(windows) || words.iter().any(|word| word == "remind"))
+}
+
+// The words of the command line of `pid`, empty if it does not run. A process
+// that exited but was not yet reaped has none.
+fn command_line(pid: u32) -> Vec<String> {
+    if cfg!(target_os = "linux") {
+        return fs::read(format!("/proc/{}/cmdline", pid))
+            .map(|bytes| bytes.split(|byte| *byte == 0).filter(|word| !word.is_empty()).map(|word| String::from_utf8_lossy(word).into_owned()).collect())
+            .unwrap_or_default();
+    }
+    let output = if cfg!(windows) {
+        Command::new("tasklist").args(["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"]).stderr(Stdio::null()).output()
+    } else {
+        Command::new("ps").args(["-p", &pid.to_string(), "-o", "args="]).stderr(Stdio::null()).output()
+    };
+    match output {
+        Ok(output) if !output.status.success() => Vec::new(),
+        // "time_tracker.exe","1234","Console",... or "INFO: No tasks are running ..."
+        Ok(output) if cfg!(windows) => String::from_utf8_lossy(&output.stdout)
+            .split(',')
+            .next()
+            .and_then(|image| image.trim().strip_prefix('"')?.strip_suffix('"').map(str::to_string))
+            .into_iter()
+            .collect(),
+        Ok(output) => String::from_utf8_lossy(&output.stdout).split_whitespace().map(str::to_string).collect(),
+        Err(e) => {
+            warn!(error = %e, pid, "could not read the command line of the daemon");
+            Vec::new()
+        }
+    }
+}
+
+fn terminate(pid: u32) -> io::Result<()> {
+    let status = if cfg!(windows) {
+        Command::new("taskkill").args(["/PID", &pid.to_string(), "/F"]).stdout(Stdio::null()).status()
+    } else {
+        Command::new("kill").arg(pid.to_string()).status()
+    }
+    .map_err(|e| io::Error::new(e.kind(), format!("Could not stop process {}: {}", pid, e)))?;
+    if !status.success() {
+        return Err(io::Error::other(format!("Could not stop process {}: kill failed with {}", pid, status)));
+    }
+    Ok(())
+}
+
+// Handles `daemon status`.
+pub fn status(data_path: &Path) -> io::Result<()> {
+    if !is_held(data_path)? {
+        println!("Not running.");
+        return Ok(());
+    }
+    let path = pid_path(data_path);
+    match read_pid(&path)? {
+        Some(pid) => {
+            let since = fs::metadata(&path)?.modified().ok().and_then(|modified| SystemTime::now().duration_since(modified).ok());
+            match since {
+                Some(since) => println!("Running as process {} for {} minute(s).", pid, since.as_secs() / 60),
+                None => println!("Running as process {}.", pid),
+            }
+        }
+        None => println!("Running."),
+    }
+    println!("Log: {}", log_path(data_path).display());
+    Ok(())
+}
+
+// Handles `daemon start`: runs `remind` in the background and waits until it
+// holds the lock.
+#[instrument(level = "debug")]
+pub fn start(data_path: &Path, strict: bool) -> io::Result<()> {
+    if is_held(data_path)? {
+        let pid = read_pid(&pid_path(data_path))?.map(|pid| format!(" as process {}", pid)).unwrap_or_default();
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("The daemon already runs{}.", pid)));
+    }
+    let log = OpenOptions::new().create(true).append(true).open(log_path(data_path))?;
+    let mut command = Command::new(env::current_exe()?);
+    if strict {
+        command.arg("--strict");
+    }
+    command.arg("remind").stdin(Stdio::null()).stdout(log.try_clone()?).stderr(log);
+    detach(&mut command);
+    let mut child = command.spawn()?;
+    let pid = child.id();
+
+    let mut waited = Duration::ZERO;
+    while waited < WAIT {
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!(
+                "The daemon exited with {}; see {}.",
+                status,
+                log_path(data_path).display()
+            )));
+        }
+        if read_pid(&pid_path(data_path))? == Some(pid) && is_held(data_path)? {
+            info!(pid, "started daemon");
+            println!("Started the daemon as process {}.", pid);
+            return Ok(());
+        }
+        thread::sleep(POLL);
+        waited += POLL;
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("Process {} did not take the daemon lock; see {}.", pid, log_path(data_path).display()),
+    ))
+}
+
+// Keeps the daemon running when the terminal that started it closes or gets Ctrl+C.
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(windows)]
+fn detach(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+    command.creation_flags(DETACHED_PROCESS);
+}
+
+#[cfg(not(any(unix, windows)))]
+fn detach(_command: &mut Command) {}
+
+// Handles `daemon stop`. Returns whether a daemon was running.
+#[instrument(level = "debug")]
+pub fn stop(data_path: &Path) -> io::Result<bool> {
+    let path = pid_path(data_path);
+    if !is_held(data_path)? {
+        println!("Not running.");
+        return Ok(false);
+    }
+    let pid = read_pid(&path)?
+        .ok_or_else(|| io::Error::other("The daemon runs but has not written its process ID yet; try again."))?;
+    if !is_watcher(pid) {
+        return Err(io::Error::other(format!(
+            "Process {} is not a watcher of this program; not stopping it. See 'daemon status'.",
+            pid
+        )));
+    }
+    terminate(pid)?;
+    let mut waited = Duration::ZERO;
+    while is_held(data_path)? {
+        if waited >= WAIT {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("Process {} did not exit.", pid)));
+        }
+        thread::sleep(POLL);
+        waited += POLL;
+    }
+    // A terminated watcher does not get to remove its process ID.
+    if read_pid(&path)? == Some(pid) {
+        remove(&path)?;
+    }
+    info!(pid, "stopped daemon");
+    println!("Stopped the daemon (process {}).", pid);
+    Ok(true)
+}
+
+// Handles `daemon restart`: stops the daemon if it runs, then starts it.
+pub fn restart(data_path: &Path, strict: bool) -> io::Result<()> {
+    stop(data_path)?;
+    start(data_path, strict)
+}
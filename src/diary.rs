@@ -0,0 +1,139 @@
+// `journal --day <day>`: the sessions of a day as a Markdown document, oldest
+// first, for standup notes or a work log. Each session gets a heading with its
+// times and project, then its tags, category and custom fields, its note, its
+// laps and, with `[hooks.git]` enabled, the commits made during it. Commit
+// subjects the git hook already put into the note are not listed twice.
+//
+// Sessions that cross midnight belong to every day they touch and are shown with
+// their full times. The running session is included, up to now.
+
+use crate::config::Config;
+use crate::integrations::git;
+use crate::laps::Lap;
+use crate::timefmt::TimeFormatter;
+use crate::{logic, Metadata, Period, TimeSheet};
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use std::io;
+use tracing::{debug, instrument, warn};
+
+// A session as the journal shows it.
+struct Session<'a> {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    running: bool,
+    metadata: &'a Metadata,
+    laps: &'a [Lap],
+}
+
+// The sessions that overlap `range`, in the order they started.
+fn sessions<'a>(time_sheet: &'a TimeSheet, range: &Period, now: DateTime<Utc>) -> Vec<Session<'a>> {
+    let mut sessions: Vec<Session> = time_sheet
+        .periods
+        .iter()
+        .filter(|period| period.overlap(range) > Duration::zero())
+        .map(|period| Session {
+            start: period.start,
+            end: period.end,
+            running: false,
+            metadata: &period.metadata,
+            laps: &period.laps,
+        })
+        .collect();
+    if let Some(start) = time_sheet.active_period_start.filter(|start| *start < range.end && now > range.start) {
+        sessions.push(Session {
+            start,
+            end: now,
+            running: true,
+            metadata: &time_sheet.active_metadata,
+            laps: &time_sheet.active_laps,
+        });
+    }
+    sessions.sort_by_key(|session| session.start);
+    sessions
+}
+
+// Renders the journal of `day`.
+#[instrument(level = "debug", skip(time_sheet, config, formatter))]
+pub fn render(time_sheet: &TimeSheet, config: &Config, day: NaiveDate, formatter: &TimeFormatter, now: DateTime<Utc>) -> io::Result<String> {
+    let range = logic::day_period(day, config.zone())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is out of range.", day)))?;
+    let sessions = sessions(time_sheet, &range, now);
+    let tracked = logic::calculate_tracked_time_in_period(time_sheet, &range, now);
+    debug!(sessions = sessions.len(), "rendering journal");
+
+    let mut markdown = format!("# {} {}\n\n", day.format("%A"), formatter.date(day));
+    if sessions.is_empty() {
+        markdown.push_str("Nothing tracked.");
+        return Ok(markdown);
+    }
+    markdown.push_str(&format!("Tracked {} in {} session(s).\n\n", formatter.duration(tracked), sessions.len()));
+
+    let git = config.hooks.git.as_ref().filter(|git| git.enabled);
+    let time = |at: DateTime<Utc>| {
+        let local = at.with_timezone(&Local);
+        if local.date_naive() == day { formatter.time(local) } else { formatter.date_time(local) }
+    };
+    for session in &sessions {
+        markdown.push_str(&format!(
+            "## {}–{} {} ({}{})\n\n",
+            time(session.start),
+            if session.running { "now".to_string() } else { time(session.end) },
+            session.metadata.project.as_deref().unwrap_or("(no project)"),
+            formatter.duration(session.end - session.start),
+            if session.running { ", running" } else { "" }
+        ));
+
+        let mut details = Vec::new();
+        if !session.metadata.tags.is_empty() {
+            details.push(format!("Tags: {}", session.metadata.tags.join(", ")));
+        }
+        if let Some(category) = &session.metadata.category {
+            details.push(format!("Category: {}", category));
+        }
+        for (key, value) in &session.metadata.fields {
+            details.push(format!("{}: {}", key, value));
+        }
+        if !details.is_empty() {
+            markdown.push_str(&format!("{}\n\n", details.join(" · ")));
+        }
+
+        let note = session.metadata.note.as_deref().unwrap_or("").trim();
+        if !note.is_empty() {
+            markdown.push_str(&format!("{}\n\n", note));
+        }
+
+        if !session.laps.is_empty() {
+            markdown.push_str("Laps:\n\n");
+            let mut previous = session.start;
+            for lap in session.laps {
+                markdown.push_str(&format!("- {} ({})", time(lap.time), formatter.duration(lap.time - previous)));
+                if let Some(note) = &lap.note {
+                    markdown.push_str(&format!(" {}", note));
+                }
+                markdown.push('\n');
+                previous = lap.time;
+            }
+            markdown.push('\n');
+        }
+
+        if let Some(git) = git {
+            match git::commits(git, session.start, session.end) {
+                Ok(commits) => {
+                    let commits: Vec<_> =
+                        commits.iter().filter(|commit| !note.lines().any(|line| line.trim() == commit.subject)).collect();
+                    if !commits.is_empty() {
+                        markdown.push_str("Commits:\n\n");
+                        for commit in commits {
+                            markdown.push_str(&format!("- `{}` {}\n", &commit.hash[..commit.hash.len().min(7)], commit.subject));
+                        }
+                        markdown.push('\n');
+                    }
+                }
+                Err(e) => warn!(error = %e, "could not list the commits of a session"),
+            }
+        }
+    }
+    // Each section ends in a blank line; the document does not.
+    markdown.truncate(markdown.trim_end().len());
+    Ok(markdown)
+}
@@ -0,0 +1,108 @@
+// Before and after of a changed period, in the style of a unified diff: a header
+// naming the period, then its times and metadata one per line, unchanged ones
+// indented, removed ones with "-" and added ones with "+". On a terminal the
+// lines are colored, red for removed and green for added, unless NO_COLOR is set.
+//
+// Used wherever a change is shown instead of, or before, being made: the
+// `--dry-run` of `retag`, `tag rename`, `gdpr enforce` and `import`, and the
+// conflicts `sync::reconcile` asks about.
+
+use crate::timefmt::TimeFormatter;
+use crate::{Metadata, Period};
+use chrono::{DateTime, Local, Utc};
+use std::env;
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const CYAN: &str = "36";
+
+// Whether to color output going to a stream that is or is not a terminal.
+pub fn use_color(terminal: bool) -> bool {
+    terminal && env::var_os("NO_COLOR").is_none()
+}
+
+// One side of a change: a recorded period, or the running session without an end.
+#[derive(Clone, Copy)]
+pub struct Version<'a> {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub metadata: &'a Metadata,
+}
+
+impl<'a> From<&'a Period> for Version<'a> {
+    fn from(period: &'a Period) -> Self {
+        Version { start: period.start, end: Some(period.end), metadata: &period.metadata }
+    }
+}
+
+impl Version<'_> {
+    // The lines of the version, each labelled so that the two sides can be lined up.
+    fn lines(&self, formatter: &TimeFormatter) -> Vec<(String, String)> {
+        let start = self.start.with_timezone(&Local);
+        let times = match self.end {
+            Some(end) => format!(
+                "{} to {} ({})",
+                formatter.date_time(start),
+                formatter.time(end.with_timezone(&Local)),
+                formatter.duration(end - self.start)
+            ),
+            None => format!("{} to now", formatter.date_time(start)),
+        };
+        let metadata = self.metadata;
+        let mut lines = vec![("time".to_string(), times)];
+        let mut push = |label: &str, value: Option<String>| {
+            if let Some(value) = value {
+                lines.push((label.to_string(), format!("{}: {}", label, value)));
+            }
+        };
+        push("project", metadata.project.clone());
+        push("tags", (!metadata.tags.is_empty()).then(|| metadata.tags.join(", ")));
+        push("category", metadata.category.clone());
+        push("note", metadata.note.clone());
+        for (key, value) in &metadata.fields {
+            push(key, Some(value.clone()));
+        }
+        lines
+    }
+}
+
+// The diff of a change from `before` to `after` under the header `title`, e.g.
+// the period's ID. Without `before` the period is added, without `after` it is
+// removed.
+pub fn render(
+    title: &str,
+    before: Option<Version<'_>>,
+    after: Option<Version<'_>>,
+    formatter: &TimeFormatter,
+    color: bool,
+) -> String {
+    let paint = |code: &str, line: String| if color { format!("\x1b[{}m{}\x1b[0m", code, line) } else { line };
+    let old = before.map(|version| version.lines(formatter)).unwrap_or_default();
+    let new = after.map(|version| version.lines(formatter)).unwrap_or_default();
+    let mut labels: Vec<&str> = Vec::new();
+    for (label, _) in old.iter().chain(&new) {
+        if !labels.contains(&label.as_str()) {
+            labels.push(label);
+        }
+    }
+
+    let mut text = paint(CYAN, format!("@@ {} @@", title));
+    text.push('\n');
+    let find = |lines: &[(String, String)], label: &str| lines.iter().find(|(l, _)| l == label).map(|(_, line)| line.clone());
+    for label in labels {
+        match (find(&old, label), find(&new, label)) {
+            (Some(old), Some(new)) if old == new => text.push_str(&format!("  {}\n", old)),
+            (old, new) => {
+                if let Some(old) = old {
+                    text.push_str(&paint(RED, format!("- {}", old)));
+                    text.push('\n');
+                }
+                if let Some(new) = new {
+                    text.push_str(&paint(GREEN, format!("+ {}", new)));
+                    text.push('\n');
+                }
+            }
+        }
+    }
+    text
+}
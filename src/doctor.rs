@@ -0,0 +1,479 @@
+// Self-diagnostics for the "doctor" command.
+//
+// The basic checks read the config, the data file and the journal without
+// changing anything. `--full` adds file permissions, traces of interrupted
+// writes, the system clock against an NTP server and the timezone setup. With
+// `--json`, the report is printed as JSON for attaching to bug reports.
+
+use crate::config::{self, CarryOverPolicy, Config, Contract};
+use crate::journal::{self, JournalEntry};
+use crate::storage::{self, StorageFormat};
+use crate::{closing, index, TimeSheet};
+use chrono::{DateTime, Duration, Local, NaiveDate, Offset, TimeZone, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+const NTP_SERVER: &str = "pool.ntp.org";
+// Seconds from 1900, the NTP epoch, to 1970.
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+// Clock skew up to this many seconds is fine.
+const MAX_SKEW_SECONDS: i64 = 60;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+            Status::Skip => "skip",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, summary: impl Into<String>) -> Check {
+        Check { name, status, summary: summary.into(), details: Vec::new() }
+    }
+
+    // Ok without problems, otherwise `status` with the problems as details.
+    fn from_problems(name: &'static str, status: Status, ok: impl Into<String>, problems: Vec<String>) -> Check {
+        if problems.is_empty() {
+            return Check::new(name, Status::Ok, ok);
+        }
+        Check { name, status, summary: format!("{} problem(s)", problems.len()), details: problems }
+    }
+}
+
+// Everything `doctor` found, with the versions and paths involved.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub app_version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub data_format: String,
+    pub data_path: Option<PathBuf>,
+    pub config_path: Option<PathBuf>,
+    pub checks: Vec<Check>,
+}
+
+// Runs the checks. Never fails itself; whatever goes wrong becomes a check.
+#[instrument(level = "debug")]
+pub fn diagnose(full: bool) -> Report {
+    let mut checks = Vec::new();
+    let config_path = config::get_config_file_path().ok();
+
+    let config = match config::load_config() {
+        Ok(config) => {
+            let summary = match &config_path {
+                Some(path) if path.exists() => format!("{} is valid", path.display()),
+                _ => "no config file, using defaults".to_string(),
+            };
+            checks.push(Check::from_problems("config", Status::Fail, summary, config_problems(&config)));
+            config
+        }
+        Err(e) => {
+            checks.push(Check::new("config", Status::Fail, e.to_string()));
+            Config::default()
+        }
+    };
+
+    let data_path = match storage::get_data_file_path(&config) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            checks.push(Check::new("data", Status::Fail, e.to_string()));
+            None
+        }
+    };
+    let data_format = match &data_path {
+        Some(path) if storage::is_monthly(path) => "monthly".to_string(),
+        Some(path) => StorageFormat::for_path(path).name().to_string(),
+        None => "unknown".to_string(),
+    };
+
+    if let Some(path) = &data_path {
+        checks.extend(check_data(path, &config));
+        if full {
+            checks.push(check_permissions(path, config_path.as_deref()));
+            checks.push(check_interrupted_writes(path));
+        }
+    }
+    if full {
+        checks.push(check_clock());
+        checks.push(check_timezone());
+    }
+
+    debug!(checks = checks.len(), "diagnosed");
+    Report {
+        app_version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        data_format,
+        data_path,
+        config_path,
+        checks,
+    }
+}
+
+// Handles the "doctor" command. Fails if any check failed, so scripts can tell.
+pub fn run(full: bool, json: bool) -> io::Result<()> {
+    let report = diagnose(full);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).map_err(io::Error::other)?);
+    } else {
+        println!("time_tracker {} ({}/{})", report.app_version, report.os, report.arch);
+        if let Some(path) = &report.data_path {
+            println!("Data: {} ({})", path.display(), report.data_format);
+        }
+        if let Some(path) = &report.config_path {
+            println!("Config: {}", path.display());
+        }
+        println!();
+        for check in &report.checks {
+            println!("[{:<4}] {}: {}", check.status.label(), check.name, check.summary);
+            for detail in &check.details {
+                println!("         - {}", detail);
+            }
+        }
+    }
+    let failed = report.checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} check(s) failed.", failed)));
+    }
+    Ok(())
+}
+
+// Settings that parse but cannot work.
+fn config_problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(hours) = config.weekly_target_hours
+        && !(hours > 0.0 && hours <= 168.0)
+    {
+        problems.push(format!("weekly_target_hours = {} is not between 0 and 168.", hours));
+    }
+    for (index, contract) in config.contracts.iter().enumerate() {
+        if contract.end.is_some_and(|end| end < contract.start) {
+            problems.push(format!("The contract starting {} ends before it starts.", contract.start));
+        }
+        if !(contract.weekly_hours > 0.0 && contract.weekly_hours <= 168.0) {
+            problems.push(format!("The contract starting {} has weekly_hours = {}, not between 0 and 168.", contract.start, contract.weekly_hours));
+        }
+        for other in &config.contracts[index + 1..] {
+            let starts_within = |a: &Contract, b: &Contract| b.covers(a.start);
+            if starts_within(contract, other) || starts_within(other, contract) {
+                problems.push(format!(
+                    "The contracts starting {} and {} overlap; the later one applies where they do.",
+                    contract.start, other.start
+                ));
+            }
+        }
+    }
+    for (project, budget) in &config.budgets {
+        if budget.alerts.contains(&0) {
+            problems.push(format!("The budget of {} has an alert at 0%, which would fire on every stop.", project));
+        }
+    }
+    if let Some(hours) = config.max_daily_hours
+        && !(hours > 0.0 && hours <= 24.0)
+    {
+        problems.push(format!("max_daily_hours = {} is not between 0 and 24.", hours));
+    }
+    if config.carry_over.policy == CarryOverPolicy::Cap && config.carry_over.cap_hours.is_none() {
+        problems.push("carry_over.policy = \"cap\" needs carry_over.cap_hours.".to_string());
+    }
+    if let Some(window) = &config.tracking_window
+        && window.start == window.end
+    {
+        problems.push("tracking_window starts and ends at the same time.".to_string());
+    }
+    if let Some(caldav) = &config.caldav
+        && caldav.url.trim().is_empty()
+    {
+        problems.push("caldav.url is empty.".to_string());
+    }
+    problems
+}
+
+// Reads the data file and journal as they are and checks the periods.
+fn check_data(path: &Path, config: &Config) -> Vec<Check> {
+    let exists = path.exists() || journal::journal_path(path).exists();
+    if !exists {
+        return vec![Check::new("data", Status::Ok, "no data file yet")];
+    }
+    let mut time_sheet = if path.exists() {
+        match storage::read(path) {
+            Ok(time_sheet) => time_sheet,
+            Err(e) => return vec![Check::new("data", Status::Fail, e.to_string())],
+        }
+    } else {
+        TimeSheet::default()
+    };
+    let mut checks = vec![Check::new("data", Status::Ok, format!("{} period(s) read", time_sheet.periods.len()))];
+
+    checks.push(match journal_problems(path) {
+        Ok(problems) => {
+            let entries = journal::entry_count(path).unwrap_or(0);
+            Check::from_problems("journal", Status::Warn, format!("{} pending entries", entries), problems)
+        }
+        Err(e) => Check::new("journal", Status::Fail, e.to_string()),
+    });
+    if let Err(e) = journal::replay(path, &mut time_sheet) {
+        checks.push(Check::new("journal", Status::Fail, e.to_string()));
+    }
+
+    let (errors, warnings) = integrity_problems(&time_sheet, config);
+    checks.push(if errors.is_empty() {
+        Check::from_problems("integrity", Status::Warn, "periods are consistent", warnings)
+    } else {
+        Check::from_problems("integrity", Status::Fail, "", errors.into_iter().chain(warnings).collect())
+    });
+
+    checks.push(match index::load(path) {
+        Ok(Some(_)) => Check::new("index", Status::Ok, "up to date"),
+        Ok(None) => Check::new("index", Status::Ok, "missing or out of date, rebuilt by the next 'status'"),
+        Err(e) => Check::new("index", Status::Warn, e.to_string()),
+    });
+    checks
+}
+
+// Damaged lines and a torn last entry in the journal.
+fn journal_problems(data_path: &Path) -> io::Result<Vec<String>> {
+    let path = journal::journal_path(data_path);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut problems: Vec<String> = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && serde_json::from_str::<JournalEntry>(line).is_err())
+        .map(|(number, _)| format!("Line {} of {} cannot be read and is skipped.", number + 1, path.display()))
+        .collect();
+    if journal::ends_torn(&path)? {
+        problems.push(format!("{} ends in an incomplete entry.", path.display()));
+    }
+    Ok(problems)
+}
+
+// Problems that make the data wrong, and ones worth knowing about.
+fn integrity_problems(time_sheet: &TimeSheet, config: &Config) -> (Vec<String>, Vec<String>) {
+    let (mut errors, mut warnings) = (Vec::new(), Vec::new());
+    let now = Utc::now();
+
+    let mut ids = HashSet::new();
+    let mut missing_ids = 0;
+    for period in &time_sheet.periods {
+        if period.end <= period.start {
+            errors.push(format!("Period {} ends before it starts.", period.id));
+        }
+        if period.end > now {
+            warnings.push(format!("Period {} ends in the future.", period.id));
+        }
+        if period.id == Uuid::nil() {
+            missing_ids += 1;
+        } else if !ids.insert(period.id) {
+            errors.push(format!("Period ID {} is used more than once.", period.id));
+        }
+    }
+    if missing_ids > 0 {
+        warnings.push(format!("{} period(s) have no ID yet; they get one when the data file is next loaded.", missing_ids));
+    }
+
+    if time_sheet.periods.windows(2).any(|pair| pair[0].start > pair[1].start) {
+        warnings.push("Periods are not sorted by start; 'compact' sorts them.".to_string());
+    }
+    let mut sorted: Vec<_> = time_sheet.periods.iter().collect();
+    sorted.sort_by_key(|p| p.start);
+    for pair in sorted.windows(2).filter(|pair| pair[0].end > pair[1].start) {
+        warnings.push(format!("Periods {} and {} overlap.", pair[0].id, pair[1].id));
+    }
+
+    if let Some(start) = time_sheet.active_period_start {
+        if start > now {
+            warnings.push(format!("The running session starts in the future, at {}.", start.with_timezone(&Local)));
+        }
+        if let Some(last) = time_sheet.periods.iter().map(|p| p.end).max()
+            && start < last
+        {
+            warnings.push("The running session starts before the last period ends.".to_string());
+        }
+    }
+
+    match closing::modified_months(time_sheet, config) {
+        Ok(months) => errors.extend(
+            months.into_iter().map(|month| format!("Closed month {} was changed after closing; its checksum no longer matches.", month)),
+        ),
+        Err(e) => errors.push(format!("Could not verify closed months: {}", e)),
+    }
+    (errors, warnings)
+}
+
+// Whether the data file, its directory and the config can be written, and whether
+// other users can write them.
+fn check_permissions(data_path: &Path, config_path: Option<&Path>) -> Check {
+    let mut paths: Vec<&Path> = vec![data_path];
+    paths.extend(data_path.parent().filter(|p| !p.as_os_str().is_empty()));
+    paths.extend(config_path);
+
+    let (mut errors, mut warnings) = (Vec::new(), Vec::new());
+    for path in paths {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                errors.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        if metadata.permissions().readonly() {
+            errors.push(format!("{} is read-only.", path.display()));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = metadata.permissions().mode();
+            if mode & 0o022 != 0 && !metadata.is_dir() {
+                warnings.push(format!("{} can be written by other users (mode {:o}).", path.display(), mode & 0o777));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Check::from_problems("permissions", Status::Warn, "files are writable", warnings)
+    } else {
+        Check::from_problems("permissions", Status::Fail, "", errors.into_iter().chain(warnings).collect())
+    }
+}
+
+// The tracker takes no lock; instead, look for what an interrupted write leaves
+// behind: temporary month files of the monthly layout.
+fn check_interrupted_writes(data_path: &Path) -> Check {
+    let mut leftovers = Vec::new();
+    if storage::is_monthly(data_path) {
+        collect_temporary_files(data_path, &mut leftovers);
+    }
+    let problems = leftovers
+        .iter()
+        .map(|path| format!("{} was left by an interrupted write and can be removed.", path.display()))
+        .collect();
+    Check::from_problems("interrupted-writes", Status::Warn, "none found", problems)
+}
+
+fn collect_temporary_files(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_temporary_files(&path, found);
+        } else if path.extension().is_some_and(|e| e == "tmp") {
+            found.push(path);
+        }
+    }
+}
+
+// Compares the system clock with an NTP server.
+fn check_clock() -> Check {
+    match ntp_offset(NTP_SERVER) {
+        Ok(offset) if offset.num_seconds().abs() > MAX_SKEW_SECONDS => Check::new(
+            "clock",
+            Status::Warn,
+            format!("the system clock is off by {:.1} s from {}", offset.num_milliseconds() as f64 / 1000.0, NTP_SERVER),
+        ),
+        Ok(offset) => Check::new(
+            "clock",
+            Status::Ok,
+            format!("{:+.3} s from {}", offset.num_milliseconds() as f64 / 1000.0, NTP_SERVER),
+        ),
+        Err(e) => Check::new("clock", Status::Skip, format!("could not reach {}: {}", NTP_SERVER, e)),
+    }
+}
+
+// How far the NTP server's time is ahead of the system clock, from a single SNTP
+// request, corrected for half the round trip.
+fn ntp_offset(server: &str) -> io::Result<Duration> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(std::time::Duration::from_secs(3)))?;
+    socket.connect((server, 123))?;
+    let mut packet = [0u8; 48];
+    // Leap indicator 0, version 4, client mode.
+    packet[0] = 0x23;
+    let sent = Utc::now();
+    socket.send(&packet)?;
+    let received = socket.recv(&mut packet)?;
+    let arrived = Utc::now();
+    if received < 48 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short NTP response"));
+    }
+    // The server's transmit timestamp: seconds and a binary fraction since 1900.
+    let seconds = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as i64 - NTP_UNIX_OFFSET;
+    let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]) as u64;
+    let nanos = ((fraction * 1_000_000_000) >> 32) as u32;
+    let server_time = DateTime::from_timestamp(seconds, nanos)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid NTP timestamp"))?;
+    Ok(server_time - (sent + (arrived - sent) / 2))
+}
+
+// The local offset now and across the year, and whether `TZ` names a zone that
+// exists: an unknown zone silently falls back to UTC.
+fn check_timezone() -> Check {
+    let now = Utc::now().with_timezone(&Local);
+    let tz = std::env::var("TZ").ok().filter(|tz| !tz.is_empty());
+    let offsets: Vec<i32> = [1, 7]
+        .iter()
+        .filter_map(|month| NaiveDate::from_ymd_opt(now.format("%Y").to_string().parse().ok()?, *month, 1))
+        .filter_map(|day| Local.from_local_datetime(&day.and_hms_opt(12, 0, 0)?).single())
+        .map(|time| time.offset().fix().local_minus_utc())
+        .collect();
+    let dst = if offsets.windows(2).any(|pair| pair[0] != pair[1]) { ", with daylight saving time" } else { "" };
+    let summary = format!(
+        "{}UTC{}{}",
+        tz.as_deref().map(|tz| format!("TZ={}, ", tz)).unwrap_or_default(),
+        now.format("%:z"),
+        dst
+    );
+
+    let mut problems = Vec::new();
+    if let Some(tz) = &tz
+        && !tz_exists(tz)
+    {
+        problems.push(format!("TZ={} is not a known zone; times may be shown in UTC.", tz));
+    }
+    if now.offset().fix().local_minus_utc() % 900 != 0 {
+        problems.push(format!("The UTC offset {} is not a multiple of 15 minutes.", now.format("%:z")));
+    }
+    Check::from_problems("timezone", Status::Warn, summary, problems)
+}
+
+// Whether `TZ` names a zone in the system database or is a POSIX rule such as
+// `CET-1CEST`. Other systems are given the benefit of the doubt.
+fn tz_exists(tz: &str) -> bool {
+    let name = tz.strip_prefix(':').unwrap_or(tz);
+    if !cfg!(unix) || name == "UTC" || name.chars().any(|c| c.is_ascii_digit()) {
+        return true;
+    }
+    let path = Path::new(name);
+    path.is_absolute() && path.exists() || Path::new("/usr/share/zoneinfo").join(name).exists()
+}
@@ -0,0 +1,188 @@
+use crate::Period;
+use crate::logic;
+use crate::pdf;
+use crate::report::Report;
+use crate::timefmt::TimeFormatter;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::{debug, instrument};
+
+// File formats periods can be exported to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Pdf,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+// Writes the periods to `path` in the given format. `title` is used as a heading
+// where the format supports one.
+#[instrument(level = "debug", skip(periods, formatter), fields(periods = periods.len()))]
+pub fn export_periods(
+    format: ExportFormat,
+    path: &Path,
+    title: &str,
+    periods: &[&Period],
+    formatter: &TimeFormatter,
+) -> io::Result<()> {
+    match format {
+        ExportFormat::Csv => fs::write(path, to_csv(periods))?,
+        ExportFormat::Pdf => pdf::write_text_pdf(path, &to_text_lines(title, periods, formatter))?,
+    }
+    debug!(path = %path.display(), "exported periods");
+    Ok(())
+}
+
+// One row per period, times in local time with offset, durations in seconds.
+fn to_csv(periods: &[&Period]) -> String {
+    let mut csv = String::from("id,start,end,duration_seconds,project,tags,note\n");
+    for period in periods {
+        let fields = [
+            period.id.to_string(),
+            period.start.with_timezone(&Local).to_rfc3339(),
+            period.end.with_timezone(&Local).to_rfc3339(),
+            (period.end - period.start).num_seconds().to_string(),
+            period.metadata.project.clone().unwrap_or_default(),
+            period.metadata.tags.join(";"),
+            period.metadata.note.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// Quotes a CSV field if it contains a separator, quote or line break.
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// A plain-text table of the periods with a total, used for the PDF export.
+fn to_text_lines(title: &str, periods: &[&Period], formatter: &TimeFormatter) -> Vec<String> {
+    let mut lines = vec![title.to_string(), String::new()];
+    lines.push(format!("{:<10} {:<5} {:<5} {:>12}  {:<16} {}", "Date", "Start", "End", "Duration", "Project", "Note"));
+
+    let mut total = Duration::zero();
+    for period in periods {
+        let start = period.start.with_timezone(&Local);
+        let end = period.end.with_timezone(&Local);
+        let duration = period.end - period.start;
+        total += duration;
+        lines.push(format!(
+            "{:<10} {:<5} {:<5} {:>12}  {:<16} {}",
+            formatter.date(start.date_naive()),
+            formatter.time(start),
+            formatter.time(end),
+            formatter.duration(duration),
+            period.metadata.project.as_deref().unwrap_or("-"),
+            period.metadata.note.as_deref().unwrap_or(""),
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push(format!("Total: {}", formatter.duration(total)));
+    lines
+}
+
+// Layouts of the month grid.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridFormat {
+    Csv,
+    Markdown,
+}
+
+// One line of the month grid, as on a paper timesheet: when work started and
+// ended that day, the untracked time in between as the break, and the total.
+#[derive(Debug, Clone)]
+pub struct GridDay {
+    pub date: NaiveDate,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub break_time: Duration,
+    pub total: Duration,
+}
+
+// Infers the grid from the periods of a month report, one line per day. Sessions
+// across midnight count on both days.
+pub fn month_grid(report: &Report) -> Vec<GridDay> {
+    report
+        .days
+        .iter()
+        .map(|day| {
+            let mut grid = GridDay { date: day.date, start: None, end: None, break_time: Duration::zero(), total: day.total };
+            if let Some(range) = logic::day_period(day.date, report.zone) {
+                for row in report.periods.iter().filter(|row| row.period.overlap(&range) > Duration::zero()) {
+                    let (start, end) = (row.period.start.max(range.start), row.period.end.min(range.end));
+                    grid.start = Some(grid.start.map_or(start, |s| s.min(start)));
+                    grid.end = Some(grid.end.map_or(end, |e| e.max(end)));
+                }
+            }
+            if let (Some(start), Some(end)) = (grid.start, grid.end) {
+                grid.break_time = (end - start - grid.total).max(Duration::zero());
+            }
+            grid
+        })
+        .collect()
+}
+
+// Renders the grid with a total line at the end. Days without time keep their
+// line, with the times left empty.
+pub fn render_grid(days: &[GridDay], format: GridFormat, title: &str, formatter: &TimeFormatter) -> String {
+    let time = |time: Option<DateTime<Utc>>| time.map(|t| formatter.time(t.with_timezone(&Local))).unwrap_or_default();
+    let duration = |day: &GridDay, value: Duration| if day.start.is_some() { formatter.duration(value) } else { String::new() };
+    let breaks: Duration = days.iter().map(|day| day.break_time).sum();
+    let total: Duration = days.iter().map(|day| day.total).sum();
+    match format {
+        GridFormat::Csv => {
+            let mut csv = String::from("date,start,end,break,total\n");
+            for day in days {
+                let fields = [
+                    formatter.date(day.date),
+                    time(day.start),
+                    time(day.end),
+                    duration(day, day.break_time),
+                    duration(day, day.total),
+                ];
+                let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+                csv.push_str(&row.join(","));
+                csv.push('\n');
+            }
+            csv.push_str(&format!("total,,,{},{}\n", csv_field(&formatter.duration(breaks)), csv_field(&formatter.duration(total))));
+            csv
+        }
+        GridFormat::Markdown => {
+            let mut markdown = format!("# {}\n\n| Date | Start | End | Break | Total |\n|---|---|---|--:|--:|\n", title);
+            for day in days {
+                markdown.push_str(&format!(
+                    "| {} {} | {} | {} | {} | {} |\n",
+                    day.date.format("%a"),
+                    formatter.date(day.date),
+                    time(day.start),
+                    time(day.end),
+                    duration(day, day.break_time),
+                    duration(day, day.total)
+                ));
+            }
+            markdown.push_str(&format!("| **Total** | | | {} | **{}** |\n", formatter.duration(breaks), formatter.duration(total)));
+            markdown
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// C ABI for native frontends, such as a menu bar app, that want to use the
+// tracker without running the command-line program. Only built with the `ffi`
+// feature, which also writes the C header to `include/time_tracker.h`. For a
+// library to link against, build with
+//
+//   cargo rustc --release --lib --features ffi --crate-type cdylib   (or staticlib)
+//
+// Every function opens the data file anew, so changes made meanwhile by the
+// command-line program are seen. A NULL `data_path` means the data file from the
+// config, as for the command-line program. Functions returning `int32_t` return 0
+// on success and -1 on failure; `tt_last_error` then describes the failure. No
+// panic crosses into C: it is reported as a failure as well.
+
+use crate::config::{self, Config};
+use crate::storage;
+use crate::tracker::Tracker;
+use crate::{logic, Metadata};
+use serde_json::json;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use tracing::debug;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// Runs `f`, turning errors and panics into `failed` and the last error.
+fn guard<T>(failed: T, f: impl FnOnce() -> io::Result<T>) -> T {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            debug!(error = %e, "ffi call failed");
+            set_last_error(&e.to_string());
+            failed
+        }
+        Err(_) => {
+            set_last_error("Internal error in the time tracker.");
+            failed
+        }
+    }
+}
+
+// A C string argument; NULL is `None`.
+unsafe fn optional_str(text: *const c_char) -> io::Result<Option<String>> {
+    if text.is_null() {
+        return Ok(None);
+    }
+    // SAFETY: the caller guarantees a valid, NUL-terminated string.
+    let text = unsafe { CStr::from_ptr(text) };
+    let text = text
+        .to_str()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Arguments must be UTF-8."))?;
+    Ok(Some(text.to_string()))
+}
+
+unsafe fn open(data_path: *const c_char) -> io::Result<(Tracker, Config)> {
+    let config = config::load_config()?;
+    let path = match unsafe { optional_str(data_path) }? {
+        Some(path) => PathBuf::from(path),
+        None => storage::get_data_file_path(&config)?,
+    };
+    Ok((Tracker::open(&path)?, config))
+}
+
+/// Starts a session now, with the config's default project if `project` is NULL.
+/// `note` may be NULL as well.
+///
+/// # Safety
+///
+/// `data_path`, `project` and `note` must each be NULL or a valid NUL-terminated
+/// string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tt_start(data_path: *const c_char, project: *const c_char, note: *const c_char) -> i32 {
+    guard(-1, || {
+        let (tracker, config) = unsafe { open(data_path) }?;
+        let project = unsafe { optional_str(project) }?.or(config.default_project);
+        let note = unsafe { optional_str(note) }?;
+        tracker.start(Metadata { project, note, ..Metadata::default() })?;
+        Ok(0)
+    })
+}
+
+/// Stops the running session.
+///
+/// # Safety
+///
+/// `data_path` must be NULL or a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tt_stop(data_path: *const c_char) -> i32 {
+    guard(-1, || {
+        let (tracker, _) = unsafe { open(data_path) }?;
+        tracker.stop()?;
+        Ok(0)
+    })
+}
+
+/// The current status as a JSON object:
+///
+///   {"active": true, "since": "2026-10-17T07:30:00Z", "elapsed_seconds": 5400,
+///    "project": "acme", "tags": [], "note": null, "today_seconds": 12600}
+///
+/// `since` and `elapsed_seconds` are null without a running session. Returns NULL
+/// on failure; free the string with `tt_string_free`.
+///
+/// # Safety
+///
+/// `data_path` must be NULL or a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tt_status_json(data_path: *const c_char) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let (tracker, config) = unsafe { open(data_path) }?;
+        let time_sheet = tracker.snapshot()?;
+        let now = chrono::Utc::now();
+        let today = logic::get_today_period(&crate::clock::SystemClock, config.zone());
+        let since = time_sheet.active_period_start();
+        let metadata = time_sheet.active_metadata();
+        let status = json!({
+            "active": since.is_some(),
+            "since": since.map(|start| start.to_rfc3339()),
+            "elapsed_seconds": since.map(|start| (now - start).num_seconds()),
+            "project": &metadata.project,
+            "tags": &metadata.tags,
+            "note": &metadata.note,
+            "today_seconds": tracker.tracked_in(&today)?.num_seconds(),
+        });
+        let text = CString::new(status.to_string()).map_err(io::Error::other)?;
+        Ok(text.into_raw())
+    })
+}
+
+/// Frees a string returned by this library. NULL is ignored.
+///
+/// # Safety
+///
+/// `text` must be NULL or a string returned by this library that was not freed
+/// before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tt_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        // SAFETY: the string was created by `CString::into_raw`.
+        drop(unsafe { CString::from_raw(text) });
+    }
+}
+
+/// Message of the last failure on this thread, or NULL if the last call succeeded.
+/// The string belongs to the library and is valid until the next call.
+#[unsafe(no_mangle)]
+pub extern "C" fn tt_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
@@ -0,0 +1,134 @@
+// Session categories and the focus report.
+//
+// A category says what kind of work a period was: by default focus (deep work),
+// meeting or admin, as configured in `session_categories`. Unlike tags, a period
+// has at most one, so the categories split the tracked time without counting
+// anything twice, and `focus` can show each week's time as shares of a whole.
+// Time without a category is shown as such rather than left out.
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::logic;
+use crate::timefmt::TimeFormatter;
+use crate::{Metadata, TimeSheet};
+use chrono::{Duration, Local, NaiveDate};
+use std::collections::BTreeMap;
+use std::io;
+use tracing::{debug, instrument};
+
+// Used when `session_categories` is not set in the config.
+pub const DEFAULT_CATEGORIES: [&str; 3] = ["focus", "meeting", "admin"];
+
+// The column of time without a category.
+const UNCATEGORIZED: &str = "none";
+
+// The configured categories, lowercased, in their configured order.
+pub fn categories(config: &Config) -> Vec<String> {
+    match &config.session_categories {
+        Some(categories) => categories.iter().map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty()).collect(),
+        None => DEFAULT_CATEGORIES.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+// The configured category `text` names, ignoring case.
+pub fn parse_category(config: &Config, text: &str) -> io::Result<String> {
+    let category = text.trim().to_lowercase();
+    let categories = categories(config);
+    if categories.contains(&category) {
+        return Ok(category);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Unknown category '{}'. Expected one of: {}.", text.trim(), categories.join(", ")),
+    ))
+}
+
+// Fails if the metadata has a category that is not configured.
+pub fn check_category(config: &Config, metadata: &Metadata) -> io::Result<()> {
+    match &metadata.category {
+        Some(category) => parse_category(config, category).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+// Tracked time of one week by category; `None` is time without one.
+pub struct WeekFocus {
+    pub first: NaiveDate,
+    pub total: Duration,
+    pub by_category: BTreeMap<Option<String>, Duration>,
+}
+
+// Time by category for each of the last `weeks` weeks, oldest first, including
+// the running session.
+pub fn weekly_focus(time_sheet: &TimeSheet, config: &Config, weeks: u32, clock: &dyn Clock) -> Vec<WeekFocus> {
+    let now = clock.now();
+    let week = logic::get_week_period(config.week_start.0, clock, config.zone());
+    let this_week = week.start.with_timezone(&Local).date_naive();
+    (0..weeks as i64)
+        .rev()
+        .map(|ago| {
+            let first = this_week - Duration::weeks(ago);
+            let range = logic::week_starting(first, config.zone());
+            let report = logic::build_report(time_sheet, config, "week", &range, now);
+            let mut by_category: BTreeMap<Option<String>, Duration> = BTreeMap::new();
+            for row in &report.periods {
+                *by_category.entry(row.period.metadata.category.clone()).or_default() += row.duration;
+            }
+            WeekFocus { first, total: report.total, by_category }
+        })
+        .collect()
+}
+
+// Handles the "focus" command: each week's time by category, with the share of
+// the week's total, and the same over all listed weeks.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn show_focus(time_sheet: &TimeSheet, config: &Config, weeks: u32, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    let rows = weekly_focus(time_sheet, config, weeks, clock);
+    if rows.iter().all(|row| row.total == Duration::zero()) {
+        println!("Nothing tracked in the last {} weeks.", weeks);
+        return Ok(());
+    }
+
+    // Configured categories first, then any that are recorded but no longer
+    // configured, then time without one.
+    let mut columns: Vec<Option<String>> = categories(config).into_iter().map(Some).collect();
+    for row in &rows {
+        for category in row.by_category.keys() {
+            if category.is_some() && !columns.contains(category) {
+                columns.push(category.clone());
+            }
+        }
+    }
+    columns.push(None);
+    let width = columns.iter().map(|c| c.as_deref().unwrap_or(UNCATEGORIZED).len()).max().unwrap_or(0).max(15);
+
+    let cell = |duration: Duration, total: Duration| {
+        let percent = if total > Duration::zero() { duration.num_seconds() as f64 * 100.0 / total.num_seconds() as f64 } else { 0.0 };
+        format!("{:>9} {:>4.0}%", formatter.duration(duration), percent)
+    };
+    let mut header = format!("{:<10}  {:>9}", "Week of", "Total");
+    for column in &columns {
+        header.push_str(&format!("  {:>width$}", column.as_deref().unwrap_or(UNCATEGORIZED), width = width));
+    }
+    println!("{}", header);
+
+    let mut overall: BTreeMap<Option<String>, Duration> = BTreeMap::new();
+    for row in &rows {
+        let mut line = format!("{:<10}  {:>9}", formatter.date(row.first), formatter.duration(row.total));
+        for column in &columns {
+            let duration = row.by_category.get(column).copied().unwrap_or_default();
+            *overall.entry(column.clone()).or_default() += duration;
+            line.push_str(&format!("  {:>width$}", cell(duration, row.total), width = width));
+        }
+        println!("{}", line);
+    }
+
+    let total: Duration = rows.iter().map(|row| row.total).sum();
+    let mut line = format!("{:<10}  {:>9}", "All", formatter.duration(total));
+    for column in &columns {
+        line.push_str(&format!("  {:>width$}", cell(overall[column], total), width = width));
+    }
+    println!("{}", line);
+    debug!(weeks, columns = columns.len(), "printed focus report");
+    Ok(())
+}
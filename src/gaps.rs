@@ -0,0 +1,208 @@
+use crate::clock::Clock;
+use crate::config::{Config, ScheduleConfig};
+use crate::logic;
+use crate::pattern;
+use crate::prompt;
+use crate::timefmt::TimeFormatter;
+use crate::timestamp;
+use crate::{Metadata, Overlaps, Period, TimeSheet};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::io;
+use tracing::{debug, info, instrument};
+
+// A stretch of the workday marked as deliberately untracked in `suggest gaps`,
+// so it is not suggested again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Break {
+    #[serde(deserialize_with = "timestamp::deserialize")]
+    pub start: DateTime<Utc>,
+    #[serde(deserialize_with = "timestamp::deserialize")]
+    pub end: DateTime<Utc>,
+}
+
+// Parses "today", "yesterday" or a YYYY-MM-DD date.
+pub fn parse_day(text: &str, clock: &dyn Clock) -> io::Result<NaiveDate> {
+    let today = clock.now().with_timezone(&Local).date_naive();
+    match text {
+        "today" => Ok(today),
+        "yesterday" => Ok(today.pred_opt().unwrap_or(today)),
+        _ => NaiveDate::parse_from_str(text, "%Y-%m-%d").map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid day '{}'. Expected today, yesterday or YYYY-MM-DD.", text),
+            )
+        }),
+    }
+}
+
+// The workday of `day` as configured in `[schedule]`.
+pub fn workday(day: NaiveDate, schedule: &ScheduleConfig, config: &Config) -> Period {
+    let start = logic::naive_to_utc(day.and_time(schedule.workday_start), config.zone());
+    let end = logic::naive_to_utc(day.and_time(schedule.workday_end), config.zone());
+    Period::range(start, end)
+}
+
+// Untracked stretches of `range` that are at least `minimum` long. Sessions, the
+// running session and recorded breaks count as covered; time after `now` is not
+// considered.
+pub fn find_gaps(time_sheet: &TimeSheet, range: &Period, minimum: Duration, now: DateTime<Utc>) -> Vec<Period> {
+    let end = range.end.min(now);
+    let mut covered: Vec<(DateTime<Utc>, DateTime<Utc>)> = time_sheet
+        .periods_in(range)
+        .map(|p| (p.start, p.end))
+        .chain(time_sheet.active_period_start.map(|start| (start, now)))
+        .chain(time_sheet.breaks.iter().map(|b| (b.start, b.end)))
+        .filter(|(start, stop)| *start < end && *stop > range.start)
+        .collect();
+    covered.sort();
+
+    let mut gaps = Vec::new();
+    let mut cursor = range.start;
+    for (start, stop) in covered.into_iter().chain([(end, end)]) {
+        let gap_end = start.min(end);
+        if gap_end > cursor && gap_end - cursor >= minimum {
+            gaps.push(Period::range(cursor, gap_end));
+        }
+        cursor = cursor.max(stop);
+    }
+    gaps
+}
+
+// The hours of `day` to look for gaps in, with the breaks to leave out: the usual
+// hours and breaks of its weekday, as learned by `pattern`, or the workday of
+// `[schedule]` if the weekday has too little history or `configured` is set.
+pub fn expected_hours(time_sheet: &TimeSheet, config: &Config, day: NaiveDate, configured: bool) -> (Period, Vec<Period>) {
+    if !configured {
+        let learned = pattern::learn(time_sheet, day, pattern::DEFAULT_WEEKS);
+        if let Some(usual) = learned.day(day.weekday()) {
+            debug!(%day, start = %usual.start, end = %usual.end, breaks = usual.breaks.len(), "using the usual hours");
+            return (usual.hours(day, config), usual.break_periods(day, config));
+        }
+    }
+    let default_schedule = ScheduleConfig::default();
+    let schedule = config.schedule.as_ref().unwrap_or(&default_schedule);
+    (workday(day, schedule, config), Vec::new())
+}
+
+// Untracked stretches of `range` at least `minimum` long, as `find_gaps` finds
+// them, with the `breaks` cut out.
+pub fn find_gaps_around(time_sheet: &TimeSheet, range: &Period, breaks: &[Period], minimum: Duration, now: DateTime<Utc>) -> Vec<Period> {
+    let mut gaps = find_gaps(time_sheet, range, Duration::zero(), now);
+    for cut in breaks {
+        gaps = gaps
+            .into_iter()
+            .flat_map(|gap| {
+                [Period::range(gap.start, gap.end.min(cut.start)), Period::range(gap.start.max(cut.end), gap.end)]
+            })
+            .filter(|piece| piece.start < piece.end)
+            .collect();
+    }
+    gaps.retain(|gap| gap.duration() >= minimum);
+    gaps
+}
+
+// The options of `suggest gaps`.
+#[derive(Debug, Clone, Copy)]
+pub struct GapSearch {
+    /// Gaps shorter than this many minutes are left out.
+    pub minimum_minutes: i64,
+    /// Only list the gaps, without asking.
+    pub list_only: bool,
+    /// Use the workday of `[schedule]` rather than the usual hours.
+    pub configured_hours: bool,
+}
+
+// Handles `suggest gaps`: lists the gaps in the usual hours of a day and, unless `list_only`, asks
+// for each whether to record it for a project, mark it as a break or skip it.
+// Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn suggest_gaps(
+    time_sheet: &mut TimeSheet,
+    config: &Config,
+    day: &str,
+    search: GapSearch,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let day = parse_day(day, clock)?;
+    let (range, breaks) = expected_hours(time_sheet, config, day, search.configured_hours);
+    let gaps = find_gaps_around(time_sheet, &range, &breaks, Duration::minutes(search.minimum_minutes), clock.now());
+    debug!(%day, gaps = gaps.len(), "found gaps");
+
+    if gaps.is_empty() {
+        println!(
+            "No untracked gaps on {} between {} and {}.",
+            day,
+            formatter.time(range.start.with_timezone(&Local)),
+            formatter.time(range.end.with_timezone(&Local))
+        );
+        return Ok(false);
+    }
+
+    if search.list_only {
+        for gap in &gaps {
+            println!("Untracked: {}", describe_gap(gap, formatter));
+        }
+        return Ok(false);
+    }
+    let (recorded, breaks) = fill_gaps(time_sheet, config, &gaps, formatter)?;
+    if recorded + breaks == 0 {
+        return Ok(false);
+    }
+    info!(recorded, breaks, "filled gaps");
+    println!("Recorded {} session(s) and {} break(s).", recorded, breaks);
+    Ok(true)
+}
+
+fn describe_gap(gap: &Period, formatter: &TimeFormatter) -> String {
+    format!(
+        "{}-{} ({})",
+        formatter.time(gap.start.with_timezone(&Local)),
+        formatter.time(gap.end.with_timezone(&Local)),
+        formatter.duration(gap.duration())
+    )
+}
+
+// Asks for each gap whether to record it for a project, mark it as a break or
+// skip it. Returns the number of sessions and breaks recorded.
+pub(crate) fn fill_gaps(
+    time_sheet: &mut TimeSheet,
+    config: &Config,
+    gaps: &[Period],
+    formatter: &TimeFormatter,
+) -> io::Result<(usize, usize)> {
+    let (mut recorded, mut breaks) = (0, 0);
+    for gap in gaps {
+        println!("Untracked: {}", describe_gap(gap, formatter));
+        let answer = prompt::ask("  Project or @favorite to record it, 'b' for a break, empty to skip", "")?;
+        match answer.as_str() {
+            "" => {}
+            "b" | "break" => {
+                time_sheet.breaks.push(Break { start: gap.start, end: gap.end });
+                breaks += 1;
+            }
+            _ => {
+                if let Some(closed) = time_sheet.closed_month_at(gap.start) {
+                    println!("  The month {} is closed, skipped.", closed.month);
+                    continue;
+                }
+                let metadata = match answer.strip_prefix('@') {
+                    Some(name) => match config.favorites.get(name) {
+                        Some(metadata) => metadata.clone(),
+                        None => {
+                            println!("  No favorite named '{}', skipped.", name);
+                            continue;
+                        }
+                    },
+                    None => Metadata { project: Some(answer), ..Metadata::default() },
+                };
+                time_sheet.insert_period(Period::new(gap.start, gap.end)?.with_metadata(metadata), Overlaps::Reject)?;
+                recorded += 1;
+            }
+        }
+    }
+
+    time_sheet.breaks.sort_by_key(|b| b.start);
+    Ok((recorded, breaks))
+}
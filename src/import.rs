@@ -0,0 +1,433 @@
+use crate::config::NormalizeConfig;
+use crate::diff::{self, Version};
+use crate::logic;
+use crate::timefmt::TimeFormatter;
+use crate::{Metadata, Overlaps, Period, TimeSheet};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use tracing::{debug, info, instrument, warn};
+
+// Recurring events are expanded at most this many times, as a safety net.
+const MAX_OCCURRENCES: usize = 10_000;
+
+// A timed calendar event read from an ICS file. All-day events are not included.
+#[derive(Debug, Clone)]
+pub struct IcsEvent {
+    pub summary: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub rrule: Option<String>,
+    pub exdates: Vec<DateTime<Utc>>,
+    /// The zone of the start's TZID, in which it recurs. None for UTC times,
+    /// unknown zones and floating times, which are read as local time.
+    pub zone: Option<Tz>,
+}
+
+// What an import did, or would do with `--dry-run`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Already in the timesheet.
+    pub duplicates: usize,
+    /// Overlapping recorded periods, or in closed months.
+    pub conflicts: usize,
+}
+
+// Handles `import ... --dry-run`: the periods `preview` has that `time_sheet` has
+// not, as a diff, then the summary. `preview` is the timesheet as the import left
+// it, made with `Transaction::preview` so that nothing is saved.
+pub fn print_preview(time_sheet: &TimeSheet, preview: &TimeSheet, summary: &ImportSummary, formatter: &TimeFormatter) {
+    let existing: HashSet<_> = time_sheet.periods.iter().map(|period| period.id).collect();
+    let color = diff::use_color(io::stdout().is_terminal());
+    for period in preview.periods.iter().filter(|period| !existing.contains(&period.id)) {
+        print!("{}", diff::render(&period.id.to_string(), None, Some(Version::from(period)), formatter, color));
+    }
+    println!(
+        "Dry run: {} new period(s), {} duplicate(s) skipped, {} conflict(s). Nothing was saved.",
+        summary.imported, summary.duplicates, summary.conflicts
+    );
+}
+
+// Handles `import ics <file>`: turns events that ended by `now` and whose summary
+// matches the filter into periods with the given metadata (by default tagged
+// `meeting`, with the event summary as note), cleaned up with `rules`. With
+// `dry_run` the summary says what would be imported; the caller works on a copy.
+#[instrument(level = "debug", skip(time_sheet, metadata))]
+pub fn import_ics(
+    time_sheet: &mut TimeSheet,
+    path: &Path,
+    filter: Option<&str>,
+    metadata: Metadata,
+    rules: &NormalizeConfig,
+    dry_run: bool,
+    now: DateTime<Utc>,
+) -> io::Result<ImportSummary> {
+    let contents = fs::read_to_string(path)?;
+    let events = parse_ics(&contents);
+
+    let mut imported = 0;
+    let mut duplicates = 0;
+    let mut locked = 0;
+    let mut overlapping = 0;
+    let mut filtered = 0;
+    let mut empty = 0;
+
+    let mut candidates = Vec::new();
+    for event in &events {
+        if !matches_filter(&event.summary, filter) {
+            filtered += 1;
+            continue;
+        }
+        for (start, end) in occurrences(event, now) {
+            if end > now {
+                continue;
+            }
+            let Ok(period) = Period::new(start, end) else {
+                empty += 1;
+                continue;
+            };
+            let mut period_metadata = metadata.clone();
+            if period_metadata.note.is_none() && !event.summary.is_empty() {
+                period_metadata.note = Some(event.summary.clone());
+            }
+            candidates.push(period.with_metadata(period_metadata));
+        }
+    }
+
+    // Duplicates are found after normalizing, so a re-import merges the same way.
+    let normalized = logic::normalize(&mut candidates, rules);
+    for period in candidates {
+        if time_sheet.periods.iter().any(|p| p.start == period.start && p.end == period.end) {
+            duplicates += 1;
+            continue;
+        }
+        if time_sheet.closed_month_at(period.start).is_some() || time_sheet.closed_month_at(period.end).is_some() {
+            locked += 1;
+            continue;
+        }
+        if time_sheet.insert_period(period, Overlaps::Reject).is_err() {
+            overlapping += 1;
+            continue;
+        }
+        imported += 1;
+    }
+
+    info!(imported, duplicates, locked, overlapping, filtered, empty, merged = normalized.merged, dropped = normalized.dropped, "imported calendar events");
+    println!("{} {} period(s) from {} event(s).", if dry_run { "Would import" } else { "Imported" }, imported, events.len());
+    if filtered > 0 {
+        println!("Skipped {} event(s) not matching the filter.", filtered);
+    }
+    if duplicates > 0 {
+        println!("Skipped {} occurrence(s) that were already imported.", duplicates);
+    }
+    if locked > 0 {
+        println!("Skipped {} occurrence(s) in closed months.", locked);
+    }
+    if overlapping > 0 {
+        println!("Skipped {} occurrence(s) that overlap recorded periods.", overlapping);
+    }
+    if empty > 0 {
+        println!("Skipped {} occurrence(s) without a positive duration.", empty);
+    }
+    normalized.print("occurrence(s)", rules);
+    Ok(ImportSummary { imported, duplicates, conflicts: locked + overlapping })
+}
+
+// The filter is a list of alternatives separated by `|`, matched case-insensitively
+// anywhere in the summary. No filter matches everything.
+fn matches_filter(summary: &str, filter: Option<&str>) -> bool {
+    let Some(filter) = filter else {
+        return true;
+    };
+    let summary = summary.to_lowercase();
+    filter
+        .split('|')
+        .map(str::trim)
+        .filter(|alternative| !alternative.is_empty())
+        .any(|alternative| summary.contains(&alternative.to_lowercase()))
+}
+
+// Parses the VEVENTs of an ICS file. Events without a usable start and end, and
+// all-day events, are skipped.
+pub fn parse_ics(contents: &str) -> Vec<IcsEvent> {
+    let mut events = Vec::new();
+    let mut current: Option<Vec<(String, String, String)>> = None;
+
+    for line in unfold(contents) {
+        let Some((name_and_params, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, params) = match name_and_params.split_once(';') {
+            Some((name, params)) => (name.to_uppercase(), params.to_string()),
+            None => (name_and_params.to_uppercase(), String::new()),
+        };
+
+        match (name.as_str(), value.trim()) {
+            ("BEGIN", "VEVENT") => current = Some(Vec::new()),
+            ("END", "VEVENT") => {
+                if let Some(properties) = current.take() {
+                    match build_event(&properties) {
+                        Some(event) => events.push(event),
+                        None => debug!("skipping calendar event without usable times"),
+                    }
+                }
+            }
+            _ => {
+                if let Some(properties) = current.as_mut() {
+                    properties.push((name, params, value.to_string()));
+                }
+            }
+        }
+    }
+
+    events
+}
+
+// Joins folded lines: a line starting with a space or tab continues the previous one.
+fn unfold(contents: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in contents.lines() {
+        let raw = raw.trim_end_matches('\r');
+        if let Some(continuation) = raw.strip_prefix([' ', '\t'])
+            && let Some(last) = lines.last_mut()
+        {
+            last.push_str(continuation);
+            continue;
+        }
+        lines.push(raw.to_string());
+    }
+    lines
+}
+
+fn build_event(properties: &[(String, String, String)]) -> Option<IcsEvent> {
+    let property = |name: &str| properties.iter().find(|(n, _, _)| n == name);
+
+    let (_, start_params, start_value) = property("DTSTART")?;
+    let start = parse_datetime(start_params, start_value)?;
+    let end = match property("DTEND") {
+        Some((_, params, value)) => parse_datetime(params, value)?,
+        None => start + parse_duration(&property("DURATION")?.2)?,
+    };
+
+    let exdates = properties
+        .iter()
+        .filter(|(n, _, _)| n == "EXDATE")
+        .flat_map(|(_, params, value)| value.split(',').filter_map(|v| parse_datetime(params, v)).collect::<Vec<_>>())
+        .collect();
+
+    Some(IcsEvent {
+        summary: property("SUMMARY").map(|(_, _, v)| unescape(v)).unwrap_or_default(),
+        start,
+        end,
+        rrule: property("RRULE").map(|(_, _, v)| v.clone()),
+        exdates,
+        zone: zone(start_params),
+    })
+}
+
+// Parses DATE-TIME values. UTC values end in `Z`; values with a TZID are read in
+// that zone, and those without any zone, or with one chrono-tz does not know, as
+// local time of this machine. DATE values (all-day) are rejected.
+fn parse_datetime(params: &str, value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if params.to_uppercase().contains("VALUE=DATE") && !params.to_uppercase().contains("VALUE=DATE-TIME") {
+        return None;
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        let naive = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some(Utc.from_utc_datetime(&naive));
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    to_utc(naive, zone(params))
+}
+
+// The zone named by the TZID parameter, if chrono-tz knows it.
+fn zone(params: &str) -> Option<Tz> {
+    let tzid = params.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("TZID").then(|| value.trim().trim_matches('"'))
+    })?;
+    let zone = tzid.parse().ok();
+    if zone.is_none() {
+        debug!(tzid, "unknown time zone, reading the time as local time");
+    }
+    zone
+}
+
+// A wall-clock time in `zone`, or in local time without one.
+fn to_utc(naive: NaiveDateTime, zone: Option<Tz>) -> Option<DateTime<Utc>> {
+    match zone {
+        Some(zone) => zone.from_local_datetime(&naive).earliest().map(|t| t.to_utc()),
+        None => Local.from_local_datetime(&naive).earliest().map(|t| t.to_utc()),
+    }
+}
+
+// Parses ICS durations such as `PT1H30M` or `P1D`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value.strip_prefix('P')?;
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => {}
+            'W' | 'D' | 'H' | 'M' | 'S' => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                total += match c {
+                    'W' => Duration::weeks(n),
+                    'D' => Duration::days(n),
+                    'H' => Duration::hours(n),
+                    'M' => Duration::minutes(n),
+                    _ => Duration::seconds(n),
+                };
+            }
+            _ => return None,
+        }
+    }
+    Some(if negative { -total } else { total })
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("\\n", " ").replace("\\N", " ").replace("\\,", ",").replace("\\;", ";").replace("\\\\", "\\")
+}
+
+// Expands an event into its occurrences that start before `until`. Daily and
+// weekly recurrence rules with INTERVAL, COUNT, UNTIL and (weekly) BYDAY are
+// supported; other rules only yield the first occurrence.
+fn occurrences(event: &IcsEvent, until: DateTime<Utc>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let length = event.end - event.start;
+    let Some(rrule) = &event.rrule else {
+        return vec![(event.start, event.end)];
+    };
+
+    let rule: Vec<(String, String)> = rrule
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .map(|(k, v)| (k.to_uppercase(), v.to_string()))
+        .collect();
+    let get = |key: &str| rule.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+
+    let interval: i64 = get("INTERVAL").and_then(|v| v.parse().ok()).unwrap_or(1).max(1);
+    let count: Option<usize> = get("COUNT").and_then(|v| v.parse().ok());
+    let rule_until = get("UNTIL").and_then(|v| {
+        parse_datetime("", v).or_else(|| {
+            let date = NaiveDate::parse_from_str(v, "%Y%m%d").ok()?;
+            to_utc(date.succ_opt()?.and_hms_opt(0, 0, 0)?, event.zone)
+        })
+    });
+    let until = rule_until.map_or(until, |u| u.min(until));
+
+    let start_local = match event.zone {
+        Some(zone) => event.start.with_timezone(&zone).naive_local(),
+        None => event.start.with_timezone(&Local).naive_local(),
+    };
+    let time_of_day = start_local.time();
+    let first_day = start_local.date();
+
+    // Rule values are case-insensitive. The date arithmetic is checked: a large
+    // INTERVAL ends the expansion at the end of the calendar instead of overflowing.
+    let days: Vec<NaiveDate> = match get("FREQ").map(str::to_ascii_uppercase).as_deref() {
+        Some("DAILY") => (0..)
+            .map_while(|i: i64| first_day.checked_add_signed(Duration::try_days(i.checked_mul(interval)?)?))
+            .take(MAX_OCCURRENCES)
+            .collect(),
+        Some("WEEKLY") => {
+            let mut weekdays: Vec<Weekday> = get("BYDAY")
+                .map(|byday| byday.split(',').filter_map(parse_weekday).collect())
+                .unwrap_or_default();
+            // Without a usable BYDAY, the event repeats on the weekday it starts.
+            if weekdays.is_empty() {
+                weekdays.push(first_day.weekday());
+            }
+            let week_start = first_day - Duration::days(first_day.weekday().num_days_from_monday() as i64);
+            (0..)
+                .map_while(|week: i64| week_start.checked_add_signed(Duration::try_weeks(week.checked_mul(interval)?)?))
+                .flat_map(|monday| {
+                    let mut days: Vec<NaiveDate> = weekdays
+                        .iter()
+                        .filter_map(|w| monday.checked_add_signed(Duration::days(w.num_days_from_monday() as i64)))
+                        .collect();
+                    days.sort();
+                    days
+                })
+                .filter(|day| *day >= first_day)
+                .take(MAX_OCCURRENCES)
+                .collect()
+        }
+        other => {
+            warn!(freq = ?other, "unsupported recurrence rule, importing the first occurrence only");
+            return vec![(event.start, event.end)];
+        }
+    };
+
+    let mut result = Vec::new();
+    for (index, day) in days.into_iter().enumerate() {
+        if count.is_some_and(|count| index >= count) {
+            break;
+        }
+        let Some(start) = to_utc(day.and_time(time_of_day), event.zone) else {
+            continue;
+        };
+        if start >= until {
+            break;
+        }
+        if !event.exdates.contains(&start) {
+            result.push((start, start + length));
+        }
+    }
+    result
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    // Ordinal prefixes such as `1MO` only make sense for monthly rules; ignore them.
+    match value.trim().trim_start_matches(|c: char| c.is_ascii_digit() || c == '-' || c == '+').to_ascii_uppercase().as_str() {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn tzid_times_recur_in_their_zone() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Standup\r\n\
+                   DTSTART;TZID=Europe/Berlin:20240322T090000\r\nDTEND;TZID=\"Europe/Berlin\":20240322T093000\r\n\
+                   RRULE:FREQ=WEEKLY;COUNT=4\r\nEXDATE;TZID=Europe/Berlin:20240405T090000\r\n\
+                   END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let events = parse_ics(ics);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].zone, Some(chrono_tz::Europe::Berlin));
+        let starts: Vec<DateTime<Utc>> = occurrences(&events[0], utc("2025-01-01T00:00:00Z")).into_iter().map(|(start, _)| start).collect();
+        // Summer time begins on 31 March; the third occurrence is excluded.
+        assert_eq!(starts, [utc("2024-03-22T08:00:00Z"), utc("2024-03-29T08:00:00Z"), utc("2024-04-12T07:00:00Z")]);
+        assert_eq!(events[0].end - events[0].start, Duration::minutes(30));
+    }
+
+    #[test]
+    fn utc_times_ignore_the_tzid() {
+        assert_eq!(parse_datetime("TZID=Europe/Berlin", "20240322T090000Z"), Some(utc("2024-03-22T09:00:00Z")));
+        assert_eq!(zone("TZID=W. Europe Standard Time"), None);
+    }
+}
@@ -0,0 +1,343 @@
+// Cached index of daily totals, kept in `<data file>.index.json`.
+//
+// The index holds the finished time per local day, overall and per project, plus
+// the running session, so `status` can answer without reading the history. It
+// records the size and modification time of the data file and journal it was
+// built from; when they no longer match, e.g. because another program or a
+// `Tracker` changed them, the index is rebuilt from the timesheet.
+//
+// After a change made by the command line, `update` patches the index for a
+// plain `start` or `stop` and rebuilds it for anything else.
+//
+// `update` also writes `<data file>.status.json` for `status --cached`: only the
+// running session and today's finished time. Reading it needs no other file, not
+// even the modification time of the data file, so scripts can ask every second.
+// It is replaced through a temporary file and a rename, so readers never see half
+// of it. Changes made by other programs show once the next command has run.
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::journal::{self, JournalEntry};
+use crate::timefmt::TimeFormatter;
+use crate::zone::Zone;
+use crate::logic::budget;
+use crate::storage::LoadOptions;
+use crate::{logic, storage, Metadata, Period, TimeSheet};
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::{debug, instrument, warn};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DailyIndex {
+    stamp: Stamp,
+    active_period_start: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    active_metadata: Metadata,
+    days: BTreeMap<NaiveDate, DayTotals>,
+}
+
+// Finished time of one day in seconds. Time without a project is under "".
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DayTotals {
+    pub total: i64,
+    pub projects: BTreeMap<String, i64>,
+}
+
+// The state `status --cached` reads.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StatusSummary {
+    active_period_start: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    active_metadata: Metadata,
+    // Local day of `finished`; on a later day, nothing has finished yet.
+    day: NaiveDate,
+    // Finished time on `day` in seconds.
+    finished: i64,
+}
+
+impl StatusSummary {
+    fn tracked_today(&self, now: DateTime<Utc>) -> Duration {
+        let today = now.with_timezone(&Local).date_naive();
+        let finished = if self.day == today { Duration::seconds(self.finished) } else { Duration::zero() };
+        let running = match (self.active_period_start, logic::day_period(today, Zone::default())) {
+            (Some(start), Some(range)) => Period::range(start, now).overlap(&range),
+            _ => Duration::zero(),
+        };
+        finished + running
+    }
+}
+
+// Size and modification time (in nanoseconds) of the files the index describes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    data: (u64, u128),
+    journal: (u64, u128),
+}
+
+impl DailyIndex {
+    // Indexes all finished periods of the timesheet.
+    pub fn build(time_sheet: &TimeSheet) -> DailyIndex {
+        let mut index = DailyIndex {
+            active_period_start: time_sheet.active_period_start,
+            active_metadata: time_sheet.active_metadata.clone(),
+            ..DailyIndex::default()
+        };
+        for period in &time_sheet.periods {
+            index.add(period);
+        }
+        index
+    }
+
+    // Adds a finished period, split between the local days it touches.
+    fn add(&mut self, period: &Period) {
+        let project = period.metadata.project.clone().unwrap_or_default();
+        let mut day = period.start.with_timezone(&Local).date_naive();
+        while let Some(range) = logic::day_period(day, Zone::default()).filter(|d| d.start < period.end) {
+            let seconds = period.overlap(&range).num_seconds();
+            if seconds > 0 {
+                let totals = self.days.entry(day).or_default();
+                totals.total += seconds;
+                *totals.projects.entry(project.clone()).or_insert(0) += seconds;
+            }
+            let Some(next) = day.succ_opt() else { break };
+            day = next;
+        }
+    }
+
+    // Finished time on a day.
+    pub fn day(&self, day: NaiveDate) -> Option<&DayTotals> {
+        self.days.get(&day)
+    }
+
+    // Finished time of every day with any, oldest first.
+    pub fn days(&self) -> impl Iterator<Item = (NaiveDate, &DayTotals)> {
+        self.days.iter().map(|(day, totals)| (*day, totals))
+    }
+
+    // Start and metadata of the running session.
+    pub fn active(&self) -> Option<(DateTime<Utc>, &Metadata)> {
+        self.active_period_start.map(|start| (start, &self.active_metadata))
+    }
+
+    // Tracked time on a day, including the running session up to `now`.
+    pub fn tracked_on(&self, day: NaiveDate, now: DateTime<Utc>) -> Duration {
+        let finished = Duration::seconds(self.day(day).map_or(0, |d| d.total));
+        let running = match (self.active_period_start, logic::day_period(day, Zone::default())) {
+            (Some(start), Some(range)) => Period::range(start, now).overlap(&range),
+            _ => Duration::zero(),
+        };
+        finished + running
+    }
+}
+
+pub fn index_path(data_path: &Path) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(".index.json");
+    PathBuf::from(name)
+}
+
+// The cached index, or none if there is none or it is out of date.
+#[instrument(level = "debug")]
+pub fn load(data_path: &Path) -> io::Result<Option<DailyIndex>> {
+    let contents = match fs::read_to_string(index_path(data_path)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let index: DailyIndex = match serde_json::from_str(&contents) {
+        Ok(index) => index,
+        Err(e) => {
+            warn!(error = %e, "ignoring unreadable index");
+            return Ok(None);
+        }
+    };
+    if index.stamp != stamp(data_path)? {
+        debug!("index is out of date");
+        return Ok(None);
+    }
+    Ok(Some(index))
+}
+
+// The cached index, rebuilt from the timesheet if it is missing or out of date.
+pub fn load_or_rebuild(data_path: &Path, options: LoadOptions) -> io::Result<DailyIndex> {
+    if let Some(index) = load(data_path)? {
+        return Ok(index);
+    }
+    let index = DailyIndex::build(&storage::load_or_create_timesheet(data_path, options)?);
+    save(data_path, index)
+}
+
+// Brings the index up to date after a change has been saved. `before` is the index
+// as loaded before saving; `entry` is the change if it was a plain start, stop or
+// switch.
+#[instrument(level = "debug", skip(before, time_sheet))]
+pub fn update(
+    data_path: &Path,
+    before: Option<DailyIndex>,
+    time_sheet: &TimeSheet,
+    entry: Option<&JournalEntry>,
+    now: DateTime<Utc>,
+) -> io::Result<()> {
+    let patched = match (before, entry) {
+        (Some(mut index), Some(JournalEntry::Start { start, metadata, .. })) => {
+            index.active_period_start = Some(*start);
+            index.active_metadata = metadata.clone();
+            Some(index)
+        }
+        // A new period is exactly the session that was running; one that extended
+        // an earlier period would be counted twice, so it falls through.
+        (Some(mut index), Some(JournalEntry::Stop { period })) => match period {
+            Some(period) if index.active_period_start == Some(period.start) => {
+                index.add(period);
+                index.active_period_start = None;
+                index.active_metadata = Metadata::default();
+                Some(index)
+            }
+            Some(_) => None,
+            None => {
+                index.active_period_start = None;
+                index.active_metadata = Metadata::default();
+                Some(index)
+            }
+        },
+        (Some(mut index), Some(JournalEntry::Switch { period, start, metadata, .. })) => {
+            let counted = match period {
+                Some(period) if index.active_period_start == Some(period.start) => {
+                    index.add(period);
+                    true
+                }
+                Some(_) => false,
+                None => true,
+            };
+            index.active_period_start = Some(*start);
+            index.active_metadata = metadata.clone();
+            counted.then_some(index)
+        }
+        _ => None,
+    };
+    let index = patched.unwrap_or_else(|| {
+        debug!("rebuilding index");
+        DailyIndex::build(time_sheet)
+    });
+    save_summary(data_path, &index, now)?;
+    save(data_path, index).map(|_| ())
+}
+
+pub fn summary_path(data_path: &Path) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(".status.json");
+    PathBuf::from(name)
+}
+
+fn save_summary(data_path: &Path, index: &DailyIndex, now: DateTime<Utc>) -> io::Result<StatusSummary> {
+    let day = now.with_timezone(&Local).date_naive();
+    let summary = StatusSummary {
+        active_period_start: index.active_period_start,
+        active_metadata: index.active_metadata.clone(),
+        day,
+        finished: index.day(day).map_or(0, |d| d.total),
+    };
+    let path = summary_path(data_path);
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+    fs::write(&temporary, serde_json::to_string(&summary).map_err(io::Error::other)?)?;
+    fs::rename(&temporary, &path)?;
+    Ok(summary)
+}
+
+// The summary for `status --cached`, or none if there is none yet.
+fn load_summary(data_path: &Path) -> Option<StatusSummary> {
+    let contents = fs::read_to_string(summary_path(data_path)).ok()?;
+    serde_json::from_str(&contents).inspect_err(|e| warn!(error = %e, "ignoring unreadable status summary")).ok()
+}
+
+fn save(data_path: &Path, mut index: DailyIndex) -> io::Result<DailyIndex> {
+    index.stamp = stamp(data_path)?;
+    let contents = serde_json::to_string(&index).map_err(io::Error::other)?;
+    fs::write(index_path(data_path), contents)?;
+    Ok(index)
+}
+
+pub(crate) fn stamp(data_path: &Path) -> io::Result<Stamp> {
+    Ok(Stamp { data: file_stamp(data_path)?, journal: file_stamp(&journal::journal_path(data_path))? })
+}
+
+// Size and latest modification of a file, or of all files below a directory.
+fn file_stamp(path: &Path) -> io::Result<(u64, u128)> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e),
+    };
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    if !metadata.is_dir() {
+        return Ok((metadata.len(), modified));
+    }
+    let (mut size, mut latest) = (0, modified);
+    for entry in fs::read_dir(path)? {
+        let (entry_size, entry_modified) = file_stamp(&entry?.path())?;
+        size += entry_size;
+        latest = latest.max(entry_modified);
+    }
+    Ok((size, latest))
+}
+
+// Handles the "status" command: one line on the running session and today's
+// total, from the index alone, followed by a line per configured budget. With
+// `cached`, only the line, from the status summary; without a summary yet, the
+// index is read and the summary written for the next time.
+pub fn print_status(data_path: &Path, config: &Config, cached: bool, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    let now = clock.now();
+    if cached {
+        let summary = match load_summary(data_path) {
+            Some(summary) => summary,
+            None => save_summary(data_path, &load_or_rebuild(data_path, config.load_options(now))?, now)?,
+        };
+        print_line(summary.active_period_start, &summary.active_metadata, summary.tracked_today(now), now, formatter);
+        return Ok(());
+    }
+    let index = load_or_rebuild(data_path, config.load_options(now))?;
+    let today = index.tracked_on(now.with_timezone(&Local).date_naive(), now);
+    print_line(index.active_period_start, &index.active_metadata, today, now, formatter);
+    let color = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    for budget in budget::usage(&index, &config.budgets, now) {
+        let line = format!(
+            "{}: {} of {} ({:.0}%{})",
+            budget.project,
+            formatter.duration(budget.used),
+            formatter.duration(budget.budget),
+            budget.percent(),
+            budget.state().label()
+        );
+        if color {
+            println!("\x1b[{}m{}\x1b[0m", budget.state().color(), line);
+        } else {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn print_line(active: Option<DateTime<Utc>>, metadata: &Metadata, today: Duration, now: DateTime<Utc>, formatter: &TimeFormatter) {
+    let today = formatter.duration(today);
+    match active {
+        Some(start) => {
+            let details = metadata.describe();
+            println!(
+                "Tracking since {} ({}){}. Today: {}.",
+                formatter.time(start.with_timezone(&Local)),
+                formatter.duration(now - start),
+                if details.is_empty() { String::new() } else { format!(", {}", details) },
+                today
+            );
+        }
+        None => println!("Not tracking. Today: {}.", today),
+    }
+}
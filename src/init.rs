@@ -0,0 +1,132 @@
+use time_tracker::config::{self, Config, WeekStart};
+use time_tracker::prompt::{ask, confirm};
+use chrono::{Local, Offset, Weekday};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+use tracing::{debug, instrument};
+
+// Handles the "init" command: asks for the basic settings, checks that the
+// environment is usable and writes the config file. With `use_defaults` no
+// questions are asked and the current (or default) values are kept.
+#[instrument(level = "debug", skip(config))]
+pub fn run_init(config: &mut Config, data_path: &Path, use_defaults: bool) -> io::Result<()> {
+    let config_path = config::get_config_file_path()?;
+    if config_path.exists() && !use_defaults && !confirm(&format!("{} already exists. Update it?", config_path.display()))? {
+        println!("Nothing changed.");
+        return Ok(());
+    }
+
+    if !use_defaults {
+        config.week_start = ask_week_start(config.week_start)?;
+        config.weekly_target_hours = ask_target_hours(config.weekly_target_hours)?;
+        let data_file = ask("Where should the timesheet be stored?", &data_path.display().to_string())?;
+        config.data_file = Some(data_file.into());
+        let project = ask("Default project (\"none\" for no default)", config.default_project.as_deref().unwrap_or(""))?;
+        config.default_project = match project.as_str() {
+            "" | "none" => None,
+            _ => Some(project),
+        };
+    }
+
+    let data_path = config.data_file.as_deref().map(config::expand_home).unwrap_or_else(|| data_path.to_path_buf());
+    let problems = check_environment(&config_path, &data_path);
+    if !problems.is_empty() {
+        println!("Problems found:");
+        for problem in &problems {
+            println!("  - {}", problem);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Setup is incomplete. Fix the problems above and run 'init' again.",
+        ));
+    }
+
+    config::save_config(config)?;
+    println!("Wrote {}.", config_path.display());
+    println!("Timesheet: {}", data_path.display());
+    println!("Week starts on {}.", config.week_start.0);
+    if let Some(hours) = config.weekly_target_hours {
+        println!("Weekly target: {} hours.", hours);
+    }
+    if let Some(project) = &config.default_project {
+        println!("Default project: {}", project);
+    }
+    println!("You're all set. Run 'start' to begin tracking.");
+    Ok(())
+}
+
+fn ask_week_start(current: WeekStart) -> io::Result<WeekStart> {
+    loop {
+        let answer = ask("First day of the week", &current.0.to_string())?;
+        match answer.parse::<Weekday>() {
+            Ok(day) => return Ok(WeekStart(day)),
+            Err(_) => eprintln!("Please enter a weekday such as Monday or Sun."),
+        }
+    }
+}
+
+fn ask_target_hours(current: Option<f64>) -> io::Result<Option<f64>> {
+    let default = current.map(|h| h.to_string()).unwrap_or_default();
+    loop {
+        let answer = ask("Contracted hours per week (\"none\" for no target)", &default)?;
+        match answer.as_str() {
+            "" | "none" => return Ok(None),
+            _ => match answer.parse::<f64>() {
+                Ok(hours) if (0.0..=168.0).contains(&hours) => return Ok(Some(hours)),
+                _ => eprintln!("Please enter a number of hours between 0 and 168."),
+            },
+        }
+    }
+}
+
+// Checks that the config and data locations are writable and that the local
+// time zone can be determined. Returns a list of problems.
+fn check_environment(config_path: &Path, data_path: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (what, path) in [("config", config_path), ("timesheet", data_path)] {
+        if let Some(dir) = path.parent()
+            && let Err(e) = check_writable(dir)
+        {
+            problems.push(format!("The {} directory {} is not writable: {}", what, dir.display(), e));
+        }
+    }
+
+    let offset = Local::now().offset().fix();
+    match env::var("TZ") {
+        Ok(tz) if !tz.is_empty() && !timezone_exists(&tz) => {
+            problems.push(format!("TZ is set to '{}', which is not a known time zone.", tz));
+        }
+        Ok(tz) if !tz.is_empty() => println!("Time zone: {} (UTC{})", tz, offset),
+        _ => println!("Time zone: system default (UTC{})", offset),
+    }
+    problems
+}
+
+// Creates the directory if needed and tries to create a file in it.
+fn check_writable(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".work_time_tracker.probe");
+    OpenOptions::new().write(true).create(true).truncate(true).open(&probe)?;
+    fs::remove_file(&probe)?;
+    debug!(dir = %dir.display(), "directory is writable");
+    Ok(())
+}
+
+// TZ values are either POSIX rules (e.g. "CET-1CEST") or names from the zoneinfo
+// database. Only names can be checked; rules are accepted as they are.
+fn timezone_exists(tz: &str) -> bool {
+    let name = tz.trim_start_matches(':');
+    if name.starts_with('/') {
+        return Path::new(name).exists();
+    }
+    let looks_like_name = name.contains('/') || name.eq_ignore_ascii_case("UTC");
+    if !looks_like_name {
+        return true;
+    }
+    ["/usr/share/zoneinfo", "/usr/lib/zoneinfo", "/usr/share/lib/zoneinfo"]
+        .iter()
+        .any(|dir| Path::new(dir).join(name).exists())
+        || name.eq_ignore_ascii_case("UTC")
+}
@@ -0,0 +1,239 @@
+// Publishes finished sessions as events to a CalDAV calendar collection.
+//
+// Every period becomes `<collection>/<period id>.ics`. What was uploaded is
+// remembered in `<data file>.caldav.json` as a hash of each event, so unchanged
+// periods are skipped, edited ones are uploaded again and deleted ones are removed
+// from the calendar. Changing the collection URL starts over.
+
+use crate::config::CaldavConfig;
+use crate::secrets;
+use crate::{Period, TimeSheet};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, instrument, warn};
+
+const PASSWORD_VARIABLE: &str = "WORK_TIME_TRACKER_CALDAV_PASSWORD";
+
+// What has been published to which calendar.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SyncState {
+    calendar: String,
+    // Period ID to the SHA-256 of the uploaded event.
+    events: BTreeMap<String, String>,
+}
+
+// Handles `sync caldav`. With `dry_run`, only reports what would be done.
+#[instrument(level = "debug", skip(time_sheet, caldav))]
+pub fn sync(time_sheet: &TimeSheet, caldav: &CaldavConfig, data_path: &Path, dry_run: bool) -> io::Result<()> {
+    let state_path = state_path(data_path);
+    let mut state = load_state(&state_path, &caldav.url)?;
+    // A dry run never contacts the server, so it needs no password.
+    let client = if dry_run { None } else { Some(Client::new(caldav)?) };
+
+    let (mut uploaded, mut unchanged, mut removed, mut failed) = (0, 0, 0, 0);
+    for period in time_sheet.periods() {
+        let id = period.id().to_string();
+        let ics = event_ics(period);
+        let hash = hash(&ics);
+        if state.events.get(&id) == Some(&hash) {
+            unchanged += 1;
+            continue;
+        }
+        let Some(client) = &client else {
+            println!("Would upload {} ({}).", id, period.start().format("%Y-%m-%d %H:%M"));
+            uploaded += 1;
+            continue;
+        };
+        match client.put(&id, &ics) {
+            Ok(()) => {
+                state.events.insert(id, hash);
+                uploaded += 1;
+            }
+            Err(e) => {
+                warn!(%id, error = %e, "could not upload event");
+                eprintln!("Could not upload {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+
+    let current: Vec<String> = time_sheet.periods().iter().map(|p| p.id().to_string()).collect();
+    let gone: Vec<String> = state.events.keys().filter(|id| !current.contains(id)).cloned().collect();
+    for id in gone {
+        let Some(client) = &client else {
+            println!("Would remove {}.", id);
+            removed += 1;
+            continue;
+        };
+        match client.delete(&id) {
+            Ok(()) => {
+                state.events.remove(&id);
+                removed += 1;
+            }
+            Err(e) => {
+                warn!(%id, error = %e, "could not remove event");
+                eprintln!("Could not remove {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if !dry_run {
+        save_state(&state_path, &state)?;
+    }
+    info!(uploaded, unchanged, removed, failed, "synced with CalDAV");
+    let verb = if dry_run { "Would upload" } else { "Uploaded" };
+    println!("{} {} event(s), {} unchanged, {} removed.", verb, uploaded, unchanged, removed);
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} event(s) could not be synced.", failed)));
+    }
+    Ok(())
+}
+
+// The event for a period. DTSTAMP is the period's end, not the upload time, so the
+// same period always gives the same text and hash.
+pub fn event_ics(period: &Period) -> String {
+    let metadata = period.metadata();
+    let mut summary = metadata.project.clone().unwrap_or_else(|| "Work".to_string());
+    if let Some(note) = &metadata.note {
+        summary.push_str(": ");
+        summary.push_str(note);
+    }
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//work_time_tracker//EN".to_string(),
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}@work_time_tracker", period.id()),
+        format!("DTSTAMP:{}", ics_time(period.end())),
+        format!("DTSTART:{}", ics_time(period.start())),
+        format!("DTEND:{}", ics_time(period.end())),
+        format!("SUMMARY:{}", escape(&summary)),
+    ];
+    if !metadata.tags.is_empty() {
+        let tags: Vec<String> = metadata.tags.iter().map(|t| escape(t)).collect();
+        lines.push(format!("CATEGORIES:{}", tags.join(",")));
+    }
+    lines.push("TRANSP:OPAQUE".to_string());
+    lines.push("END:VEVENT".to_string());
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+fn ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(';', "\\;").replace(',', "\\,").replace('\n', "\\n")
+}
+
+// Lines longer than 75 bytes are continued on the next line after a space.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+fn hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn state_path(data_path: &Path) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(".caldav.json");
+    PathBuf::from(name)
+}
+
+fn load_state(path: &Path, calendar: &str) -> io::Result<SyncState> {
+    let state = match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str::<SyncState>(&contents).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid sync state {}: {}", path.display(), e))
+        })?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => SyncState::default(),
+        Err(e) => return Err(e),
+    };
+    if state.calendar != calendar {
+        debug!(previous = %state.calendar, calendar, "calendar changed, starting a new sync");
+        return Ok(SyncState { calendar: calendar.to_string(), events: BTreeMap::new() });
+    }
+    Ok(state)
+}
+
+fn save_state(path: &Path, state: &SyncState) -> io::Result<()> {
+    let contents = serde_json::to_string_pretty(state).map_err(io::Error::other)?;
+    fs::write(path, contents)
+}
+
+// Minimal CalDAV client: plain PUT and DELETE of calendar resources.
+struct Client {
+    agent: ureq::Agent,
+    collection: String,
+    authorization: String,
+}
+
+impl Client {
+    fn new(caldav: &CaldavConfig) -> io::Result<Client> {
+        // The environment wins over the config, which wins over the keyring.
+        let password = match &caldav.password {
+            Some(password) if env::var_os(PASSWORD_VARIABLE).is_none() => password.clone(),
+            _ => secrets::lookup(secrets::CALDAV_PASSWORD, PASSWORD_VARIABLE)?,
+        };
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", caldav.username, password));
+        let collection = format!("{}/", caldav.url.trim_end_matches('/'));
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build();
+        Ok(Client { agent, collection, authorization: format!("Basic {}", credentials) })
+    }
+
+    fn put(&self, id: &str, ics: &str) -> io::Result<()> {
+        let url = format!("{}{}.ics", self.collection, id);
+        debug!(%url, "uploading event");
+        self.agent
+            .put(&url)
+            .set("Authorization", &self.authorization)
+            .set("Content-Type", "text/calendar; charset=utf-8")
+            .send_string(ics)
+            .map(|_| ())
+            .map_err(http_error)
+    }
+
+    fn delete(&self, id: &str) -> io::Result<()> {
+        let url = format!("{}{}.ics", self.collection, id);
+        debug!(%url, "removing event");
+        match self.agent.delete(&url).set("Authorization", &self.authorization).call() {
+            Ok(_) | Err(ureq::Error::Status(404 | 410, _)) => Ok(()),
+            Err(e) => Err(http_error(e)),
+        }
+    }
+}
+
+fn http_error(error: ureq::Error) -> io::Error {
+    match error {
+        ureq::Error::Status(401 | 403, _) => {
+            io::Error::new(io::ErrorKind::PermissionDenied, "The CalDAV server rejected the credentials.")
+        }
+        ureq::Error::Status(code, response) => {
+            io::Error::other(format!("The CalDAV server answered {} {}.", code, response.status_text()))
+        }
+        ureq::Error::Transport(transport) => io::Error::other(transport.to_string()),
+    }
+}
@@ -0,0 +1,31 @@
+// Desktop notifications through the system's own tool: `notify-send` on Linux
+// and other Unix systems, `osascript` on macOS.
+
+use std::io;
+use std::process::Command;
+use tracing::debug;
+
+pub fn notify(title: &str, body: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!("display notification {} with title {}", quoted(body), quoted(title)));
+        command
+    } else if cfg!(windows) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "Desktop notifications are not supported on Windows."));
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name", "Work Time Tracker", title, body]);
+        command
+    };
+    let status = command.status()?;
+    debug!(%status, "sent desktop notification");
+    if !status.success() {
+        return Err(io::Error::other(format!("{:?} failed with {}", command.get_program(), status)));
+    }
+    Ok(())
+}
+
+// An AppleScript string literal.
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,140 @@
+// Fills in what a session was about from the git commits made during it.
+//
+// With `[hooks.git]`, `stop` asks git for the commits of the repository it runs
+// in, or of the configured ones, whose commit time falls within the session. By
+// default only the user's own commits count, as set with `git config user.email`.
+// Their subjects are added to the note and the issue keys they mention, such as
+// "PROJ-123" or "#42", become tags like "issue:PROJ-123". Outside a repository the
+// hook does nothing.
+
+use crate::config::GitHookConfig;
+use crate::Metadata;
+use chrono::{DateTime, Utc};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, instrument};
+
+pub const TAG_PREFIX: &str = "issue:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub hash: String,
+    pub subject: String,
+}
+
+// Adds the commits made between `start` and `end` to `metadata`. Subjects already
+// in the note and tags already set are not added again. Returns how many commits
+// were found.
+#[instrument(level = "debug", skip(git, metadata))]
+pub fn annotate(git: &GitHookConfig, start: DateTime<Utc>, end: DateTime<Utc>, metadata: &mut Metadata) -> io::Result<usize> {
+    let commits = commits(git, start, end)?;
+    if git.subjects {
+        let mut note = metadata.note.take().unwrap_or_default();
+        for commit in &commits {
+            if !note.lines().any(|line| line.trim() == commit.subject) {
+                if !note.is_empty() {
+                    note.push('\n');
+                }
+                note.push_str(&commit.subject);
+            }
+        }
+        metadata.note = (!note.is_empty()).then_some(note);
+    }
+    if git.issue_tags {
+        for key in commits.iter().flat_map(|commit| issue_keys(&commit.subject)) {
+            let tag = format!("{}{}", TAG_PREFIX, key);
+            if !metadata.tags.contains(&tag) {
+                metadata.tags.push(tag);
+            }
+        }
+    }
+    debug!(commits = commits.len(), "added git commits to the session");
+    Ok(commits.len())
+}
+
+// The commits made between `start` and `end` in the configured repositories,
+// without duplicates.
+pub fn commits(git: &GitHookConfig, start: DateTime<Utc>, end: DateTime<Utc>) -> io::Result<Vec<Commit>> {
+    let here = [PathBuf::from(".")];
+    let repositories = if git.repositories.is_empty() { &here[..] } else { &git.repositories[..] };
+    let mut commits: Vec<Commit> = Vec::new();
+    for repository in repositories {
+        for commit in commits_between(repository, git.all_authors, start, end)? {
+            if !commits.iter().any(|c| c.hash == commit.hash) {
+                commits.push(commit);
+            }
+        }
+    }
+    Ok(commits)
+}
+
+// Commits on local branches whose commit time is within the range, oldest first.
+// None outside a git repository.
+fn commits_between(repository: &Path, all_authors: bool, start: DateTime<Utc>, end: DateTime<Utc>) -> io::Result<Vec<Commit>> {
+    if run(repository, &["rev-parse", "--git-dir"]).is_err() {
+        debug!(repository = %repository.display(), "not a git repository");
+        return Ok(Vec::new());
+    }
+    let mut args = vec![
+        "log".to_string(),
+        "--branches".to_string(),
+        "--reverse".to_string(),
+        "--fixed-strings".to_string(),
+        format!("--since={}", start.to_rfc3339()),
+        format!("--until={}", end.to_rfc3339()),
+        "--format=%H%x1f%s".to_string(),
+    ];
+    if !all_authors && let Ok(email) = run(repository, &["config", "user.email"]) {
+        args.push(format!("--author=<{}>", email.trim()));
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = run(repository, &args)?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.split_once('\u{1f}'))
+        .map(|(hash, subject)| Commit { hash: hash.to_string(), subject: subject.trim().to_string() })
+        .collect())
+}
+
+// Issue keys in a commit subject: "PROJ-123" as Jira writes them and "#123" as
+// GitHub and GitLab do.
+fn issue_keys(subject: &str) -> Vec<String> {
+    subject
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '#'))
+        .map(|word| word.trim_matches('-'))
+        .filter(|word| is_jira_key(word) || is_github_key(word))
+        .map(str::to_string)
+        .collect()
+}
+
+fn is_jira_key(word: &str) -> bool {
+    let Some((project, number)) = word.split_once('-') else { return false };
+    project.len() >= 2
+        && project.starts_with(|c: char| c.is_ascii_uppercase())
+        && project.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_github_key(word: &str) -> bool {
+    word.strip_prefix('#').is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn run(repository: &Path, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repository)
+        .args(args)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("Could not run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "'git {}' failed in {}: {}",
+            args.join(" "),
+            repository.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,53 @@
+// Connections to outside services. Each integration is opt-in through its own
+// section in the config and does nothing without it.
+
+pub mod caldav;
+pub mod desktop;
+pub mod git;
+pub mod remote;
+pub mod slack;
+pub mod taskwarrior;
+
+use crate::config::Config;
+use crate::journal::JournalEntry;
+use crate::timefmt::TimeFormatter;
+use crate::TimeSheet;
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+// Runs the hooks that add to the running session just before `stop` or
+// `switch` ends it at `end`, so that what they add is saved with the period. A
+// failing hook is reported and the session stopped as it is.
+pub fn before_stop(config: &Config, time_sheet: &mut TimeSheet, end: DateTime<Utc>) {
+    let Some(start) = time_sheet.active_period_start else { return };
+    if let Some(git) = config.hooks.git.as_ref().filter(|git| git.enabled)
+        && let Err(e) = git::annotate(git, start, end, &mut time_sheet.active_metadata)
+    {
+        warn!(error = %e, "git hook failed");
+        eprintln!("Warning: could not add the git commits to the session: {}", e);
+    }
+}
+
+// Runs the hooks for a `start`, `stop` or `switch` that has been saved. A failing
+// hook is reported but never undoes or fails the change itself.
+pub fn run_hooks(config: &Config, entry: &JournalEntry, formatter: &TimeFormatter) {
+    if let Some(slack) = config.hooks.slack.as_ref().filter(|slack| slack.enabled) {
+        let result = match entry {
+            JournalEntry::Start { metadata, .. } | JournalEntry::Switch { metadata, .. } => {
+                slack::set_status(slack, metadata.project.as_deref())
+            }
+            JournalEntry::Stop { .. } => slack::clear_status(),
+        };
+        if let Err(e) = result {
+            warn!(error = %e, "Slack hook failed");
+            eprintln!("Warning: could not update the Slack status: {}", e);
+        }
+    }
+    if config.hooks.annotate_tasks
+        && let JournalEntry::Stop { period: Some(period) } | JournalEntry::Switch { period: Some(period), .. } = entry
+        && let Err(e) = taskwarrior::annotate(&config.taskwarrior, period, formatter)
+    {
+        warn!(error = %e, "Taskwarrior hook failed");
+        eprintln!("Warning: could not annotate the Taskwarrior task: {}", e);
+    }
+}
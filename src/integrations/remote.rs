@@ -0,0 +1,459 @@
+// Encrypted off-machine backups of the timesheet, pushed as versioned snapshots
+// to a WebDAV collection or an S3 bucket.
+//
+// Every push uploads the whole timesheet as `snapshot-<UTC time>.wtt`, so older
+// snapshots stay restorable until `keep` prunes them. Snapshots are encrypted
+// before they leave the machine: the key is derived with Argon2id from the
+// "remote-passphrase" secret and a random salt, and the JSON is sealed with
+// ChaCha20-Poly1305. A file is the magic bytes, the salt, the nonce and the
+// ciphertext. Without the passphrase the snapshots cannot be read, by the remote
+// or by anyone restoring them, so keep it somewhere besides this machine.
+//
+// Any rclone remote can be used by serving it over WebDAV with
+// `rclone serve webdav remote:path`, or through its S3 gateway.
+
+use crate::clock::Clock;
+use crate::config::{Config, RemoteConfig};
+use crate::logic;
+use crate::prompt;
+use crate::secrets;
+use crate::timefmt::TimeFormatter;
+use crate::TimeSheet;
+use argon2::Argon2;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::io::{self, IsTerminal, Read};
+use std::time::Duration;
+use tracing::{debug, info, instrument, warn};
+
+const PASSWORD_VARIABLE: &str = "WORK_TIME_TRACKER_REMOTE_PASSWORD";
+const PASSPHRASE_VARIABLE: &str = "WORK_TIME_TRACKER_REMOTE_PASSPHRASE";
+
+const MAGIC: &[u8] = b"WTTSNAP1";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const PREFIX: &str = "snapshot-";
+const EXTENSION: &str = ".wtt";
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// Handles `sync remote`: uploads an encrypted snapshot of the timesheet and
+// prunes the oldest ones beyond `keep`.
+#[instrument(level = "debug", skip(time_sheet, remote, formatter, clock))]
+pub fn push(time_sheet: &TimeSheet, remote: &RemoteConfig, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    let passphrase = secrets::lookup(secrets::REMOTE_PASSPHRASE, PASSPHRASE_VARIABLE)?;
+    let client = Client::new(remote)?;
+    let now = clock.now();
+    let name = format!("{}{}{}", PREFIX, now.format(TIME_FORMAT), EXTENSION);
+    let plain = serde_json::to_vec(time_sheet).map_err(io::Error::other)?;
+    client.put(&name, encrypt(&plain, &passphrase)?)?;
+    info!(%name, periods = time_sheet.periods.len(), "pushed snapshot");
+    println!(
+        "Uploaded snapshot {} with {} period(s).",
+        formatter.date_time(now.with_timezone(&Local)),
+        time_sheet.periods.len()
+    );
+
+    let Some(keep) = remote.keep.filter(|keep| *keep > 0) else { return Ok(()) };
+    let snapshots = client.list()?;
+    let excess = snapshots.len().saturating_sub(keep);
+    for (_, old) in &snapshots[..excess] {
+        client.delete(old)?;
+        debug!(name = %old, "pruned snapshot");
+    }
+    if excess > 0 {
+        println!("Removed {} older snapshot(s), keeping {}.", excess, keep);
+    }
+    Ok(())
+}
+
+// Handles `sync pull --list`: prints the snapshots on the remote, oldest first.
+pub fn list(remote: &RemoteConfig, formatter: &TimeFormatter) -> io::Result<()> {
+    let snapshots = Client::new(remote)?.list()?;
+    if snapshots.is_empty() {
+        println!("No snapshots on the remote yet.");
+    }
+    for (time, _) in snapshots {
+        println!("{}", formatter.date_time(time.with_timezone(&Local)));
+    }
+    Ok(())
+}
+
+// Handles `sync pull`: replaces the timesheet with the latest snapshot taken at
+// or before `at`, or the latest one. Asks first on a terminal; elsewhere `yes` is
+// required. Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, remote, formatter, clock))]
+pub fn pull(
+    time_sheet: &mut TimeSheet,
+    remote: &RemoteConfig,
+    at: Option<&str>,
+    yes: bool,
+    config: &Config,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let at = match at {
+        Some(text) => Some(parse_at(text, config, clock)?),
+        None => None,
+    };
+    let client = Client::new(remote)?;
+    let snapshots = client.list()?;
+    let Some((time, name)) = snapshots.iter().rev().find(|(time, _)| at.is_none_or(|at| *time <= at)) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            match at {
+                Some(at) => format!("No snapshot on the remote from {} or earlier.", formatter.date_time(at.with_timezone(&Local))),
+                None => "No snapshots on the remote yet.".to_string(),
+            },
+        ));
+    };
+    let passphrase = secrets::lookup(secrets::REMOTE_PASSPHRASE, PASSPHRASE_VARIABLE)?;
+    let restored: TimeSheet = serde_json::from_slice(&decrypt(&client.get(name)?, &passphrase)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid snapshot {}: {}", name, e)))?;
+
+    let question = format!(
+        "Replace the timesheet ({} period(s)) with the snapshot from {} ({} period(s))?",
+        time_sheet.periods.len(),
+        formatter.date_time(time.with_timezone(&Local)),
+        restored.periods.len()
+    );
+    if !yes {
+        if !io::stdin().is_terminal() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} Pass --yes to confirm.", question)));
+        }
+        if !prompt::confirm(&question)? {
+            println!("Nothing restored.");
+            return Ok(false);
+        }
+    }
+    info!(%name, periods = restored.periods.len(), "restored snapshot");
+    *time_sheet = restored;
+    println!("Restored the snapshot from {}.", formatter.date_time(time.with_timezone(&Local)));
+    Ok(true)
+}
+
+// A local time as for `add`, or a day for the end of it.
+fn parse_at(text: &str, config: &Config, clock: &dyn Clock) -> io::Result<DateTime<Utc>> {
+    if let Ok(time) = logic::parse_local_time(text, clock, config.zone()) {
+        return Ok(time);
+    }
+    let day = crate::gaps::parse_day(text.trim(), clock).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid time '{}'. Expected YYYY-MM-DD, optionally followed by HH:MM.", text),
+        )
+    })?;
+    let next = day.succ_opt().unwrap_or(day);
+    Ok(logic::naive_to_utc(next.and_time(chrono::NaiveTime::MIN), config.zone()) - chrono::Duration::seconds(1))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> io::Result<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::other(format!("Could not derive the snapshot key: {}", e)))?;
+    Ok(key)
+}
+
+fn encrypt(plain: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, plain).map_err(|_| io::Error::other("Could not encrypt the snapshot."))?;
+    Ok([MAGIC, &salt, nonce.as_slice(), &sealed].concat())
+}
+
+fn decrypt(data: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+    let Some(rest) = data.strip_prefix(MAGIC).filter(|rest| rest.len() > SALT_LENGTH + NONCE_LENGTH) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "This is not a snapshot written by 'sync remote'."));
+    };
+    let (salt, rest) = rest.split_at(SALT_LENGTH);
+    let (nonce, sealed) = rest.split_at(NONCE_LENGTH);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    cipher.decrypt(Nonce::from_slice(nonce), sealed).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Could not decrypt the snapshot. Is the remote-passphrase the one it was pushed with?",
+        )
+    })
+}
+
+// The time a snapshot was taken, from its file name.
+fn snapshot_time(name: &str) -> Option<DateTime<Utc>> {
+    let stamp = name.strip_prefix(PREFIX)?.strip_suffix(EXTENSION)?;
+    NaiveDateTime::parse_from_str(stamp, TIME_FORMAT).ok().map(|time| time.and_utc())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Minimal client for the two kinds of remote: plain PUT, GET and DELETE of
+// snapshot files, and listing them.
+struct Client {
+    agent: ureq::Agent,
+    backend: Backend,
+}
+
+enum Backend {
+    WebDav { collection: String, authorization: String },
+    S3 { endpoint: String, host: String, bucket: String, prefix: String, region: String, access_key: String, secret_key: String },
+}
+
+impl Client {
+    fn new(remote: &RemoteConfig) -> io::Result<Client> {
+        // The environment wins over the config, which wins over the keyring.
+        let password = match &remote.password {
+            Some(password) if env::var_os(PASSWORD_VARIABLE).is_none() => password.clone(),
+            _ => secrets::lookup(secrets::REMOTE_PASSWORD, PASSWORD_VARIABLE)?,
+        };
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(60)).build();
+        let backend = if let Some(location) = remote.url.strip_prefix("s3://") {
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            let endpoint = remote.endpoint.clone().unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", remote.region));
+            let endpoint = endpoint.trim_end_matches('/').to_string();
+            let host = endpoint.split_once("://").map_or(endpoint.as_str(), |(_, rest)| rest).split('/').next().unwrap_or("").to_string();
+            let prefix = prefix.trim_matches('/');
+            Backend::S3 {
+                host,
+                endpoint,
+                bucket: bucket.to_string(),
+                prefix: if prefix.is_empty() { String::new() } else { format!("{}/", prefix) },
+                region: remote.region.clone(),
+                access_key: remote.username.clone(),
+                secret_key: password,
+            }
+        } else if remote.url.starts_with("https://") || remote.url.starts_with("http://") {
+            let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", remote.username, password));
+            Backend::WebDav {
+                collection: format!("{}/", remote.url.trim_end_matches('/')),
+                authorization: format!("Basic {}", credentials),
+            }
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported remote '{}'. Use an http(s) WebDAV URL or s3://bucket/prefix.", remote.url),
+            ));
+        };
+        Ok(Client { agent, backend })
+    }
+
+    fn put(&self, name: &str, data: Vec<u8>) -> io::Result<()> {
+        debug!(name, bytes = data.len(), "uploading snapshot");
+        match &self.backend {
+            Backend::WebDav { collection, authorization } => {
+                let url = format!("{}{}", collection, name);
+                let request = self.agent.put(&url).set("Authorization", authorization).set("Content-Type", "application/octet-stream");
+                match request.clone().send_bytes(&data) {
+                    // The collection does not exist yet.
+                    Err(ureq::Error::Status(404 | 409, _)) => {
+                        self.agent.request("MKCOL", collection).set("Authorization", authorization).call().map_err(http_error)?;
+                        request.send_bytes(&data).map(|_| ()).map_err(http_error)
+                    }
+                    result => result.map(|_| ()).map_err(http_error),
+                }
+            }
+            Backend::S3 { prefix, .. } => {
+                self.s3_request("PUT", &format!("{}{}", prefix, name), &[], &data)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn get(&self, name: &str) -> io::Result<Vec<u8>> {
+        debug!(name, "downloading snapshot");
+        let response = match &self.backend {
+            Backend::WebDav { collection, authorization } => self
+                .agent
+                .get(&format!("{}{}", collection, name))
+                .set("Authorization", authorization)
+                .call()
+                .map_err(http_error)?,
+            Backend::S3 { prefix, .. } => self.s3_request("GET", &format!("{}{}", prefix, name), &[], &[])?,
+        };
+        let mut data = Vec::new();
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        let result = match &self.backend {
+            Backend::WebDav { collection, authorization } => {
+                self.agent.delete(&format!("{}{}", collection, name)).set("Authorization", authorization).call().map(|_| ())
+            }
+            Backend::S3 { prefix, .. } => return self.s3_request("DELETE", &format!("{}{}", prefix, name), &[], &[]).map(|_| ()),
+        };
+        match result {
+            Ok(()) | Err(ureq::Error::Status(404 | 410, _)) => Ok(()),
+            Err(e) => Err(http_error(e)),
+        }
+    }
+
+    // The snapshots on the remote with the time each was taken, oldest first.
+    fn list(&self) -> io::Result<Vec<(DateTime<Utc>, String)>> {
+        let names = match &self.backend {
+            Backend::WebDav { collection, authorization } => {
+                let body = r#"<?xml version="1.0" encoding="utf-8"?><propfind xmlns="DAV:"><prop><resourcetype/></prop></propfind>"#;
+                let response = self
+                    .agent
+                    .request("PROPFIND", collection)
+                    .set("Authorization", authorization)
+                    .set("Depth", "1")
+                    .set("Content-Type", "application/xml; charset=utf-8")
+                    .send_string(body);
+                match response {
+                    Ok(response) => element_texts(&response.into_string()?, "href"),
+                    // Nothing was pushed yet.
+                    Err(ureq::Error::Status(404, _)) => Vec::new(),
+                    Err(e) => return Err(http_error(e)),
+                }
+            }
+            Backend::S3 { prefix, .. } => {
+                let mut names = Vec::new();
+                let mut token: Option<String> = None;
+                loop {
+                    let mut query = vec![("list-type", "2".to_string()), ("prefix", format!("{}{}", prefix, PREFIX))];
+                    if let Some(token) = &token {
+                        query.push(("continuation-token", token.clone()));
+                    }
+                    let text = self.s3_request("GET", "", &query, &[])?.into_string()?;
+                    names.extend(element_texts(&text, "Key"));
+                    token = element_texts(&text, "NextContinuationToken").into_iter().next();
+                    if token.is_none() {
+                        break;
+                    }
+                }
+                names
+            }
+        };
+        let mut snapshots: Vec<(DateTime<Utc>, String)> = names
+            .iter()
+            .filter_map(|path| {
+                let name = path.trim_end_matches('/').rsplit('/').next()?;
+                Some((snapshot_time(name)?, name.to_string()))
+            })
+            .collect();
+        snapshots.sort();
+        snapshots.dedup();
+        Ok(snapshots)
+    }
+
+    // A request to the bucket, signed with AWS Signature Version 4. Objects are
+    // addressed path-style, which S3-compatible servers such as MinIO and rclone's
+    // gateway accept as well.
+    fn s3_request(&self, method: &str, key: &str, query: &[(&str, String)], body: &[u8]) -> io::Result<ureq::Response> {
+        let Backend::S3 { endpoint, host, bucket, region, access_key, secret_key, .. } = &self.backend else {
+            unreachable!("S3 request to a WebDAV remote")
+        };
+        let path = if key.is_empty() {
+            format!("/{}", uri_encode(bucket, false))
+        } else {
+            format!("/{}/{}", uri_encode(bucket, false), uri_encode(key, false))
+        };
+        let mut pairs: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k, true), uri_encode(v, true))).collect();
+        pairs.sort();
+        let canonical_query = pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = sha256_hex(body);
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, canonical_query, host, payload_hash, amz_date, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, sha256_hex(canonical_request.as_bytes()));
+        let mut key = format!("AWS4{}", secret_key).into_bytes();
+        for part in [date.as_str(), region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature: String = hmac_sha256(&key, string_to_sign.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            access_key, scope, signature
+        );
+
+        let mut url = format!("{}{}", endpoint, path);
+        if !canonical_query.is_empty() {
+            url.push('?');
+            url.push_str(&canonical_query);
+        }
+        debug!(method, %url, "S3 request");
+        self.agent
+            .request(method, &url)
+            .set("Authorization", &authorization)
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("x-amz-date", &amz_date)
+            .send_bytes(body)
+            .map_err(http_error)
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Percent-encoding as S3 signatures require: everything but unreserved
+// characters, and slashes too unless they separate path segments.
+fn uri_encode(text: &str, encode_slash: bool) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// The text of every element with the given local name, whatever its namespace
+// prefix. Enough for the PROPFIND and ListObjectsV2 answers, which carry names
+// without nested markup.
+fn element_texts(xml: &str, name: &str) -> Vec<String> {
+    let mut texts = Vec::new();
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find('>') else { break };
+        let tag = &rest[..close];
+        rest = &rest[close + 1..];
+        let local = tag.split_whitespace().next().unwrap_or("").rsplit(':').next().unwrap_or("");
+        if local != name || tag.ends_with('/') {
+            continue;
+        }
+        let Some(end) = rest.find("</") else { break };
+        texts.push(unescape(&rest[..end]));
+        rest = &rest[end..];
+    }
+    texts
+}
+
+fn unescape(text: &str) -> String {
+    text.trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn http_error(error: ureq::Error) -> io::Error {
+    match error {
+        ureq::Error::Status(401 | 403, _) => {
+            io::Error::new(io::ErrorKind::PermissionDenied, "The remote rejected the credentials.")
+        }
+        ureq::Error::Status(code, response) => {
+            warn!(code, "remote request failed");
+            io::Error::other(format!("The remote answered {} {}.", code, response.status_text()))
+        }
+        ureq::Error::Transport(transport) => io::Error::other(transport.to_string()),
+    }
+}
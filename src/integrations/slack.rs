@@ -0,0 +1,61 @@
+// Shows the running session as the Slack status.
+//
+// `start` sets the configured text and emoji, `stop` clears them again. The API
+// token is the "slack-token" secret, or WORK_TIME_TRACKER_SLACK_TOKEN in the
+// environment. It needs the `users.profile:write` scope.
+//
+// Store it with `secret set slack-token`.
+
+use crate::config::SlackHookConfig;
+use crate::secrets;
+use serde_json::{json, Value};
+use std::io;
+use std::time::Duration;
+use tracing::{debug, instrument};
+
+const API_URL: &str = "https://slack.com/api/users.profile.set";
+const TOKEN_VARIABLE: &str = "WORK_TIME_TRACKER_SLACK_TOKEN";
+
+// Sets the status for a session on `project`.
+#[instrument(level = "debug", skip(slack))]
+pub fn set_status(slack: &SlackHookConfig, project: Option<&str>) -> io::Result<()> {
+    set_profile(&status_text(slack, project), &slack.status_emoji)
+}
+
+// Removes the status again.
+#[instrument(level = "debug")]
+pub fn clear_status() -> io::Result<()> {
+    set_profile("", "")
+}
+
+// `status_text` with "{project}" replaced. Without a project the text is just
+// "Working", since a sentence like "Working on {project}" would not make sense.
+fn status_text(slack: &SlackHookConfig, project: Option<&str>) -> String {
+    match project {
+        Some(project) => slack.status_text.replace("{project}", project),
+        None if slack.status_text.contains("{project}") => "Working".to_string(),
+        None => slack.status_text.clone(),
+    }
+}
+
+fn set_profile(text: &str, emoji: &str) -> io::Result<()> {
+    let token = secrets::lookup(secrets::SLACK_TOKEN, TOKEN_VARIABLE)?;
+    let body = json!({ "profile": { "status_text": text, "status_emoji": emoji, "status_expiration": 0 } });
+    debug!(text, emoji, "updating Slack status");
+    let response = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .post(API_URL)
+        .set("Authorization", &format!("Bearer {}", token))
+        .set("Content-Type", "application/json; charset=utf-8")
+        .send_string(&body.to_string())
+        .map_err(|e| io::Error::other(format!("Could not reach Slack: {}", e)))?;
+
+    // Slack answers 200 even for failures and reports them in the body.
+    let answer: Value = serde_json::from_str(&response.into_string()?).map_err(io::Error::other)?;
+    if answer["ok"].as_bool() != Some(true) {
+        let error = answer["error"].as_str().unwrap_or("unknown error");
+        return Err(io::Error::other(format!("Slack rejected the status update: {}", error)));
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+// Links sessions to Taskwarrior tasks.
+//
+// `start --task 42` looks the task up with `task export` and starts a session with
+// the task's project, its description as the note and a `task:<uuid>` tag. With
+// `annotate_tasks` in `[hooks]`, `stop` adds the session's duration to the task as
+// an annotation.
+
+use crate::config::TaskwarriorConfig;
+use crate::timefmt::TimeFormatter;
+use crate::{Metadata, Period};
+use serde::Deserialize;
+use std::io;
+use std::process::Command;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+pub const TAG_PREFIX: &str = "task:";
+
+#[derive(Deserialize, Debug)]
+struct Task {
+    uuid: String,
+    description: String,
+    #[serde(default)]
+    project: Option<String>,
+}
+
+// Adds the task's details to `metadata`. Values given on the command line win,
+// and the task tag is added to any given tags.
+#[instrument(level = "debug", skip(taskwarrior, metadata))]
+pub fn with_task(taskwarrior: &TaskwarriorConfig, id: &str, metadata: Metadata) -> io::Result<Metadata> {
+    let task = find_task(taskwarrior, id)?;
+    let tag = format!("{}{}", TAG_PREFIX, task.uuid);
+    let mut metadata = metadata.or(&Metadata { project: task.project, note: Some(task.description), ..Metadata::default() });
+    if !metadata.tags.contains(&tag) {
+        metadata.tags.push(tag);
+    }
+    Ok(metadata)
+}
+
+fn find_task(taskwarrior: &TaskwarriorConfig, id: &str) -> io::Result<Task> {
+    if id.is_empty() || id.starts_with('-') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid task '{}'.", id)));
+    }
+    let output = run(taskwarrior, &["rc.verbose=nothing", id, "export"])?;
+    let mut tasks: Vec<Task> = serde_json::from_str(&output).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Could not read the output of task export: {}", e))
+    })?;
+    match tasks.len() {
+        1 => Ok(tasks.remove(0)),
+        0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("No Taskwarrior task '{}'.", id))),
+        n => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' matches {} tasks; give a single task ID or UUID.", id, n),
+        )),
+    }
+}
+
+// Annotates every task the period is tagged with, e.g. "Worked 01:15:00". Only
+// tags holding a UUID count: anything else after "task:" would be read by
+// Taskwarrior as a filter and could annotate many tasks.
+#[instrument(level = "debug", skip(taskwarrior, period, formatter))]
+pub fn annotate(taskwarrior: &TaskwarriorConfig, period: &Period, formatter: &TimeFormatter) -> io::Result<()> {
+    let annotation = format!("Worked {}", formatter.duration(period.duration()));
+    for tag in period.metadata.tags.iter().filter_map(|tag| tag.strip_prefix(TAG_PREFIX)) {
+        let Ok(uuid) = Uuid::parse_str(tag) else {
+            debug!(tag, "not a task UUID, not annotating");
+            continue;
+        };
+        let uuid = uuid.to_string();
+        run(taskwarrior, &["rc.verbose=nothing", "rc.confirmation=off", &uuid, "annotate", &annotation])?;
+        debug!(uuid, annotation, "annotated task");
+    }
+    Ok(())
+}
+
+fn run(taskwarrior: &TaskwarriorConfig, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(&taskwarrior.command).args(args).output().map_err(|e| {
+        io::Error::new(e.kind(), format!("Could not run '{}': {}", taskwarrior.command, e))
+    })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "'{} {}' failed: {}",
+            taskwarrior.command,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,471 @@
+// The monthly invoice summary: tracked hours per project times the `[rates]` of
+// the project, with subtotals per currency and a grand total converted into the
+// invoice currency with `[invoice.exchange_rates]`. Time in a `[schedule]`
+// category with a surcharge, such as evening or holiday hours, is billed on a line
+// of its own at the rate times the multiplier. Projects without a rate are billed
+// at the rate of the `[[contracts]]` entry in effect when the time was tracked.
+//
+// Amounts are decimals, never floats. Each line's net amount and VAT are rounded
+// to cents (half away from zero) before anything is added up, so the numbers can
+// be pasted into an invoice and sum up exactly. All currencies are taken to have
+// cents.
+
+use crate::absence;
+use crate::categories::{self, Category};
+use crate::clock::Clock;
+use crate::closing;
+use crate::config::Config;
+use crate::logic;
+use crate::report::{PeriodRow, Report};
+use crate::timefmt::TimeFormatter;
+use crate::TimeSheet;
+use chrono::{Duration, Local, NaiveDate};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::{Add, AddAssign};
+use tracing::{debug, instrument};
+
+// Net amount, VAT and their sum, in one currency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Amounts {
+    pub net: Decimal,
+    pub vat: Decimal,
+    pub gross: Decimal,
+}
+
+impl Amounts {
+    // Rounds the net amount to cents and adds the VAT on it, also in cents.
+    pub fn with_vat(net: Decimal, vat_percent: Decimal) -> Amounts {
+        let net = cents(net);
+        let vat = cents(net * vat_percent / Decimal::ONE_HUNDRED);
+        Amounts { net, vat, gross: net + vat }
+    }
+
+    // Converted with an exchange rate; each part is rounded, the sum kept exact.
+    fn converted(&self, rate: Decimal) -> Amounts {
+        let (net, vat) = (cents(self.net * rate), cents(self.vat * rate));
+        Amounts { net, vat, gross: net + vat }
+    }
+}
+
+impl Add for Amounts {
+    type Output = Amounts;
+
+    fn add(self, other: Amounts) -> Amounts {
+        Amounts { net: self.net + other.net, vat: self.vat + other.vat, gross: self.gross + other.gross }
+    }
+}
+
+impl AddAssign for Amounts {
+    fn add_assign(&mut self, other: Amounts) {
+        *self = *self + other;
+    }
+}
+
+fn cents(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+// Config values are floats; read them as the decimal they were written as.
+fn decimal(value: f64) -> Decimal {
+    Decimal::from_f64(value).unwrap_or_default()
+}
+
+// A rate multiplier for the time in one category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Surcharge {
+    pub category: Category,
+    pub multiplier: Decimal,
+}
+
+#[derive(Debug, Clone)]
+pub struct InvoiceLine {
+    pub project: String,
+    /// `None` for the time billed at the plain rate.
+    pub surcharge: Option<Surcharge>,
+    pub tracked: Duration,
+    /// The rate with the surcharge applied.
+    pub hourly: Decimal,
+    pub vat_percent: Decimal,
+    pub currency: String,
+    pub amounts: Amounts,
+}
+
+#[derive(Debug, Clone)]
+pub struct Invoice {
+    /// `YYYY-MM`.
+    pub month: String,
+    pub lines: Vec<InvoiceLine>,
+    /// Sum of the lines per currency.
+    pub subtotals: BTreeMap<String, Amounts>,
+    /// The subtotals converted into `currency`, unless an exchange rate is missing.
+    pub total: Option<Amounts>,
+    pub currency: String,
+    /// Currencies without an exchange rate.
+    pub unconverted: Vec<String>,
+    /// Tracked time of projects without a rate, periods without a project as "".
+    pub unbilled: Vec<(String, Duration)>,
+}
+
+// Time of one project billed at one rate.
+struct Charge<'a> {
+    project: &'a str,
+    hourly: Decimal,
+    currency: String,
+    vat_percent: Decimal,
+    surcharges: &'a BTreeMap<Category, f64>,
+    periods: Vec<&'a PeriodRow>,
+    total: Duration,
+}
+
+// Bills the projects of the report with their rates. Projects without a rate of
+// their own are billed at the rate of the contract in effect on the day each
+// period started. Fails if there are surcharges but no `[schedule]` to classify
+// the time with.
+pub fn build(report: &Report, config: &Config) -> io::Result<Invoice> {
+    let mut lines = Vec::new();
+    let mut unbilled = Vec::new();
+    for row in &report.projects {
+        let project = row.project.clone().unwrap_or_default();
+        let periods = report.periods.iter().filter(|p| p.period.metadata.project == row.project);
+        let mut charges = Vec::new();
+        if let Some(rate) = config.rates.get(&project) {
+            charges.push(Charge {
+                project: &project,
+                hourly: decimal(rate.hourly),
+                currency: rate.currency.clone().unwrap_or_else(|| config.invoice.currency.clone()),
+                vat_percent: decimal(rate.vat_percent.unwrap_or(config.invoice.vat_percent)),
+                surcharges: rate.surcharges.as_ref().unwrap_or(&config.invoice.surcharges),
+                periods: periods.collect(),
+                total: row.total,
+            });
+        } else {
+            // One charge per contract, in the order they started.
+            let mut by_contract: BTreeMap<NaiveDate, Charge> = BTreeMap::new();
+            let mut uncovered = Duration::zero();
+            for period in periods {
+                let contract = absence::contract_on(config, config.zone().date(period.period.start));
+                let Some((contract, hourly)) = contract.and_then(|contract| Some((contract, contract.rate?))) else {
+                    uncovered += period.duration;
+                    continue;
+                };
+                let charge = by_contract.entry(contract.start).or_insert_with(|| Charge {
+                    project: &project,
+                    hourly: decimal(hourly),
+                    currency: config.invoice.currency.clone(),
+                    vat_percent: decimal(config.invoice.vat_percent),
+                    surcharges: &config.invoice.surcharges,
+                    periods: Vec::new(),
+                    total: Duration::zero(),
+                });
+                charge.periods.push(period);
+                charge.total += period.duration;
+            }
+            if uncovered > Duration::zero() {
+                unbilled.push((project.clone(), uncovered));
+            }
+            charges.extend(by_contract.into_values());
+        }
+        for charge in &charges {
+            lines.extend(bill(charge, report, config)?);
+        }
+    }
+
+    let mut subtotals: BTreeMap<String, Amounts> = BTreeMap::new();
+    for line in &lines {
+        *subtotals.entry(line.currency.clone()).or_default() += line.amounts;
+    }
+    let mut total = Amounts::default();
+    let mut unconverted = Vec::new();
+    for (currency, subtotal) in &subtotals {
+        if *currency == config.invoice.currency {
+            total += *subtotal;
+        } else if let Some(rate) = config.invoice.exchange_rates.get(currency) {
+            total += subtotal.converted(decimal(*rate));
+        } else {
+            unconverted.push(currency.clone());
+        }
+    }
+
+    Ok(Invoice {
+        month: report.range.start().with_timezone(&Local).format("%Y-%m").to_string(),
+        lines,
+        subtotals,
+        total: unconverted.is_empty().then_some(total),
+        currency: config.invoice.currency.clone(),
+        unconverted,
+        unbilled,
+    })
+}
+
+// The lines of a charge: one at the plain rate, and one for each surcharge.
+fn bill(charge: &Charge, report: &Report, config: &Config) -> io::Result<Vec<InvoiceLine>> {
+    let mut lines = Vec::new();
+    for (surcharge, tracked) in split_by_surcharge(charge, report, config)? {
+        let hourly = charge.hourly * surcharge.map_or(Decimal::ONE, |s| s.multiplier);
+        let hours = Decimal::from(tracked.num_seconds()) / Decimal::from(3600);
+        lines.push(InvoiceLine {
+            project: charge.project.to_string(),
+            surcharge,
+            tracked,
+            hourly,
+            vat_percent: charge.vat_percent,
+            currency: charge.currency.clone(),
+            amounts: Amounts::with_vat(hours * hourly, charge.vat_percent),
+        });
+    }
+    Ok(lines)
+}
+
+// The time of a charge split by surcharge: the time at the plain rate, then that
+// of each surcharged category with time. Multipliers of 1 are no surcharge.
+fn split_by_surcharge(charge: &Charge, report: &Report, config: &Config) -> io::Result<Vec<(Option<Surcharge>, Duration)>> {
+    let surcharges: Vec<Surcharge> = charge
+        .surcharges
+        .iter()
+        .map(|(category, multiplier)| Surcharge { category: *category, multiplier: decimal(*multiplier) })
+        .filter(|surcharge| surcharge.multiplier != Decimal::ONE)
+        .collect();
+    if surcharges.is_empty() {
+        return Ok(vec![(None, charge.total)]);
+    }
+    let Some(schedule) = &config.schedule else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Surcharges need a [schedule] in the config to tell evening, night, weekend and holiday hours apart.",
+        ));
+    };
+    let mut by_category: BTreeMap<Category, Duration> = BTreeMap::new();
+    for period in &charge.periods {
+        let Some(counted) = logic::attributed_range(&period.period, &report.range, config.month_attribution, report.zone) else {
+            continue;
+        };
+        for (category, duration) in categories::split(schedule, &period.period, &counted, report.zone) {
+            *by_category.entry(category).or_insert_with(Duration::zero) += duration;
+        }
+    }
+    let surcharged: Vec<(Option<Surcharge>, Duration)> = surcharges
+        .into_iter()
+        .filter_map(|surcharge| Some((Some(surcharge), *by_category.get(&surcharge.category)?)))
+        .filter(|(_, tracked)| *tracked > Duration::zero())
+        .collect();
+    let plain = charge.total - surcharged.iter().map(|(_, tracked)| *tracked).sum::<Duration>();
+    let mut split = Vec::new();
+    if plain > Duration::zero() || surcharged.is_empty() {
+        split.push((None, plain));
+    }
+    split.extend(surcharged);
+    Ok(split)
+}
+
+// Handles `invoice [--month YYYY-MM]`, by default for the current month.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn show_invoice(
+    time_sheet: &TimeSheet,
+    config: &Config,
+    month: Option<&str>,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<()> {
+    let (year, month) = closing::month_or_current(month, clock)?;
+    let range = logic::month_period(year, month, config.zone());
+    let report = logic::build_report(time_sheet, config, "month", &range, clock.now());
+    let invoice = build(&report, config)?;
+    debug!(lines = invoice.lines.len(), currencies = invoice.subtotals.len(), "built invoice");
+    print(&invoice, formatter);
+    Ok(())
+}
+
+pub fn print(invoice: &Invoice, formatter: &TimeFormatter) {
+    println!("Invoice summary for {}", invoice.month);
+    if invoice.lines.is_empty() {
+        println!("No billable time. Set hourly rates per project under [rates] or per contract under [[contracts]].");
+    }
+    for line in &invoice.lines {
+        let name = match line.surcharge {
+            Some(surcharge) => format!("{} ({} x{})", line.project, surcharge.category.name(), surcharge.multiplier.normalize()),
+            None => line.project.clone(),
+        };
+        println!(
+            "  {:<20} {:>10}  x {:>8.2} {}  net {:>10.2}  VAT {:>5}% {:>9.2}  gross {:>10.2} {}",
+            name,
+            formatter.duration(line.tracked),
+            line.hourly,
+            line.currency,
+            line.amounts.net,
+            line.vat_percent.normalize(),
+            line.amounts.vat,
+            line.amounts.gross,
+            line.currency
+        );
+    }
+    if invoice.subtotals.len() > 1 || invoice.subtotals.keys().any(|c| *c != invoice.currency) {
+        for (currency, subtotal) in &invoice.subtotals {
+            println!("Subtotal {}: {}", currency, describe(subtotal));
+        }
+    }
+    match &invoice.total {
+        _ if invoice.lines.is_empty() => {}
+        Some(total) => println!("Total {}: {}", invoice.currency, describe(total)),
+        None => println!(
+            "No total in {}: missing exchange rate for {} under [invoice.exchange_rates].",
+            invoice.currency,
+            invoice.unconverted.join(", ")
+        ),
+    }
+    for (project, tracked) in &invoice.unbilled {
+        let name = if project.is_empty() { "(no project)" } else { project.as_str() };
+        println!("Not billed, no rate: {} ({})", name, formatter.duration(*tracked));
+    }
+}
+
+fn describe(amounts: &Amounts) -> String {
+    format!("net {:.2}, VAT {:.2}, gross {:.2}", amounts.net, amounts.vat, amounts.gross)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MonthAttribution, Rate, ScheduleConfig};
+    use crate::{Metadata, Period};
+    use chrono::{DateTime, Utc};
+
+    fn dec(text: &str) -> Decimal {
+        text.parse().unwrap()
+    }
+
+    fn amounts(net: &str, vat: &str, gross: &str) -> Amounts {
+        Amounts { net: dec(net), vat: dec(vat), gross: dec(gross) }
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    #[test]
+    fn with_vat_rounds_half_cents_away_from_zero() {
+        assert_eq!(Amounts::with_vat(dec("10.005"), Decimal::ZERO), amounts("10.01", "0", "10.01"));
+        assert_eq!(Amounts::with_vat(dec("10.004"), Decimal::ZERO), amounts("10.00", "0", "10.00"));
+        assert_eq!(Amounts::with_vat(dec("-10.005"), Decimal::ZERO), amounts("-10.01", "0", "-10.01"));
+        // 0.05 * 19% = 0.0095
+        assert_eq!(Amounts::with_vat(dec("0.05"), dec("19")), amounts("0.05", "0.01", "0.06"));
+    }
+
+    #[test]
+    fn with_vat_charges_vat_on_the_rounded_net_amount() {
+        // On the unrounded 0.025 the VAT would be 0.00475, rounded to 0.00.
+        assert_eq!(Amounts::with_vat(dec("0.025"), dec("19")), amounts("0.03", "0.01", "0.04"));
+        assert_eq!(Amounts::with_vat(dec("100"), dec("19")), amounts("100", "19", "119"));
+        assert_eq!(Amounts::with_vat(dec("126.6666"), dec("19")), amounts("126.67", "24.07", "150.74"));
+    }
+
+    #[test]
+    fn with_vat_at_zero_percent_is_the_net_amount() {
+        let zero = Amounts::with_vat(dec("123.456"), Decimal::ZERO);
+        assert_eq!(zero, amounts("123.46", "0", "123.46"));
+        assert_eq!(zero.gross, zero.net);
+    }
+
+    #[test]
+    fn converted_rounds_each_part_and_adds_them_up() {
+        // Converting the gross amount directly would give 0.03.
+        let converted = amounts("0.15", "0.15", "0.30").converted(dec("0.1"));
+        assert_eq!(converted, amounts("0.02", "0.02", "0.04"));
+        let converted = Amounts::with_vat(dec("200"), dec("19")).converted(dec("0.92"));
+        assert_eq!(converted, amounts("184.00", "34.96", "218.96"));
+    }
+
+    fn rate(hourly: f64, currency: Option<&str>, vat_percent: Option<f64>) -> Rate {
+        Rate { hourly, currency: currency.map(str::to_string), vat_percent, surcharges: None }
+    }
+
+    fn period(start: &str, end: &str, project: &str) -> Period {
+        Period::range(utc(start), utc(end)).with_metadata(Metadata { project: Some(project.to_string()), ..Metadata::default() })
+    }
+
+    #[test]
+    fn subtotals_and_total_are_exact_sums_of_the_lines() {
+        let mut config = Config { time_zone: Some(chrono_tz::UTC), ..Config::default() };
+        config.invoice.vat_percent = 19.0;
+        config.invoice.exchange_rates.insert("USD".to_string(), 0.92);
+        config.rates.insert("a".to_string(), rate(95.0, None, None));
+        config.rates.insert("b".to_string(), rate(60.0, None, Some(0.0)));
+        config.rates.insert("c".to_string(), rate(100.0, Some("USD"), None));
+        let time_sheet = TimeSheet {
+            periods: vec![
+                period("2024-05-02T09:00:00Z", "2024-05-02T10:20:00Z", "a"),
+                period("2024-05-02T11:00:00Z", "2024-05-02T11:50:00Z", "b"),
+                period("2024-05-03T09:00:00Z", "2024-05-03T11:00:00Z", "c"),
+            ],
+            ..TimeSheet::default()
+        };
+        let range = logic::month_period(2024, 5, config.zone());
+        let report = logic::build_report(&time_sheet, &config, "month", &range, utc("2024-06-01T00:00:00Z"));
+        let invoice = build(&report, &config).unwrap();
+
+        let line = |project: &str| invoice.lines.iter().find(|line| line.project == project).unwrap().amounts;
+        assert_eq!(line("a"), amounts("126.67", "24.07", "150.74"));
+        assert_eq!(line("b"), amounts("50.00", "0", "50.00"));
+        assert_eq!(line("c"), amounts("200", "38", "238"));
+        for line in &invoice.lines {
+            assert_eq!(line.amounts.gross, line.amounts.net + line.amounts.vat);
+        }
+
+        assert_eq!(invoice.subtotals["EUR"], line("a") + line("b"));
+        assert_eq!(invoice.subtotals["EUR"], amounts("176.67", "24.07", "200.74"));
+        assert_eq!(invoice.subtotals["USD"], line("c"));
+        let total = invoice.total.unwrap();
+        assert_eq!(total, invoice.subtotals["EUR"] + invoice.subtotals["USD"].converted(dec("0.92")));
+        assert_eq!(total, amounts("360.67", "59.03", "419.70"));
+        assert_eq!(total.gross, total.net + total.vat);
+    }
+
+    #[test]
+    fn no_total_without_an_exchange_rate() {
+        let mut config = Config { time_zone: Some(chrono_tz::UTC), ..Config::default() };
+        config.rates.insert("c".to_string(), rate(100.0, Some("USD"), None));
+        let time_sheet =
+            TimeSheet { periods: vec![period("2024-05-03T09:00:00Z", "2024-05-03T10:00:00Z", "c")], ..TimeSheet::default() };
+        let range = logic::month_period(2024, 5, config.zone());
+        let report = logic::build_report(&time_sheet, &config, "month", &range, utc("2024-06-01T00:00:00Z"));
+        let invoice = build(&report, &config).unwrap();
+        assert_eq!(invoice.total, None);
+        assert_eq!(invoice.unconverted, vec!["USD".to_string()]);
+    }
+
+    // 23:00 on Friday, May 31st, to 01:00 on Saturday, June 1st, in Berlin: an
+    // hour of night time, then an hour of weekend time.
+    fn surcharge_lines(attribution: MonthAttribution, month: u32) -> Vec<(Option<Category>, Amounts)> {
+        let mut config = Config {
+            month_attribution: attribution,
+            time_zone: Some(chrono_tz::Europe::Berlin),
+            schedule: Some(ScheduleConfig::default()),
+            ..Config::default()
+        };
+        config.invoice.surcharges = BTreeMap::from([(Category::Night, 1.25), (Category::Weekend, 1.5)]);
+        config.rates.insert("a".to_string(), rate(100.0, None, None));
+        let time_sheet =
+            TimeSheet { periods: vec![period("2024-05-31T21:00:00Z", "2024-05-31T23:00:00Z", "a")], ..TimeSheet::default() };
+        let range = logic::month_period(2024, month, config.zone());
+        let report = logic::build_report(&time_sheet, &config, "month", &range, utc("2024-07-01T00:00:00Z"));
+        let invoice = build(&report, &config).unwrap();
+        invoice.lines.iter().map(|line| (line.surcharge.map(|s| s.category), line.amounts)).collect()
+    }
+
+    #[test]
+    fn split_attribution_bills_each_month_the_surcharges_of_its_part() {
+        assert_eq!(surcharge_lines(MonthAttribution::Split, 5), vec![(Some(Category::Night), amounts("125", "0", "125"))]);
+        assert_eq!(surcharge_lines(MonthAttribution::Split, 6), vec![(Some(Category::Weekend), amounts("150", "0", "150"))]);
+    }
+
+    #[test]
+    fn start_attribution_bills_all_surcharges_in_the_start_month() {
+        assert_eq!(
+            surcharge_lines(MonthAttribution::Start, 5),
+            vec![(Some(Category::Weekend), amounts("150", "0", "150")), (Some(Category::Night), amounts("125", "0", "125"))]
+        );
+        assert_eq!(surcharge_lines(MonthAttribution::Start, 6), vec![]);
+    }
+}
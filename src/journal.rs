@@ -0,0 +1,233 @@
+// Append-only journal next to the data file.
+//
+// `start` and `stop` append one JSON line to `<data file>.journal` instead of
+// rewriting the whole timesheet. Loading replays the journal on top of the data
+// file. Any other change, the `compact` command, or a journal longer than
+// `compact_after_entries` writes the full timesheet and removes the journal.
+//
+// Replaying is idempotent: a `stop` replaces the period with the same ID, so a
+// journal left behind by an interrupted compaction does no harm.
+//
+// Each entry is synced to disk before the change takes effect, so after a crash
+// the next start finds the running session again. A damaged entry, such as one
+// torn by a crash while it was written, is skipped with a warning.
+
+use crate::suspend::SuspendMark;
+use crate::{Metadata, Overlaps, Period, TimeSheet};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, instrument, warn};
+
+// Used when `compact_after_entries` is not set in the config.
+pub const DEFAULT_COMPACT_AFTER_ENTRIES: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JournalEntry {
+    // A session was started.
+    Start {
+        start: DateTime<Utc>,
+        #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+        metadata: Metadata,
+        // Seconds, from `start --remind-after`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        remind_after: Option<u64>,
+        // How long the system had slept, see `suspend`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        suspend: Option<SuspendMark>,
+    },
+    // The active session was stopped. `period` is the period that was added or
+    // extended, or none if the session was discarded.
+    Stop {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        period: Option<Period>,
+    },
+    // `switch`: the active session was stopped as for `stop`, with `period` the
+    // period that was added or extended, and a new one started at `start`, in one
+    // entry so that a crash cannot leave only one half of it.
+    Switch {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        period: Option<Period>,
+        start: DateTime<Utc>,
+        #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+        metadata: Metadata,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        suspend: Option<SuspendMark>,
+    },
+}
+
+impl JournalEntry {
+    fn apply(self, time_sheet: &mut TimeSheet) {
+        match self {
+            JournalEntry::Start { start, metadata, remind_after, suspend } => {
+                time_sheet.active_period_start = Some(start);
+                time_sheet.active_metadata = metadata;
+                time_sheet.active_laps.clear();
+                time_sheet.active_remind_after = remind_after;
+                time_sheet.active_suspend = suspend;
+            }
+            JournalEntry::Stop { period } => {
+                time_sheet.active_period_start = None;
+                time_sheet.active_metadata = Metadata::default();
+                time_sheet.active_laps.clear();
+                time_sheet.active_remind_after = None;
+                time_sheet.active_suspend = None;
+                if let Some(period) = period {
+                    match time_sheet.periods.iter_mut().find(|p| p.id == period.id) {
+                        Some(existing) => *existing = period,
+                        None => {
+                            if let Err(e) = time_sheet.insert_period(period, Overlaps::Allow) {
+                                warn!(error = %e, "skipping period in the journal");
+                            }
+                        }
+                    }
+                }
+            }
+            JournalEntry::Switch { period, start, metadata, suspend } => {
+                JournalEntry::Stop { period }.apply(time_sheet);
+                JournalEntry::Start { start, metadata, remind_after: None, suspend }.apply(time_sheet);
+            }
+        }
+    }
+}
+
+// The journal belonging to a data file, e.g. `timesheet.json.journal`.
+pub fn journal_path(data_path: &Path) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(".journal");
+    PathBuf::from(name)
+}
+
+// Entry for a `stop`, given the last period before stopping: the period that was
+// added or extended, if any.
+pub fn stop_entry(last_before: Option<&Period>, time_sheet: &TimeSheet) -> JournalEntry {
+    let period = time_sheet
+        .periods
+        .last()
+        .filter(|last| last_before.is_none_or(|before| before.id != last.id || before.end != last.end))
+        .cloned();
+    JournalEntry::Stop { period }
+}
+
+// Applies the journal to a freshly loaded timesheet. A damaged last line, as left
+// by an interrupted write, is skipped. Returns the number of entries applied.
+#[instrument(level = "debug", skip(time_sheet))]
+pub fn replay(data_path: &Path, time_sheet: &mut TimeSheet) -> io::Result<usize> {
+    let path = journal_path(data_path);
+    if !path.exists() {
+        return Ok(0);
+    }
+    let contents = fs::read_to_string(&path)?;
+    let mut applied = 0;
+    for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        match serde_json::from_str::<JournalEntry>(line) {
+            Ok(entry) => {
+                entry.apply(time_sheet);
+                applied += 1;
+            }
+            Err(e) => warn!(path = %path.display(), line = number + 1, error = %e, "skipping damaged journal entry"),
+        }
+    }
+    debug!(applied, "replayed journal");
+    if applied > 0
+        && let Some(start) = time_sheet.active_period_start
+    {
+        info!(since = %start, "active session restored from the journal");
+    }
+    Ok(applied)
+}
+
+// Appends an entry to the journal. Returns the number of entries in the journal.
+#[instrument(level = "debug")]
+pub fn append(data_path: &Path, entry: &JournalEntry) -> io::Result<usize> {
+    let path = journal_path(data_path);
+    let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+    line.push('\n');
+    // A crash while appending can leave a last line without its newline. Start
+    // on a fresh line, so only the torn entry is lost and not this one as well.
+    if ends_torn(&path)? {
+        warn!(path = %path.display(), "journal ends in a torn entry");
+        line.insert(0, '\n');
+    }
+    let mut file = OpenOptions::new().append(true).create(true).open(&path)?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    let entries = entry_count(data_path)?;
+    debug!(path = %path.display(), entries, "appended journal entry");
+    Ok(entries)
+}
+
+// Whether the journal's last byte is something other than a newline.
+pub(crate) fn ends_torn(path: &Path) -> io::Result<bool> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    let mut last = [0u8];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
+}
+
+// Number of entries in the journal, zero if there is none.
+pub fn entry_count(data_path: &Path) -> io::Result<usize> {
+    match fs::read_to_string(journal_path(data_path)) {
+        Ok(contents) => Ok(contents.lines().filter(|line| !line.trim().is_empty()).count()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+// Where the journal is moved while a save that folds it in replaces the data file.
+fn set_aside_path(data_path: &Path) -> PathBuf {
+    let mut name = journal_path(data_path).into_os_string();
+    name.push(".folded");
+    PathBuf::from(name)
+}
+
+// Moves the journal out of the way of a save. Returns false if there is none.
+pub(crate) fn set_aside(data_path: &Path) -> io::Result<bool> {
+    match fs::rename(journal_path(data_path), set_aside_path(data_path)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn is_set_aside(data_path: &Path) -> bool {
+    set_aside_path(data_path).exists()
+}
+
+// Restores the journal after a save that did not replace the data file.
+pub(crate) fn put_back(data_path: &Path) -> io::Result<()> {
+    fs::rename(set_aside_path(data_path), journal_path(data_path))
+}
+
+// Drops the journal once the saved data file holds its entries.
+pub(crate) fn discard_set_aside(data_path: &Path) -> io::Result<()> {
+    match fs::remove_file(set_aside_path(data_path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Removes the journal after the full timesheet has been written.
+pub fn clear(data_path: &Path) -> io::Result<()> {
+    let path = journal_path(data_path);
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            info!(path = %path.display(), "removed journal after compaction");
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
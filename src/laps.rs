@@ -0,0 +1,81 @@
+use crate::clock::Clock;
+use crate::timefmt::TimeFormatter;
+use crate::timestamp::Lenient;
+use crate::TimeSheet;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::io;
+use tracing::{info, instrument};
+
+// An intermediate marker within a session, set with `lap`. Stored as a
+// `[time, note]` pair.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "(Lenient, Option<String>)", into = "(DateTime<Utc>, Option<String>)")]
+pub struct Lap {
+    pub time: DateTime<Utc>,
+    pub note: Option<String>,
+}
+
+impl From<(Lenient, Option<String>)> for Lap {
+    fn from((Lenient(time), note): (Lenient, Option<String>)) -> Lap {
+        Lap { time, note }
+    }
+}
+
+impl From<Lap> for (DateTime<Utc>, Option<String>) {
+    fn from(lap: Lap) -> Self {
+        (lap.time, lap.note)
+    }
+}
+
+// Handles `lap`: marks the current time in the running session. Returns whether
+// the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, formatter, clock))]
+pub fn record_lap(time_sheet: &mut TimeSheet, note: Option<String>, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<bool> {
+    let Some(start) = time_sheet.active_period_start else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No active session to mark a lap in."));
+    };
+    let now = clock.now();
+    let previous = time_sheet.active_laps.last().map_or(start, |lap| lap.time);
+    if now < previous {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("A lap cannot be earlier than the previous mark at {}.", previous.with_timezone(&Local)),
+        ));
+    }
+    let note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    time_sheet.active_laps.push(Lap { time: now, note });
+
+    info!(lap = time_sheet.active_laps.len(), time = %now, "recorded lap");
+    println!(
+        "Lap {} at {}, {} since {}.",
+        time_sheet.active_laps.len(),
+        formatter.time(now.with_timezone(&Local)),
+        formatter.duration(now - previous),
+        if time_sheet.active_laps.len() == 1 { "the start" } else { "the previous lap" }
+    );
+    Ok(true)
+}
+
+// Prints the laps of a session from `start` to `end`, each with the time since
+// the previous mark, and the split from the last lap to the end.
+pub fn print_laps(laps: &[Lap], start: DateTime<Utc>, end: DateTime<Utc>, end_label: &str, formatter: &TimeFormatter) {
+    let mut previous = start;
+    for (number, lap) in laps.iter().enumerate() {
+        let line = format!(
+            "    lap {:<3} {}  {:>10}  {}",
+            number + 1,
+            formatter.time(lap.time.with_timezone(&Local)),
+            formatter.duration(lap.time - previous),
+            lap.note.as_deref().unwrap_or("")
+        );
+        println!("{}", line.trim_end());
+        previous = lap.time;
+    }
+    println!(
+        "    {:<7} {}  {:>10}",
+        end_label,
+        formatter.time(end.with_timezone(&Local)),
+        formatter.duration(end - previous)
+    );
+}
@@ -0,0 +1,55 @@
+// Fitting output lines to the width of the terminal, so that long notes and
+// project names do not wrap into the next row of a listing.
+//
+// The width is only known when standard output is a terminal: from COLUMNS if
+// set, as shells do for their children, otherwise from the terminal itself on
+// Linux. Piped output is never shortened. Widths are counted in characters,
+// which is off for wide characters such as CJK or emoji.
+
+use std::env;
+use std::io::{self, IsTerminal};
+
+// Columns assumed for a terminal whose width cannot be found out.
+const DEFAULT_WIDTH: usize = 80;
+
+// The width of the terminal standard output goes to, if it is one.
+pub fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let columns = env::var("COLUMNS").ok().and_then(|value| value.trim().parse().ok()).filter(|columns| *columns > 0);
+    Some(columns.or_else(query_width).unwrap_or(DEFAULT_WIDTH))
+}
+
+#[cfg(target_os = "linux")]
+fn query_width() -> Option<usize> {
+    let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    // SAFETY: TIOCGWINSZ writes a winsize to the valid pointer it is given.
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+        return None;
+    }
+    Some(size.ws_col as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn query_width() -> Option<usize> {
+    None
+}
+
+pub fn width_of(text: &str) -> usize {
+    text.chars().count()
+}
+
+// Shortens `text` to at most `width` characters, ending it with an ellipsis if
+// anything was cut.
+pub fn truncate(text: &str, width: usize) -> String {
+    if width_of(text) <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut short: String = text.chars().take(width - 1).collect::<String>().trim_end().to_string();
+    short.push('…');
+    short
+}
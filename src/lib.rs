@@ -0,0 +1,410 @@
+// Library part of the work time tracker. The command-line interface in `main.rs`
+// is built on it; other programs can embed it through `tracker::Tracker`.
+
+pub mod absence;
+pub mod activity;
+pub mod anonymize;
+pub mod balance;
+pub mod capacity;
+pub mod categories;
+pub mod clock;
+pub mod columnar;
+pub mod compaction;
+pub mod closing;
+pub mod config;
+pub mod daemon;
+pub mod diary;
+pub mod diff;
+pub mod doctor;
+pub mod export;
+pub mod focus;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gaps;
+pub mod import;
+pub mod index;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod integrations;
+pub mod invoice;
+pub mod journal;
+pub mod laps;
+pub mod layout;
+pub mod logic;
+pub mod metrics;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod notes;
+pub(crate) mod pdf;
+pub mod pattern;
+pub mod plan;
+pub mod presets;
+pub mod prompt;
+pub mod retag;
+pub mod review;
+pub(crate) mod recovery;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod reminders;
+pub mod report;
+pub mod retention;
+pub mod search;
+pub mod secrets;
+pub mod serve;
+pub(crate) mod shards;
+pub mod stats;
+pub mod strict;
+pub mod suspend;
+pub mod storage;
+pub mod sync;
+pub mod templating;
+pub mod timefmt;
+pub mod timestamp;
+pub mod timewarrior;
+pub mod tracker;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod zone;
+
+// The types most programs embedding the tracker need.
+pub mod prelude {
+    pub use crate::clock::{Clock, FixedClock, SystemClock};
+    pub use crate::config::Config;
+    pub use crate::timefmt::{HoursFormat, TimeFormatter};
+    pub use crate::tracker::{Tracker, Transaction};
+    pub use crate::{Metadata, Period, TimeSheet};
+}
+
+use absence::Absence;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use closing::ClosedMonth;
+use gaps::Break;
+use laps::Lap;
+use plan::WeekPlan;
+use suspend::SuspendMark;
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+// What a period was spent on. Everything is optional; empty fields are not written
+// to the data file, so files without metadata stay unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Kind of work, one of the configured session categories. Unlike tags a
+    /// period has at most one, so the categories split the tracked time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Custom key=value fields, e.g. a ticket number a client asks for.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.project.is_none() && self.tags.is_empty() && self.note.is_none() && self.category.is_none() && self.fields.is_empty()
+    }
+
+    /// Fills every field that is not set here from `defaults`.
+    pub fn or(self, defaults: &Metadata) -> Metadata {
+        Metadata {
+            project: self.project.or_else(|| defaults.project.clone()),
+            tags: if self.tags.is_empty() { defaults.tags.clone() } else { self.tags },
+            note: self.note.or_else(|| defaults.note.clone()),
+            category: self.category.or_else(|| defaults.category.clone()),
+            fields: defaults.fields.clone().into_iter().chain(self.fields).collect(),
+        }
+    }
+
+    /// Short human-readable summary, e.g. "project: internal, tags: meeting".
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(project) = &self.project {
+            parts.push(format!("project: {}", project));
+        }
+        if !self.tags.is_empty() {
+            parts.push(format!("tags: {}", self.tags.join(", ")));
+        }
+        if let Some(note) = &self.note {
+            parts.push(format!("note: {}", note));
+        }
+        if let Some(category) = &self.category {
+            parts.push(format!("category: {}", category));
+        }
+        for (key, value) in &self.fields {
+            parts.push(format!("{}: {}", key, value));
+        }
+        parts.join(", ")
+    }
+}
+
+// Represents a single time period with a start and end time.
+// Added Clone to make it easier to pass around.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Period {
+    // Stable identifier, assigned when the period is created and never changed,
+    // so that later references do not depend on the position in the list.
+    // Files written before IDs existed load as nil and get an ID on load.
+    #[serde(default = "Uuid::nil")]
+    pub(crate) id: Uuid,
+    #[serde(deserialize_with = "timestamp::deserialize")]
+    pub(crate) start: DateTime<Utc>,
+    #[serde(deserialize_with = "timestamp::deserialize")]
+    pub(crate) end: DateTime<Utc>,
+    #[serde(flatten)]
+    pub(crate) metadata: Metadata,
+    // Markers set with `lap` while the session was running.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) laps: Vec<Lap>,
+}
+
+impl Period {
+    /// Creates a new tracked period with a freshly generated ID and no metadata.
+    /// Fails unless the period ends after it starts.
+    pub fn new(start: DateTime<Utc>, end: DateTime<Utc>) -> io::Result<Period> {
+        if end <= start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("A period must end after it starts (start {}, end {}).", start, end),
+            ));
+        }
+        Ok(Period { id: Uuid::new_v4(), start, end, metadata: Metadata::default(), laps: Vec::new() })
+    }
+
+    /// Sets what the period was spent on.
+    pub fn with_metadata(mut self, metadata: Metadata) -> Period {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Creates a time range used for reporting. Ranges are never stored, so they carry no ID.
+    pub fn range(start: DateTime<Utc>, end: DateTime<Utc>) -> Period {
+        Period { id: Uuid::nil(), start, end, metadata: Metadata::default(), laps: Vec::new() }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn start(&self) -> DateTime<Utc> {
+        self.start
+    }
+
+    pub fn end(&self) -> DateTime<Utc> {
+        self.end
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn laps(&self) -> &[Lap] {
+        &self.laps
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Calculates the overlapping duration between this period and another.
+    pub fn overlap(&self, other: &Period) -> Duration {
+        let overlap_start = cmp::max(self.start, other.start);
+        let overlap_end = cmp::min(self.end, other.end);
+
+        if overlap_start < overlap_end {
+            overlap_end - overlap_start
+        } else {
+            Duration::zero()
+        }
+    }
+}
+
+// Represents the overall state of the time tracker.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TimeSheet {
+    pub(crate) periods: Vec<Period>,
+    #[serde(default, deserialize_with = "timestamp::deserialize_option")]
+    pub(crate) active_period_start: Option<DateTime<Utc>>,
+    // Metadata of the active period, moved onto the period when it is stopped.
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub(crate) active_metadata: Metadata,
+    // Laps of the active period, moved onto the period when it is stopped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) active_laps: Vec<Lap>,
+    // Seconds after which `remind` reports the active period, from `start --remind-after`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) active_remind_after: Option<u64>,
+    // How long the system had slept when the active period started, from the
+    // system clock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) active_suspend: Option<SuspendMark>,
+    // Months locked by `close month`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) closed_months: Vec<ClosedMonth>,
+    // Sick and vacation days, one entry per day.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) absences: Vec<Absence>,
+    // Workday gaps marked as deliberately untracked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) breaks: Vec<Break>,
+    // Days confirmed in `review week`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) reviewed_days: Vec<NaiveDate>,
+    // Hours planned per project and week with `plan set`, by week.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) plan: Vec<WeekPlan>,
+    // Whether the periods are known not to overlap, set by `check_periods` and kept
+    // by `insert_period`. Without it, `periods_in` scans from the first period, so
+    // every lookup takes time linear in the whole history instead of logarithmic.
+    #[serde(skip)]
+    pub(crate) disjoint: bool,
+}
+
+// Whether `TimeSheet::insert_period` records a period over others. Only sessions
+// that really happened are recorded regardless: a stopped running session, also
+// when the journal replays its stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Overlaps {
+    Reject,
+    Allow,
+}
+
+impl TimeSheet {
+    pub fn periods(&self) -> &[Period] {
+        &self.periods
+    }
+
+    /// Periods overlapping `range`, by start. The periods are sorted by start, and
+    /// while none overlap another, by end as well; the history outside the range
+    /// is then skipped with binary searches. Once two periods overlap, the history
+    /// before the range is scanned period by period.
+    pub fn periods_in(&self, range: &Period) -> impl Iterator<Item = &Period> {
+        let first = if self.disjoint { self.periods.partition_point(|p| p.end <= range.start) } else { 0 };
+        let last = self.periods.partition_point(|p| p.start < range.end).max(first);
+        self.periods[first..last].iter().filter(|p| p.overlap(range) > Duration::zero())
+    }
+
+    /// Adds a finished period at its place by start time. Fails if a period with
+    /// the same ID is recorded, and with `Overlaps::Reject` if the period overlaps
+    /// a recorded one.
+    pub(crate) fn insert_period(&mut self, period: Period, overlaps: Overlaps) -> io::Result<()> {
+        if self.periods.iter().any(|p| p.id == period.id && !p.id.is_nil()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("A period with ID {} is already recorded.", period.id),
+            ));
+        }
+        let other = self.periods_in(&period).next().cloned();
+        if let Some(other) = other {
+            if overlaps == Overlaps::Reject {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "The period overlaps {} from {} to {}.",
+                        other.id,
+                        other.start.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                        other.end.with_timezone(&Local).format("%H:%M")
+                    ),
+                ));
+            }
+            warn!(id = %period.id, other = %other.id, "recording overlapping period");
+            self.disjoint = false;
+        }
+        let index = self.periods.partition_point(|p| p.start <= period.start);
+        self.periods.insert(index, period);
+        Ok(())
+    }
+
+    /// Establishes the invariants of `periods` on load, since files edited by hand
+    /// or written by older versions need not keep them: sorts by start, drops exact
+    /// duplicates and notes whether any periods overlap. Returns the IDs of the
+    /// periods that overlap the one after them.
+    pub(crate) fn check_periods(&mut self) -> Vec<(Uuid, Uuid)> {
+        if !self.periods.is_sorted_by_key(|p| p.start) {
+            debug!("sorting periods by start");
+            self.periods.sort_by_key(|p| p.start);
+        }
+        let before = self.periods.len();
+        self.periods.dedup_by(|a, b| a.id == b.id && a.start == b.start && a.end == b.end);
+        if self.periods.len() < before {
+            warn!(dropped = before - self.periods.len(), "dropped duplicate periods");
+        }
+        let overlaps: Vec<(Uuid, Uuid)> =
+            self.periods.windows(2).filter(|pair| pair[0].end > pair[1].start).map(|pair| (pair[0].id, pair[1].id)).collect();
+        self.disjoint = overlaps.is_empty();
+        if !self.disjoint {
+            debug!(overlaps = overlaps.len(), "some periods overlap");
+        }
+        overlaps
+    }
+
+    /// Start of the running session, if any.
+    pub fn active_period_start(&self) -> Option<DateTime<Utc>> {
+        self.active_period_start
+    }
+
+    pub fn active_metadata(&self) -> &Metadata {
+        &self.active_metadata
+    }
+
+    pub fn active_laps(&self) -> &[Lap] {
+        &self.active_laps
+    }
+
+    /// How long the system had slept when the running session started, if known.
+    pub fn active_suspend(&self) -> Option<&SuspendMark> {
+        self.active_suspend.as_ref()
+    }
+
+    /// Reminder threshold set for the running session, if any.
+    pub fn active_remind_after(&self) -> Option<Duration> {
+        self.active_remind_after.map(|seconds| Duration::seconds(seconds as i64))
+    }
+
+    pub fn closed_months(&self) -> &[ClosedMonth] {
+        &self.closed_months
+    }
+
+    pub fn absences(&self) -> &[Absence] {
+        &self.absences
+    }
+
+    pub fn breaks(&self) -> &[Break] {
+        &self.breaks
+    }
+
+    pub fn reviewed_days(&self) -> &[NaiveDate] {
+        &self.reviewed_days
+    }
+
+    pub fn plan(&self) -> &[WeekPlan] {
+        &self.plan
+    }
+
+    /// Gives every period without an ID a new one. Returns how many were assigned.
+    pub(crate) fn assign_missing_ids(&mut self) -> usize {
+        let mut assigned = 0;
+        for period in self.periods.iter_mut().filter(|p| p.id.is_nil()) {
+            period.id = Uuid::new_v4();
+            assigned += 1;
+        }
+        assigned
+    }
+
+    /// Returns the closed month containing `time`, if any. Closed months must not change.
+    pub fn closed_month_at(&self, time: DateTime<Utc>) -> Option<&ClosedMonth> {
+        let key = closing::month_key(time);
+        self.closed_months.iter().find(|c| c.month == key)
+    }
+
+    /// Whether the period touches a closed month and so must not change.
+    pub(crate) fn is_locked(&self, period: &Period) -> bool {
+        self.closed_month_at(period.start).is_some()
+            || self.closed_month_at(period.end - Duration::seconds(1)).is_some()
+    }
+}
@@ -0,0 +1,934 @@
+pub mod budget;
+pub mod periods;
+
+use crate::absence;
+use crate::clock::Clock;
+use crate::categories;
+use crate::closing;
+use crate::layout;
+use crate::zone::Zone;
+use crate::prompt;
+use crate::report::{ActiveSession, CategoryRow, DayRow, PeriodRow, ProjectRow, Report, TagRow, WeekRow, WindowRow};
+use crate::config::{Config, DstPolicy, MonthAttribution, NormalizeConfig, ShortSessionPolicy};
+use crate::timefmt::TimeFormatter;
+use crate::{Metadata, Overlaps, Period, TimeSheet};
+use chrono::{DateTime, Datelike, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
+use tracing::{debug, info, instrument};
+
+// Handles the "start" command.
+// `remind_after` is when `remind` should report the session as long-running.
+#[instrument(level = "debug", skip(time_sheet, clock))]
+pub fn start_tracking(
+    time_sheet: &mut TimeSheet,
+    metadata: Metadata,
+    remind_after: Option<Duration>,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    if let Some(start_time) = time_sheet.active_period_start {
+        println!("Already tracking time since {}.", start_time.with_timezone(&Local));
+        Ok(false)
+    } else {
+        let now = clock.now();
+        if let Some(closed) = time_sheet.closed_month_at(now) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Month {} is closed; no new sessions can be started in it.", closed.month),
+            ));
+        }
+        time_sheet.active_period_start = Some(now);
+        info!(start = %now, "started active period");
+        println!("Started tracking time at {}.", now.with_timezone(&Local));
+        if !metadata.is_empty() {
+            println!("Tracking {}.", metadata.describe());
+        }
+        time_sheet.active_metadata = metadata;
+        time_sheet.active_laps.clear();
+        time_sheet.active_remind_after = remind_after.map(|after| after.num_seconds() as u64);
+        time_sheet.active_suspend = clock.suspend_mark();
+        if let Some(after) = remind_after {
+            let minutes = after.num_minutes();
+            println!("'remind' will notify you after {}:{:02} h.", minutes / 60, minutes % 60);
+        }
+        Ok(true)
+    }
+}
+
+/// Parses a local time as typed for `add`: "9:00" for today, or with a day
+/// before it, as in "yesterday 9:00" or "2024-05-03 9:00".
+pub fn parse_local_time(text: &str, clock: &dyn Clock, zone: Zone) -> io::Result<DateTime<Utc>> {
+    let (day, time) = match text.trim().rsplit_once(' ') {
+        Some((day, time)) => (crate::gaps::parse_day(day.trim(), clock)?, time),
+        None => (zone.date(clock.now()), text.trim()),
+    };
+    let time = NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid time '{}'. Expected HH:MM, optionally after today, yesterday or YYYY-MM-DD.", text),
+        )
+    })?;
+    Ok(naive_to_utc(day.and_time(time), zone))
+}
+
+/// Records a finished period entered by hand. Fails if it lies in the future, in a
+/// closed month, or overlaps a recorded period or the running session.
+pub fn add_manual_period(
+    time_sheet: &mut TimeSheet,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    metadata: Metadata,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let period = Period::new(start, end)?.with_metadata(metadata);
+    if end > clock.now() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A period cannot end in the future."));
+    }
+    if let Some(closed) = time_sheet.closed_month_at(start).or_else(|| time_sheet.closed_month_at(end - Duration::seconds(1))) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Month {} is closed; no periods can be added to it.", closed.month),
+        ));
+    }
+    if let Some(active) = time_sheet.active_period_start.filter(|active| *active < end) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("The period overlaps the session running since {}.", active.with_timezone(&Local)),
+        ));
+    }
+
+    time_sheet.insert_period(period.clone(), Overlaps::Reject)?;
+    info!(start = %start, end = %end, "added manual period");
+    println!(
+        "Added {} to {} ({}).",
+        formatter.date_time(start.with_timezone(&Local)),
+        formatter.time(end.with_timezone(&Local)),
+        formatter.duration(period.duration())
+    );
+    if !period.metadata.is_empty() {
+        println!("Tracked {}.", period.metadata.describe());
+    }
+    println!("Period ID: {}", period.id);
+    Ok(true)
+}
+
+/// Like `add_manual_period`, with the end given as the time worked after `start`.
+pub fn add_manual_period_with_duration(
+    time_sheet: &mut TimeSheet,
+    start: DateTime<Utc>,
+    duration: Duration,
+    metadata: Metadata,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    add_manual_period(time_sheet, start, start + duration, metadata, formatter, clock)
+}
+
+// The minimum session length enforced by `stop`, unless overridden with `--keep`.
+#[derive(Debug, Clone, Copy)]
+pub struct MinimumSession {
+    pub length: Duration,
+    pub policy: ShortSessionPolicy,
+}
+
+// Handles the "stop" command.
+#[instrument(level = "debug", skip(time_sheet, formatter, clock))]
+pub fn stop_tracking(
+    time_sheet: &mut TimeSheet,
+    formatter: &TimeFormatter,
+    minimum: Option<MinimumSession>,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    if let Some(start_time) = time_sheet.active_period_start.take() {
+        let end_time = clock.now();
+        let metadata = std::mem::take(&mut time_sheet.active_metadata);
+        let laps = std::mem::take(&mut time_sheet.active_laps);
+        time_sheet.active_remind_after = None;
+        time_sheet.active_suspend = None;
+
+        if let Some(minimum) = minimum.filter(|m| end_time - start_time < m.length)
+            && !matches!(handle_short_session(time_sheet, start_time, end_time, minimum, formatter)?, ShortSession::Kept)
+        {
+            return Ok(true);
+        }
+
+        let mut new_period = Period::new(start_time, end_time)?.with_metadata(metadata);
+        new_period.laps = laps;
+        let period_id = new_period.id;
+        time_sheet.insert_period(new_period, Overlaps::Allow)?;
+        let duration = end_time - start_time;
+        info!(start = %start_time, end = %end_time, "stopped active period");
+        println!("Stopped tracking time at {}.", end_time.with_timezone(&Local));
+        println!("Duration of last session: {}", formatter.duration(duration));
+        println!("Period ID: {}", period_id);
+        Ok(true)
+    } else {
+        println!("No active time tracking period to stop.");
+        Ok(false)
+    }
+}
+
+// Handles the "switch" command: stops the running session at `end` and starts
+// one with `metadata` now. `end` comes from the same checks as for `stop`, for
+// activity and time slept; without it the two sessions meet without a gap or an
+// overlap. A session below `minimum` is merged or discarded as `stop` would.
+// Returns the period that was added or extended, or none if the session was
+// discarded or had only just started and was relabelled instead.
+#[instrument(level = "debug", skip(time_sheet, formatter, clock))]
+pub fn switch_tracking(
+    time_sheet: &mut TimeSheet,
+    metadata: Metadata,
+    formatter: &TimeFormatter,
+    minimum: Option<MinimumSession>,
+    end: Option<DateTime<Utc>>,
+    clock: &dyn Clock,
+) -> io::Result<Option<Period>> {
+    let Some(start_time) = time_sheet.active_period_start else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No active time tracking period to switch from; use 'start' instead.",
+        ));
+    };
+    let now = clock.now();
+    if let Some(closed) = time_sheet.closed_month_at(now) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Month {} is closed; no new sessions can be started in it.", closed.month),
+        ));
+    }
+    let end_time = end.map_or(now, |end| end.min(now));
+    let metadata_before = std::mem::take(&mut time_sheet.active_metadata);
+    let laps = std::mem::take(&mut time_sheet.active_laps);
+    let short = match minimum.filter(|m| end_time > start_time && end_time - start_time < m.length) {
+        Some(minimum) => handle_short_session(time_sheet, start_time, end_time, minimum, formatter)?,
+        None => ShortSession::Kept,
+    };
+    let stopped = match short {
+        ShortSession::Merged(period) => Some(period),
+        ShortSession::Discarded => None,
+        ShortSession::Kept if end_time > start_time => {
+            let mut period = Period::new(start_time, end_time)?.with_metadata(metadata_before);
+            period.laps = laps;
+            time_sheet.insert_period(period.clone(), Overlaps::Allow)?;
+            info!(start = %start_time, end = %end_time, "stopped active period");
+            println!("Stopped tracking time at {} after {}.", end_time.with_timezone(&Local), formatter.duration(period.duration()));
+            println!("Period ID: {}", period.id);
+            Some(period)
+        }
+        ShortSession::Kept => None,
+    };
+    time_sheet.active_period_start = Some(now);
+    time_sheet.active_metadata = metadata;
+    time_sheet.active_remind_after = None;
+    time_sheet.active_suspend = clock.suspend_mark();
+    info!(start = %now, "started active period");
+    println!("Started tracking time at {}.", now.with_timezone(&Local));
+    if !time_sheet.active_metadata.is_empty() {
+        println!("Tracking {}.", time_sheet.active_metadata.describe());
+    }
+    Ok(stopped)
+}
+
+// What the short session policy did with a session below the minimum length.
+#[derive(Debug, Clone)]
+enum ShortSession {
+    // To be recorded after all.
+    Kept,
+    // Added to the end of this earlier period.
+    Merged(Period),
+    Discarded,
+}
+
+// Applies the short session policy to a session below the minimum length.
+fn handle_short_session(
+    time_sheet: &mut TimeSheet,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    minimum: MinimumSession,
+    formatter: &TimeFormatter,
+) -> io::Result<ShortSession> {
+    let duration = formatter.duration(end_time - start_time);
+    let minimum_text = formatter.duration(minimum.length);
+
+    if minimum.policy == ShortSessionPolicy::Prompt
+        && io::stdin().is_terminal()
+        && prompt::confirm(&format!("The session lasted only {}. Keep it?", duration))?
+    {
+        return Ok(ShortSession::Kept);
+    }
+
+    // Periods in a closed month must not be extended.
+    let closed_keys: Vec<String> = time_sheet.closed_months.iter().map(|c| c.month.clone()).collect();
+    if minimum.policy == ShortSessionPolicy::Merge {
+        let previous = time_sheet
+            .periods
+            .iter_mut()
+            .filter(|p| p.end <= start_time && start_time - p.end < minimum.length)
+            .filter(|p| !closed_keys.contains(&closing::month_key(p.start)))
+            .max_by_key(|p| p.end);
+        if let Some(previous) = previous {
+            info!(id = %previous.id, end = %end_time, "merged short session into previous period");
+            previous.end = end_time;
+            println!("Stopped tracking time at {}.", end_time.with_timezone(&Local));
+            println!(
+                "Session of {} is shorter than the minimum of {}; merged into the previous period {}.",
+                duration, minimum_text, previous.id
+            );
+            return Ok(ShortSession::Merged(previous.clone()));
+        }
+    }
+
+    info!(start = %start_time, end = %end_time, "discarded short session");
+    println!(
+        "Session of {} is shorter than the minimum of {} and was discarded.",
+        duration, minimum_text
+    );
+    println!("Use --keep to record short sessions anyway.");
+    Ok(ShortSession::Discarded)
+}
+
+/// Converts a local date and time to UTC. Times that occur twice (when clocks go
+/// back) are resolved by the zone's policy. Times that do not exist (when clocks go forward)
+/// move to the first minute after the gap, so a day starting in a gap starts when
+/// the clocks have been changed.
+pub fn naive_to_utc(naive: NaiveDateTime, zone: Zone) -> DateTime<Utc> {
+    let mut candidate = naive;
+    // Gaps are at most a few hours; a day of steps is a generous bound.
+    for _ in 0..24 * 60 {
+        match zone.from_local(&candidate) {
+            LocalResult::Single(time) => return time,
+            LocalResult::Ambiguous(earliest, latest) => {
+                debug!(%naive, policy = ?zone.policy, "resolving ambiguous local time");
+                return match zone.policy {
+                    DstPolicy::Earliest => earliest,
+                    DstPolicy::Latest => latest,
+                };
+            }
+            LocalResult::None => candidate += Duration::minutes(1),
+        }
+    }
+    // Not reachable with real time zone data; fall back to reading the time as UTC.
+    Utc.from_utc_datetime(&naive)
+}
+
+/// Generates a Period struct representing the current day in the local timezone.
+pub fn get_today_period(clock: &dyn Clock, zone: Zone) -> Period {
+    let today_local_naive = zone.date(clock.now());
+    local_days(today_local_naive, 1, zone)
+}
+
+/// Generates a Period struct representing the current week in the local timezone,
+/// starting on `week_start`.
+pub fn get_week_period(week_start: Weekday, clock: &dyn Clock, zone: Zone) -> Period {
+    let today_local_naive = zone.date(clock.now());
+    let days_from_week_start = today_local_naive.weekday().days_since(week_start);
+    let start_of_week_naive = today_local_naive - Duration::days(days_from_week_start as i64);
+    week_starting(start_of_week_naive, zone)
+}
+
+/// Generates a Period struct for the seven local days starting with `first`.
+pub fn week_starting(first: NaiveDate, zone: Zone) -> Period {
+    local_days(first, 7, zone)
+}
+
+/// Generates a Period struct representing the given local calendar day.
+pub fn day_period(day: NaiveDate, zone: Zone) -> Option<Period> {
+    day.succ_opt()?;
+    Some(local_days(day, 1, zone))
+}
+
+/// Generates a Period struct representing the current month in the local timezone.
+pub fn get_month_period(clock: &dyn Clock, zone: Zone) -> Period {
+    let today_local_naive = zone.date(clock.now());
+    month_period(today_local_naive.year(), today_local_naive.month(), zone)
+}
+
+/// Generates a Period struct representing the given month in the local timezone.
+pub fn month_period(year: i32, month: u32, zone: Zone) -> Period {
+    let start_of_month_naive = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+    let (next_month_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    let start_of_next_month_naive = NaiveDate::from_ymd_opt(next_month_year, next_month, 1).unwrap();
+    let days = (start_of_next_month_naive - start_of_month_naive).num_days();
+    local_days(start_of_month_naive, days, zone)
+}
+
+// The Mondays of the ISO weeks from `from` to `to`, for `report weeks`. `to`
+// defaults to the current week.
+pub fn iso_weeks(from: &str, to: Option<&str>, clock: &dyn Clock, zone: Zone) -> io::Result<Vec<NaiveDate>> {
+    let first = periods::parse_iso_week(from)?;
+    let last = match to {
+        Some(to) => periods::parse_iso_week(to)?,
+        None => {
+            let today = zone.date(clock.now());
+            today - Duration::days(today.weekday().num_days_from_monday() as i64)
+        }
+    };
+    if last < first {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} comes before {}.", periods::iso_week_name(last), periods::iso_week_name(first)),
+        ));
+    }
+    Ok(first.iter_weeks().take_while(|monday| *monday <= last).collect())
+}
+
+// The range covered by the weeks starting on `mondays`.
+pub fn weeks_range(mondays: &[NaiveDate], zone: Zone) -> Period {
+    let first = mondays.first().copied().unwrap_or_default();
+    local_days(first, 7 * mondays.len() as i64, zone)
+}
+
+// Tracked time and target of each of the weeks starting on `mondays`.
+pub fn week_rows(time_sheet: &TimeSheet, config: &Config, mondays: &[NaiveDate], now: DateTime<Utc>) -> Vec<WeekRow> {
+    mondays
+        .iter()
+        .map(|monday| {
+            let range = week_starting(*monday, config.zone());
+            let tracked = calculate_tracked_time_in_period(time_sheet, &range, now);
+            WeekRow {
+                week: periods::iso_week_name(*monday),
+                first: *monday,
+                tracked,
+                balance: absence::balance(time_sheet, config, &range, tracked, now),
+            }
+        })
+        .collect()
+}
+
+// The local days from midnight of `first` to midnight `days` days later. Days are
+// counted on the calendar, so a range spanning a DST change is an hour shorter or longer.
+fn local_days(first: NaiveDate, days: i64, zone: Zone) -> Period {
+    let start_naive = first.and_hms_opt(0, 0, 0).unwrap();
+    let end_naive = start_naive + Duration::days(days);
+    Period::range(naive_to_utc(start_naive, zone), naive_to_utc(end_naive, zone))
+}
+
+// Resolves a named reporting period: "today", "week", "month" or an alias from
+// `[periods]`.
+pub fn reporting_period(period_name: &str, config: &Config, clock: &dyn Clock) -> io::Result<Period> {
+    match period_name {
+        "today" => Ok(get_today_period(clock, config.zone())),
+        "week" => Ok(get_week_period(config.week_start.0, clock, config.zone())),
+        "month" => Ok(get_month_period(clock, config.zone())),
+        _ => {
+            periods::resolve(period_name, config, clock.now())?.ok_or_else(|| {
+                let names: Vec<&str> = ["today", "week", "month"].into_iter().chain(config.periods.keys().map(String::as_str)).collect();
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown period '{}'. Expected one of: {}.", period_name, names.join(", ")),
+                )
+            })
+        }
+    }
+}
+
+// The last `days` days up to now, for `report rolling <days>`.
+pub fn rolling_period(days: u32, clock: &dyn Clock, zone: Zone) -> io::Result<Period> {
+    if days == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A rolling window needs at least one day."));
+    }
+    periods::PeriodSpec::RollingDays(days).current(clock.now(), zone)
+}
+
+// Builds the report for a named reporting period, as `reporting_period` resolves it.
+pub fn named_report(time_sheet: &TimeSheet, period_name: &str, config: &Config, clock: &dyn Clock) -> io::Result<Report> {
+    let range = reporting_period(period_name, config, clock)?;
+    let mut report = build_report(time_sheet, config, period_name, &range, clock.now());
+    report.rolling = periods::is_rolling(period_name, config);
+    Ok(report)
+}
+
+// Builds the report on `range`. The active session counts up to `now`.
+#[instrument(level = "debug", skip(time_sheet, config))]
+pub fn build_report(time_sheet: &TimeSheet, config: &Config, title: &str, range: &Period, now: DateTime<Utc>) -> Report {
+    debug!(start = %range.start, end = %range.end, attribution = ?config.month_attribution, "building report");
+    let zone = config.zone();
+    let active = time_sheet.active_period_start.map(|start| ActiveSession {
+        start,
+        elapsed: now - start,
+        metadata: time_sheet.active_metadata.clone(),
+    });
+    let running = time_sheet.active_period_start.map(|start| {
+        let mut period = Period::range(start, now);
+        period.metadata = time_sheet.active_metadata.clone();
+        period.laps = time_sheet.active_laps.clone();
+        period
+    });
+    // Each period with the part of the time it counts in this report for.
+    let (periods, counted): (Vec<PeriodRow>, Vec<Period>) = time_sheet
+        .periods_in(range)
+        .map(|p| (p.clone(), false))
+        .chain(running.map(|p| (p, true)))
+        .filter_map(|(period, active)| {
+            let counted = attributed_range(&period, range, config.month_attribution, zone)?;
+            let duration = period.overlap(&counted);
+            (duration > Duration::zero()).then_some((PeriodRow { period, duration, active }, counted))
+        })
+        .unzip();
+
+    let mut projects: BTreeMap<Option<String>, Duration> = BTreeMap::new();
+    let mut tags: BTreeMap<String, Duration> = BTreeMap::new();
+    for row in &periods {
+        *projects.entry(row.period.metadata.project.clone()).or_default() += row.duration;
+        for tag in &row.period.metadata.tags {
+            *tags.entry(tag.clone()).or_default() += row.duration;
+        }
+    }
+    let days = absence::days_in(range, zone)
+        .filter_map(|date| {
+            let day = day_period(date, zone)?;
+            let day = Period::range(day.start.max(range.start), day.end.min(range.end));
+            let mut by_project: BTreeMap<Option<String>, Duration> = BTreeMap::new();
+            for (row, counted) in periods.iter().zip(&counted) {
+                // Time counted beyond the range belongs to the day the session started.
+                let overlap = if counted.end > range.end {
+                    if day.start <= row.period.start && row.period.start < day.end { row.duration } else { Duration::zero() }
+                } else {
+                    row.period.overlap(&day)
+                };
+                if overlap > Duration::zero() {
+                    *by_project.entry(row.period.metadata.project.clone()).or_default() += overlap;
+                }
+            }
+            Some(DayRow { date, total: by_project.values().copied().sum(), projects: project_rows(by_project) })
+        })
+        .collect();
+
+    let total = periods.iter().map(|row| row.duration).sum();
+    let pieces = || periods.iter().map(|row| &row.period).zip(&counted);
+    let categories = match &config.schedule {
+        Some(schedule) => categories::breakdown(schedule, pieces(), zone)
+            .into_iter()
+            .map(|(category, total)| CategoryRow { category, total })
+            .collect(),
+        None => Vec::new(),
+    };
+    let outside_window = config
+        .tracking_window
+        .map(|window| WindowRow { window, total: categories::outside_window(&window, pieces(), zone) })
+        .filter(|row| row.total > Duration::zero());
+
+    Report {
+        title: title.to_string(),
+        range: range.clone(),
+        generated_at: now,
+        rolling: false,
+        zone,
+        total,
+        projects: project_rows(projects),
+        tags: tags.into_iter().map(|(tag, total)| TagRow { tag, total }).collect(),
+        categories,
+        outside_window,
+        days,
+        periods,
+        active,
+        absences: absence::absences_in(time_sheet, range, zone).into_iter().cloned().collect(),
+        balance: absence::balance(time_sheet, config, range, total, now),
+    }
+}
+
+// The time within which `period` counts towards a report on `range`: the range
+// itself, unless the period runs into another month of `zone` and `attribution`
+// is `Start`. Then the whole period counts in the range it started in and none of
+// it in others.
+pub fn attributed_range(period: &Period, range: &Period, attribution: MonthAttribution, zone: Zone) -> Option<Period> {
+    if attribution == MonthAttribution::Split || !crosses_month(period, zone) {
+        return Some(range.clone());
+    }
+    (range.start <= period.start && period.start < range.end).then(|| Period::range(period.start, period.end))
+}
+
+// Whether the period starts and ends in different local months.
+fn crosses_month(period: &Period, zone: Zone) -> bool {
+    let start = zone.local_time(period.start);
+    let last = zone.local_time(period.end - Duration::nanoseconds(1));
+    (start.year(), start.month()) != (last.year(), last.month())
+}
+
+fn project_rows(totals: BTreeMap<Option<String>, Duration>) -> Vec<ProjectRow> {
+    totals.into_iter().map(|(project, total)| ProjectRow { project, total }).collect()
+}
+
+// What `normalize` did.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Normalized {
+    pub merged: usize,
+    pub dropped: usize,
+}
+
+impl Normalized {
+    // Reports the counts after an import; `what` names the imported items.
+    pub fn print(&self, what: &str, rules: &NormalizeConfig) {
+        if self.merged > 0 {
+            println!("Merged {} {} into the one before (gaps under {}s).", self.merged, what, rules.merge_gap_seconds);
+        }
+        if self.dropped > 0 {
+            println!("Dropped {} {} shorter than {}s.", self.dropped, what, rules.min_interval_seconds);
+        }
+    }
+}
+
+// Cleans up fragmented intervals, such as the output of an activity sampler,
+// before they are stored: intervals with the same metadata less than
+// `merge_gap_seconds` apart are joined, then those shorter than
+// `min_interval_seconds` are dropped. Leaves the periods sorted by start.
+#[instrument(level = "debug", skip(periods), fields(periods = periods.len()))]
+pub fn normalize(periods: &mut Vec<Period>, rules: &NormalizeConfig) -> Normalized {
+    periods.sort_by_key(|p| p.start);
+    let gap = Duration::seconds(rules.merge_gap_seconds as i64);
+    let mut normalized = Normalized::default();
+    let mut kept: Vec<Period> = Vec::with_capacity(periods.len());
+    for period in periods.drain(..) {
+        match kept.last_mut() {
+            Some(previous) if previous.metadata == period.metadata && period.start - previous.end < gap => {
+                previous.end = previous.end.max(period.end);
+                previous.laps.extend(period.laps);
+                normalized.merged += 1;
+            }
+            _ => kept.push(period),
+        }
+    }
+    let minimum = Duration::seconds(rules.min_interval_seconds as i64);
+    let before = kept.len();
+    kept.retain(|p| p.duration() >= minimum);
+    normalized.dropped = before - kept.len();
+    *periods = kept;
+    debug!(merged = normalized.merged, dropped = normalized.dropped, "normalized intervals");
+    normalized
+}
+
+// Calculates the total tracked time within a given period using iterators.
+// The active period counts up to `now`.
+#[instrument(level = "trace", skip(time_sheet), ret)]
+pub fn calculate_tracked_time_in_period(time_sheet: &TimeSheet, reporting_period: &Period, now: DateTime<Utc>) -> Duration {
+    // Calculate total duration from completed periods using an iterator chain.
+    let completed_duration: Duration = time_sheet.periods_in(reporting_period)
+        .map(|p| p.overlap(reporting_period))
+        .sum();
+
+    // Calculate duration from the currently active period, if any.
+    let active_duration = time_sheet.active_period_start.map_or(Duration::zero(), |start| {
+        let active_period = Period::range(start, now);
+        active_period.overlap(reporting_period)
+    });
+
+    completed_duration + active_duration
+}
+
+// A session together with the running totals of its day and week, up to and
+// including the session itself.
+#[derive(Debug, Clone)]
+pub struct PeriodWithTotals {
+    pub period: Period,
+    /// Whether this is the active session, which has no end time yet.
+    pub active: bool,
+    pub day_total: Duration,
+    pub week_total: Duration,
+}
+
+// Returns the last `limit` sessions in chronological order with running totals.
+// The active session, if any, is included with the current time as its end.
+// Totals are accumulated in a single pass and reset when the local day or the
+// week (starting on `week_start`) changes.
+#[instrument(level = "debug", skip(time_sheet))]
+pub fn recent_periods_with_totals(
+    time_sheet: &TimeSheet,
+    limit: usize,
+    week_start: Weekday,
+    now: DateTime<Utc>,
+) -> Vec<PeriodWithTotals> {
+    let mut sessions: Vec<(Period, bool)> = time_sheet.periods.iter().map(|p| (p.clone(), false)).collect();
+    if let Some(start) = time_sheet.active_period_start {
+        let mut active = Period::range(start, now);
+        active.metadata = time_sheet.active_metadata.clone();
+        active.laps = time_sheet.active_laps.clone();
+        sessions.push((active, true));
+    }
+    sessions.sort_by_key(|(p, _)| p.start);
+
+    let mut day_total = Duration::zero();
+    let mut week_total = Duration::zero();
+    let mut current_day: Option<NaiveDate> = None;
+    let mut current_week: Option<NaiveDate> = None;
+
+    let mut with_totals: Vec<PeriodWithTotals> = sessions
+        .into_iter()
+        .map(|(period, active)| {
+            let day = period.start.with_timezone(&Local).date_naive();
+            let week = day - Duration::days(day.weekday().days_since(week_start) as i64);
+            if current_day != Some(day) {
+                current_day = Some(day);
+                day_total = Duration::zero();
+            }
+            if current_week != Some(week) {
+                current_week = Some(week);
+                week_total = Duration::zero();
+            }
+            let duration = period.end - period.start;
+            day_total += duration;
+            week_total += duration;
+            PeriodWithTotals { period, active, day_total, week_total }
+        })
+        .collect();
+
+    let skip = with_totals.len().saturating_sub(limit);
+    with_totals.drain(..skip);
+    with_totals
+}
+
+// Fewer columns than this left for the details of a session put them on a line
+// of their own.
+const MIN_DETAILS_WIDTH: usize = 20;
+
+// Prints the most recent sessions, optionally with running totals. On a terminal,
+// details that do not fit are shortened, and on narrow ones the columns are
+// stacked.
+pub fn show_log(
+    time_sheet: &TimeSheet,
+    limit: usize,
+    cumulative: bool,
+    show_laps: bool,
+    week_start: Weekday,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) {
+    let sessions = recent_periods_with_totals(time_sheet, limit, week_start, clock.now());
+    if sessions.is_empty() {
+        println!("No sessions recorded yet.");
+        return;
+    }
+    let width = layout::terminal_width();
+
+    for session in sessions {
+        let start = session.period.start.with_timezone(&Local);
+        let end = if session.active {
+            "now  ".to_string()
+        } else {
+            formatter.time(session.period.end.with_timezone(&Local))
+        };
+        let times = format!(
+            "{} {}  {}-{}  {:>10}",
+            formatter.date(start.date_naive()),
+            start.format("%a"),
+            formatter.time(start),
+            end,
+            formatter.duration(session.period.end - session.period.start)
+        );
+        let totals = cumulative.then(|| {
+            format!("day {:>10}  week {:>10}", formatter.duration(session.day_total), formatter.duration(session.week_total))
+        });
+        let details = session.period.metadata.describe();
+        let mut columns: Vec<&str> = [Some(times.as_str()), totals.as_deref()].into_iter().flatten().collect();
+        let line = columns.join("  ");
+        match width {
+            // Too narrow to keep the details beside the times: one column per line,
+            // the later ones indented.
+            Some(width) if !details.is_empty() && layout::width_of(&line) + 2 + MIN_DETAILS_WIDTH > width => {
+                columns.push(&details);
+                println!("{}", layout::truncate(columns[0], width));
+                for column in &columns[1..] {
+                    println!("  {}", layout::truncate(column, width.saturating_sub(2)));
+                }
+            }
+            Some(width) if !details.is_empty() => {
+                println!("{}  {}", line, layout::truncate(&details, width - layout::width_of(&line) - 2));
+            }
+            _ if !details.is_empty() => println!("{}  {}", line, details),
+            _ => println!("{}", line),
+        }
+        if show_laps && !session.period.laps.is_empty() {
+            let end_label = if session.active { "now" } else { "end" };
+            crate::laps::print_laps(&session.period.laps, session.period.start, session.period.end, end_label, formatter);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    const POLICIES: [DstPolicy; 2] = [DstPolicy::Earliest, DstPolicy::Latest];
+
+    fn berlin(policy: DstPolicy) -> Zone {
+        Zone::new(Some(chrono_tz::Europe::Berlin), policy)
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    fn local(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn naive_to_utc_moves_times_in_the_spring_gap_past_it() {
+        // On 2024-03-31 clocks go from 02:00 to 03:00.
+        for policy in POLICIES {
+            assert_eq!(naive_to_utc(local("2024-03-31 02:00"), berlin(policy)), utc("2024-03-31T01:00:00Z"));
+            assert_eq!(naive_to_utc(local("2024-03-31 02:30"), berlin(policy)), utc("2024-03-31T01:00:00Z"));
+            assert_eq!(naive_to_utc(local("2024-03-31 03:00"), berlin(policy)), utc("2024-03-31T01:00:00Z"));
+            assert_eq!(naive_to_utc(local("2024-03-31 01:59"), berlin(policy)), utc("2024-03-31T00:59:00Z"));
+        }
+    }
+
+    #[test]
+    fn naive_to_utc_resolves_the_autumn_repeat_by_policy() {
+        // On 2024-10-27 clocks go from 03:00 back to 02:00.
+        let time = local("2024-10-27 02:30");
+        assert_eq!(naive_to_utc(time, berlin(DstPolicy::Earliest)), utc("2024-10-27T00:30:00Z"));
+        assert_eq!(naive_to_utc(time, berlin(DstPolicy::Latest)), utc("2024-10-27T01:30:00Z"));
+        for policy in POLICIES {
+            assert_eq!(naive_to_utc(local("2024-10-27 01:59"), berlin(policy)), utc("2024-10-26T23:59:00Z"));
+            assert_eq!(naive_to_utc(local("2024-10-27 03:00"), berlin(policy)), utc("2024-10-27T02:00:00Z"));
+        }
+    }
+
+    #[test]
+    fn local_days_are_an_hour_shorter_or_longer_across_a_change() {
+        for policy in POLICIES {
+            let zone = berlin(policy);
+            let march = local_days(date("2024-03-31"), 1, zone);
+            assert_eq!((march.start, march.end), (utc("2024-03-30T23:00:00Z"), utc("2024-03-31T22:00:00Z")));
+            assert_eq!(march.duration(), Duration::hours(23));
+
+            let october = local_days(date("2024-10-27"), 1, zone);
+            assert_eq!((october.start, october.end), (utc("2024-10-26T22:00:00Z"), utc("2024-10-27T23:00:00Z")));
+            assert_eq!(october.duration(), Duration::hours(25));
+
+            assert_eq!(local_days(date("2024-03-30"), 1, zone).duration(), Duration::hours(24));
+            assert_eq!(local_days(date("2024-10-28"), 1, zone).duration(), Duration::hours(24));
+        }
+    }
+
+    #[test]
+    fn weeks_containing_a_change_span_it() {
+        for policy in POLICIES {
+            let zone = berlin(policy);
+            let march = week_starting(date("2024-03-25"), zone);
+            assert_eq!((march.start, march.end), (utc("2024-03-24T23:00:00Z"), utc("2024-03-31T22:00:00Z")));
+            assert_eq!(march.duration(), Duration::hours(7 * 24 - 1));
+
+            let october = week_starting(date("2024-10-21"), zone);
+            assert_eq!((october.start, october.end), (utc("2024-10-20T22:00:00Z"), utc("2024-10-27T23:00:00Z")));
+            assert_eq!(october.duration(), Duration::hours(7 * 24 + 1));
+
+            // Longer ranges count every day on the calendar as well.
+            assert_eq!(local_days(date("2024-10-21"), 14, zone).duration(), Duration::hours(14 * 24 + 1));
+        }
+    }
+
+    #[test]
+    fn current_day_and_week_at_a_change_come_from_the_clock() {
+        for policy in POLICIES {
+            let zone = berlin(policy);
+            // 03:30 in the gap night, already on summer time.
+            let clock = FixedClock::new(utc("2024-03-31T01:30:00Z"));
+            assert_eq!(get_today_period(&clock, zone).duration(), Duration::hours(23));
+            assert_eq!(get_week_period(Weekday::Mon, &clock, zone).start, utc("2024-03-24T23:00:00Z"));
+            // The second 02:30 of the night clocks go back.
+            let clock = FixedClock::new(utc("2024-10-27T01:30:00Z"));
+            assert_eq!(get_today_period(&clock, zone).duration(), Duration::hours(25));
+            let week = get_week_period(Weekday::Mon, &clock, zone);
+            assert_eq!((week.start, week.end), (utc("2024-10-20T22:00:00Z"), utc("2024-10-27T23:00:00Z")));
+            // Weeks starting on Sunday begin on the day of the change.
+            assert_eq!(get_week_period(Weekday::Sun, &clock, zone).start, utc("2024-10-26T22:00:00Z"));
+        }
+    }
+
+    #[test]
+    fn parse_local_time_reads_the_repeated_hour_by_policy() {
+        let clock = FixedClock::new(utc("2024-10-28T12:00:00Z"));
+        let earliest = parse_local_time("2024-10-27 02:30", &clock, berlin(DstPolicy::Earliest)).unwrap();
+        let latest = parse_local_time("2024-10-27 02:30", &clock, berlin(DstPolicy::Latest)).unwrap();
+        assert_eq!(earliest, utc("2024-10-27T00:30:00Z"));
+        assert_eq!(latest, utc("2024-10-27T01:30:00Z"));
+        assert_eq!(parse_local_time("2024-03-31 02:30", &clock, berlin(DstPolicy::Latest)).unwrap(), utc("2024-03-31T01:00:00Z"));
+    }
+
+    // 23:00 on the last day of May to 01:00 on June 1st, in Berlin.
+    fn across_months() -> Period {
+        Period::range(utc("2024-05-31T21:00:00Z"), utc("2024-05-31T23:00:00Z"))
+            .with_metadata(Metadata { project: Some("a".to_string()), ..Metadata::default() })
+    }
+
+    fn month_config(attribution: MonthAttribution) -> Config {
+        Config { month_attribution: attribution, time_zone: Some(chrono_tz::Europe::Berlin), ..Config::default() }
+    }
+
+    fn day_total(report: &Report, day: &str) -> Duration {
+        report.days.iter().find(|row| row.date == date(day)).unwrap().total
+    }
+
+    #[test]
+    fn crosses_month_compares_the_local_months_of_start_and_end() {
+        let zone = berlin(DstPolicy::Earliest);
+        assert!(crosses_month(&across_months(), zone));
+        // Ending at midnight is still within the month.
+        assert!(!crosses_month(&Period::range(utc("2024-05-31T20:00:00Z"), utc("2024-05-31T22:00:00Z")), zone));
+        // In UTC, the same session lies within May.
+        assert!(!crosses_month(&across_months(), Zone::new(Some(chrono_tz::UTC), DstPolicy::Earliest)));
+    }
+
+    #[test]
+    fn attributed_range_splits_or_gives_the_session_to_its_start_month() {
+        let zone = berlin(DstPolicy::Earliest);
+        let (may, june) = (month_period(2024, 5, zone), month_period(2024, 6, zone));
+        let session = across_months();
+
+        for range in [&may, &june] {
+            let counted = attributed_range(&session, range, MonthAttribution::Split, zone).unwrap();
+            assert_eq!((counted.start, counted.end), (range.start, range.end));
+        }
+        let counted = attributed_range(&session, &may, MonthAttribution::Start, zone).unwrap();
+        assert_eq!((counted.start, counted.end), (session.start, session.end));
+        assert!(attributed_range(&session, &june, MonthAttribution::Start, zone).is_none());
+    }
+
+    #[test]
+    fn split_attribution_reports_each_month_its_part() {
+        let config = month_config(MonthAttribution::Split);
+        let time_sheet = TimeSheet { periods: vec![across_months()], ..TimeSheet::default() };
+        let now = utc("2024-07-01T00:00:00Z");
+
+        let may = build_report(&time_sheet, &config, "month", &month_period(2024, 5, config.zone()), now);
+        assert_eq!(may.total, Duration::hours(1));
+        assert_eq!(may.projects[0].total, Duration::hours(1));
+        assert_eq!(day_total(&may, "2024-05-31"), Duration::hours(1));
+        assert_eq!(may.days.iter().map(|row| row.total).sum::<Duration>(), may.total);
+
+        let june = build_report(&time_sheet, &config, "month", &month_period(2024, 6, config.zone()), now);
+        assert_eq!(june.total, Duration::hours(1));
+        assert_eq!(day_total(&june, "2024-06-01"), Duration::hours(1));
+        assert_eq!(june.days.iter().map(|row| row.total).sum::<Duration>(), june.total);
+    }
+
+    #[test]
+    fn start_attribution_reports_the_session_in_its_start_month() {
+        let config = month_config(MonthAttribution::Start);
+        let time_sheet = TimeSheet { periods: vec![across_months()], ..TimeSheet::default() };
+        let now = utc("2024-07-01T00:00:00Z");
+
+        let may = build_report(&time_sheet, &config, "month", &month_period(2024, 5, config.zone()), now);
+        assert_eq!(may.total, Duration::hours(2));
+        assert_eq!(may.projects[0].total, Duration::hours(2));
+        // The time after midnight counts on the day the session started.
+        assert_eq!(day_total(&may, "2024-05-31"), Duration::hours(2));
+        assert_eq!(may.days.iter().map(|row| row.total).sum::<Duration>(), may.total);
+
+        let june = build_report(&time_sheet, &config, "month", &month_period(2024, 6, config.zone()), now);
+        assert_eq!(june.total, Duration::zero());
+        assert!(june.periods.is_empty() && june.projects.is_empty());
+        assert_eq!(day_total(&june, "2024-06-01"), Duration::zero());
+    }
+}
@@ -0,0 +1,103 @@
+// Project budgets from `[budgets]`: how much of each budget has been used, and
+// whether that is fine (green), getting close (yellow) or used up (red). Budgets
+// with `alerts` also report when their use crosses one of the given percentages.
+
+use crate::config::Budget;
+use crate::index::DailyIndex;
+use crate::zone::Zone;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+// Share of a budget from which it shows as yellow.
+const WARN_PERCENT: f64 = 80.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetState {
+    Green,
+    Yellow,
+    Red,
+}
+
+impl BudgetState {
+    // ANSI color code for the state.
+    pub fn color(&self) -> &'static str {
+        match self {
+            BudgetState::Green => "32",
+            BudgetState::Yellow => "33",
+            BudgetState::Red => "31",
+        }
+    }
+
+    // Shown after the numbers, so the state is clear without color too.
+    pub fn label(&self) -> &'static str {
+        match self {
+            BudgetState::Green => "",
+            BudgetState::Yellow => ", nearly used up",
+            BudgetState::Red => ", used up",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BudgetUse {
+    pub project: String,
+    pub used: Duration,
+    pub budget: Duration,
+}
+
+impl BudgetUse {
+    pub fn percent(&self) -> f64 {
+        if self.budget <= Duration::zero() {
+            return 100.0;
+        }
+        self.used.num_seconds() as f64 * 100.0 / self.budget.num_seconds() as f64
+    }
+
+    pub fn state(&self) -> BudgetState {
+        match self.percent() {
+            p if p >= 100.0 => BudgetState::Red,
+            p if p >= WARN_PERCENT => BudgetState::Yellow,
+            _ => BudgetState::Green,
+        }
+    }
+}
+
+// Use of every budget, from the daily index plus the running session up to `now`.
+// Days count from their start, so `since` includes all of that day.
+pub fn usage(index: &DailyIndex, budgets: &BTreeMap<String, Budget>, now: DateTime<Utc>) -> Vec<BudgetUse> {
+    budgets
+        .iter()
+        .map(|(project, budget)| {
+            let mut used = Duration::seconds(
+                index
+                    .days()
+                    .filter(|(day, _)| budget.since.is_none_or(|since| *day >= since))
+                    .filter_map(|(_, totals)| totals.projects.get(project))
+                    .sum(),
+            );
+            if let Some((start, metadata)) = index.active()
+                && metadata.project.as_deref() == Some(project.as_str())
+            {
+                let from = match budget.since.and_then(|since| crate::logic::day_period(since, Zone::default())) {
+                    Some(day) => start.max(day.start),
+                    None => start,
+                };
+                if now > from {
+                    used += now - from;
+                }
+            }
+            BudgetUse { project: project.clone(), used, budget: Duration::seconds((budget.hours * 3600.0) as i64) }
+        })
+        .collect()
+}
+
+// The highest alert of `budget` that `used` has reached, if any.
+pub fn reached_alert(budget: &Budget, used: &BudgetUse) -> Option<u32> {
+    budget.alerts.iter().copied().filter(|alert| used.percent() >= *alert as f64).max()
+}
+
+// The alert to send for a budget, given the highest one sent before: the highest
+// reached, if that is above it. Several crossed at once give one alert.
+pub fn due_alert(budget: &Budget, used: &BudgetUse, sent: Option<u32>) -> Option<u32> {
+    reached_alert(budget, used).filter(|alert| sent.is_none_or(|sent| *alert > sent))
+}
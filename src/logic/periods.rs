@@ -0,0 +1,368 @@
+// Reporting periods named in the `[periods]` section of the config, so that
+// `report payperiod` covers the current pay period like `report month` covers
+// the current month. Two kinds of period are understood:
+//
+// - "26th..25th": from a day of one month to the day before it in the next. Days
+//   a month does not have fall on its last day, so "31st..30th" starts on
+//   February 28. "1st..last" is the calendar month.
+// - "2-week cycles anchored 2024-01-03": back-to-back cycles of a number of
+//   days or weeks, one of which starts on the anchor date.
+// - "rolling 30 days": the trailing window ending now, whatever the weekday or
+//   time of day, as `report rolling 30` gives without an alias.
+//
+// The built-in names today, week and month cannot be replaced.
+//
+// ISO 8601 weeks, as `report weeks` takes them, are read here as well. They start
+// on Monday, whatever `week_start` says, and belong to the year their Thursday
+// falls in: 2020-12-31 is in 2020-W53 and 2021-01-03 still is too, 2024-12-30 is
+// in 2025-W01.
+
+use super::local_days;
+use crate::config::Config;
+use crate::zone::Zone;
+use crate::Period;
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Utc, Weekday};
+use std::io;
+
+// What a period alias describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeriodSpec {
+    // From this day of a month to the day before it in the next month.
+    Monthly { first_day: u32 },
+    // Cycles of `days` days, one of them starting on `anchor`.
+    Cycle { days: i64, anchor: NaiveDate },
+    // The last this many days up to now.
+    RollingDays(u32),
+}
+
+impl PeriodSpec {
+    // Parses the text of an alias, e.g. "26th..25th", "2-week cycles anchored
+    // 2024-01-03" or "rolling 30 days".
+    pub fn parse(text: &str) -> io::Result<PeriodSpec> {
+        let text = text.trim();
+        let spec = match text.split_once("..") {
+            Some((first, last)) => parse_monthly(first.trim(), last.trim()),
+            None => parse_cycle(text).or_else(|| parse_rolling(text)),
+        };
+        spec.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid period '{}'. Expected days of the month like \"26th..25th\", cycles like \"2-week cycles anchored 2024-01-03\" or \"rolling 30 days\".",
+                    text
+                ),
+            )
+        })
+    }
+
+    // The current period at `now`. Fails for a rolling window reaching back
+    // before the earliest date chrono can represent.
+    pub fn current(&self, now: DateTime<Utc>, zone: Zone) -> io::Result<Period> {
+        match *self {
+            PeriodSpec::RollingDays(days) => {
+                let start = now.checked_sub_signed(Duration::days(days as i64)).ok_or_else(|| too_long(days))?;
+                Ok(Period::range(start, now))
+            }
+            _ => self.containing(zone.date(now), zone),
+        }
+    }
+
+    // The period that contains `day`. A rolling window is the one ending at the
+    // end of `day`.
+    pub fn containing(&self, day: NaiveDate, zone: Zone) -> io::Result<Period> {
+        Ok(match *self {
+            PeriodSpec::Monthly { first_day } => {
+                let this_month = day_in_month(day, first_day);
+                let start = if day >= this_month {
+                    this_month
+                } else {
+                    day_in_month(day.checked_sub_months(Months::new(1)).unwrap_or(day), first_day)
+                };
+                let end = day_in_month(start.checked_add_months(Months::new(1)).unwrap_or(start), first_day);
+                local_days(start, (end - start).num_days(), zone)
+            }
+            PeriodSpec::Cycle { days, anchor } => {
+                let since = (day - anchor).num_days();
+                let start = Duration::try_days(since - since.rem_euclid(days))
+                    .and_then(|offset| anchor.checked_add_signed(offset))
+                    .filter(|start| Duration::try_days(days).and_then(|length| start.checked_add_signed(length)).is_some())
+                    .ok_or_else(|| cycle_too_long(days))?;
+                local_days(start, days, zone)
+            }
+            PeriodSpec::RollingDays(days) => {
+                let first = day.checked_sub_signed(Duration::days(days as i64 - 1)).ok_or_else(|| too_long(days))?;
+                local_days(first, days as i64, zone)
+            }
+        })
+    }
+}
+
+fn too_long(days: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("A rolling window of {} days reaches back before the earliest supported date.", days),
+    )
+}
+
+fn cycle_too_long(days: i64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("A cycle of {} days reaches beyond the latest supported date.", days),
+    )
+}
+
+// "26th", "1st", "2nd", "3rd" or a plain number.
+fn parse_ordinal(text: &str) -> Option<u32> {
+    let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = &text[digits.len()..];
+    if !["", "st", "nd", "rd", "th"].contains(&suffix) {
+        return None;
+    }
+    digits.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+fn parse_monthly(first: &str, last: &str) -> Option<PeriodSpec> {
+    let first_day = parse_ordinal(first)?;
+    let ends_before_next = match last {
+        "last" => first_day == 1,
+        _ => {
+            let last_day = parse_ordinal(last)?;
+            last_day + 1 == first_day || (first_day == 1 && last_day == 31)
+        }
+    };
+    ends_before_next.then_some(PeriodSpec::Monthly { first_day })
+}
+
+// "<N>-week cycles anchored <YYYY-MM-DD>", or with days instead of weeks.
+fn parse_cycle(text: &str) -> Option<PeriodSpec> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let [length, "cycles" | "cycle", "anchored", anchor] = words.as_slice() else {
+        return None;
+    };
+    let (count, unit) = length.split_once('-')?;
+    let count: i64 = count.parse().ok().filter(|count| *count > 0)?;
+    let days = match unit {
+        "day" => count,
+        "week" => count.checked_mul(7)?,
+        _ => return None,
+    };
+    let anchor = NaiveDate::parse_from_str(anchor, "%Y-%m-%d").ok()?;
+    Some(PeriodSpec::Cycle { days, anchor })
+}
+
+// "rolling <N> days", or "rolling 1 day".
+fn parse_rolling(text: &str) -> Option<PeriodSpec> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let ["rolling", count, "days" | "day"] = words.as_slice() else {
+        return None;
+    };
+    count.parse().ok().filter(|count| *count > 0).map(PeriodSpec::RollingDays)
+}
+
+// Day `day_of_month` of the month `date` is in, or the month's last day if it
+// is shorter.
+fn day_in_month(date: NaiveDate, day_of_month: u32) -> NaiveDate {
+    (day_of_month.min(28)..=day_of_month)
+        .rev()
+        .find_map(|day| date.with_day(day))
+        .unwrap_or(date)
+}
+
+// The current period of the alias `name` from `[periods]`, or none if there is
+// no such alias.
+pub fn resolve(name: &str, config: &Config, now: DateTime<Utc>) -> io::Result<Option<Period>> {
+    let Some(text) = config.periods.get(name) else {
+        return Ok(None);
+    };
+    let spec = PeriodSpec::parse(text).map_err(|error| io::Error::new(error.kind(), format!("[periods] {}: {}", name, error)))?;
+    Ok(Some(spec.current(now, config.zone())?))
+}
+
+// Whether `name` is an alias for a rolling window in `[periods]`.
+pub fn is_rolling(name: &str, config: &Config) -> bool {
+    config
+        .periods
+        .get(name)
+        .and_then(|text| PeriodSpec::parse(text).ok())
+        .is_some_and(|spec| matches!(spec, PeriodSpec::RollingDays(_)))
+}
+
+// The Monday of an ISO week written as "2024-W05", "2024W05" or "2024-w5".
+pub fn parse_iso_week(text: &str) -> io::Result<NaiveDate> {
+    let text = text.trim();
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid ISO week '{}'. Expected the year and week number like 2024-W05.", text),
+        )
+    };
+    let (year, week) = text.split_once(['W', 'w']).ok_or_else(invalid)?;
+    let year: i32 = year.strip_suffix('-').unwrap_or(year).parse().map_err(|_| invalid())?;
+    let week: u32 = week.parse().map_err(|_| invalid())?;
+    NaiveDate::from_isoywd_opt(year, week, Weekday::Mon).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no week {}; its ISO weeks are 1 to {}.", year, week, iso_weeks_in(year)),
+        )
+    })
+}
+
+// How many ISO weeks a year has, 52 or 53. December 28 is always in the last one.
+pub fn iso_weeks_in(year: i32) -> u32 {
+    NaiveDate::from_ymd_opt(year, 12, 28).map_or(52, |day| day.iso_week().week())
+}
+
+// The ISO week of a day, as "2024-W05".
+pub fn iso_week_name(day: NaiveDate) -> String {
+    let week = day.iso_week();
+    format!("{}-W{:02}", week.year(), week.week())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DstPolicy;
+
+    fn utc_zone() -> Zone {
+        Zone::new(Some(chrono_tz::UTC), DstPolicy::Earliest)
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    // The first and last day of the period containing `day`.
+    fn days(spec: &PeriodSpec, day: &str) -> (NaiveDate, NaiveDate) {
+        let period = spec.containing(date(day), utc_zone()).unwrap();
+        (period.start.date_naive(), period.end.date_naive().pred_opt().unwrap())
+    }
+
+    #[test]
+    fn parse_accepts_the_documented_forms() {
+        let cases = [
+            ("26th..25th", PeriodSpec::Monthly { first_day: 26 }),
+            (" 2nd .. 1st ", PeriodSpec::Monthly { first_day: 2 }),
+            ("31st..30th", PeriodSpec::Monthly { first_day: 31 }),
+            ("1st..last", PeriodSpec::Monthly { first_day: 1 }),
+            ("1..31", PeriodSpec::Monthly { first_day: 1 }),
+            ("2-week cycles anchored 2024-01-03", PeriodSpec::Cycle { days: 14, anchor: date("2024-01-03") }),
+            ("10-day cycle anchored 2024-01-01", PeriodSpec::Cycle { days: 10, anchor: date("2024-01-01") }),
+            ("rolling 30 days", PeriodSpec::RollingDays(30)),
+            ("rolling 1 day", PeriodSpec::RollingDays(1)),
+        ];
+        for (text, spec) in cases {
+            assert_eq!(PeriodSpec::parse(text).unwrap(), spec, "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_everything_else() {
+        let cases = [
+            "",
+            "26th..24th",
+            "25th..26th",
+            "2nd..last",
+            "0..31",
+            "32nd..31st",
+            "26xx..25th",
+            "0-week cycles anchored 2024-01-03",
+            "-2-week cycles anchored 2024-01-03",
+            "2-month cycles anchored 2024-01-03",
+            "2-week cycles anchored 2024-13-01",
+            "2-week cycles from 2024-01-03",
+            "2000000000000000000-week cycles anchored 2024-01-03",
+            "rolling 0 days",
+            "rolling days",
+            "rolling 30 weeks",
+        ];
+        for text in cases {
+            let error = PeriodSpec::parse(text).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", text);
+        }
+    }
+
+    #[test]
+    fn monthly_periods_run_to_the_day_before_the_first_day() {
+        let spec = PeriodSpec::Monthly { first_day: 26 };
+        assert_eq!(days(&spec, "2024-05-25"), (date("2024-04-26"), date("2024-05-25")));
+        assert_eq!(days(&spec, "2024-05-26"), (date("2024-05-26"), date("2024-06-25")));
+        assert_eq!(days(&spec, "2024-05-31"), (date("2024-05-26"), date("2024-06-25")));
+        assert_eq!(days(&spec, "2024-06-01"), (date("2024-05-26"), date("2024-06-25")));
+        // Across the turn of the year and through February.
+        assert_eq!(days(&spec, "2024-01-10"), (date("2023-12-26"), date("2024-01-25")));
+        assert_eq!(days(&spec, "2024-02-29"), (date("2024-02-26"), date("2024-03-25")));
+        assert_eq!(days(&spec, "2023-02-25"), (date("2023-01-26"), date("2023-02-25")));
+
+        let calendar = PeriodSpec::Monthly { first_day: 1 };
+        assert_eq!(days(&calendar, "2024-02-15"), (date("2024-02-01"), date("2024-02-29")));
+    }
+
+    #[test]
+    fn monthly_days_a_month_lacks_fall_on_its_last_day() {
+        let spec = PeriodSpec::Monthly { first_day: 31 };
+        assert_eq!(days(&spec, "2024-01-31"), (date("2024-01-31"), date("2024-02-28")));
+        assert_eq!(days(&spec, "2024-02-15"), (date("2024-01-31"), date("2024-02-28")));
+        assert_eq!(days(&spec, "2024-02-29"), (date("2024-02-29"), date("2024-03-30")));
+        assert_eq!(days(&spec, "2023-02-28"), (date("2023-02-28"), date("2023-03-30")));
+        assert_eq!(days(&spec, "2024-03-31"), (date("2024-03-31"), date("2024-04-29")));
+        assert_eq!(days(&spec, "2024-04-30"), (date("2024-04-30"), date("2024-05-30")));
+    }
+
+    #[test]
+    fn monthly_periods_cover_every_day_once() {
+        for first_day in [1, 15, 26, 29, 30, 31] {
+            let spec = PeriodSpec::Monthly { first_day };
+            let mut period = spec.containing(date("2023-11-01"), utc_zone()).unwrap();
+            for day in date("2023-11-01").iter_days().take_while(|day| *day <= date("2025-03-31")) {
+                if period.end.date_naive() <= day {
+                    let next = spec.containing(day, utc_zone()).unwrap();
+                    assert_eq!(next.start, period.end, "{} on {}", first_day, day);
+                    period = next;
+                }
+                assert!(period.start.date_naive() <= day && day < period.end.date_naive());
+            }
+        }
+    }
+
+    #[test]
+    fn cycles_repeat_before_and_after_the_anchor() {
+        let spec = PeriodSpec::parse("2-week cycles anchored 2024-01-03").unwrap();
+        assert_eq!(days(&spec, "2024-01-03"), (date("2024-01-03"), date("2024-01-16")));
+        assert_eq!(days(&spec, "2024-01-16"), (date("2024-01-03"), date("2024-01-16")));
+        assert_eq!(days(&spec, "2024-01-17"), (date("2024-01-17"), date("2024-01-30")));
+        assert_eq!(days(&spec, "2024-01-02"), (date("2023-12-20"), date("2024-01-02")));
+        assert_eq!(days(&spec, "2023-12-20"), (date("2023-12-20"), date("2024-01-02")));
+        assert_eq!(days(&spec, "2023-12-19"), (date("2023-12-06"), date("2023-12-19")));
+        assert_eq!(days(&spec, "2022-01-01"), (date("2021-12-22"), date("2022-01-04")));
+    }
+
+    #[test]
+    fn cycles_beyond_the_supported_dates_fail() {
+        let spec = PeriodSpec::parse("1000000000000-day cycles anchored 2024-01-03").unwrap();
+        let error = spec.containing(date("2024-05-01"), utc_zone()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rolling_windows_end_now_or_with_the_day() {
+        let spec = PeriodSpec::RollingDays(7);
+        let now = utc("2024-05-15T13:45:00Z");
+        let current = spec.current(now, utc_zone()).unwrap();
+        assert_eq!((current.start, current.end), (utc("2024-05-08T13:45:00Z"), now));
+        assert_eq!(days(&spec, "2024-05-15"), (date("2024-05-09"), date("2024-05-15")));
+        assert!(PeriodSpec::RollingDays(u32::MAX).current(now, utc_zone()).is_err());
+    }
+
+    #[test]
+    fn iso_weeks_belong_to_the_year_of_their_thursday() {
+        assert_eq!(parse_iso_week("2024-W05").unwrap(), date("2024-01-29"));
+        assert_eq!(parse_iso_week("2025w1").unwrap(), date("2024-12-30"));
+        assert_eq!(iso_week_name(date("2021-01-03")), "2020-W53");
+        assert_eq!(iso_weeks_in(2020), 53);
+        assert!(parse_iso_week("2024-W53").is_err());
+    }
+}
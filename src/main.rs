@@ -1,241 +1,749 @@
-use chrono::{DateTime, Utc, Duration, Local, Datelike, NaiveDate, TimeZone};
-use serde::{Serialize, Deserialize};
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, BufWriter};
-use std::env;
-use std::path::PathBuf;
-use std::cmp;
-
-// Represents a single time period with a start and end time.
-// Added Clone and Copy to make it easier to pass around.
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
-struct Period {
-    start: DateTime<Utc>,
-    end: DateTime<Utc>,
-}
+mod cli;
+mod completions;
+mod init;
 
-impl Period {
-    /// Calculates the overlapping duration between this period and another.
-    fn overlap(&self, other: &Period) -> Duration {
-        let overlap_start = cmp::max(self.start, other.start);
-        let overlap_end = cmp::min(self.end, other.end);
+use chrono::{DateTime, Duration, Local, Utc};
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Read};
+use std::env;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{debug, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use clap::{CommandFactory, Parser};
+use cli::{AbsenceAction, BatchCommand, BatchLine, Cli, CloseAction, Command, DaemonAction, ExportTarget, FavoriteAction, GdprAction, ImportSource, MetricsAction, NoteAction, PlanAction, ReportAction, ReviewAction, SecretAction, StatsAction, SuggestAction, SyncTarget, TagAction};
+use time_tracker::clock::{Clock, FixedClock, SystemClock};
+use time_tracker::journal::{self, JournalEntry};
+use time_tracker::storage::{self, get_data_file_path, load_or_create_timesheet, save_timesheet};
+use time_tracker::timefmt::{self, TimeFormatter};
+use time_tracker::tracker::Transaction;
+use time_tracker::{absence, activity, anonymize, balance, capacity, closing, columnar, compaction, config, daemon, diary, doctor, export, focus, gaps, import, index, integrations, invoice, laps, logic, metrics, notes, pattern, plan, presets, prompt, reminders, report, retag, retention, review, search, secrets, serve, stats, suspend, sync, templating, timewarrior, zone, Metadata, Period, TimeSheet};
 
-        if overlap_start < overlap_end {
-            overlap_end - overlap_start
-        } else {
-            Duration::zero()
-        }
+// Main function to parse command-line arguments and dispatch to the correct handler.
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+    init_logging(&cli)?;
+    debug!(?cli, "parsed command line");
+    // Before the config is loaded, so a broken config is reported, not fatal.
+    if let Command::Doctor { full, json } = cli.command {
+        return doctor::run(full, json);
     }
-}
 
-// Represents the overall state of the time tracker.
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct TimeSheet {
-    periods: Vec<Period>,
-    active_period_start: Option<DateTime<Utc>>,
-}
+    let mut config = config::load_config()?;
+    let formatter = TimeFormatter::new(cli.hours_format.unwrap_or(config.hours_format))
+        .with_precision(cli.precision.unwrap_or(config.precision))
+        .with_dates(config.date_format.clone(), config.clock_format);
+    let clock: &dyn Clock = match cli.now {
+        Some(time) => &FixedClock::new(time),
+        None => &SystemClock,
+    };
 
-// Main function to parse command-line arguments and dispatch to the correct handler.
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    // These commands only touch the config, not the timesheet.
+    match cli.command {
+        Command::Favorite { action } => return handle_favorite(&mut config, action),
+        Command::Completions { shell } => {
+            return completions::generate(shell, &bin_name(), &mut io::stdout());
+        }
+        Command::Convert { input, output } => return storage::convert(&input, &output),
+        Command::Secret { action } => return handle_secret(action),
+        _ => {}
+    }
 
-    if args.len() != 2 {
-        print_usage();
-        return Ok(());
+    let data_path = get_data_file_path(&config)?;
+    if let Command::Init { defaults } = cli.command {
+        return init::run_init(&mut config, &data_path, defaults);
+    }
+    // Not before `init`, which saves the config.
+    if cli.strict && !config.strict {
+        info!("strict loading enabled");
+        config.strict = true;
+    }
+    if let Command::Status { cached } = cli.command {
+        return index::print_status(&data_path, &config, cached, &formatter, clock);
+    }
+    if let Command::Search { query, limit } = &cli.command {
+        return search::search(&data_path, &config, &query.join(" "), *limit, &formatter, clock);
+    }
+    if let Command::Activity = cli.command {
+        return activity::record(&data_path, clock.now());
+    }
+    if let Command::Remind { once } = cli.command {
+        return reminders::watch(&data_path, &config, once, &formatter, clock);
+    }
+    if let Command::Daemon { action } = cli.command {
+        return match action {
+            DaemonAction::Start => daemon::start(&data_path, config.strict),
+            DaemonAction::Status => daemon::status(&data_path),
+            DaemonAction::Stop => daemon::stop(&data_path).map(|_| ()),
+            DaemonAction::Restart => daemon::restart(&data_path, config.strict),
+        };
+    }
+    if let Command::Serve { port, bind } = cli.command {
+        return serve::serve(&data_path, &config, bind, port, &formatter, clock);
     }
 
-    let command = &args[1];
-    let mut time_sheet = load_or_create_timesheet()?;
+    if let Command::Report { tz: Some(tz), .. } = &cli.command {
+        let tz = zone::parse(tz)?;
+        info!(%tz, "using time zone for reports");
+        config.time_zone = Some(tz);
+    }
+    // Reports only need the months they cover, which saves reading with the
+    // monthly storage layout.
+    let report_range = match &cli.command {
+        Command::Today => Some(logic::reporting_period("today", &config, clock)?),
+        Command::Week => Some(logic::reporting_period("week", &config, clock)?),
+        Command::Month => Some(logic::reporting_period("month", &config, clock)?),
+        Command::Report { action: Some(ReportAction::Missing { month }), .. } => {
+            let (year, month) = closing::month_or_current(month.as_deref(), clock)?;
+            Some(logic::month_period(year, month, config.zone()))
+        }
+        Command::Report { action: Some(ReportAction::Weeks { from, to }), .. } => {
+            Some(logic::weeks_range(&logic::iso_weeks(from, to.as_deref(), clock, config.zone())?, config.zone()))
+        }
+        Command::Report { period: Some(period), days, .. } => Some(report_period(period, *days, &config, clock)?.1),
+        // A day and the ones around it, for sessions across midnight.
+        Command::Journal { day, .. } => {
+            let day = gaps::parse_day(day, clock)?;
+            Some(logic::week_starting(day.pred_opt().unwrap_or(day), config.zone()))
+        }
+        _ => None,
+    };
+    let mut time_sheet = match &report_range {
+        Some(range) => storage::load_range(&data_path, range, config.load_options(clock.now()))?,
+        None => load_or_create_timesheet(&data_path, config.load_options(clock.now()))?,
+    };
+    let snapshot = sync::Snapshot::take(&data_path, &time_sheet)?;
     let mut state_changed = false;
+    // Set by commands whose change can be appended to the journal instead of
+    // rewriting the data file.
+    let mut journal_entry = None;
 
-    match command.as_str() {
-        "start" => {
-            state_changed = start_tracking(&mut time_sheet)?;
+    match cli.command {
+        Command::Start { favorite, resume, task, remind_after, metadata } => {
+            let remind_after = remind_after.as_deref().map(timefmt::parse_duration).transpose()?;
+            let mut metadata = resolve_start_metadata(&config, favorite.as_deref(), metadata.into())?;
+            if resume {
+                let Some(last) = time_sheet.periods().iter().max_by_key(|period| period.end()) else {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "No stopped session to resume."));
+                };
+                metadata = metadata.or(last.metadata());
+                if !metadata.is_empty() {
+                    println!("Resuming {}", metadata.describe());
+                }
+            }
+            if let Some(task) = &task {
+                metadata = integrations::taskwarrior::with_task(&config.taskwarrior, task, metadata)?;
+            }
+            if metadata.project.is_none() {
+                metadata.project = config.default_project.clone();
+            }
+            focus::check_category(&config, &metadata)?;
+            if !confirm_tracking_window(&config, &time_sheet, &formatter, clock)? {
+                println!("Not started.");
+                return Ok(());
+            }
+            state_changed = logic::start_tracking(&mut time_sheet, metadata, remind_after, clock)?;
+            if let Some(start) = time_sheet.active_period_start() {
+                journal_entry = Some(JournalEntry::Start {
+                    start,
+                    metadata: time_sheet.active_metadata().clone(),
+                    remind_after: time_sheet.active_remind_after().map(|after| after.num_seconds() as u64),
+                    suspend: time_sheet.active_suspend().cloned(),
+                });
+            }
+        }
+        Command::Add { start, end, duration, metadata } => {
+            let mut metadata = Metadata::from(metadata);
+            if metadata.project.is_none() {
+                metadata.project = config.default_project.clone();
+            }
+            focus::check_category(&config, &metadata)?;
+            let start = logic::parse_local_time(&start, clock, config.zone())?;
+            state_changed = match (end, duration) {
+                (_, Some(duration)) => {
+                    let duration = timefmt::parse_duration(&duration)?;
+                    logic::add_manual_period_with_duration(&mut time_sheet, start, duration, metadata, &formatter, clock)?
+                }
+                (Some(end), None) => {
+                    let end = logic::parse_local_time(&end, clock, config.zone())?;
+                    logic::add_manual_period(&mut time_sheet, start, end, metadata, &formatter, clock)?
+                }
+                (None, None) => unreachable!("clap requires --end or --duration"),
+            };
+        }
+        Command::Stop { keep, at_last_activity } => {
+            let minimum = minimum_session(&config, keep);
+            let last_before = time_sheet.periods().last().cloned();
+            let end = session_end(&data_path, &time_sheet, at_last_activity, &config, &formatter, clock)?;
+            let end_clock = end.map(FixedClock::new);
+            let stop_clock: &dyn Clock = match &end_clock {
+                Some(end_clock) => end_clock,
+                None => clock,
+            };
+            integrations::before_stop(&config, &mut time_sheet, stop_clock.now());
+            state_changed = logic::stop_tracking(&mut time_sheet, &formatter, minimum, stop_clock)?;
+            journal_entry = Some(journal::stop_entry(last_before.as_ref(), &time_sheet));
+        }
+        Command::Switch { target, keep, at_last_activity, metadata } => {
+            let mut metadata = Metadata::from(metadata);
+            match target {
+                Some(favorite) if favorite.starts_with('@') => {
+                    metadata = resolve_start_metadata(&config, Some(&favorite), metadata)?;
+                }
+                Some(project) => metadata.project = Some(project),
+                None => {}
+            }
+            if metadata.project.is_none() {
+                metadata.project = config.default_project.clone();
+            }
+            focus::check_category(&config, &metadata)?;
+            // The old session ends as for `stop`; the new one starts now.
+            let end = session_end(&data_path, &time_sheet, at_last_activity, &config, &formatter, clock)?;
+            integrations::before_stop(&config, &mut time_sheet, end.unwrap_or_else(|| clock.now()));
+            let period = logic::switch_tracking(&mut time_sheet, metadata, &formatter, minimum_session(&config, keep), end, clock)?;
+            if let Some(start) = time_sheet.active_period_start() {
+                journal_entry = Some(JournalEntry::Switch {
+                    period,
+                    start,
+                    metadata: time_sheet.active_metadata().clone(),
+                    suspend: time_sheet.active_suspend().cloned(),
+                });
+            }
+            state_changed = true;
+        }
+        Command::Journal { day, output } => {
+            let day = gaps::parse_day(&day, clock)?;
+            let markdown = diary::render(&time_sheet, &config, day, &formatter, clock.now())?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, markdown + "\n")?;
+                    println!("Wrote the journal of {} to {}.", formatter.date(day), path.display());
+                }
+                None => println!("{}", markdown),
+            }
+        }
+        Command::Compact => {
+            let entries = journal::entry_count(&data_path)?;
+            let before = storage::size_on_disk(&data_path)?;
+            let compaction = compaction::canonicalize(&mut time_sheet, &config.compaction);
+            sync::reconcile(&data_path, &snapshot, &mut time_sheet, cli.conflicts, config.load_options(clock.now()), config.zone())?;
+            save_timesheet(&data_path, &time_sheet)?;
+            let after = storage::size_on_disk(&data_path)?;
+            println!("Compacted {} journal entries into {}.", entries, data_path.display());
+            if compaction.changed() {
+                println!(
+                    "Merged {} period(s), cleaned up metadata in {} place(s){}.",
+                    compaction.merged,
+                    compaction.cleaned,
+                    if compaction.reordered { ", sorted periods by start" } else { "" }
+                );
+            }
+            println!("Size: {} bytes before, {} bytes after ({} saved).", before, after, before.saturating_sub(after));
+        }
+        Command::Log { limit, cumulative, details } => {
+            logic::show_log(&time_sheet, limit, cumulative, details, config.week_start.0, &formatter, clock)
+        }
+        Command::Batch => state_changed = run_batch(&mut time_sheet, &config, &formatter, clock)?,
+        Command::Lap { note } => state_changed = laps::record_lap(&mut time_sheet, note, &formatter, clock)?,
+        Command::Note { action: NoteAction::Edit { id } } => state_changed = notes::edit_note(&mut time_sheet, &id, &formatter)?,
+        Command::Today => report::print_summary(&logic::named_report(&time_sheet, "today", &config, clock)?, &formatter),
+        Command::Week => report::print_summary(&logic::named_report(&time_sheet, "week", &config, clock)?, &formatter),
+        Command::Month => report::print_summary(&logic::named_report(&time_sheet, "month", &config, clock)?, &formatter),
+        Command::Report { action: Some(ReportAction::Missing { month }), .. } => {
+            let (year, month) = closing::month_or_current(month.as_deref(), clock)?;
+            let range = logic::month_period(year, month, config.zone());
+            let report = logic::build_report(&time_sheet, &config, &format!("{}-{:02}", year, month), &range, clock.now());
+            report::print_missing(&report, &config, clock.now().with_timezone(&Local).date_naive(), &formatter);
+        }
+        Command::Report { action: Some(ReportAction::Weeks { from, to }), .. } => {
+            let mondays = logic::iso_weeks(&from, to.as_deref(), clock, config.zone())?;
+            report::print_weeks(&logic::week_rows(&time_sheet, &config, &mondays, clock.now()), &formatter);
+        }
+        Command::Report { period, days, template, dump_context, filter, group_by, .. } => {
+            let Some(period) = period else { unreachable!("clap requires a period without a subcommand") };
+            let (title, range, rolling) = report_period(&period, days, &config, clock)?;
+            let mut report = if filter.is_empty() {
+                logic::build_report(&time_sheet, &config, &title, &range, clock.now())
+            } else {
+                logic::build_report(&retag::only_matching(&time_sheet, &filter), &config, &title, &range, clock.now())
+            };
+            report.rolling = rolling;
+            let context = templating::build_context(&report, &group_by, &formatter);
+            if dump_context {
+                println!("{}", serde_json::to_string_pretty(&context).map_err(io::Error::other)?);
+            } else {
+                print!("{}", templating::render(&context, template.as_deref())?);
+            }
+        }
+        Command::Metrics { action: MetricsAction::Export { textfile } } => {
+            metrics::export(&time_sheet, &config, textfile.as_deref(), clock)?
+        }
+        Command::Focus { weeks } => focus::show_focus(&time_sheet, &config, weeks, &formatter, clock)?,
+        Command::Capacity { weeks } => capacity::show_capacity(&time_sheet, &config, weeks, &formatter, clock)?,
+        Command::Balance { weeks } => balance::show_balance(&time_sheet, &config, weeks, &formatter, clock)?,
+        Command::Invoice { month } => invoice::show_invoice(&time_sheet, &config, month.as_deref(), &formatter, clock)?,
+        Command::Stats { action: StatsAction::OnThisDay { date, years, weeks } } => {
+            stats::on_this_day(&time_sheet, date.as_deref(), years, weeks, &formatter, clock)?
+        }
+        Command::Stats { action: StatsAction::Anomalies { days, sigma } } => {
+            stats::show_anomalies(&time_sheet, &config, days, sigma, &formatter, clock)
+        }
+        Command::Stats { action: StatsAction::Pattern { weeks } } => pattern::show_pattern(&time_sheet, &config, weeks, &formatter, clock)?,
+        Command::Suggest { action: SuggestAction::Gaps { day, min_minutes, list, configured_hours } } => {
+            let search = gaps::GapSearch { minimum_minutes: min_minutes, list_only: list, configured_hours };
+            state_changed = gaps::suggest_gaps(&mut time_sheet, &config, &day, search, &formatter, clock)?;
+        }
+        Command::Absence { action: AbsenceAction::Add { kind, days, note } } => {
+            state_changed = absence::add_absence(&mut time_sheet, kind, &days, note)?;
+        }
+        Command::Plan { action: PlanAction::Set { week, projects } } => {
+            state_changed = plan::set_plan(&mut time_sheet, &config, &week, &projects, &formatter, clock)?;
+        }
+        Command::Plan { action: PlanAction::Status { week } } => plan::show_status(&time_sheet, &config, &week, &formatter, clock)?,
+        Command::Plan { action: PlanAction::Clear { week } } => {
+            state_changed = plan::clear_plan(&mut time_sheet, &config, &week, &formatter, clock)?;
+        }
+        Command::Review { action: ReviewAction::Week { all } } => {
+            state_changed = review::review_week(&mut time_sheet, &config, all, &formatter, clock)?;
+        }
+        Command::Tag { action: TagAction::Rename { old, new, dry_run } } => {
+            state_changed = retag::rename_tag(&mut time_sheet, &old, &new, dry_run, &formatter)?;
+        }
+        Command::Retag { filter, add, remove, category, dry_run } => {
+            let category = category.map(|category| focus::parse_category(&config, &category)).transpose()?;
+            state_changed = retag::retag(&mut time_sheet, &filter, &add, &remove, category.as_deref(), dry_run, &formatter)?;
+        }
+        Command::Absence { action: AbsenceAction::List } => absence::list_absences(&time_sheet),
+        Command::Sync { target: SyncTarget::Caldav { dry_run } } => {
+            let Some(caldav) = &config.caldav else {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No [caldav] section in the config. Add url and username to use 'sync caldav'.",
+                ));
+            };
+            integrations::caldav::sync(&time_sheet, caldav, &data_path, dry_run)?;
+        }
+        Command::Sync { target: SyncTarget::Remote } => {
+            integrations::remote::push(&time_sheet, remote_config(&config)?, &formatter, clock)?;
+        }
+        Command::Sync { target: SyncTarget::Pull { list: true, .. } } => {
+            integrations::remote::list(remote_config(&config)?, &formatter)?;
+        }
+        Command::Sync { target: SyncTarget::Pull { at, yes, .. } } => {
+            let remote = remote_config(&config)?;
+            state_changed = integrations::remote::pull(&mut time_sheet, remote, at.as_deref(), yes, &config, &formatter, clock)?;
+        }
+        Command::Close { action: CloseAction::Month { month } } => {
+            state_changed = closing::close_month(&mut time_sheet, &config, &month, &formatter, clock)?;
+        }
+        Command::Gdpr { action: GdprAction::Enforce { dry_run, yes, export } } => {
+            state_changed = retention::enforce(&mut time_sheet, &config, dry_run, yes, export.as_deref(), &formatter, clock)?;
         }
-        "stop" => {
-            state_changed = stop_tracking(&mut time_sheet)?;
+        Command::Close { action: CloseAction::List } => closing::list_closed_months(&time_sheet, &formatter),
+        Command::Import { dry_run, source } => {
+            let run = |time_sheet: &mut TimeSheet| match source {
+                ImportSource::Timew { file, metadata } => {
+                    timewarrior::import_timew(time_sheet, &file, metadata.into(), &config.import.timew, dry_run)
+                }
+                ImportSource::Ics { file, filter, metadata } => {
+                    let mut metadata = Metadata::from(metadata);
+                    if metadata.tags.is_empty() {
+                        metadata.tags.push("meeting".to_string());
+                    }
+                    import::import_ics(time_sheet, &file, filter.as_deref(), metadata, &config.import.ics, dry_run, clock.now())
+                }
+            };
+            if dry_run {
+                let (summary, preview) = Transaction::preview(&time_sheet, clock, |change| run(change.time_sheet_mut()))?;
+                import::print_preview(&time_sheet, &preview, &summary, &formatter);
+            } else {
+                state_changed = run(&mut time_sheet)?.imported > 0;
+            }
         }
-        "today" | "week" | "month" => {
-            report_summary(&time_sheet, command.as_str())?;
+        Command::Export { anonymize, target: ExportTarget::Timew { output } } => {
+            if anonymize {
+                time_sheet = anonymize::anonymize(&time_sheet);
+            }
+            let json = timewarrior::export_timew(&time_sheet)?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, json + "\n")?;
+                    println!("Exported {} period(s) to {}.", time_sheet.periods().len(), path.display());
+                }
+                None => println!("{}", json),
+            }
+        }
+        Command::Export { anonymize, target: ExportTarget::Grid { month, format, output } } => {
+            if anonymize {
+                time_sheet = anonymize::anonymize(&time_sheet);
+            }
+            let (year, month) = closing::month_or_current(month.as_deref(), clock)?;
+            let range = logic::month_period(year, month, config.zone());
+            let report = logic::build_report(&time_sheet, &config, "month", &range, clock.now());
+            let title = format!("Timesheet {:04}-{:02}", year, month);
+            let contents = export::render_grid(&export::month_grid(&report), format, &title, &formatter);
+            match output {
+                Some(path) => {
+                    fs::write(&path, contents)?;
+                    println!("Exported {} to {}.", title, path.display());
+                }
+                None => print!("{}", contents),
+            }
+        }
+        Command::Export { anonymize, target: ExportTarget::Parquet { output } } => {
+            if anonymize {
+                time_sheet = anonymize::anonymize(&time_sheet);
+            }
+            let periods: Vec<_> = time_sheet.periods().iter().collect();
+            columnar::write(&output, &periods, config.zone())?;
+            println!("Exported {} period(s) to {}.", periods.len(), output.display());
+        }
+        Command::Export { anonymize, target: ExportTarget::Preset { name, period, output } } => {
+            if anonymize {
+                time_sheet = anonymize::anonymize(&time_sheet);
+            }
+            let preset = presets::find(&name)?;
+            let report = logic::named_report(&time_sheet, &period, &config, clock)?;
+            let (contents, rows) = preset.render(&report, &config)?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, contents)?;
+                    println!("Exported {} row(s) for {} to {}.", rows, preset.description, path.display());
+                }
+                None => print!("{}", contents),
+            }
+        }
+        Command::CompleteHelper { kind } => {
+            for name in completions::candidates(kind, &time_sheet, &config) {
+                println!("{}", name);
+            }
+        }
+        Command::Doctor { .. }
+        | Command::Favorite { .. }
+        | Command::Completions { .. }
+        | Command::Convert { .. }
+        | Command::Secret { .. }
+        | Command::Init { .. }
+        | Command::Status { .. }
+        | Command::Search { .. }
+        | Command::Activity
+        | Command::Remind { .. }
+        | Command::Daemon { .. }
+        | Command::Serve { .. } => {
+            unreachable!("handled above")
         }
-        _ => print_usage(),
     }
 
     // Only save the timesheet if a change was actually made.
     if state_changed {
-        save_timesheet(&time_sheet)?;
+        let limit = config.compact_after_entries.unwrap_or(journal::DEFAULT_COMPACT_AFTER_ENTRIES);
+        // After a merge the journal entry no longer describes the whole change.
+        let merged = sync::reconcile(&data_path, &snapshot, &mut time_sheet, cli.conflicts, config.load_options(clock.now()), config.zone())?;
+        let index_before = index::load(&data_path)?;
+        match &journal_entry {
+            Some(entry) if !merged && limit > 0 && journal::append(&data_path, entry)? <= limit => {}
+            _ => save_timesheet(&data_path, &time_sheet)?,
+        }
         println!("State saved.");
+        if let Err(e) = index::update(&data_path, index_before, &time_sheet, journal_entry.as_ref(), clock.now()) {
+            // The index is only a cache; the next `status` rebuilds it.
+            warn!(error = %e, "could not update the daily index");
+        }
+        if let Some(entry) = &journal_entry {
+            integrations::run_hooks(&config, entry, &formatter);
+        }
+        if let Some(JournalEntry::Stop { .. } | JournalEntry::Switch { .. }) = &journal_entry
+            && let Err(e) = reminders::check_budgets(&data_path, &config, clock.now(), &formatter)
+        {
+            warn!(error = %e, "could not check the budget alerts");
+            eprintln!("Warning: could not check the budget alerts: {}", e);
+        }
     }
 
     Ok(())
 }
 
-// Prints the usage instructions for the command-line tool.
-fn print_usage() {
-    println!("Usage: work_time_tracker <command>");
-    println!("Commands:");
-    println!("  start   - Start tracking a new time period.");
-    println!("  stop    - Stop the currently tracked time period.");
-    println!("  today   - Show tracked time for today.");
-    println!("  week    - Show tracked time for this week.");
-    println!("  month   - Show tracked time for this month.");
+// The minimum session length `stop` and `switch` enforce, unless given `--keep`.
+fn minimum_session(config: &config::Config, keep: bool) -> Option<logic::MinimumSession> {
+    (!keep && config.min_session_seconds > 0).then(|| logic::MinimumSession {
+        length: Duration::seconds(config.min_session_seconds as i64),
+        policy: config.short_session_policy,
+    })
 }
 
-// Gets the path to the timesheet data file.
-fn get_data_file_path() -> io::Result<PathBuf> {
-    match dirs::home_dir() {
-        Some(mut path) => {
-            path.push(".work_time_tracker.json");
-            Ok(path)
-        }
-        None => Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Could not find home directory.",
-        )),
+// When `stop` or `switch` should end the running session, if not now: at the last
+// activity, or before the time the computer slept.
+fn session_end(
+    data_path: &Path,
+    time_sheet: &TimeSheet,
+    at_last_activity: bool,
+    config: &config::Config,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<Option<DateTime<Utc>>> {
+    match activity::stop_time(data_path, time_sheet, at_last_activity, config, formatter, clock)? {
+        Some(end) => Ok(Some(end)),
+        None => suspend::stop_time(time_sheet, config, formatter, clock),
     }
 }
 
-// Loads the TimeSheet from the data file.
-fn load_or_create_timesheet() -> io::Result<TimeSheet> {
-    let path = get_data_file_path()?;
-    if !path.exists() {
-        return Ok(TimeSheet::default());
-    }
-
-    let file = File::open(&path)?;
-    let reader = BufReader::new(file);
-
-    match serde_json::from_reader(reader) {
-        Ok(time_sheet) => Ok(time_sheet),
-        Err(e) if e.is_eof() => Ok(TimeSheet::default()),
-        Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+// The title and range of `report <period> [days]`: a reporting period by name, or
+// the trailing window of `report rolling <days>`. Also whether it is a rolling
+// window, from the command or from a `[periods]` alias.
+fn report_period(period: &str, days: Option<u32>, config: &config::Config, clock: &dyn Clock) -> io::Result<(String, Period, bool)> {
+    match (period, days) {
+        ("rolling", Some(days)) => Ok((format!("last {} days", days), logic::rolling_period(days, clock, config.zone())?, true)),
+        ("rolling", None) => Err(io::Error::new(io::ErrorKind::InvalidInput, "Say how many days, e.g. 'report rolling 7'.")),
+        (_, Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("A number of days only goes with 'rolling', not '{}'.", period),
+        )),
+        (_, None) => Ok((period.to_string(), logic::reporting_period(period, config, clock)?, logic::periods::is_rolling(period, config))),
     }
 }
 
-// Saves the TimeSheet data to the JSON file.
-fn save_timesheet(time_sheet: &TimeSheet) -> io::Result<()> {
-    let path = get_data_file_path()?;
-    let file = OpenOptions::new().write(true).truncate(true).create(true).open(&path)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer_pretty(writer, time_sheet).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+// The name the binary was invoked as, used in completion scripts.
+fn bin_name() -> String {
+    env::args()
+        .next()
+        .as_deref()
+        .map(Path::new)
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| Cli::command().get_name().to_string())
 }
 
-// Handles the "start" command.
-fn start_tracking(time_sheet: &mut TimeSheet) -> io::Result<bool> {
-    if let Some(start_time) = time_sheet.active_period_start {
-        println!("Already tracking time since {}.", start_time.with_timezone(&Local));
-        Ok(false)
-    } else {
-        let now = Utc::now();
-        time_sheet.active_period_start = Some(now);
-        println!("Started tracking time at {}.", now.with_timezone(&Local));
-        Ok(true)
+// Works out the metadata for `start [@favorite] [--project ..] [--tags ..] [--note ..]`.
+// Options given on the command line take precedence over the favorite.
+fn resolve_start_metadata(config: &config::Config, favorite: Option<&str>, metadata: Metadata) -> io::Result<Metadata> {
+    let Some(favorite) = favorite else {
+        return Ok(metadata);
+    };
+    let Some(name) = favorite.strip_prefix('@') else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Favorites are started as @name, got '{}'.", favorite),
+        ));
+    };
+    match config.favorites.get(name) {
+        Some(defaults) => Ok(metadata.or(defaults)),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No favorite named '{}'. See 'favorite list'.", name),
+        )),
     }
 }
 
-// Handles the "stop" command.
-fn stop_tracking(time_sheet: &mut TimeSheet) -> io::Result<bool> {
-    if let Some(start_time) = time_sheet.active_period_start.take() {
-        let end_time = Utc::now();
-        let new_period = Period { start: start_time, end: end_time };
-        time_sheet.periods.push(new_period);
-        let duration = end_time - start_time;
-        println!("Stopped tracking time at {}.", end_time.with_timezone(&Local));
-        println!("Duration of last session: {}", format_duration(duration));
-        Ok(true)
-    } else {
-        println!("No active time tracking period to stop.");
-        Ok(false)
+// Handles `batch`: applies the commands on standard input to the timesheet in
+// order. The first failing line stops the batch, and since the timesheet is only
+// saved afterwards, none of the lines before it are kept either.
+fn run_batch(time_sheet: &mut TimeSheet, config: &config::Config, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<bool> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    let mut applied = 0;
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let failed = |e: io::Error| {
+            io::Error::new(e.kind(), format!("Line {}: {} Nothing was changed.", index + 1, e.to_string().trim_end()))
+        };
+        let words = shlex::split(line)
+            .ok_or_else(|| failed(io::Error::new(io::ErrorKind::InvalidInput, "Unbalanced quotes.")))?;
+        let command = BatchLine::try_parse_from(words).map_err(|e| {
+            // Clap's message without the usage and tip below it, on one line.
+            let message = e.to_string();
+            let message = message.split("\n\n").next().unwrap_or_default().trim_start_matches("error: ");
+            let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+            failed(io::Error::new(io::ErrorKind::InvalidInput, format!("{}.", message.trim_end_matches(['.', ':']))))
+        })?;
+        debug!(line = index + 1, ?command, "applying batch line");
+        apply_batch_command(time_sheet, command.command, config, formatter, clock).map_err(failed)?;
+        applied += 1;
     }
+    println!("Applied {} command(s).", applied);
+    Ok(applied > 0)
 }
 
-/// Generates a Period struct representing the current day in the local timezone.
-fn get_today_period() -> Period {
-    let now_local = Local::now();
-    let today_local_naive = now_local.date_naive();
-    let start_naive = today_local_naive.and_hms_opt(0, 0, 0).unwrap();
-    let end_naive = start_naive + Duration::days(1);
-    Period {
-        start: Local.from_local_datetime(&start_naive).unwrap().to_utc(),
-        end: Local.from_local_datetime(&end_naive).unwrap().to_utc(),
+fn apply_batch_command(
+    time_sheet: &mut TimeSheet,
+    command: BatchCommand,
+    config: &config::Config,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<()> {
+    let at = |day: &str, time: &str| logic::parse_local_time(&format!("{} {}", day, time), clock, config.zone());
+    let with_project = |project: Option<String>, metadata: cli::MetadataArgs| {
+        let mut metadata = Metadata::from(metadata);
+        metadata.project = project.or(metadata.project).or_else(|| config.default_project.clone());
+        focus::check_category(config, &metadata).map(|_| metadata)
+    };
+    match command {
+        BatchCommand::Start { day, time, project_name, metadata } => {
+            let start = at(&day, &time)?;
+            if start > clock.now() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "A session cannot start in the future."));
+            }
+            if !logic::start_tracking(time_sheet, with_project(project_name, metadata)?, None, &FixedClock::new(start))? {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "A session is already running."));
+            }
+        }
+        BatchCommand::Stop { day, time, keep } => {
+            let end = at(&day, &time)?;
+            if end > clock.now() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "A session cannot end in the future."));
+            }
+            let minimum = (!keep && config.min_session_seconds > 0).then(|| logic::MinimumSession {
+                length: Duration::seconds(config.min_session_seconds as i64),
+                policy: config.short_session_policy,
+            });
+            if !logic::stop_tracking(time_sheet, formatter, minimum, &FixedClock::new(end))? {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "No session is running."));
+            }
+        }
+        BatchCommand::Add { day, start, end, project_name, metadata } => {
+            let start = at(&day, &start)?;
+            let mut end = at(&day, &end)?;
+            if end <= start {
+                end += Duration::days(1);
+            }
+            logic::add_manual_period(time_sheet, start, end, with_project(project_name, metadata)?, formatter, clock)?;
+        }
+        BatchCommand::Tag { action: TagAction::Rename { old, new, dry_run } } => {
+            retag::rename_tag(time_sheet, &old, &new, dry_run, formatter)?;
+        }
+        BatchCommand::Retag { filter, add, remove, category } => {
+            let category = category.map(|category| focus::parse_category(config, &category)).transpose()?;
+            retag::retag(time_sheet, &filter, &add, &remove, category.as_deref(), false, formatter)?;
+        }
+        BatchCommand::Absence { action: AbsenceAction::Add { kind, days, note } } => {
+            absence::add_absence(time_sheet, kind, &days, note)?;
+        }
+        BatchCommand::Absence { action: AbsenceAction::List } => absence::list_absences(time_sheet),
     }
+    Ok(())
 }
 
-/// Generates a Period struct representing the current week (Mon-Sun) in the local timezone.
-fn get_week_period() -> Period {
-    let now_local = Local::now();
-    let today_local_naive = now_local.date_naive();
-    let days_from_monday = today_local_naive.weekday().num_days_from_monday();
-    let start_of_week_naive = today_local_naive - Duration::days(days_from_monday as i64);
-    let start_naive = start_of_week_naive.and_hms_opt(0, 0, 0).unwrap();
-    let end_naive = start_naive + Duration::weeks(1);
-    Period {
-        start: Local.from_local_datetime(&start_naive).unwrap().to_utc(),
-        end: Local.from_local_datetime(&end_naive).unwrap().to_utc(),
+// Asks before starting outside the configured tracking window. Without a terminal
+// to ask on, it only warns.
+fn confirm_tracking_window(
+    config: &config::Config,
+    time_sheet: &TimeSheet,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let Some(window) = &config.tracking_window else {
+        return Ok(true);
+    };
+    let now = clock.now().with_timezone(&Local).time();
+    if time_sheet.active_period_start().is_some() || window.contains(now) {
+        return Ok(true);
     }
+    let outside = format!(
+        "It is {}, outside your tracking hours ({}-{}).",
+        formatter.time(now),
+        formatter.time(window.start),
+        formatter.time(window.end)
+    );
+    if !io::stdin().is_terminal() {
+        eprintln!("Warning: {}", outside);
+        return Ok(true);
+    }
+    prompt::confirm(&format!("{} Start anyway?", outside))
 }
 
-/// Generates a Period struct representing the current month in the local timezone.
-fn get_month_period() -> Period {
-    let now_local = Local::now();
-    let today_local_naive = now_local.date_naive();
-    let start_of_month_naive = NaiveDate::from_ymd_opt(today_local_naive.year(), today_local_naive.month(), 1).unwrap();
-    let start_naive = start_of_month_naive.and_hms_opt(0, 0, 0).unwrap();
-    let (next_month_year, next_month) = if today_local_naive.month() == 12 {
-        (today_local_naive.year() + 1, 1)
-    } else {
-        (today_local_naive.year(), today_local_naive.month() + 1)
-    };
-    let start_of_next_month_naive = NaiveDate::from_ymd_opt(next_month_year, next_month, 1).unwrap();
-    let end_naive = start_of_next_month_naive.and_hms_opt(0, 0, 0).unwrap();
-    Period {
-        start: Local.from_local_datetime(&start_naive).unwrap().to_utc(),
-        end: Local.from_local_datetime(&end_naive).unwrap().to_utc(),
+// Handles the "favorite" command: add, list or remove timer templates.
+fn handle_favorite(config: &mut config::Config, action: FavoriteAction) -> io::Result<()> {
+    match action {
+        FavoriteAction::Add { name, metadata } => {
+            let metadata = Metadata::from(metadata);
+            let name = name.trim_start_matches('@').to_string();
+            if metadata.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "A favorite needs at least one of --project, --tags or --note.",
+                ));
+            }
+            focus::check_category(config, &metadata)?;
+            let replaced = config.favorites.insert(name.clone(), metadata).is_some();
+            config::save_config(config)?;
+            println!("{} favorite '{}'. Start it with 'start @{}'.", if replaced { "Updated" } else { "Added" }, name, name);
+        }
+        FavoriteAction::Remove { name } => {
+            let name = name.trim_start_matches('@');
+            if config.favorites.remove(name).is_none() {
+                println!("No favorite named '{}'.", name);
+                return Ok(());
+            }
+            config::save_config(config)?;
+            println!("Removed favorite '{}'.", name);
+        }
+        FavoriteAction::List => {
+            if config.favorites.is_empty() {
+                println!("No favorites defined.");
+            }
+            for (name, metadata) in &config.favorites {
+                println!("@{:<15} {}", name, metadata.describe());
+            }
+        }
     }
+    Ok(())
 }
 
-// Generates and prints a summary report.
-fn report_summary(time_sheet: &TimeSheet, period_name: &str) -> io::Result<()> {
-    let reporting_period = match period_name {
-        "today" => get_today_period(),
-        "week" => get_week_period(),
-        "month" => get_month_period(),
-        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid summary period")),
-    };
-
-    let total_duration = calculate_tracked_time_in_period(time_sheet, &reporting_period);
-    println!("Total time tracked for this {}: {}", period_name, format_duration(total_duration));
+// The [remote] section needed by `sync remote` and `sync pull`.
+fn remote_config(config: &config::Config) -> io::Result<&config::RemoteConfig> {
+    config.remote.as_ref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "No [remote] section in the config. Add url and username to back up with 'sync remote'.",
+        )
+    })
+}
 
+fn handle_secret(action: SecretAction) -> io::Result<()> {
+    match action {
+        SecretAction::Set { name } => {
+            let secret = prompt::ask(&format!("Value for '{}'", name), "")?;
+            secrets::set(&name, &secret)?;
+            println!("Stored '{}' in the keyring.", name);
+        }
+        SecretAction::Get { name } => match secrets::get(&name)? {
+            Some(secret) => println!("{}", secret),
+            None => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("No secret named '{}'.", name)));
+            }
+        },
+        SecretAction::Delete { name } => {
+            if secrets::delete(&name)? {
+                println!("Removed '{}' from the keyring.", name);
+            } else {
+                println!("No secret named '{}'.", name);
+            }
+        }
+    }
     Ok(())
 }
 
-// Calculates the total tracked time within a given period using iterators.
-fn calculate_tracked_time_in_period(time_sheet: &TimeSheet, reporting_period: &Period) -> Duration {
-    // Calculate total duration from completed periods using an iterator chain.
-    let completed_duration: Duration = time_sheet.periods
-        .iter()
-        .map(|p| p.overlap(reporting_period))
-        .sum();
-
-    // Calculate duration from the currently active period, if any.
-    let active_duration = time_sheet.active_period_start.map_or(Duration::zero(), |start| {
-        let active_period = Period { start, end: Utc::now() };
-        active_period.overlap(reporting_period)
-    });
-
-    completed_duration + active_duration
-}
+// Sets up the tracing subscriber. Logs go to stderr unless a log file is given.
+fn init_logging(cli: &Cli) -> io::Result<()> {
+    let level = match cli.verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let builder = tracing_subscriber::fmt().with_max_level(level).with_target(false);
 
-// Formats a Duration into a human-readable string (HH:MM:SS).
-fn format_duration(duration: Duration) -> String {
-    if duration < Duration::zero() {
-        return "00:00:00".to_string();
+    match &cli.log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            builder.with_ansi(false).with_writer(Mutex::new(file)).init();
+        }
+        None => builder.with_writer(io::stderr).init(),
     }
-    let seconds = duration.num_seconds();
-    let hours = seconds / 3600;
-    let minutes = (seconds % 3600) / 60;
-    let seconds = seconds % 60;
-    format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+
+    Ok(())
 }
 
 // To make this code runnable, you'll need to add the following dependencies
@@ -243,7 +751,28 @@ fn format_duration(duration: Duration) -> String {
 //
 // [dependencies]
 // chrono = { version = "0.4", features = ["serde"] }
+// chrono-tz = "0.10"
 // serde = { version = "1.0", features = ["derive"] }
 // serde_json = "1.0"
+// base64 = "0.22"
 // dirs = "5.0"
+// rmp-serde = { version = "1", optional = true }  (feature "binary")
+// parquet = { version = "54", default-features = false, features = ["arrow", "snap"], optional = true }  (feature "parquet")
+// arrow-array = { version = "54", optional = true }  (feature "parquet")
+// arrow-schema = { version = "54", optional = true }  (feature "parquet")
+// sha2 = "0.10"
+// shlex = "1.3"
+// clap = { version = "4", features = ["derive"] }
+// clap_complete = "4"
+// tinytemplate = "1"
+// toml = "0.8"
+// tracing = "0.1"
+// tracing-subscriber = { version = "0.3", default-features = false, features = ["fmt", "std", "ansi"] }
+// argon2 = "0.5"
+// chacha20poly1305 = "0.10"
+// hmac = "0.12"
+// keyring = { version = "3", features = ["linux-native", "apple-native", "windows-native"] }
+// libc = "0.2"  (Linux only)
+// ureq = "2"
+// uuid = { version = "1", features = ["v4", "serde"] }
 
@@ -0,0 +1,80 @@
+// Tracked totals as Prometheus metrics, for graphing in Grafana and the like.
+//
+// `metrics export --textfile` writes them for node_exporter's textfile collector,
+// which expects the file to be replaced in one go, so it is written through a
+// temporary file and a rename. `serve` answers them on /metrics as well. The
+// values are gauges computed when asked for; nothing is kept between runs.
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::logic;
+use crate::TimeSheet;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::{debug, instrument};
+
+// The content type of the text format, for HTTP responses.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// The metrics in the Prometheus text format.
+pub fn render(time_sheet: &TimeSheet, config: &Config, clock: &dyn Clock) -> String {
+    let now = clock.now();
+    let today = logic::get_today_period(clock, config.zone());
+    let week = logic::get_week_period(config.week_start.0, clock, config.zone());
+    let month = logic::get_month_period(clock, config.zone());
+    let week_report = logic::build_report(time_sheet, config, "week", &week, now);
+
+    let mut text = String::new();
+    let mut gauge = |name: &str, help: &str, samples: &[(String, i64)]| {
+        let _ = writeln!(text, "# HELP time_tracker_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE time_tracker_{} gauge", name);
+        for (labels, value) in samples {
+            let _ = writeln!(text, "time_tracker_{}{} {}", name, labels, value);
+        }
+    };
+    let seconds = |range| logic::calculate_tracked_time_in_period(time_sheet, range, now).num_seconds();
+    gauge("tracked_seconds_today", "Time tracked today, including the running session.", &[(String::new(), seconds(&today))]);
+    gauge("tracked_seconds_week", "Time tracked this week, including the running session.", &[(String::new(), week_report.total.num_seconds())]);
+    gauge("tracked_seconds_month", "Time tracked this month, including the running session.", &[(String::new(), seconds(&month))]);
+    let active = time_sheet.active_period_start.filter(|start| *start <= now);
+    gauge("active_session", "Whether a session is running.", &[(String::new(), active.is_some() as i64)]);
+    gauge(
+        "active_session_seconds",
+        "How long the running session has been going, 0 without one.",
+        &[(String::new(), active.map_or(0, |start| (now - start).num_seconds()))],
+    );
+    let projects: Vec<(String, i64)> = week_report
+        .projects
+        .iter()
+        .map(|row| (format!("{{project=\"{}\"}}", escape(row.project.as_deref().unwrap_or(""))), row.total.num_seconds()))
+        .collect();
+    gauge("project_tracked_seconds_week", "Time tracked this week per project; \"\" is time without one.", &projects);
+    if let Some(balance) = &week_report.balance {
+        gauge("target_seconds_week", "This week's target up to today.", &[(String::new(), balance.target.num_seconds())]);
+        gauge("balance_seconds_week", "Tracked and credited time minus the target this week.", &[(String::new(), balance.balance.num_seconds())]);
+    }
+    text
+}
+
+// Label values escape backslashes, quotes and newlines.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Handles `metrics export`: writes the metrics to `textfile`, or prints them.
+#[instrument(level = "debug", skip(time_sheet, config, clock))]
+pub fn export(time_sheet: &TimeSheet, config: &Config, textfile: Option<&Path>, clock: &dyn Clock) -> io::Result<()> {
+    let text = render(time_sheet, config, clock);
+    let Some(path) = textfile else {
+        print!("{}", text);
+        return Ok(());
+    };
+    let mut temporary = path.as_os_str().to_os_string();
+    temporary.push(".tmp");
+    fs::write(&temporary, &text)?;
+    fs::rename(&temporary, path)?;
+    debug!(path = %path.display(), "wrote metrics");
+    Ok(())
+}
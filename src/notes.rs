@@ -0,0 +1,120 @@
+// Editing a period's note in the user's editor, for notes longer than a
+// `--note` flag comfortably holds. Works like `git commit`: the note is written
+// to a temporary file with a commented header, the editor is run on it, and
+// whatever is left without the comment lines becomes the note.
+
+use crate::timefmt::TimeFormatter;
+use crate::{Metadata, TimeSheet};
+use chrono::Local;
+use std::env;
+use std::fs;
+use std::io;
+use std::process::Command;
+use tracing::{debug, info, instrument};
+
+// Finds the recorded period with the given ID, or the only one whose ID starts
+// with it, so that the first few characters are enough as with git commits.
+fn find_period(time_sheet: &TimeSheet, id: &str) -> io::Result<usize> {
+    let id = id.trim().to_lowercase();
+    if id.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Give the ID of a period."));
+    }
+    let matches: Vec<usize> = (0..time_sheet.periods.len())
+        .filter(|&index| time_sheet.periods[index].id.to_string().starts_with(&id))
+        .collect();
+    match matches.as_slice() {
+        [index] => Ok(*index),
+        [] => Err(io::Error::new(io::ErrorKind::NotFound, format!("No period with ID {}.", id))),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} periods have an ID starting with {}. Give more of it.", matches.len(), id),
+        )),
+    }
+}
+
+// The editor to run, looked up like git does: $VISUAL, then $EDITOR, then vi.
+fn editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+// The command running the editor on a file given as the last argument. As with
+// git, the editor setting is a shell command, so it may carry options.
+#[cfg(unix)]
+fn editor_command(editor: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(format!("{} \"$@\"", editor)).arg(editor);
+    command
+}
+
+#[cfg(not(unix))]
+fn editor_command(editor: &str) -> Command {
+    let mut words = editor.split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or("notepad"));
+    command.args(words);
+    command
+}
+
+// Drops comment lines and surrounding blank lines from the edited text.
+fn strip_comments(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).map(str::trim_end).collect();
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+// Handles `note edit`: opens the note of the period with the given ID in the
+// editor and stores the result. An empty note removes it. Returns whether the
+// timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, formatter))]
+pub fn edit_note(time_sheet: &mut TimeSheet, id: &str, formatter: &TimeFormatter) -> io::Result<bool> {
+    let index = find_period(time_sheet, id)?;
+    let period = &time_sheet.periods[index];
+    if time_sheet.is_locked(period) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Period {} is in a closed month and cannot change.", period.id),
+        ));
+    }
+
+    let mut header = format!(
+        "# Note for {} to {} ({})",
+        formatter.date_time(period.start.with_timezone(&Local)),
+        formatter.time(period.end.with_timezone(&Local)),
+        formatter.duration(period.duration())
+    );
+    let details = Metadata { note: None, ..period.metadata.clone() }.describe();
+    if !details.is_empty() {
+        header.push_str(&format!(", {}", details));
+    }
+    let current = period.metadata.note.clone().unwrap_or_default();
+    let text = format!(
+        "{}\n\n{}\n# Lines starting with '#' are ignored. An empty note removes it.\n",
+        current, header
+    );
+
+    let path = env::temp_dir().join(format!("time-tracker-note-{}.txt", period.id));
+    fs::write(&path, text)?;
+    let editor = editor();
+    debug!(%editor, path = %path.display(), "running editor");
+    let status = editor_command(&editor).arg(&path).status();
+    let edited = match status {
+        Ok(status) if status.success() => fs::read_to_string(&path),
+        Ok(status) => Err(io::Error::other(format!("The editor {} exited with {}. The note was not changed.", editor, status))),
+        Err(e) => Err(io::Error::new(e.kind(), format!("Could not run the editor {}: {}", editor, e))),
+    };
+    let _ = fs::remove_file(&path);
+    let note = strip_comments(&edited?);
+
+    let note = (!note.is_empty()).then_some(note);
+    if note == time_sheet.periods[index].metadata.note {
+        println!("Note unchanged.");
+        return Ok(false);
+    }
+    let period = &mut time_sheet.periods[index];
+    info!(id = %period.id, removed = note.is_none(), "edited note");
+    println!("{} the note of period {}.", if note.is_none() { "Removed" } else { "Updated" }, period.id);
+    period.metadata.note = note;
+    Ok(true)
+}
@@ -0,0 +1,200 @@
+// The weekly work pattern: the usual start and end of each weekday and its usual
+// breaks, learned from the tracked history. `stats pattern` shows it, and `suggest
+// gaps` looks for untracked time within those hours, leaving the usual breaks
+// out, instead of in the fixed workday of `[schedule]`.
+//
+// Each weekday is learned from its tracked days in the weeks before: the start is
+// the median of their first tracked minute, the end the median of their last. A
+// quarter hour between the two is a break if most of the days that worked across
+// it left it untracked. Weekdays tracked on too few days have no pattern.
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::logic;
+use crate::timefmt::TimeFormatter;
+use crate::zone::Zone;
+use crate::{Period, TimeSheet};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, Timelike, Utc, Weekday};
+use std::io;
+use tracing::{debug, instrument};
+
+// Weeks of history the pattern is learned from by default.
+pub const DEFAULT_WEEKS: u32 = 8;
+// Fewer tracked days of a weekday than this say too little about it.
+const MIN_DAYS: usize = 3;
+// Breaks are found in steps of this many minutes.
+const SLOT_MINUTES: usize = 15;
+const MINUTES_PER_DAY: usize = 24 * 60;
+
+// The usual hours of one weekday.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DayPattern {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// Usual breaks between start and end, as local start and end times.
+    pub breaks: Vec<(NaiveTime, NaiveTime)>,
+    /// Tracked days the pattern was learned from.
+    pub days: usize,
+}
+
+impl DayPattern {
+    // The usual hours on `day`, which falls on the pattern's weekday.
+    pub fn hours(&self, day: NaiveDate, config: &Config) -> Period {
+        local_range(day, self.start, self.end, config)
+    }
+
+    // The usual breaks on `day`.
+    pub fn break_periods(&self, day: NaiveDate, config: &Config) -> Vec<Period> {
+        self.breaks.iter().map(|(start, end)| local_range(day, *start, *end, config)).collect()
+    }
+}
+
+fn local_range(day: NaiveDate, start: NaiveTime, end: NaiveTime, config: &Config) -> Period {
+    Period::range(
+        logic::naive_to_utc(day.and_time(start), config.zone()),
+        logic::naive_to_utc(day.and_time(end), config.zone()),
+    )
+}
+
+// What was learned for each weekday, Monday first.
+#[derive(Debug, Clone, Default)]
+pub struct WorkPattern {
+    /// Tracked days per weekday, including those with too few to learn from.
+    pub tracked_days: [usize; 7],
+    days: [Option<DayPattern>; 7],
+}
+
+impl WorkPattern {
+    // The usual hours of the weekday, if it was tracked often enough.
+    pub fn day(&self, weekday: Weekday) -> Option<&DayPattern> {
+        self.days[weekday.num_days_from_monday() as usize].as_ref()
+    }
+}
+
+// Learns the pattern from the `weeks` weeks before `until`, which is left out.
+pub fn learn(time_sheet: &TimeSheet, until: NaiveDate, weeks: u32) -> WorkPattern {
+    // The tracked minutes of each tracked day, by weekday.
+    let mut samples: [Vec<Vec<bool>>; 7] = Default::default();
+    for day in (until - Duration::weeks(weeks as i64)).iter_days().take_while(|day| *day < until) {
+        let Some(range) = logic::day_period(day, Zone::default()) else { continue };
+        let mut tracked = vec![false; MINUTES_PER_DAY];
+        for period in time_sheet.periods_in(&range) {
+            let start = minute_of_day(period.start.max(range.start), day);
+            let end = minute_of_day(period.end.min(range.end), day);
+            tracked[start..end.max(start)].fill(true);
+        }
+        if tracked.contains(&true) {
+            samples[day.weekday().num_days_from_monday() as usize].push(tracked);
+        }
+    }
+
+    let mut pattern = WorkPattern::default();
+    for (index, days) in samples.iter().enumerate() {
+        pattern.tracked_days[index] = days.len();
+        if days.len() >= MIN_DAYS {
+            pattern.days[index] = Some(learn_day(days));
+        }
+    }
+    debug!(%until, weeks, tracked_days = ?pattern.tracked_days, "learned work pattern");
+    pattern
+}
+
+// The minute of `day` a time falls on, 1440 for its end.
+fn minute_of_day(time: DateTime<Utc>, day: NaiveDate) -> usize {
+    let local = time.with_timezone(&Local);
+    if local.date_naive() > day {
+        return MINUTES_PER_DAY;
+    }
+    (local.hour() * 60 + local.minute()) as usize
+}
+
+fn learn_day(days: &[Vec<bool>]) -> DayPattern {
+    let spans: Vec<(usize, usize)> = days
+        .iter()
+        .map(|tracked| {
+            let first = tracked.iter().position(|t| *t).unwrap_or(0);
+            let last = tracked.iter().rposition(|t| *t).unwrap_or(0) + 1;
+            (first, last)
+        })
+        .collect();
+    let start = median(spans.iter().map(|(first, _)| *first).collect());
+    let end = median(spans.iter().map(|(_, last)| *last).collect()).max(start);
+
+    // Quarter hours, aligned to the clock, untracked on most days that worked
+    // across them.
+    let mut breaks: Vec<(usize, usize)> = Vec::new();
+    let first_slot = start.div_ceil(SLOT_MINUTES) * SLOT_MINUTES;
+    for slot in (first_slot..end.saturating_sub(SLOT_MINUTES - 1)).step_by(SLOT_MINUTES) {
+        let slot_end = slot + SLOT_MINUTES;
+        let (mut worked, mut idle) = (0, 0);
+        for (tracked, (first, last)) in days.iter().zip(&spans) {
+            if *first <= slot && slot_end <= *last {
+                worked += 1;
+                if !tracked[slot..slot_end].contains(&true) {
+                    idle += 1;
+                }
+            }
+        }
+        if worked > 0 && idle * 2 > worked {
+            match breaks.last_mut() {
+                Some((_, last_end)) if *last_end == slot => *last_end = slot_end,
+                _ => breaks.push((slot, slot_end)),
+            }
+        }
+    }
+
+    DayPattern {
+        start: time_of(start),
+        end: time_of(end),
+        breaks: breaks.into_iter().map(|(start, end)| (time_of(start), time_of(end))).collect(),
+        days: days.len(),
+    }
+}
+
+fn median(mut values: Vec<usize>) -> usize {
+    values.sort_unstable();
+    values[(values.len() - 1) / 2]
+}
+
+// The local time of a minute of the day; the end of the day is its last minute.
+fn time_of(minute: usize) -> NaiveTime {
+    let minute = minute.min(MINUTES_PER_DAY - 1) as u32;
+    NaiveTime::from_hms_opt(minute / 60, minute % 60, 0).unwrap_or(NaiveTime::MIN)
+}
+
+// Handles `stats pattern`: the usual hours and breaks of each weekday, learned
+// from the `weeks` weeks before today.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn show_pattern(time_sheet: &TimeSheet, config: &Config, weeks: u32, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    let today = clock.now().with_timezone(&Local).date_naive();
+    let pattern = learn(time_sheet, today, weeks);
+    let total: usize = pattern.tracked_days.iter().sum();
+    if total == 0 {
+        println!("Nothing tracked in the last {} weeks.", weeks);
+        return Ok(());
+    }
+    println!("Usual hours, from {} tracked day(s) in the last {} weeks:", total, weeks);
+    let mut weekday = config.week_start.0;
+    for _ in 0..7 {
+        let tracked = pattern.tracked_days[weekday.num_days_from_monday() as usize];
+        match pattern.day(weekday) {
+            Some(day) => {
+                let breaks: Vec<String> =
+                    day.breaks.iter().map(|(start, end)| format!("{}-{}", formatter.time(*start), formatter.time(*end))).collect();
+                let breaks = if breaks.is_empty() { "no usual breaks".to_string() } else { format!("breaks {}", breaks.join(", ")) };
+                println!(
+                    "  {}  {}-{}  {}  ({} days)",
+                    weekday,
+                    formatter.time(day.start),
+                    formatter.time(day.end),
+                    breaks,
+                    day.days
+                );
+            }
+            None if tracked == 0 => println!("  {}  nothing tracked", weekday),
+            None => println!("  {}  too few days to tell ({})", weekday, tracked),
+        }
+        weekday = weekday.succ();
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+// Weekly plans: hours set aside per project for a week, with `plan set`, and
+// compared with the tracked time by `plan status`, so the rest of the week can be
+// rebalanced. Plans are kept in the `plan` section of the data file, one entry
+// per week.
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::logic;
+use crate::timefmt::{self, TimeFormatter};
+use crate::TimeSheet;
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use tracing::{debug, info, instrument};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WeekPlan {
+    /// First day of the week, as set by `week_start` when the plan was made.
+    pub week: NaiveDate,
+    /// Planned seconds per project.
+    pub projects: BTreeMap<String, u64>,
+}
+
+// The first day of the week named by `text`: "week" for the current one,
+// "next-week", "last-week", or any day of the week as YYYY-MM-DD.
+pub fn parse_week(text: &str, config: &Config, clock: &dyn Clock) -> io::Result<NaiveDate> {
+    let today = clock.now().with_timezone(&Local).date_naive();
+    let day = match text.trim() {
+        "week" => today,
+        "next-week" => today + Duration::weeks(1),
+        "last-week" => today - Duration::weeks(1),
+        other => NaiveDate::parse_from_str(other, "%Y-%m-%d").map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid week '{}'. Expected week, next-week, last-week or a day as YYYY-MM-DD.", text),
+            )
+        })?,
+    };
+    Ok(day - Duration::days(day.weekday().days_since(config.week_start.0) as i64))
+}
+
+// The plan of the week starting on `first`. A plan made while the week started
+// on another day still counts for the week its first day falls in.
+pub fn plan_for(time_sheet: &TimeSheet, first: NaiveDate) -> Option<&WeekPlan> {
+    time_sheet.plan.iter().find(|plan| covers(plan, first))
+}
+
+fn covers(plan: &WeekPlan, first: NaiveDate) -> bool {
+    plan.week >= first && plan.week < first + Duration::weeks(1)
+}
+
+// Handles `plan set`: replaces the plan of the week with the given allocations,
+// given as project and duration pairs. Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn set_plan(
+    time_sheet: &mut TimeSheet,
+    config: &Config,
+    week: &str,
+    allocations: &[String],
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let first = parse_week(week, config, clock)?;
+    let mut projects = BTreeMap::new();
+    for pair in allocations.chunks(2) {
+        let [project, duration] = pair else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Every --project needs a name and a duration."));
+        };
+        let project = project.trim();
+        if project.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The project name is empty."));
+        }
+        let duration = timefmt::parse_duration(duration)?;
+        if duration <= Duration::zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The time planned for {} must be more than zero.", project),
+            ));
+        }
+        if projects.insert(project.to_string(), duration.num_seconds() as u64).is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Project {} is given twice.", project)));
+        }
+    }
+
+    let plan = WeekPlan { week: first, projects };
+    let existing = time_sheet.plan.iter().position(|p| covers(p, first));
+    if existing.is_some_and(|index| time_sheet.plan[index] == plan) {
+        println!("The plan for the week of {} is unchanged.", formatter.date(first));
+        return Ok(false);
+    }
+    let total = Duration::seconds(plan.projects.values().sum::<u64>() as i64);
+    info!(week = %first, projects = plan.projects.len(), "set weekly plan");
+    println!(
+        "Planned {} on {} project(s) for the week of {}.",
+        formatter.duration(total),
+        plan.projects.len(),
+        formatter.date(first)
+    );
+    match existing {
+        Some(index) => time_sheet.plan[index] = plan,
+        None => {
+            let index = time_sheet.plan.partition_point(|p| p.week < first);
+            time_sheet.plan.insert(index, plan);
+        }
+    }
+    Ok(true)
+}
+
+// Handles `plan clear`: removes the plan of the week. Returns whether the
+// timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn clear_plan(time_sheet: &mut TimeSheet, config: &Config, week: &str, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<bool> {
+    let first = parse_week(week, config, clock)?;
+    let before = time_sheet.plan.len();
+    time_sheet.plan.retain(|plan| !covers(plan, first));
+    if time_sheet.plan.len() == before {
+        println!("There is no plan for the week of {}.", formatter.date(first));
+        return Ok(false);
+    }
+    info!(week = %first, "cleared weekly plan");
+    println!("Removed the plan for the week of {}.", formatter.date(first));
+    Ok(true)
+}
+
+// Handles `plan status`: the planned time of each project next to the time
+// tracked on it that week, including the running session, and what is left;
+// time beyond the plan shows with a "+". Projects tracked without a plan are
+// listed after the planned ones.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn show_status(time_sheet: &TimeSheet, config: &Config, week: &str, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    let first = parse_week(week, config, clock)?;
+    let Some(plan) = plan_for(time_sheet, first) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("There is no plan for the week of {}. Make one with 'plan set'.", formatter.date(first)),
+        ));
+    };
+    let range = logic::week_starting(first, config.zone());
+    let report = logic::build_report(time_sheet, config, "week", &range, clock.now());
+    let tracked: BTreeMap<Option<&str>, Duration> =
+        report.projects.iter().map(|row| (row.project.as_deref(), row.total)).collect();
+
+    println!("Plan for the week of {}:", formatter.date(first));
+    println!("  {:<20} {:>10} {:>10} {:>10} {:>5}", "Project", "Planned", "Tracked", "Left", "Done");
+    let (mut planned_total, mut tracked_total) = (Duration::zero(), Duration::zero());
+    for (project, seconds) in &plan.projects {
+        let planned = Duration::seconds(*seconds as i64);
+        let done = tracked.get(&Some(project.as_str())).copied().unwrap_or_default();
+        planned_total += planned;
+        tracked_total += done;
+        let left = planned - done;
+        let left = if left < Duration::zero() {
+            format!("+{}", formatter.duration(-left))
+        } else {
+            formatter.duration(left)
+        };
+        println!(
+            "  {:<20} {:>10} {:>10} {:>10} {:>4.0}%",
+            project,
+            formatter.duration(planned),
+            formatter.duration(done),
+            left,
+            done.num_seconds() as f64 * 100.0 / planned.num_seconds() as f64
+        );
+    }
+    let unplanned: Vec<_> = report
+        .projects
+        .iter()
+        .filter(|row| row.project.as_ref().is_none_or(|project| !plan.projects.contains_key(project)))
+        .collect();
+    for row in &unplanned {
+        tracked_total += row.total;
+        println!(
+            "  {:<20} {:>10} {:>10}",
+            row.project.as_deref().unwrap_or("(no project)"),
+            "-",
+            formatter.duration(row.total)
+        );
+    }
+    println!("  {:<20} {:>10} {:>10}", "Total", formatter.duration(planned_total), formatter.duration(tracked_total));
+    debug!(week = %first, planned = plan.projects.len(), unplanned = unplanned.len(), "printed plan status");
+    Ok(())
+}
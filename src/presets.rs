@@ -0,0 +1,122 @@
+// Exports in the fixed layouts of payroll and timesheet systems, for
+// `export preset <name>`. Each preset turns the per-day, per-project totals of a
+// report into the rows the system imports; to add one, write a render function
+// and list it in `PRESETS`.
+//
+//   datev     DATEV Lohn und Gehalt: Personalnummer;Datum;Lohnart;Stunden;Kostenstelle
+//             with dates as DD.MM.YYYY and hours with a decimal comma.
+//   sap-cats  SAP CATS upload: PERNR;WORKDATE;AWART;CATSHOURS;RKOSTL;LTXA1
+//             with dates as YYYYMMDD and hours with a decimal point.
+//
+// Hours are rounded to two decimals. Both read `[presets]` for the personnel
+// number, the wage or attendance type and the cost center of each project.
+
+use crate::config::{Config, PresetsConfig};
+use crate::report::Report;
+use chrono::{Duration, NaiveDate};
+use std::io;
+use tracing::{debug, instrument};
+
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    render: fn(&[Row], &PresetsConfig) -> String,
+}
+
+// The registry of presets, in the order they are listed.
+pub const PRESETS: &[Preset] = &[
+    Preset { name: "datev", description: "DATEV Lohn und Gehalt hours import", render: datev },
+    Preset { name: "sap-cats", description: "SAP CATS timesheet upload", render: sap_cats },
+];
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    PRESETS.iter().map(|preset| preset.name)
+}
+
+pub fn find(name: &str) -> io::Result<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Unknown export preset '{}'. Known presets: {}.", name, names().collect::<Vec<_>>().join(", ")),
+        )
+    })
+}
+
+// Time of one project on one day.
+struct Row {
+    date: NaiveDate,
+    project: String,
+    hours: Duration,
+}
+
+impl Preset {
+    // Renders the report in the preset's layout. Returns the file contents and
+    // the number of data rows.
+    #[instrument(level = "debug", skip_all, fields(preset = self.name))]
+    pub fn render(&self, report: &Report, config: &Config) -> io::Result<(String, usize)> {
+        if config.presets.employee_id.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Set employee_id under [presets] to export with a preset.",
+            ));
+        }
+        let rows: Vec<Row> = report
+            .days
+            .iter()
+            .flat_map(|day| {
+                // Rows that would show 0.00 hours are left out.
+                day.projects.iter().filter(|p| p.total >= Duration::seconds(18)).map(|p| Row {
+                    date: day.date,
+                    project: p.project.clone().unwrap_or_default(),
+                    hours: p.total,
+                })
+            })
+            .collect();
+        debug!(rows = rows.len(), "rendering preset");
+        Ok(((self.render)(&rows, &config.presets), rows.len()))
+    }
+}
+
+fn hours(duration: Duration) -> String {
+    format!("{:.2}", duration.num_seconds() as f64 / 3600.0)
+}
+
+// Semicolons separate fields in both layouts, and SAP short texts are limited.
+fn field(value: &str, max_chars: usize) -> String {
+    value.replace([';', '\n', '\r'], " ").chars().take(max_chars).collect()
+}
+
+fn cost_center<'a>(presets: &'a PresetsConfig, project: &str) -> &'a str {
+    presets.cost_centers.get(project).map_or("", String::as_str)
+}
+
+fn datev(rows: &[Row], presets: &PresetsConfig) -> String {
+    let mut csv = String::from("Personalnummer;Datum;Lohnart;Stunden;Kostenstelle\r\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{};{};{};{};{}\r\n",
+            field(&presets.employee_id, 20),
+            row.date.format("%d.%m.%Y"),
+            field(&presets.datev_wage_type, 4),
+            hours(row.hours).replace('.', ","),
+            field(cost_center(presets, &row.project), 36)
+        ));
+    }
+    csv
+}
+
+fn sap_cats(rows: &[Row], presets: &PresetsConfig) -> String {
+    let mut csv = String::from("PERNR;WORKDATE;AWART;CATSHOURS;RKOSTL;LTXA1\r\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{};{};{};{};{};{}\r\n",
+            field(&presets.employee_id, 8),
+            row.date.format("%Y%m%d"),
+            field(&presets.sap_attendance_type, 4),
+            hours(row.hours),
+            field(cost_center(presets, &row.project), 10),
+            field(&row.project, 40)
+        ));
+    }
+    csv
+}
@@ -0,0 +1,26 @@
+use std::io::{self, BufRead, Write};
+
+// Asks a yes/no question on the terminal. Anything but "y"/"yes" means no.
+pub fn confirm(question: &str) -> io::Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    Ok(matches!(read_answer()?.to_lowercase().as_str(), "y" | "yes"))
+}
+
+// Asks for a value, showing the default in brackets. An empty answer keeps the default.
+pub fn ask(question: &str, default: &str) -> io::Result<String> {
+    if default.is_empty() {
+        eprint!("{}: ", question);
+    } else {
+        eprint!("{} [{}]: ", question, default);
+    }
+    io::stderr().flush()?;
+    let answer = read_answer()?;
+    Ok(if answer.is_empty() { default.to_string() } else { answer })
+}
+
+fn read_answer() -> io::Result<String> {
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
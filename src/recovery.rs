@@ -0,0 +1,318 @@
+use crate::prompt::confirm;
+use crate::timestamp;
+use crate::TimeSheet;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument};
+
+// The result of salvaging a data file that failed to parse.
+#[derive(Debug)]
+pub struct Recovery {
+    pub time_sheet: TimeSheet,
+    /// Short excerpts of the entries that could not be read.
+    pub dropped: Vec<String>,
+}
+
+// Tries to recover whatever is still readable from a damaged data file.
+//
+// Every entry of the top-level lists, such as `periods`, `breaks` or
+// `closed_months`, is parsed on its own as the type of its list, and so are the
+// objects describing the running session. A single bad period (or a stray
+// character between periods) only loses that one entry. Entries that cannot be
+// restored, including truncated ones, are reported as dropped under the name of
+// what they were.
+#[instrument(level = "debug", skip(contents), fields(bytes = contents.len()))]
+pub fn salvage(contents: &str) -> Recovery {
+    let mut time_sheet = TimeSheet { active_period_start: find_active_period_start(contents), ..TimeSheet::default() };
+    let mut dropped = Vec::new();
+
+    for (key, entry) in top_level_entries(contents) {
+        let dropped = &mut dropped;
+        match key {
+            "periods" => time_sheet.periods.extend(restore(entry, "period", dropped)),
+            "breaks" => time_sheet.breaks.extend(restore(entry, "break", dropped)),
+            "closed_months" => time_sheet.closed_months.extend(restore(entry, "closed month", dropped)),
+            "absences" => time_sheet.absences.extend(restore(entry, "absence", dropped)),
+            "reviewed_days" => time_sheet.reviewed_days.extend(restore::<NaiveDate>(entry, "reviewed day", dropped)),
+            "plan" => time_sheet.plan.extend(restore(entry, "plan", dropped)),
+            "active_laps" => time_sheet.active_laps.extend(restore(entry, "lap of the running session", dropped)),
+            "active_metadata" => {
+                time_sheet.active_metadata = restore(entry, "metadata of the running session", dropped).unwrap_or_default();
+            }
+            "active_suspend" => time_sheet.active_suspend = restore(entry, "suspend time of the running session", dropped),
+            _ => {
+                debug!(key, "dropping entry of unknown list");
+                dropped.push(format!("{}: {}", key, excerpt(entry.unwrap_or_else(|text| text))));
+            }
+        }
+    }
+    Recovery { time_sheet, dropped }
+}
+
+// Reads one entry as `T`, or notes it as dropped under `what`.
+fn restore<T: DeserializeOwned>(entry: Result<&str, &str>, what: &str, dropped: &mut Vec<String>) -> Option<T> {
+    let text = match entry {
+        Ok(text) => text,
+        Err(text) => {
+            dropped.push(format!("{} (cut off): {}", what, excerpt(text)));
+            return None;
+        }
+    };
+    match serde_json::from_str(text) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            debug!(error = %e, what, "dropping unreadable entry");
+            dropped.push(format!("{}: {}", what, excerpt(text)));
+            None
+        }
+    }
+}
+
+// Interactive recovery flow for a data file that could not be parsed.
+//
+// Writes the salvaged data next to the original as `<file>.recovered` and reports
+// what was lost. When running in a terminal, offers to replace the data file with
+// the recovered version (keeping the original as `<file>.corrupt`). Returns the
+// recovered timesheet only if the user accepted it.
+pub fn recover_data_file(path: &Path, contents: &str, error: &serde_json::Error) -> io::Result<TimeSheet> {
+    debug!(path = %path.display(), "attempting to recover data file");
+    let recovery = salvage(contents);
+    let recovered_path = with_suffix(path, "recovered");
+    write_time_sheet(&recovered_path, &recovery.time_sheet)?;
+
+    eprintln!("Could not read {}: {}", path.display(), error);
+    eprintln!("Recovered {}.", describe(&recovery.time_sheet));
+    if recovery.dropped.is_empty() {
+        eprintln!("No entries had to be dropped.");
+    } else {
+        eprintln!("Dropped {} unreadable entr{}:", recovery.dropped.len(), if recovery.dropped.len() == 1 { "y" } else { "ies" });
+        for entry in &recovery.dropped {
+            eprintln!("  {}", entry);
+        }
+    }
+    eprintln!("The recovered data was written to {}.", recovered_path.display());
+
+    if io::stdin().is_terminal() && confirm("Replace the data file with the recovered data?")? {
+        let corrupt_path = with_suffix(path, "corrupt");
+        fs::rename(path, &corrupt_path)?;
+        fs::rename(&recovered_path, path)?;
+        eprintln!("Data file replaced. The damaged original was kept as {}.", corrupt_path.display());
+        return Ok(recovery.time_sheet);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Data file {} is damaged. Review {} and move it into place to continue.",
+            path.display(),
+            recovered_path.display()
+        ),
+    ))
+}
+
+// What a salvaged timesheet holds, e.g. "12 period(s), 2 closed month(s) and the
+// active session".
+fn describe(time_sheet: &TimeSheet) -> String {
+    let counts = [
+        (time_sheet.periods.len(), "period(s)"),
+        (time_sheet.closed_months.len(), "closed month(s)"),
+        (time_sheet.absences.len(), "absence(s)"),
+        (time_sheet.breaks.len(), "break(s)"),
+        (time_sheet.plan.len(), "planned week(s)"),
+        (time_sheet.reviewed_days.len(), "reviewed day(s)"),
+    ];
+    let mut parts: Vec<String> = counts
+        .iter()
+        .enumerate()
+        .filter(|(index, (count, _))| *index == 0 || *count > 0)
+        .map(|(_, (count, what))| format!("{} {}", count, what))
+        .collect();
+    if time_sheet.active_period_start.is_some() {
+        parts.push("the active session".to_string());
+    }
+    match parts.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => parts.join(""),
+    }
+}
+
+// Appends an extra extension, e.g. `data.json` -> `data.json.recovered`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn write_time_sheet(path: &Path, time_sheet: &TimeSheet) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(time_sheet).map_err(io::Error::other)?;
+    fs::write(path, json)
+}
+
+// Finds the entries of the top-level object, with their key: each object, list or
+// string directly in one of its lists, whatever it contains, and each object
+// directly under a key. Braces and brackets inside strings are ignored. An entry
+// that is never closed is returned as `Err` with the text up to the end of the
+// input.
+fn top_level_entries(contents: &str) -> Vec<(&str, Result<&str, &str>)> {
+    let mut entries = Vec::new();
+    // The open objects and lists, outermost first.
+    let mut open: Vec<char> = Vec::new();
+    let mut key = "";
+    let mut string_start = 0;
+    let mut last_string = "";
+    let mut entry_start: Option<usize> = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    // Whether the parent of the current position is a list in the top-level object.
+    let in_list = |open: &[char]| open.len() == 2 && open[0] == '{' && open[1] == '[';
+
+    for (i, c) in contents.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    in_string = false;
+                    last_string = &contents[string_start + 1..i];
+                    if in_list(&open) && entry_start == Some(string_start) {
+                        entries.push((key, Ok(&contents[string_start..=i])));
+                        entry_start = None;
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                string_start = i;
+                if in_list(&open) {
+                    entry_start = Some(i);
+                }
+            }
+            ':' if open.len() == 1 => key = last_string,
+            '{' | '[' => {
+                if in_list(&open) || (c == '{' && open.len() == 1) {
+                    entry_start = Some(i);
+                }
+                open.push(c);
+            }
+            '}' | ']' => {
+                // A stray closing brace or bracket is ignored.
+                let opening = if c == '}' { '{' } else { '[' };
+                if open.last() == Some(&opening) {
+                    open.pop();
+                }
+                if (in_list(&open) || open.len() == 1)
+                    && let Some(start) = entry_start.take()
+                {
+                    entries.push((key, Ok(&contents[start..=i])));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(start) = entry_start {
+        entries.push((key, Err(&contents[start..])));
+    }
+    entries
+}
+
+// Looks for the `active_period_start` timestamp in otherwise unreadable JSON.
+fn find_active_period_start(contents: &str) -> Option<DateTime<Utc>> {
+    let key = "\"active_period_start\"";
+    let rest = &contents[contents.find(key)? + key.len()..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    let value = &rest[..rest.find('"')?];
+    timestamp::parse(value)
+}
+
+// Shortens an entry to a single line suitable for the report.
+fn excerpt(text: &str) -> String {
+    let line: String = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() > 72 {
+        format!("{}...", line.chars().take(72).collect::<String>())
+    } else {
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn salvage_keeps_periods_with_custom_fields() {
+        let contents = r#"{"periods":[
+            {"id":"6f1c8a52-3f4e-4d2b-9a61-0c1d2e3f4a5b","start":"2024-05-02T09:00:00Z","end":"2024-05-02T10:00:00Z","project":"acme","fields":{"ticket":"X-1"}},
+            {"id":"7a2d9b63-4a5f-4e3c-8b72-1d2e3f4a5b6c","start":"2024-05-02T11:00:00Z","end":"2024-05-02T12:00:00Z","project":"acme","fields":{"ticket":"X-2"}},
+            {"id":"8b3e0c74-5b6a-4f4d-9c83-2e3f4a5b6c7d","start":"2024-05-02T13:00:00Z","end":"#;
+        let recovery = salvage(contents);
+        let fields: Vec<&str> = recovery.time_sheet.periods.iter().map(|p| p.metadata.fields["ticket"].as_str()).collect();
+        assert_eq!(fields, ["X-1", "X-2"]);
+        assert_eq!(recovery.time_sheet.periods[0].metadata.project.as_deref(), Some("acme"));
+        assert_eq!(recovery.dropped.len(), 1);
+        assert!(recovery.dropped[0].starts_with(r#"period (cut off): {"id":"8b3e0c74"#));
+    }
+
+    #[test]
+    fn salvage_drops_only_the_damaged_period() {
+        let contents = r#"{"periods":[
+            {"start":"2024-05-02T09:00:00Z","end":"2024-05-02T10:00:00Z","note":"a } in a note"},
+            {"start":"2024-05-02T11:00:00Z","end":"garbage"}},
+            {"start":"2024-05-02T13:00:00Z","end":"2024-05-02T14:00:00Z"}
+        ],"active_period_start":"2024-05-02T15:00:00Z"}"#;
+        let recovery = salvage(contents);
+        assert_eq!(recovery.time_sheet.periods.len(), 2);
+        assert_eq!(recovery.time_sheet.periods[0].metadata.note.as_deref(), Some("a } in a note"));
+        assert_eq!(recovery.dropped.len(), 1);
+        assert!(recovery.time_sheet.active_period_start.is_some());
+    }
+
+    #[test]
+    fn salvage_keeps_breaks_apart_from_periods() {
+        let contents = r#"{"periods":[{"start":"2024-05-02T09:00:00Z","end":"2024-05-02T12:00:00Z"}],
+            "breaks":[{"start":"2024-05-02T12:00:00Z","end":"2024-05-02T13:00:00Z"}],
+            "closed_months":[{"month":"2024-04""#;
+        let recovery = salvage(contents);
+        assert_eq!(recovery.time_sheet.periods.len(), 1);
+        assert_eq!(recovery.time_sheet.periods[0].duration(), chrono::Duration::hours(3));
+        assert_eq!(recovery.time_sheet.breaks.len(), 1);
+        assert_eq!(recovery.time_sheet.breaks[0].end, "2024-05-02T13:00:00Z".parse::<DateTime<Utc>>().unwrap());
+    }
+
+    #[test]
+    fn salvage_keeps_closed_months_and_the_running_session() {
+        let contents = r#"{"periods":[{"start":"2024-05-02T09:00:00Z","end":"2024-05-02T10:00:00Z"},{"start":"x"}],
+            "active_period_start":"2024-05-03T09:00:00Z",
+            "active_metadata":{"project":"acme","fields":{"ticket":"X-1"}},
+            "closed_months":[{"month":"2024-04","closed_at":"2024-05-01T08:00:00Z","checksum":"ab"},{"month":"2024-05"}],
+            "reviewed_days":["2024-05-02"],
+            "mystery":[{"a":1}],
+            "absences":[{"date":"2024-05-0"#;
+        let recovery = salvage(contents);
+        let time_sheet = &recovery.time_sheet;
+        assert_eq!(time_sheet.periods.len(), 1);
+        assert_eq!(time_sheet.active_metadata.project.as_deref(), Some("acme"));
+        assert_eq!(time_sheet.active_metadata.fields["ticket"], "X-1");
+        assert_eq!(time_sheet.closed_months.len(), 1);
+        assert_eq!(time_sheet.closed_months[0].month, "2024-04");
+        assert_eq!(time_sheet.reviewed_days.len(), 1);
+        assert_eq!(
+            recovery.dropped,
+            [
+                r#"period: {"start":"x"}"#,
+                r#"closed month: {"month":"2024-05"}"#,
+                r#"mystery: {"a":1}"#,
+                r#"absence (cut off): {"date":"2024-05-0"#,
+            ]
+        );
+        assert_eq!(describe(time_sheet), "1 period(s), 1 closed month(s), 1 reviewed day(s) and the active session");
+    }
+}
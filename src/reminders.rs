@@ -0,0 +1,282 @@
+// Reminders about long-running sessions, for `remind`. A session is due once it
+// has run longer than its `--remind-after`, or `[reminders] after_hours` when it
+// was started without one. The timesheet is read again for every check, so
+// sessions started and stopped meanwhile are picked up.
+//
+// With `daily_limits`, `remind` also tells when today's tracked time reaches the
+// daily target, on working days with one, and when it reaches `max_daily_hours`.
+// Each is told once a day while watching; with `--once`, every check past it
+// tells again. Every reminder can run `[reminders] command` as well. Watching
+// holds the lock of `daemon`, so it runs once per data file.
+//
+// Budgets with `alerts` are checked by `remind` and after every `stop` and
+// `switch`. The highest alert sent for each project is kept in
+// `<data file>.alerts.json`, so that each is sent once however the budget was
+// crossed; if the use falls back below an alert, e.g. because the budget was
+// raised, it is sent again when crossed again.
+
+use crate::absence;
+use crate::clock::Clock;
+use crate::index;
+use crate::logic::budget;
+use crate::config::Config;
+use crate::daemon;
+use crate::integrations::desktop;
+use crate::timefmt::TimeFormatter;
+use crate::{logic, storage};
+use crate::TimeSheet;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use tracing::{debug, info, instrument, warn};
+
+// How often `remind` looks at the running session.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Threshold for the running session, if it has one.
+pub fn threshold(time_sheet: &TimeSheet, config: &Config) -> Option<Duration> {
+    time_sheet
+        .active_remind_after()
+        .or_else(|| config.reminders.after_hours.map(|hours| Duration::seconds((hours * 3600.0) as i64)))
+}
+
+// A limit of the time tracked in a day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DailyLimit {
+    Target,
+    MaxHours,
+}
+
+impl DailyLimit {
+    fn event(&self) -> &'static str {
+        match self {
+            DailyLimit::Target => "target",
+            DailyLimit::MaxHours => "max_daily_hours",
+        }
+    }
+}
+
+// The limits of `day` and their length: the daily target on a working day that
+// has one, and `max_daily_hours`.
+pub fn daily_limits(config: &Config, day: NaiveDate) -> Vec<(DailyLimit, Duration)> {
+    let target = absence::daily_target(config, day).filter(|_| absence::is_workday(config, day));
+    let max_hours = config.max_daily_hours.map(|hours| Duration::seconds((hours * 3600.0) as i64));
+    [(DailyLimit::Target, target), (DailyLimit::MaxHours, max_hours)]
+        .into_iter()
+        .filter_map(|(limit, length)| Some((limit, length?)))
+        .filter(|(_, length)| *length > Duration::zero())
+        .collect()
+}
+
+// Handles `remind`: checks every minute until interrupted, or a single time with
+// `once` (e.g. from cron, which then decides how often to repeat).
+#[instrument(level = "debug", skip(config, formatter, clock))]
+pub fn watch(data_path: &Path, config: &Config, once: bool, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    // Only one watcher per data file, see `daemon`.
+    let _lock = if once { None } else { Some(daemon::InstanceLock::acquire(data_path)?) };
+    if !once {
+        let limits = if config.reminders.daily_limits { ", daily limits" } else { "" };
+        let budgets = if has_budget_alerts(config) { ", budget alerts" } else { "" };
+        println!("Watching for long sessions{}{}; press Ctrl+C to stop.", limits, budgets);
+    }
+    // Start of the session last reminded about, and when.
+    let mut last: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    // The daily limits told about, and the day.
+    let mut told: (Option<NaiveDate>, Vec<DailyLimit>) = (None, Vec::new());
+    loop {
+        // A data file caught mid-write by a sync client should not end the watch;
+        // it is read again on the next tick.
+        let time_sheet = match storage::load_or_create_timesheet(data_path, config.load_options(clock.now())) {
+            Ok(time_sheet) => time_sheet,
+            Err(e) if !once => {
+                warn!(error = %e, "could not read the timesheet, trying again on the next check");
+                eprintln!("Warning: could not read the timesheet: {}", e);
+                thread::sleep(CHECK_INTERVAL);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let now = clock.now();
+        if config.reminders.daily_limits {
+            let today = config.zone().date(now);
+            if told.0 != Some(today) {
+                told = (Some(today), Vec::new());
+            }
+            let tracked = logic::day_period(today, config.zone())
+                .map_or_else(Duration::zero, |range| logic::calculate_tracked_time_in_period(&time_sheet, &range, now));
+            for (limit, length) in daily_limits(config, today) {
+                if tracked >= length && !told.1.contains(&limit) {
+                    tell_limit(config, limit, length, tracked, now, formatter);
+                    told.1.push(limit);
+                }
+            }
+        }
+        if let (Some(start), Some(after)) = (time_sheet.active_period_start(), threshold(&time_sheet, config))
+            && now - start >= after
+        {
+            let repeat = Duration::minutes(config.reminders.repeat_minutes as i64);
+            let remind = match last {
+                Some((session, at)) if session == start => repeat > Duration::zero() && now - at >= repeat,
+                _ => true,
+            };
+            if remind {
+                remind_about(&time_sheet, config, start, now, formatter);
+                last = Some((start, now));
+            }
+        } else {
+            debug!("no session to remind about");
+        }
+        if let Err(e) = check_budgets(data_path, config, now, formatter) {
+            warn!(error = %e, "could not check the budget alerts");
+            eprintln!("Warning: could not check the budget alerts: {}", e);
+        }
+        if once {
+            return Ok(());
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+fn remind_about(time_sheet: &TimeSheet, config: &Config, start: DateTime<Utc>, now: DateTime<Utc>, formatter: &TimeFormatter) {
+    let details = time_sheet.active_metadata().describe();
+    let body = format!(
+        "Tracking for {} since {}{}. Time for a break, or did you forget to stop?",
+        formatter.duration(now - start),
+        formatter.time(start.with_timezone(&Local)),
+        if details.is_empty() { String::new() } else { format!(" ({})", details) }
+    );
+    info!(since = %start, "reminding about long session");
+    let reminder = Reminder { event: "long_session", title: "Long work session", body, tracked: now - start, env: Vec::new() };
+    send(config, &reminder, now, formatter);
+}
+
+fn tell_limit(config: &Config, limit: DailyLimit, length: Duration, tracked: Duration, now: DateTime<Utc>, formatter: &TimeFormatter) {
+    let (title, body) = match limit {
+        DailyLimit::Target => (
+            "Daily target reached",
+            format!("{} tracked today, your daily target is {}. Time to wrap up?", formatter.duration(tracked), formatter.duration(length)),
+        ),
+        DailyLimit::MaxHours => (
+            "Daily maximum reached",
+            format!(
+                "{} tracked today, the most allowed in a day is {}. Please stop for today.",
+                formatter.duration(tracked),
+                formatter.duration(length)
+            ),
+        ),
+    };
+    info!(limit = limit.event(), "reminding about daily limit");
+    send(config, &Reminder { event: limit.event(), title, body, tracked, env: Vec::new() }, now, formatter);
+}
+
+fn has_budget_alerts(config: &Config) -> bool {
+    config.budgets.values().any(|budget| !budget.alerts.is_empty())
+}
+
+pub fn alerts_path(data_path: &Path) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(".alerts.json");
+    PathBuf::from(name)
+}
+
+// Sends the budget alerts that are due at `now`.
+#[instrument(level = "debug", skip(config, formatter))]
+pub fn check_budgets(data_path: &Path, config: &Config, now: DateTime<Utc>, formatter: &TimeFormatter) -> io::Result<()> {
+    if !has_budget_alerts(config) {
+        return Ok(());
+    }
+    let path = alerts_path(data_path);
+    // The highest alert sent per project.
+    let sent: BTreeMap<String, u32> = match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!(error = %e, "ignoring unreadable budget alerts");
+            BTreeMap::new()
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(e),
+    };
+    let index = index::load_or_rebuild(data_path, config.load_options(now))?;
+    let mut reached = BTreeMap::new();
+    for used in budget::usage(&index, &config.budgets, now) {
+        let Some(project_budget) = config.budgets.get(&used.project) else { continue };
+        if let Some(alert) = budget::due_alert(project_budget, &used, sent.get(&used.project).copied()) {
+            tell_budget(config, &used, alert, now, formatter);
+        }
+        if let Some(alert) = budget::reached_alert(project_budget, &used) {
+            reached.insert(used.project.clone(), alert);
+        }
+    }
+    if reached != sent {
+        fs::write(&path, serde_json::to_string(&reached).map_err(io::Error::other)?)?;
+    }
+    Ok(())
+}
+
+fn tell_budget(config: &Config, used: &budget::BudgetUse, alert: u32, now: DateTime<Utc>, formatter: &TimeFormatter) {
+    let title = if alert >= 100 { "Budget used up" } else { "Budget alert" };
+    let body = format!(
+        "{} has used {} of its {} budget ({:.0}%).",
+        used.project,
+        formatter.duration(used.used),
+        formatter.duration(used.budget),
+        used.percent()
+    );
+    info!(project = %used.project, alert, "sending budget alert");
+    let reminder = Reminder {
+        event: "budget",
+        title,
+        body,
+        tracked: used.used,
+        env: vec![("TT_PROJECT", used.project.clone()), ("TT_ALERT_PERCENT", alert.to_string())],
+    };
+    send(config, &reminder, now, formatter);
+}
+
+struct Reminder {
+    event: &'static str,
+    title: &'static str,
+    body: String,
+    tracked: Duration,
+    /// More variables for the command, depending on the event.
+    env: Vec<(&'static str, String)>,
+}
+
+// Shows the reminder as a desktop notification, or rings the terminal bell if
+// that fails, and runs the configured command.
+fn send(config: &Config, reminder: &Reminder, now: DateTime<Utc>, formatter: &TimeFormatter) {
+    match desktop::notify(reminder.title, &reminder.body) {
+        Ok(()) => println!("{}  {}", formatter.time(now.with_timezone(&Local)), reminder.body),
+        Err(e) => {
+            warn!(error = %e, "desktop notification failed");
+            println!("\x07{}", reminder.body);
+        }
+    }
+    if let Some(command) = &config.reminders.command
+        && let Err(e) = run_command(command, reminder)
+    {
+        warn!(error = %e, "reminder command failed");
+        eprintln!("Warning: {}", e);
+    }
+}
+
+fn run_command(command: &str, reminder: &Reminder) -> io::Result<()> {
+    let mut shell = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command);
+    let status = shell
+        .env("TT_EVENT", reminder.event)
+        .env("TT_TRACKED_SECONDS", reminder.tracked.num_seconds().to_string())
+        .env("TT_MESSAGE", &reminder.body)
+        .envs(reminder.env.iter().map(|(key, value)| (key, value)))
+        .status()
+        .map_err(|e| io::Error::new(e.kind(), format!("Could not run '{}': {}", command, e)))?;
+    debug!(%status, event = reminder.event, "ran reminder command");
+    if !status.success() {
+        return Err(io::Error::other(format!("'{}' failed with {}", command, status)));
+    }
+    Ok(())
+}
@@ -0,0 +1,299 @@
+// Typed model of a report over a time range. `logic::build_report` computes it
+// from the timesheet; the renderers (the text summary here, templates, the shared
+// week page of `serve`) only format it and compute no totals of their own.
+
+use crate::absence::{is_workday, Absence, AbsenceKind, Balance};
+use crate::categories::Category;
+use crate::config::{Config, TrackingWindow};
+use crate::timefmt::TimeFormatter;
+use crate::zone::Zone;
+use crate::{Metadata, Period};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Name of the reporting period, such as "week".
+    pub title: String,
+    pub range: Period,
+    pub generated_at: DateTime<Utc>,
+    /// Whether the range is a trailing window up to now, as for `report rolling`,
+    /// rather than a calendar period. `build_report` leaves it unset.
+    pub rolling: bool,
+    /// The zone the days of the report are counted in.
+    pub zone: Zone,
+    /// Time tracked within the range, including the running session.
+    pub total: Duration,
+    /// Sorted by project, periods without a project first.
+    pub projects: Vec<ProjectRow>,
+    /// Sorted by tag.
+    pub tags: Vec<TagRow>,
+    /// Empty without `[schedule]`.
+    pub categories: Vec<CategoryRow>,
+    /// Time outside `[tracking_window]`, if there is any.
+    pub outside_window: Option<WindowRow>,
+    /// Every local day of the range, with or without tracked time.
+    pub days: Vec<DayRow>,
+    /// Periods overlapping the range by start, the running session last.
+    pub periods: Vec<PeriodRow>,
+    pub active: Option<ActiveSession>,
+    pub absences: Vec<Absence>,
+    /// Only with a weekly target.
+    pub balance: Option<Balance>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectRow {
+    pub project: Option<String>,
+    pub total: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct TagRow {
+    pub tag: String,
+    pub total: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct CategoryRow {
+    pub category: Category,
+    pub total: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowRow {
+    pub window: TrackingWindow,
+    pub total: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct DayRow {
+    pub date: NaiveDate,
+    pub total: Duration,
+    pub projects: Vec<ProjectRow>,
+}
+
+// A row of `report weeks`.
+#[derive(Debug, Clone)]
+pub struct WeekRow {
+    /// The ISO week, e.g. "2024-W05".
+    pub week: String,
+    /// Its Monday.
+    pub first: NaiveDate,
+    pub tracked: Duration,
+    /// Target and balance up to today, if there is a target.
+    pub balance: Option<Balance>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PeriodRow {
+    /// The running session ends at the time the report was generated.
+    pub period: Period,
+    /// The part of the period within the range.
+    pub duration: Duration,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActiveSession {
+    pub start: DateTime<Utc>,
+    pub elapsed: Duration,
+    pub metadata: Metadata,
+}
+
+// An axis to group the time of a report by, from `report --group-by`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Grouping {
+    Project,
+    Tag,
+    /// The session category.
+    Category,
+    /// A custom field, by its key.
+    Field(String),
+    Weekday,
+}
+
+impl Grouping {
+    // Parses project, tag, category, weekday or field:KEY. There are no clients as such:
+    // client is short for field:client.
+    pub fn parse(text: &str) -> io::Result<Grouping> {
+        match text.trim() {
+            "project" => Ok(Grouping::Project),
+            "tag" => Ok(Grouping::Tag),
+            "category" => Ok(Grouping::Category),
+            "weekday" => Ok(Grouping::Weekday),
+            "client" => Ok(Grouping::Field("client".to_string())),
+            other => match other.strip_prefix("field:").map(str::trim) {
+                Some(key) if !key.is_empty() => Ok(Grouping::Field(key.to_string())),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid grouping '{}'. Expected project, tag, category, client, field:KEY or weekday.", text),
+                )),
+            },
+        }
+    }
+
+    /// What is grouped by, e.g. "project" or the key of a field.
+    pub fn name(&self) -> &str {
+        match self {
+            Grouping::Project => "project",
+            Grouping::Tag => "tag",
+            Grouping::Category => "category",
+            Grouping::Field(key) => key,
+            Grouping::Weekday => "weekday",
+        }
+    }
+}
+
+// The time of one group. `None` is the time without a project, tag, category or
+// field value.
+#[derive(Debug, Clone)]
+pub struct GroupRow {
+    pub name: Option<String>,
+    pub total: Duration,
+}
+
+impl Report {
+    /// The time grouped along `grouping`: sorted by name with the time without one
+    /// first, weekdays from Monday. A period with several tags counts for each.
+    /// Weekdays split sessions across midnight like the days of the report.
+    pub fn group(&self, grouping: &Grouping) -> Vec<GroupRow> {
+        if *grouping == Grouping::Weekday {
+            let mut totals = [Duration::zero(); 7];
+            for day in &self.days {
+                totals[day.date.weekday().num_days_from_monday() as usize] += day.total;
+            }
+            return (0..7u8)
+                .filter_map(|index| Some((Weekday::try_from(index).ok()?, totals[index as usize])))
+                .filter(|(_, total)| *total > Duration::zero())
+                .map(|(weekday, total)| GroupRow { name: Some(weekday_name(weekday).to_string()), total })
+                .collect();
+        }
+        let mut totals: BTreeMap<Option<String>, Duration> = BTreeMap::new();
+        for row in &self.periods {
+            let metadata = &row.period.metadata;
+            let names = match grouping {
+                Grouping::Project => vec![metadata.project.clone()],
+                Grouping::Tag if metadata.tags.is_empty() => vec![None],
+                Grouping::Tag => metadata.tags.iter().cloned().map(Some).collect(),
+                Grouping::Category => vec![metadata.category.clone()],
+                Grouping::Field(key) => vec![metadata.fields.get(key).cloned()],
+                Grouping::Weekday => unreachable!("grouped by day above"),
+            };
+            for name in names {
+                *totals.entry(name).or_default() += row.duration;
+            }
+        }
+        totals.into_iter().map(|(name, total)| GroupRow { name, total }).collect()
+    }
+
+    /// The keys of the custom fields of the report's periods, sorted.
+    pub fn field_keys(&self) -> BTreeSet<&str> {
+        self.periods.iter().flat_map(|row| row.period.metadata.fields.keys().map(String::as_str)).collect()
+    }
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+// Prints the summary of `today`, `week` and `month`.
+pub fn print_summary(report: &Report, formatter: &TimeFormatter) {
+    println!("Total time tracked for this {}: {}", report.title, formatter.duration(report.total));
+    for row in &report.categories {
+        println!("  {:<8} {}", row.category.name(), formatter.duration(row.total));
+    }
+    if let Some(outside) = &report.outside_window {
+        println!(
+            "  Outside tracking hours ({}-{}): {}",
+            formatter.time(outside.window.start),
+            formatter.time(outside.window.end),
+            formatter.duration(outside.total)
+        );
+    }
+    for kind in [AbsenceKind::Sick, AbsenceKind::Vacation] {
+        let days: Vec<String> =
+            report.absences.iter().filter(|a| a.kind == kind).map(|a| format!("{} {}", a.date.format("%a"), formatter.date(a.date))).collect();
+        if !days.is_empty() {
+            println!("Absent ({}): {} day(s): {}", kind.name(), days.len(), days.join(", "));
+        }
+    }
+    if let Some(balance) = &report.balance {
+        println!("Target: {}", formatter.duration(balance.target));
+        if balance.credited > Duration::zero() {
+            println!("Credited for absences: {}", formatter.duration(balance.credited));
+        }
+        let sign = if balance.balance < Duration::zero() { "-" } else { "+" };
+        println!("Balance: {}{}", sign, formatter.duration(balance.balance.abs()));
+    }
+}
+
+// Working days of the report before `today` with neither tracked time nor an
+// absence: the days that were likely forgotten.
+pub fn untracked_workdays(report: &Report, config: &Config, today: NaiveDate) -> Vec<NaiveDate> {
+    report
+        .days
+        .iter()
+        .filter(|day| day.date < today && day.total.is_zero() && is_workday(config, day.date))
+        .filter(|day| !report.absences.iter().any(|a| a.date == day.date))
+        .map(|day| day.date)
+        .collect()
+}
+
+// Prints the untracked working days for `report missing`.
+pub fn print_missing(report: &Report, config: &Config, today: NaiveDate, formatter: &TimeFormatter) {
+    let missing = untracked_workdays(report, config, today);
+    if missing.is_empty() {
+        println!("No untracked working days in {}.", report.title);
+        return;
+    }
+    println!("Working days without tracked time in {}:", report.title);
+    for day in &missing {
+        println!("  {} {}", day.format("%a"), formatter.date(*day));
+    }
+    println!("{} day(s).", missing.len());
+}
+
+// Prints `report weeks`: a row per ISO week, then the totals.
+pub fn print_weeks(rows: &[WeekRow], formatter: &TimeFormatter) {
+    let targets = rows.iter().any(|row| row.balance.is_some());
+    let signed = |duration: Duration| {
+        format!("{}{}", if duration < Duration::zero() { "-" } else { "+" }, formatter.duration(duration.abs()))
+    };
+    let line = |label: &str, dates: &str, tracked: Duration, balance: Option<(Duration, Duration)>| match balance {
+        Some((target, difference)) => println!(
+            "{:<9} {:<23} {:>10} {:>10} {:>11}",
+            label,
+            dates,
+            formatter.duration(tracked),
+            formatter.duration(target),
+            signed(difference)
+        ),
+        None => println!("{:<9} {:<23} {:>10}", label, dates, formatter.duration(tracked)),
+    };
+    if targets {
+        println!("{:<9} {:<23} {:>10} {:>10} {:>11}", "Week", "Days", "Tracked", "Target", "Difference");
+    } else {
+        println!("{:<9} {:<23} {:>10}", "Week", "Days", "Tracked");
+    }
+    for row in rows {
+        let dates = format!("{} - {}", formatter.date(row.first), formatter.date(row.first + Duration::days(6)));
+        line(&row.week, &dates, row.tracked, row.balance.as_ref().map(|b| (b.target, b.balance)));
+    }
+    let tracked = rows.iter().map(|row| row.tracked).sum();
+    let balance = targets.then(|| {
+        let balances = rows.iter().filter_map(|row| row.balance.as_ref());
+        balances.fold((Duration::zero(), Duration::zero()), |(target, difference), b| (target + b.target, difference + b.balance))
+    });
+    println!();
+    line("Total", &format!("{} week(s)", rows.len()), tracked, balance);
+}
@@ -0,0 +1,180 @@
+// Bulk changes to the tags of many periods: `tag rename` and `retag`.
+//
+// Both apply to every matching period and to the running session, skip periods in
+// closed months, and with `dry_run` only show what would change, as a diff. `report` uses the
+// same filters to count only some periods.
+
+use crate::diff::{self, Version};
+use crate::timefmt::TimeFormatter;
+use crate::{Metadata, TimeSheet};
+use std::io::{self, IsTerminal};
+use tracing::{info, instrument};
+
+// A condition from `--filter key=value`: `project=acme` (`project=` for periods
+// without a project), `tag=billable`, `note=text` for notes containing the text,
+// `category=focus` (`category=` for periods without), or `field.ticket=ABC-1` for
+// a custom field (`field.ticket=` for periods without).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Project(Option<String>),
+    Tag(String),
+    Note(String),
+    Category(Option<String>),
+    Field(String, Option<String>),
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> io::Result<Filter> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid filter '{}'. Expected project=NAME, tag=NAME, note=TEXT, category=NAME or field.KEY=VALUE.", text),
+            )
+        };
+        let (key, value) = text.split_once('=').ok_or_else(invalid)?;
+        let value = value.trim();
+        match key.trim() {
+            "project" => Ok(Filter::Project(Some(value.to_string()).filter(|v| !v.is_empty()))),
+            "tag" if !value.is_empty() => Ok(Filter::Tag(value.to_string())),
+            "note" if !value.is_empty() => Ok(Filter::Note(value.to_string())),
+            "category" => Ok(Filter::Category(Some(value.to_lowercase()).filter(|v| !v.is_empty()))),
+            key => match key.strip_prefix("field.").map(str::trim) {
+                Some(field) if !field.is_empty() => {
+                    Ok(Filter::Field(field.to_string(), Some(value.to_string()).filter(|v| !v.is_empty())))
+                }
+                _ => Err(invalid()),
+            },
+        }
+    }
+
+    fn matches(&self, metadata: &Metadata) -> bool {
+        match self {
+            Filter::Project(project) => metadata.project == *project,
+            Filter::Tag(tag) => metadata.tags.contains(tag),
+            Filter::Note(text) => metadata.note.as_deref().is_some_and(|note| note.contains(text.as_str())),
+            Filter::Category(category) => metadata.category == *category,
+            Filter::Field(key, value) => metadata.fields.get(key) == value.as_ref(),
+        }
+    }
+}
+
+// A copy of the timesheet with only the periods, and the running session, that
+// match all filters.
+pub fn only_matching(time_sheet: &TimeSheet, filters: &[Filter]) -> TimeSheet {
+    let matches = |metadata: &Metadata| filters.iter().all(|filter| filter.matches(metadata));
+    let mut matching = time_sheet.clone();
+    matching.periods.retain(|period| matches(&period.metadata));
+    if !matches(&matching.active_metadata) {
+        matching.active_period_start = None;
+        matching.active_metadata = Metadata::default();
+        matching.active_laps.clear();
+    }
+    matching
+}
+
+// Handles `tag rename <old> <new>`. A period that already has `new` keeps it once.
+// Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, formatter))]
+pub fn rename_tag(time_sheet: &mut TimeSheet, old: &str, new: &str, dry_run: bool, formatter: &TimeFormatter) -> io::Result<bool> {
+    let new = new.trim();
+    if new.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The new tag name is empty."));
+    }
+    let filters = [Filter::Tag(old.to_string())];
+    apply(time_sheet, &filters, dry_run, formatter, |metadata| {
+        metadata.tags.retain(|tag| tag != old);
+        if !metadata.tags.iter().any(|tag| tag == new) {
+            metadata.tags.push(new.to_string());
+        }
+    })
+}
+
+// Handles `retag`: adds and removes tags on every period matching all filters,
+// and sets their category if one is given. Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, formatter))]
+pub fn retag(
+    time_sheet: &mut TimeSheet,
+    filters: &[Filter],
+    add: &[String],
+    remove: &[String],
+    category: Option<&str>,
+    dry_run: bool,
+    formatter: &TimeFormatter,
+) -> io::Result<bool> {
+    apply(time_sheet, filters, dry_run, formatter, |metadata| {
+        if let Some(category) = category {
+            metadata.category = Some(category.to_string());
+        }
+        metadata.tags.retain(|tag| !remove.contains(tag));
+        for tag in add {
+            if !metadata.tags.contains(tag) {
+                metadata.tags.push(tag.clone());
+            }
+        }
+    })
+}
+
+// Runs `change` on the metadata of every matching period and the running session,
+// and reports the result.
+fn apply(
+    time_sheet: &mut TimeSheet,
+    filters: &[Filter],
+    dry_run: bool,
+    formatter: &TimeFormatter,
+    change: impl Fn(&mut Metadata),
+) -> io::Result<bool> {
+    let matches = |metadata: &Metadata| filters.iter().all(|filter| filter.matches(metadata));
+    let color = diff::use_color(io::stdout().is_terminal());
+    let (mut changed, mut locked) = (0, 0);
+    for index in 0..time_sheet.periods.len() {
+        let period = &time_sheet.periods[index];
+        if !matches(&period.metadata) {
+            continue;
+        }
+        let mut metadata = period.metadata.clone();
+        change(&mut metadata);
+        if metadata == period.metadata {
+            continue;
+        }
+        if time_sheet.is_locked(period) {
+            locked += 1;
+            continue;
+        }
+        if dry_run {
+            let after = Version { metadata: &metadata, ..Version::from(period) };
+            print!("{}", diff::render(&period.id.to_string(), Some(period.into()), Some(after), formatter, color));
+        }
+        changed += 1;
+        if !dry_run {
+            time_sheet.periods[index].metadata = metadata;
+        }
+    }
+
+    let mut active = false;
+    if time_sheet.active_period_start.is_some() && matches(&time_sheet.active_metadata) {
+        let mut metadata = time_sheet.active_metadata.clone();
+        change(&mut metadata);
+        if metadata != time_sheet.active_metadata {
+            active = true;
+            if let Some(start) = time_sheet.active_period_start.filter(|_| dry_run) {
+                let before = Version { start, end: None, metadata: &time_sheet.active_metadata };
+                let after = Version { metadata: &metadata, ..before };
+                print!("{}", diff::render("running session", Some(before), Some(after), formatter, color));
+            } else {
+                time_sheet.active_metadata = metadata;
+            }
+        }
+    }
+
+    info!(changed, locked, active, dry_run, "retagged periods");
+    let session = if active { " and the running session" } else { "" };
+    if dry_run {
+        println!("Would change {} period(s){}. Nothing was saved.", changed, session);
+    } else {
+        println!("Changed {} period(s){}.", changed, session);
+    }
+    if locked > 0 {
+        println!("Skipped {} period(s) in closed months.", locked);
+    }
+    Ok(!dry_run && (changed > 0 || active))
+}
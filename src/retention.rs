@@ -0,0 +1,188 @@
+// Enforcing the retention policy of `[retention]` with `gdpr enforce`.
+//
+// Periods that ended before the cutoff, `after_years` before today, lose
+// everything that could identify a client: project, tags, note, custom fields
+// and lap notes. Their times stay, so balances and totals do not change; only
+// the category, which says nothing about the client, is kept. With `action =
+// "delete"` the periods are removed instead. Absence notes before the cutoff go
+// the same way, or the absences themselves.
+//
+// Data protection comes before the lock of closed months, so periods in closed
+// months are purged as well and the checksums of those months taken again.
+// Months whose periods had already changed are left as they were, so `doctor`
+// still reports them. Copies kept elsewhere, such as remote snapshots or a
+// CalDAV calendar, are not touched.
+
+use crate::clock::Clock;
+use crate::closing;
+use crate::config::{Config, RetentionAction};
+use crate::diff::{self, Version};
+use crate::timefmt::TimeFormatter;
+use crate::{absence::Absence, logic, prompt, Metadata, Period, TimeSheet};
+use chrono::{Duration, Local, Months, NaiveDate};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use tracing::{debug, info, instrument};
+
+// What `--export` writes: the data as it was before the policy was applied.
+#[derive(Serialize)]
+struct Removed<'a> {
+    cutoff: NaiveDate,
+    action: RetentionAction,
+    periods: Vec<&'a Period>,
+    absences: Vec<&'a Absence>,
+}
+
+// Whether the period carries anything the policy removes when anonymizing.
+fn has_details(period: &Period) -> bool {
+    !Metadata { category: None, ..period.metadata.clone() }.is_empty() || period.laps.iter().any(|lap| lap.note.is_some())
+}
+
+fn anonymize(period: &mut Period) {
+    period.metadata = Metadata { category: period.metadata.category.take(), ..Metadata::default() };
+    for lap in &mut period.laps {
+        lap.note = None;
+    }
+}
+
+// Handles `gdpr enforce`: applies the retention policy to everything before the
+// cutoff. `export` receives what is removed, also with `dry_run`. Asks first on
+// a terminal; elsewhere `yes` is required. Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn enforce(
+    time_sheet: &mut TimeSheet,
+    config: &Config,
+    dry_run: bool,
+    yes: bool,
+    export: Option<&Path>,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<bool> {
+    let Some(policy) = &config.retention else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No retention policy is configured. Add a [retention] section with after_years to the config.",
+        ));
+    };
+    let today = clock.now().with_timezone(&Local).date_naive();
+    let cutoff = today.checked_sub_months(Months::new(policy.after_years.saturating_mul(12))).unwrap_or(NaiveDate::MIN);
+    let Some(cutoff_time) = logic::day_period(cutoff, config.zone()).map(|day| day.start) else {
+        return Err(io::Error::other(format!("Cannot find the start of {} in local time.", cutoff)));
+    };
+    let delete = policy.action == RetentionAction::Delete;
+
+    let periods: Vec<usize> = (0..time_sheet.periods.len())
+        .filter(|&index| {
+            let period = &time_sheet.periods[index];
+            period.end <= cutoff_time && (delete || has_details(period))
+        })
+        .collect();
+    let absences: Vec<usize> = (0..time_sheet.absences.len())
+        .filter(|&index| {
+            let absence = &time_sheet.absences[index];
+            absence.date < cutoff && (delete || absence.note.is_some())
+        })
+        .collect();
+    debug!(%cutoff, periods = periods.len(), absences = absences.len(), "found data under the retention policy");
+    if periods.is_empty() && absences.is_empty() {
+        println!("Nothing from before {} falls under the retention policy.", formatter.date(cutoff));
+        return Ok(false);
+    }
+
+    if let Some(path) = export {
+        let removed = Removed {
+            cutoff,
+            action: policy.action,
+            periods: periods.iter().map(|&index| &time_sheet.periods[index]).collect(),
+            absences: absences.iter().map(|&index| &time_sheet.absences[index]).collect(),
+        };
+        let json = serde_json::to_string_pretty(&removed).map_err(io::Error::other)?;
+        fs::write(path, json)?;
+        println!("Exported what the policy removes to {}.", path.display());
+    }
+
+    let verb = if delete { "Delete" } else { "Anonymize" };
+    if dry_run {
+        let color = diff::use_color(io::stdout().is_terminal());
+        for &index in &periods {
+            let period = &time_sheet.periods[index];
+            let mut anonymized = period.clone();
+            anonymize(&mut anonymized);
+            let after = (!delete).then(|| Version::from(&anonymized));
+            print!("{}", diff::render(&period.id.to_string(), Some(period.into()), after, formatter, color));
+        }
+        for &index in &absences {
+            let absence = &time_sheet.absences[index];
+            let line = format!("{}  {:<8}  {}", formatter.date(absence.date), absence.kind.name(), absence.note.as_deref().unwrap_or(""));
+            println!("{}", line.trim_end());
+        }
+    }
+    let mut summary = format!("{} {} period(s)", verb, periods.len());
+    if let (Some(&first), Some(&last)) = (periods.first(), periods.last()) {
+        summary.push_str(&format!(
+            " from {} to {}",
+            formatter.date(time_sheet.periods[first].start.with_timezone(&Local).date_naive()),
+            formatter.date(time_sheet.periods[last].end.with_timezone(&Local).date_naive())
+        ));
+    }
+    summary.push_str(&format!(
+        " and {} {}, everything before {}",
+        absences.len(),
+        if delete { "absence(s)" } else { "absence note(s)" },
+        formatter.date(cutoff)
+    ));
+    if dry_run {
+        println!("{}. Nothing was changed.", summary);
+        return Ok(false);
+    }
+    let question = format!("{}?", summary);
+    if !yes {
+        if !io::stdin().is_terminal() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} Pass --yes to confirm.", question)));
+        }
+        if !prompt::confirm(&question)? {
+            println!("Nothing was changed.");
+            return Ok(false);
+        }
+    }
+
+    let already_modified = closing::modified_months(time_sheet, config)?;
+    let mut months = BTreeSet::new();
+    for &index in &periods {
+        let period = &time_sheet.periods[index];
+        for time in [period.start, period.end - Duration::seconds(1)] {
+            if let Some(closed) = time_sheet.closed_month_at(time) {
+                months.insert(closed.month.clone());
+            }
+        }
+    }
+    if delete {
+        for &index in periods.iter().rev() {
+            time_sheet.periods.remove(index);
+        }
+        for &index in absences.iter().rev() {
+            time_sheet.absences.remove(index);
+        }
+    } else {
+        for &index in &periods {
+            anonymize(&mut time_sheet.periods[index]);
+        }
+        for &index in &absences {
+            time_sheet.absences[index].note = None;
+        }
+    }
+    let months: Vec<String> = months.into_iter().filter(|month| !already_modified.contains(month)).collect();
+    for month in &months {
+        closing::reseal(time_sheet, month, config)?;
+    }
+
+    info!(%cutoff, delete, periods = periods.len(), absences = absences.len(), resealed = months.len(), "enforced retention policy");
+    println!("{}d {} period(s) and {} absence(s) from before {}.", verb, periods.len(), absences.len(), formatter.date(cutoff));
+    if !months.is_empty() {
+        println!("Updated the checksums of the closed month(s) {}.", months.join(", "));
+    }
+    Ok(true)
+}
@@ -0,0 +1,172 @@
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::timefmt::TimeFormatter;
+use crate::zone::Zone;
+use crate::{absence, gaps, logic, prompt, Metadata, Period, TimeSheet};
+use chrono::{Duration, Local, NaiveDate};
+use std::io::{self, IsTerminal};
+use tracing::{debug, info, instrument};
+use uuid::Uuid;
+
+// Periods with the same metadata that are at most this far apart are offered for
+// merging as fragments of one session.
+const FRAGMENT_GAP_MINUTES: i64 = 5;
+// Shortest gap offered for filling.
+const MIN_GAP_MINUTES: i64 = 15;
+
+// Handles `review week`: walks through the days of the current week up to today
+// and, for each, asks for missing projects, offers to merge fragments and to fill
+// gaps in the workday, and finally to mark the day as reviewed. Reviewed days are
+// skipped unless `all` is set. Returns whether the timesheet changed.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn review_week(time_sheet: &mut TimeSheet, config: &Config, all: bool, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "'review' asks questions and needs a terminal."));
+    }
+    let now = clock.now();
+    let today = now.with_timezone(&Local).date_naive();
+    let week = logic::get_week_period(config.week_start.0, clock, config.zone());
+
+    let mut changed = false;
+    for day in absence::days_in(&week, config.zone()).filter(|day| *day <= today) {
+        if time_sheet.reviewed_days.contains(&day) && !all {
+            println!("{} was already reviewed.", describe_day(day, formatter));
+            continue;
+        }
+        if let Some(range) = logic::day_period(day, config.zone())
+            && let Some(closed) = time_sheet.closed_month_at(range.start)
+        {
+            println!("{} is in the closed month {}.", describe_day(day, formatter), closed.month);
+            continue;
+        }
+        changed |= review_day(time_sheet, config, day, formatter, clock)?;
+    }
+    time_sheet.reviewed_days.sort();
+    Ok(changed)
+}
+
+fn review_day(time_sheet: &mut TimeSheet, config: &Config, day: NaiveDate, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<bool> {
+    let Some(range) = logic::day_period(day, config.zone()) else { return Ok(false) };
+    // A day off without sessions has nothing to review.
+    if !absence::is_workday(config, day) && periods_on(time_sheet, day).is_empty() {
+        return Ok(false);
+    }
+    let tracked = logic::calculate_tracked_time_in_period(time_sheet, &range, clock.now());
+    println!("== {}: {} tracked ==", describe_day(day, formatter), formatter.duration(tracked));
+    for period in periods_on(time_sheet, day) {
+        println!("  {}", describe(period, formatter));
+    }
+    let mut changed = false;
+
+    // Missing projects.
+    let unassigned: Vec<Uuid> =
+        periods_on(time_sheet, day).into_iter().filter(|p| p.metadata.project.is_none()).map(|p| p.id).collect();
+    for id in unassigned {
+        let Some(period) = time_sheet.periods.iter_mut().find(|p| p.id == id) else { continue };
+        let question = format!("  Project for {}, empty to skip", describe(period, formatter));
+        let project = prompt::ask(&question, "")?;
+        if !project.is_empty() {
+            period.metadata.project = Some(project);
+            changed = true;
+        }
+    }
+
+    // Fragments of one session.
+    for run in fragments(time_sheet, day) {
+        let Some(first) = time_sheet.periods.iter().find(|p| p.id == run[0]).cloned() else { continue };
+        let last_end = time_sheet.periods.iter().filter(|p| run.contains(&p.id)).map(|p| p.end).max().unwrap_or(first.end);
+        let question = format!(
+            "  Merge {} fragments {}-{}{}?",
+            run.len(),
+            formatter.time(first.start.with_timezone(&Local)),
+            formatter.time(last_end.with_timezone(&Local)),
+            describe_metadata(&first.metadata)
+        );
+        if prompt::confirm(&question)? {
+            merge(time_sheet, &run);
+            changed = true;
+        }
+    }
+
+    // Gaps in the workday.
+    let absent = time_sheet.absences.iter().any(|a| a.date == day);
+    if absence::is_workday(config, day) && !absent {
+        let (hours, breaks) = gaps::expected_hours(time_sheet, config, day, false);
+        let found = gaps::find_gaps_around(time_sheet, &hours, &breaks, Duration::minutes(MIN_GAP_MINUTES), clock.now());
+        if !found.is_empty() {
+            let (recorded, breaks) = gaps::fill_gaps(time_sheet, config, &found, formatter)?;
+            changed |= recorded + breaks > 0;
+        }
+    }
+
+    if prompt::confirm(&format!("  Mark {} as reviewed?", day))? {
+        if !time_sheet.reviewed_days.contains(&day) {
+            time_sheet.reviewed_days.push(day);
+            changed = true;
+        }
+        info!(%day, "reviewed day");
+    }
+    println!();
+    Ok(changed)
+}
+
+// Finished periods starting on the local day, by start time.
+fn periods_on(time_sheet: &TimeSheet, day: NaiveDate) -> Vec<&Period> {
+    let Some(range) = logic::day_period(day, Zone::default()) else { return Vec::new() };
+    time_sheet.periods_in(&range).filter(|p| p.start.with_timezone(&Local).date_naive() == day).collect()
+}
+
+// Runs of two or more periods on the day with the same metadata, each starting at
+// most `FRAGMENT_GAP_MINUTES` after the previous one ended.
+fn fragments(time_sheet: &TimeSheet, day: NaiveDate) -> Vec<Vec<Uuid>> {
+    let mut runs: Vec<Vec<Uuid>> = Vec::new();
+    let mut previous: Option<&Period> = None;
+    for period in periods_on(time_sheet, day) {
+        let joins = previous.is_some_and(|previous| {
+            previous.metadata == period.metadata
+                && period.start >= previous.end
+                && period.start - previous.end <= Duration::minutes(FRAGMENT_GAP_MINUTES)
+        });
+        match runs.last_mut() {
+            Some(run) if joins => run.push(period.id),
+            _ => runs.push(vec![period.id]),
+        }
+        previous = Some(period);
+    }
+    runs.retain(|run| run.len() > 1);
+    debug!(%day, runs = runs.len(), "found fragments");
+    runs
+}
+
+// Extends the first period of the run over the others, which are removed.
+fn merge(time_sheet: &mut TimeSheet, run: &[Uuid]) {
+    let (mut end, mut laps) = (None, Vec::new());
+    for period in time_sheet.periods.iter().filter(|p| run[1..].contains(&p.id)) {
+        end = end.max(Some(period.end));
+        laps.extend(period.laps.iter().cloned());
+    }
+    time_sheet.periods.retain(|p| !run[1..].contains(&p.id));
+    if let Some(first) = time_sheet.periods.iter_mut().find(|p| p.id == run[0]) {
+        first.end = first.end.max(end.unwrap_or(first.end));
+        first.laps.extend(laps);
+    }
+}
+
+fn describe_day(day: NaiveDate, formatter: &TimeFormatter) -> String {
+    format!("{} {}", day.format("%a"), formatter.date(day))
+}
+
+fn describe(period: &Period, formatter: &TimeFormatter) -> String {
+    format!(
+        "{}-{} ({}){}",
+        formatter.time(period.start.with_timezone(&Local)),
+        formatter.time(period.end.with_timezone(&Local)),
+        formatter.duration(period.duration()),
+        describe_metadata(&period.metadata)
+    )
+}
+
+fn describe_metadata(metadata: &Metadata) -> String {
+    let details = metadata.describe();
+    if details.is_empty() { String::new() } else { format!("  {}", details) }
+}
@@ -0,0 +1,247 @@
+// `search <query>`: finds periods by their project, tags, note, category and
+// custom fields.
+//
+// Every word of the query has to match one of those fields, either as a part of
+// it or fuzzily, with its letters in order and only a few others in between, so
+// "mtg" finds "meeting". Parts count more than fuzzy matches, and matches at the
+// start of a word more than inside one. The best matches come first, the latest
+// first among equals. On a terminal, the matched letters are highlighted.
+//
+// With `search_index = true` in the config, the searched fields are kept in
+// `<data file>.search.json`, so that a search reads that file instead of the whole
+// history. Like the daily index, it records the data file and journal it was built
+// from and is rebuilt once they change.
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::index::{self, Stamp};
+use crate::storage::LoadOptions;
+use crate::timefmt::TimeFormatter;
+use crate::{storage, Metadata, TimeSheet};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument, warn};
+use uuid::Uuid;
+
+// Score of a query word found as a part of a field, before bonuses.
+const PART_SCORE: i64 = 100;
+// Score of a fuzzy match without any letters between the matched ones.
+const FUZZY_SCORE: i64 = 50;
+// Added when the match starts a word.
+const WORD_START_BONUS: i64 = 20;
+// A fuzzy match may spread over at most this many times the word's length.
+const MAX_SPREAD: usize = 3;
+
+// The searchable side of a period.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Entry {
+    id: Uuid,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    active: bool,
+    /// Field name and text, in the order `Metadata::describe` shows them.
+    fields: Vec<(String, String)>,
+}
+
+impl Entry {
+    fn new(id: Uuid, start: DateTime<Utc>, end: DateTime<Utc>, active: bool, metadata: &Metadata) -> Entry {
+        let mut fields = Vec::new();
+        if let Some(project) = &metadata.project {
+            fields.push(("project".to_string(), project.clone()));
+        }
+        if !metadata.tags.is_empty() {
+            fields.push(("tags".to_string(), metadata.tags.join(", ")));
+        }
+        if let Some(note) = &metadata.note {
+            fields.push(("note".to_string(), note.clone()));
+        }
+        if let Some(category) = &metadata.category {
+            fields.push(("category".to_string(), category.clone()));
+        }
+        for (key, value) in &metadata.fields {
+            fields.push((key.clone(), value.clone()));
+        }
+        Entry { id, start, end, active, fields }
+    }
+}
+
+// The entries of every period and of the running session, whose end is filled
+// in when it is shown.
+fn entries(time_sheet: &TimeSheet) -> Vec<Entry> {
+    let mut entries: Vec<Entry> =
+        time_sheet.periods.iter().map(|p| Entry::new(p.id, p.start, p.end, false, &p.metadata)).collect();
+    if let Some(start) = time_sheet.active_period_start {
+        entries.push(Entry::new(Uuid::nil(), start, start, true, &time_sheet.active_metadata));
+    }
+    entries
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SearchIndex {
+    stamp: Stamp,
+    entries: Vec<Entry>,
+}
+
+pub fn search_index_path(data_path: &Path) -> PathBuf {
+    let mut name = OsString::from(data_path.as_os_str());
+    name.push(".search.json");
+    PathBuf::from(name)
+}
+
+// The entries from the search index, rebuilding it if it is missing or out of date.
+fn load_or_rebuild(data_path: &Path, options: LoadOptions) -> io::Result<Vec<Entry>> {
+    let stamp = index::stamp(data_path)?;
+    let path = search_index_path(data_path);
+    match fs::read_to_string(&path) {
+        Ok(contents) => match serde_json::from_str::<SearchIndex>(&contents) {
+            Ok(index) if index.stamp == stamp => return Ok(index.entries),
+            Ok(_) => debug!("search index is out of date"),
+            Err(e) => warn!(error = %e, "ignoring unreadable search index"),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    debug!("rebuilding search index");
+    let index = SearchIndex { stamp, entries: entries(&storage::load_or_create_timesheet(data_path, options)?) };
+    fs::write(&path, serde_json::to_string(&index).map_err(io::Error::other)?)?;
+    Ok(index.entries)
+}
+
+// Where a query word matched: the field and the positions of the matched
+// characters in it.
+struct FieldMatch {
+    field: usize,
+    positions: Vec<usize>,
+    score: i64,
+}
+
+// The best match of a lowercase query word in a field.
+fn match_field(word: &[char], text: &str) -> Option<(Vec<usize>, i64)> {
+    let text: Vec<char> = text.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect();
+    let word_start = |at: usize| at == 0 || !text[at - 1].is_alphanumeric();
+    let bonus = |at: usize| if word_start(at) { WORD_START_BONUS } else { 0 };
+
+    let starts = (0..text.len()).filter(|at| text[*at] == word[0]);
+    // As a part, preferably at the start of a word.
+    if let Some(at) = starts.clone().filter(|at| text[*at..].starts_with(word)).max_by_key(|at| (bonus(*at), -(*at as i64))) {
+        return Some(((at..at + word.len()).collect(), PART_SCORE + bonus(at)));
+    }
+    // Fuzzily: from each possible first letter, the nearest of each following one,
+    // keeping the tightest.
+    starts
+        .filter_map(|at| {
+            let mut positions = vec![at];
+            for letter in &word[1..] {
+                let last = *positions.last()?;
+                positions.push(last + 1 + text[last + 1..].iter().position(|c| c == letter)?);
+            }
+            let spread = positions.last()? + 1 - at;
+            (spread <= word.len() * MAX_SPREAD).then(|| {
+                let score = FUZZY_SCORE - (spread - word.len()) as i64 + bonus(at);
+                (positions, score)
+            })
+        })
+        .max_by_key(|(_, score)| *score)
+}
+
+// The matches of every query word in the entry, or none if a word matches nowhere.
+fn match_entry(words: &[Vec<char>], entry: &Entry) -> Option<Vec<FieldMatch>> {
+    words
+        .iter()
+        .map(|word| {
+            entry
+                .fields
+                .iter()
+                .enumerate()
+                .filter_map(|(field, (_, text))| {
+                    match_field(word, text).map(|(positions, score)| FieldMatch { field, positions, score })
+                })
+                .max_by_key(|m| m.score)
+        })
+        .collect()
+}
+
+// A field as "name: text", with the matched characters highlighted.
+fn render_field(name: &str, text: &str, positions: &[usize], color: bool) -> String {
+    let mut out = format!("{}: ", name);
+    let mut highlighted = false;
+    for (at, c) in text.chars().enumerate() {
+        let matched = color && positions.contains(&at);
+        if matched != highlighted {
+            out.push_str(if matched { "\x1b[1;33m" } else { "\x1b[0m" });
+            highlighted = matched;
+        }
+        out.push(c);
+    }
+    if highlighted {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+// Handles `search <query>`: the `limit` best matching periods.
+#[instrument(level = "debug", skip(data_path, config, formatter, clock))]
+pub fn search(data_path: &Path, config: &Config, query: &str, limit: usize, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    let words: Vec<Vec<char>> = query.split_whitespace().map(|word| word.to_lowercase().chars().collect()).collect();
+    if words.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Give something to search for."));
+    }
+    let options = config.load_options(clock.now());
+    let entries = if config.search_index {
+        load_or_rebuild(data_path, options)?
+    } else {
+        entries(&storage::load_or_create_timesheet(data_path, options)?)
+    };
+
+    let mut found: Vec<(i64, &Entry, Vec<FieldMatch>)> = entries
+        .iter()
+        .filter_map(|entry| {
+            let matches = match_entry(&words, entry)?;
+            Some((matches.iter().map(|m| m.score).sum(), entry, matches))
+        })
+        .collect();
+    found.sort_by(|(a, first, _), (b, second, _)| b.cmp(a).then(second.start.cmp(&first.start)));
+    debug!(entries = entries.len(), found = found.len(), "searched");
+    if found.is_empty() {
+        println!("Nothing matches '{}'.", query.trim());
+        return Ok(());
+    }
+
+    let color = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let now = clock.now();
+    for (_, entry, matches) in found.iter().take(limit) {
+        let start = entry.start.with_timezone(&Local);
+        let end = if entry.active { now } else { entry.end };
+        let id = if entry.active { "running ".to_string() } else { entry.id.to_string()[..8].to_string() };
+        let fields: Vec<String> = entry
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(field, (name, text))| {
+                let positions: Vec<usize> =
+                    matches.iter().filter(|m| m.field == field).flat_map(|m| m.positions.iter().copied()).collect();
+                render_field(name, text, &positions, color)
+            })
+            .collect();
+        println!(
+            "{}  {} {}  {}-{}  {:>10}  {}",
+            id,
+            formatter.date(start.date_naive()),
+            start.format("%a"),
+            formatter.time(start),
+            if entry.active { "now  ".to_string() } else { formatter.time(end.with_timezone(&Local)) },
+            formatter.duration(end - entry.start),
+            fields.join(", ")
+        );
+    }
+    if found.len() > limit {
+        println!("{} more; show them with --limit.", found.len() - limit);
+    }
+    Ok(())
+}
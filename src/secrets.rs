@@ -0,0 +1,83 @@
+// Tokens and passwords for integrations, kept in the OS keyring (Keychain,
+// Windows Credential Manager, the Linux kernel keyring) instead of the config.
+//
+// Every secret is stored under the service "work_time_tracker" with its name as
+// the entry, e.g. "slack-token". Integrations also accept their secret from an
+// environment variable, which takes precedence, for scripts and CI.
+
+use std::env;
+use std::io;
+use tracing::{debug, instrument};
+
+const SERVICE: &str = "work_time_tracker";
+
+// Secret names used by the integrations, listed in `secret --help`.
+pub const SLACK_TOKEN: &str = "slack-token";
+pub const CALDAV_PASSWORD: &str = "caldav-password";
+pub const SHARE_TOKEN: &str = "share-token";
+pub const REMOTE_PASSWORD: &str = "remote-password";
+pub const REMOTE_PASSPHRASE: &str = "remote-passphrase";
+
+fn entry(name: &str) -> io::Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, name).map_err(keyring_error)
+}
+
+fn keyring_error(error: keyring::Error) -> io::Error {
+    match error {
+        keyring::Error::NoStorageAccess(e) | keyring::Error::PlatformFailure(e) => {
+            io::Error::other(format!("The OS keyring is not available: {}", e))
+        }
+        e => io::Error::other(e),
+    }
+}
+
+// The stored secret, or none if there is no entry.
+#[instrument(level = "debug")]
+pub fn get(name: &str) -> io::Result<Option<String>> {
+    match entry(name)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(keyring_error(e)),
+    }
+}
+
+// Stores `secret`, replacing an existing one.
+#[instrument(level = "debug", skip(secret))]
+pub fn set(name: &str, secret: &str) -> io::Result<()> {
+    if secret.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A secret cannot be empty."));
+    }
+    entry(name)?.set_password(secret).map_err(|e| match e {
+        // Linux reports a missing session keyring, e.g. in a container, this way.
+        keyring::Error::NoEntry => io::Error::other("The OS keyring is not available: no session keyring."),
+        e => keyring_error(e),
+    })?;
+    debug!(name, "stored secret");
+    Ok(())
+}
+
+// Removes the secret. Returns false if there was none.
+#[instrument(level = "debug")]
+pub fn delete(name: &str) -> io::Result<bool> {
+    match entry(name)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(keyring_error(e)),
+    }
+}
+
+// Looks a secret up in the environment variable `variable` first, then in the
+// keyring. Fails with a hint on how to store it when neither has it.
+pub fn lookup(name: &str, variable: &str) -> io::Result<String> {
+    if let Ok(secret) = env::var(variable)
+        && !secret.is_empty()
+    {
+        return Ok(secret);
+    }
+    get(name)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No secret '{}'. Store it with 'secret set {}' or set {}.", name, name, variable),
+        )
+    })
+}
@@ -0,0 +1,319 @@
+// Read-only web dashboard on localhost.
+//
+// `serve` answers plain HTTP/1.1 GET requests one at a time:
+//
+//   /               the dashboard page, which polls the JSON endpoints below
+//   /api/status     whether a session is running, and today's and this week's totals
+//   /api/week       tracked time per day of the current week
+//   /api/sessions   the most recent sessions (`?limit=N`, default 10)
+//   /metrics        totals as Prometheus metrics (see `metrics`)
+//
+// These are only answered for the local machine. With `--bind` on another
+// address, other machines can read the current week's report, with totals per day
+// and project but no notes, given the "share-token" secret as `?token=...`:
+//
+//   /report/week.json
+//   /report/week.html
+//
+// The timesheet is read from disk for every request, so changes made with the
+// command line show up on the next refresh. Nothing is ever written.
+
+use crate::absence;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::logic;
+use crate::metrics;
+use crate::report::Report;
+use crate::secrets;
+use crate::storage;
+use crate::timefmt::TimeFormatter;
+use crate::TimeSheet;
+use chrono::{Duration, Local};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::path::Path;
+use tracing::{debug, info, instrument, warn};
+
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+// Sessions shown when the request gives no limit.
+const DEFAULT_SESSION_LIMIT: usize = 10;
+
+// Requests are answered one at a time, so a client that sends nothing, or an
+// endless request line, must not hold up the others.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const MAX_REQUEST_LINE: usize = 8 * 1024;
+
+const SHARE_TOKEN_VARIABLE: &str = "WORK_TIME_TRACKER_SHARE_TOKEN";
+
+// Handles the "serve" command. Runs until the process is stopped.
+#[instrument(level = "debug", skip(config, formatter, clock))]
+pub fn serve(data_path: &Path, config: &Config, bind: IpAddr, port: u16, formatter: &TimeFormatter, clock: &dyn Clock) -> io::Result<()> {
+    let share_token = match secrets::lookup(secrets::SHARE_TOKEN, SHARE_TOKEN_VARIABLE) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            debug!(error = %e, "no share token, the week report is not shared");
+            None
+        }
+    };
+    let listener = TcpListener::bind((bind, port))?;
+    info!(%bind, port, shared = share_token.is_some(), "serving dashboard");
+    let host = if bind.is_unspecified() { IpAddr::from([127, 0, 0, 1]) } else { bind };
+    println!("Dashboard at http://{}:{}/ (press Ctrl+C to stop)", host, port);
+    match &share_token {
+        Some(_) => println!("Shared week report at http://{}:{}/report/week.html?token=<share-token>", host, port),
+        None if !bind.is_loopback() => println!(
+            "Other machines get no data until a share token is stored with 'secret set {}'.",
+            secrets::SHARE_TOKEN
+        ),
+        None => {}
+    }
+
+    for stream in listener.incoming() {
+        let result = stream
+            .and_then(|stream| handle_connection(stream, data_path, config, share_token.as_deref(), formatter, clock));
+        if let Err(e) = result {
+            warn!(error = %e, "request failed");
+        }
+    }
+    Ok(())
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    data_path: &Path,
+    config: &Config,
+    share_token: Option<&str>,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<()> {
+    // Requests from this machine come from loopback or from the bound address.
+    let peer = stream.peer_addr()?.ip();
+    let local = peer.is_loopback() || peer == stream.local_addr()?.ip();
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST_LINE as u64)).read_line(&mut request_line)?;
+    if request_line.len() >= MAX_REQUEST_LINE && !request_line.ends_with('\n') {
+        return respond(&mut stream, "414 URI Too Long", "text/plain", "The request line is too long.\n");
+    }
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or("/"));
+    debug!(method, target, "request");
+
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", "Only GET is supported.\n");
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    match path {
+        "/report/week.json" | "/report/week.html" => {
+            let Some(expected) = share_token else {
+                return respond(&mut stream, "404 Not Found", "text/plain", "Sharing is off.\n");
+            };
+            if !query_value(query, "token").is_some_and(|token| same_token(token, expected)) {
+                return respond(&mut stream, "403 Forbidden", "text/plain", "Missing or wrong token.\n");
+            }
+            let time_sheet = match storage::load_or_create_timesheet(data_path, config.load_options(clock.now())) {
+                Ok(time_sheet) => time_sheet,
+                Err(e) => {
+                    warn!(error = %e, "could not read the timesheet");
+                    return respond(&mut stream, "500 Internal Server Error", "text/plain", "Could not read the timesheet.\n");
+                }
+            };
+            let report = week_report(&time_sheet, config, formatter, clock);
+            match path {
+                "/report/week.json" => respond(&mut stream, "200 OK", "application/json", &report.to_string()),
+                _ => respond(&mut stream, "200 OK", "text/html; charset=utf-8", &week_report_html(&report)),
+            }
+        }
+        _ if !local => respond(&mut stream, "403 Forbidden", "text/plain", "Only the shared report is available.\n"),
+        "/" | "/index.html" => respond(&mut stream, "200 OK", "text/html; charset=utf-8", DASHBOARD_HTML),
+        "/metrics" => match storage::load_or_create_timesheet(data_path, config.load_options(clock.now())) {
+            Ok(time_sheet) => respond(&mut stream, "200 OK", metrics::CONTENT_TYPE, &metrics::render(&time_sheet, config, clock)),
+            Err(e) => {
+                warn!(error = %e, "could not read the timesheet");
+                respond(&mut stream, "500 Internal Server Error", "text/plain", "Could not read the timesheet.\n")
+            }
+        },
+        "/api/status" | "/api/week" | "/api/sessions" => {
+            let body = match storage::load_or_create_timesheet(data_path, config.load_options(clock.now())) {
+                Ok(time_sheet) => match path {
+                    "/api/status" => status(&time_sheet, config, formatter, clock),
+                    "/api/week" => week(&time_sheet, config, formatter, clock),
+                    _ => sessions(&time_sheet, config, session_limit(query), formatter, clock),
+                },
+                Err(e) => {
+                    warn!(error = %e, "could not read the timesheet");
+                    let body = json!({ "error": e.to_string() }).to_string();
+                    return respond(&mut stream, "500 Internal Server Error", "application/json", &body);
+                }
+            };
+            respond(&mut stream, "200 OK", "application/json", &body.to_string())
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found.\n"),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn query_value<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').filter_map(|pair| pair.split_once('=')).find(|(key, _)| *key == name).map(|(_, value)| value)
+}
+
+fn session_limit(query: &str) -> usize {
+    query_value(query, "limit").and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_SESSION_LIMIT)
+}
+
+// Compares without stopping at the first difference, so the time taken does not
+// tell how much of a guess was right.
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn duration_json(duration: Duration, formatter: &TimeFormatter) -> Value {
+    json!({ "seconds": duration.num_seconds(), "text": formatter.duration(duration) })
+}
+
+fn status(time_sheet: &TimeSheet, config: &Config, formatter: &TimeFormatter, clock: &dyn Clock) -> Value {
+    let now = clock.now();
+    let today = logic::get_today_period(clock, config.zone());
+    let week = logic::get_week_period(config.week_start.0, clock, config.zone());
+    let active = time_sheet.active_period_start().map(|start| {
+        json!({
+            "since": formatter.date_time(start.with_timezone(&Local)),
+            "elapsed": duration_json(now - start, formatter),
+            "details": time_sheet.active_metadata().describe(),
+        })
+    });
+    json!({
+        "active": active,
+        "today": duration_json(logic::calculate_tracked_time_in_period(time_sheet, &today, now), formatter),
+        "week": duration_json(logic::calculate_tracked_time_in_period(time_sheet, &week, now), formatter),
+        "weekly_target": absence::weekly_target(config, clock.now().with_timezone(&Local).date_naive())
+            .map(|hours| duration_json(Duration::seconds((hours * 3600.0) as i64), formatter)),
+    })
+}
+
+fn week(time_sheet: &TimeSheet, config: &Config, formatter: &TimeFormatter, clock: &dyn Clock) -> Value {
+    let week = logic::get_week_period(config.week_start.0, clock, config.zone());
+    let report = logic::build_report(time_sheet, config, "week", &week, clock.now());
+    let days: Vec<Value> = report
+        .days
+        .iter()
+        .map(|day| {
+            json!({
+                "date": formatter.date(day.date),
+                "weekday": day.date.format("%a").to_string(),
+                "tracked": duration_json(day.total, formatter),
+            })
+        })
+        .collect();
+    json!({ "start": week_start(&report, formatter), "days": days })
+}
+
+fn sessions(time_sheet: &TimeSheet, config: &Config, limit: usize, formatter: &TimeFormatter, clock: &dyn Clock) -> Value {
+    let sessions: Vec<Value> = logic::recent_periods_with_totals(time_sheet, limit, config.week_start.0, clock.now())
+        .into_iter()
+        .rev()
+        .map(|session| {
+            let start = session.period.start().with_timezone(&Local);
+            json!({
+                "date": format!("{} {}", formatter.date(start.date_naive()), start.format("%a")),
+                "start": formatter.time(start),
+                "end": (!session.active).then(|| formatter.time(session.period.end().with_timezone(&Local))),
+                "duration": duration_json(session.period.duration(), formatter),
+                "details": session.period.metadata().describe(),
+                "active": session.active,
+            })
+        })
+        .collect();
+    json!({ "sessions": sessions })
+}
+
+// The current week for sharing: time per day and project, without notes or tags.
+fn week_report(time_sheet: &TimeSheet, config: &Config, formatter: &TimeFormatter, clock: &dyn Clock) -> Value {
+    let week = logic::get_week_period(config.week_start.0, clock, config.zone());
+    let report = logic::build_report(time_sheet, config, "week", &week, clock.now());
+    let days: Vec<Value> = report
+        .days
+        .iter()
+        .map(|day| {
+            let projects: Vec<Value> = day
+                .projects
+                .iter()
+                .map(|p| json!({ "project": p.project.clone().unwrap_or_default(), "tracked": duration_json(p.total, formatter) }))
+                .collect();
+            json!({
+                "date": formatter.date(day.date),
+                "weekday": day.date.format("%a").to_string(),
+                "tracked": duration_json(day.total, formatter),
+                "projects": projects,
+            })
+        })
+        .collect();
+    json!({
+        "start": week_start(&report, formatter),
+        "days": days,
+        "total": duration_json(report.total, formatter),
+        "weekly_target": absence::weekly_target(config, clock.now().with_timezone(&Local).date_naive())
+            .map(|hours| duration_json(Duration::seconds((hours * 3600.0) as i64), formatter)),
+    })
+}
+
+fn week_start(report: &Report, formatter: &TimeFormatter) -> String {
+    formatter.date(report.range.start().with_timezone(&Local).date_naive())
+}
+
+// Renders `week_report` as a plain HTML table.
+fn week_report_html(report: &Value) -> String {
+    let text = |value: &Value| escape_html(value.as_str().unwrap_or(""));
+    let mut rows = String::new();
+    for day in report["days"].as_array().into_iter().flatten() {
+        let projects: Vec<String> = day["projects"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|p| {
+                let name = p["project"].as_str().filter(|name| !name.is_empty()).unwrap_or("(no project)");
+                format!("{} {}", escape_html(name), text(&p["tracked"]["text"]))
+            })
+            .collect();
+        rows.push_str(&format!(
+            "<tr><td>{} {}</td><td class=\"n\">{}</td><td>{}</td></tr>\n",
+            text(&day["weekday"]),
+            text(&day["date"]),
+            text(&day["tracked"]["text"]),
+            projects.join(", ")
+        ));
+    }
+    let target = match report["weekly_target"]["text"].as_str() {
+        Some(target) => format!(" of {}", escape_html(target)),
+        None => String::new(),
+    };
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Week of {start}</title>\n\
+         <style>body{{font-family:sans-serif}}td,th{{padding:2px 12px;text-align:left}}.n{{text-align:right}}</style>\n\
+         </head><body>\n<h1>Week of {start}</h1>\n<table>\n<tr><th>Day</th><th class=\"n\">Tracked</th><th>Projects</th></tr>\n\
+         {rows}<tr><th>Total</th><th class=\"n\">{total}{target}</th><th></th></tr>\n</table>\n</body></html>\n",
+        start = text(&report["start"]),
+        rows = rows,
+        total = text(&report["total"]["text"]),
+        target = target
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,158 @@
+// Monthly storage layout, used when the data path is a directory:
+//
+//   state.json     everything but the periods: the running session, closed
+//                  months, absences, breaks and plans
+//   2024/05.json   the periods that start in May 2024 (local time), as a list
+//
+// Writing compares each file with what is on disk and only replaces the ones that
+// changed, so stopping a session rewrites a single month and synced copies see
+// small diffs. Reports read only the months they cover.
+
+use crate::storage::LoadOptions;
+use crate::{strict, Period, TimeSheet};
+use chrono::{DateTime, Datelike, Local, Months, NaiveDate, Utc};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, instrument};
+
+const STATE_FILE: &str = "state.json";
+
+// Reads the state and every month.
+#[instrument(level = "debug")]
+pub fn read_all(dir: &Path, options: LoadOptions) -> io::Result<TimeSheet> {
+    let mut time_sheet = read_state(dir, options)?;
+    let shards = shard_paths(dir)?;
+    for path in &shards {
+        time_sheet.periods.extend(read_shard(path, options)?);
+    }
+    debug!(shards = shards.len(), periods = time_sheet.periods.len(), "loaded monthly timesheet");
+    Ok(time_sheet)
+}
+
+// Reads the state and the months that can hold periods overlapping `range`: the
+// months it touches and the one before, for sessions running past midnight at the
+// turn of the month.
+#[instrument(level = "debug")]
+pub fn read_range(dir: &Path, range: &Period, options: LoadOptions) -> io::Result<TimeSheet> {
+    let mut time_sheet = read_state(dir, options)?;
+    let first = month_start(range.start);
+    let last = month_start(range.end);
+    let mut month = first.checked_sub_months(Months::new(1)).unwrap_or(first);
+    let mut read = 0;
+    while month <= last {
+        let path = shard_path(dir, month);
+        if path.exists() {
+            time_sheet.periods.extend(read_shard(&path, options)?);
+            read += 1;
+        }
+        let Some(next) = month.checked_add_months(Months::new(1)) else { break };
+        month = next;
+    }
+    debug!(shards = read, periods = time_sheet.periods.len(), "loaded months for range");
+    Ok(time_sheet)
+}
+
+// Writes the timesheet, replacing only files whose contents changed and removing
+// months that no longer have periods. Returns the number of files written.
+#[instrument(level = "debug", skip(time_sheet), fields(periods = time_sheet.periods.len()))]
+pub fn write(dir: &Path, time_sheet: &TimeSheet) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+    let mut months: BTreeMap<PathBuf, Vec<&Period>> = BTreeMap::new();
+    for period in &time_sheet.periods {
+        months.entry(shard_path(dir, month_start(period.start))).or_default().push(period);
+    }
+
+    let state = TimeSheet { periods: Vec::new(), ..time_sheet.clone() };
+    let mut written = write_if_changed(&dir.join(STATE_FILE), &to_json(&state)?)? as usize;
+    for (path, periods) in &months {
+        written += write_if_changed(path, &to_json(periods)?)? as usize;
+    }
+    for path in shard_paths(dir)? {
+        if !months.contains_key(&path) {
+            debug!(path = %path.display(), "removing empty month");
+            fs::remove_file(&path)?;
+        }
+    }
+    debug!(written, "saved monthly timesheet");
+    Ok(written)
+}
+
+fn read_state(dir: &Path, options: LoadOptions) -> io::Result<TimeSheet> {
+    let path = dir.join(STATE_FILE);
+    match fs::read_to_string(&path) {
+        Ok(contents) => strict::from_json(&path, &contents, options).map_err(|e| invalid_data(&path, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(TimeSheet::default()),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_shard(path: &Path, options: LoadOptions) -> io::Result<Vec<Period>> {
+    let contents = fs::read_to_string(path)?;
+    strict::from_json(path, &contents, options).map_err(|e| invalid_data(path, e))
+}
+
+// All month files, oldest first.
+fn shard_paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    if !dir.exists() {
+        return Ok(paths);
+    }
+    for year in fs::read_dir(dir)? {
+        let year = year?.path();
+        let is_year = year.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.parse::<i32>().is_ok());
+        if !year.is_dir() || !is_year {
+            continue;
+        }
+        for month in fs::read_dir(&year)? {
+            let month = month?.path();
+            if month.extension().is_some_and(|e| e == "json") {
+                paths.push(month);
+            }
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn shard_path(dir: &Path, month: NaiveDate) -> PathBuf {
+    dir.join(month.format("%Y").to_string()).join(month.format("%m.json").to_string())
+}
+
+// The first day of the local month of `time`.
+fn month_start(time: DateTime<Utc>) -> NaiveDate {
+    let date = time.with_timezone(&Local).date_naive();
+    date.with_day(1).unwrap_or(date)
+}
+
+fn to_json(value: &impl serde::Serialize) -> io::Result<String> {
+    serde_json::to_string_pretty(value).map_err(io::Error::other)
+}
+
+// Replaces the file through a temporary one, unless it already holds `contents`.
+fn write_if_changed(path: &Path, contents: &str) -> io::Result<bool> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)?;
+    Ok(true)
+}
+
+fn invalid_data(path: &Path, error: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Could not read {}: {}", path.display(), error))
+}
+
+// Total size of the files in the directory, for `convert`.
+pub fn size(dir: &Path) -> io::Result<u64> {
+    let mut total = fs::metadata(dir.join(STATE_FILE)).map(|m| m.len()).unwrap_or(0);
+    for path in shard_paths(dir)? {
+        total += fs::metadata(path)?.len();
+    }
+    Ok(total)
+}
@@ -0,0 +1,273 @@
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::logic;
+use crate::timefmt::TimeFormatter;
+use crate::zone::Zone;
+use crate::{Period, TimeSheet};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, Utc};
+use std::collections::BTreeMap;
+use std::io;
+use tracing::{debug, instrument};
+
+// Days of tracked time before a day that its baseline is taken from.
+const BASELINE_DAYS: i64 = 28;
+// Fewer tracked days than this in the baseline say too little about what is usual.
+const MIN_BASELINE_SAMPLES: usize = 5;
+// An hour of the day is usually busy if at least half of it was tracked on this
+// share of the baseline days.
+const BUSY_HOUR_SHARE: f64 = 0.75;
+// Usually busy hours in an untracked stretch of a day that make it a gap.
+const MIN_GAP_HOURS: usize = 2;
+// Days within this of the usual total are never unusual, however regular the
+// baseline is.
+const MIN_DEVIATION_MINUTES: i64 = 30;
+
+// Tracked time per local calendar day. Periods crossing midnight are split
+// between the days they touch. Days without tracked time have no entry.
+pub struct DailyTotals {
+    totals: BTreeMap<NaiveDate, Duration>,
+}
+
+impl DailyTotals {
+    // Indexes all periods of the timesheet, including the active session up to `now`.
+    pub fn build(time_sheet: &TimeSheet, now: DateTime<Utc>) -> Self {
+        let mut totals: BTreeMap<NaiveDate, Duration> = BTreeMap::new();
+        let active = time_sheet.active_period_start.filter(|start| *start < now).map(|start| Period::range(start, now));
+        for period in time_sheet.periods.iter().chain(active.as_ref()) {
+            let mut day = period.start.with_timezone(&Local).date_naive();
+            while let Some(day_range) = logic::day_period(day, Zone::default()).filter(|d| d.start < period.end) {
+                let overlap = period.overlap(&day_range);
+                if overlap > Duration::zero() {
+                    *totals.entry(day).or_insert_with(Duration::zero) += overlap;
+                }
+                let Some(next) = day.succ_opt() else { break };
+                day = next;
+            }
+        }
+        debug!(days = totals.len(), "indexed daily totals");
+        DailyTotals { totals }
+    }
+
+    pub fn get(&self, day: NaiveDate) -> Duration {
+        self.totals.get(&day).copied().unwrap_or_else(Duration::zero)
+    }
+
+    pub fn first_day(&self) -> Option<NaiveDate> {
+        self.totals.keys().next().copied()
+    }
+}
+
+// Handles `stats onthisday`: compares the tracked time on `date` with the same
+// date in previous years and the same weekday in previous weeks.
+#[instrument(level = "debug", skip(time_sheet, formatter, clock))]
+pub fn on_this_day(
+    time_sheet: &TimeSheet,
+    date: Option<&str>,
+    years: u32,
+    weeks: u32,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<()> {
+    let now = clock.now();
+    let date = match date {
+        Some(text) => NaiveDate::parse_from_str(text, "%Y-%m-%d").map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid date '{}'. Expected YYYY-MM-DD, e.g. 2024-05-17.", text),
+            )
+        })?,
+        None => now.with_timezone(&Local).date_naive(),
+    };
+
+    let index = DailyTotals::build(time_sheet, now);
+    let Some(first_day) = index.first_day() else {
+        println!("No time tracked yet.");
+        return Ok(());
+    };
+
+    println!("{}: {}", describe(date, formatter), formatter.duration(index.get(date)));
+
+    let previous_years: Vec<NaiveDate> = (1..=years as i32)
+        .filter_map(|back| same_date_in_year(date, date.year() - back))
+        .take_while(|day| *day >= first_day)
+        .collect();
+    if !previous_years.is_empty() {
+        println!();
+        println!("Same date in previous years:");
+        print_days(&index, &previous_years, formatter);
+    }
+
+    let previous_weeks: Vec<NaiveDate> = (1..=weeks as i64)
+        .map(|back| date - Duration::weeks(back))
+        .take_while(|day| *day >= first_day)
+        .collect();
+    if !previous_weeks.is_empty() {
+        println!();
+        println!("Same weekday in previous weeks:");
+        print_days(&index, &previous_weeks, formatter);
+    }
+
+    Ok(())
+}
+
+fn print_days(index: &DailyTotals, days: &[NaiveDate], formatter: &TimeFormatter) {
+    let mut total = Duration::zero();
+    for day in days {
+        let tracked = index.get(*day);
+        total += tracked;
+        println!("  {}: {}", describe(*day, formatter), formatter.duration(tracked));
+    }
+    println!("  Average: {}", formatter.duration(total / days.len() as i32));
+}
+
+fn describe(day: NaiveDate, formatter: &TimeFormatter) -> String {
+    format!("{} {}", formatter.date(day), day.format("%a"))
+}
+
+// The same month and day in another year. February 29 falls back to February 28.
+fn same_date_in_year(date: NaiveDate, year: i32) -> Option<NaiveDate> {
+    date.with_year(year).or_else(|| NaiveDate::from_ymd_opt(year, date.month(), date.day() - 1))
+}
+
+// Tracked time of one local day, in total and per hour of the day.
+struct DayProfile {
+    total: Duration,
+    busy_hours: [bool; 24],
+    first_hour: Option<usize>,
+    last_hour: Option<usize>,
+}
+
+impl DayProfile {
+    fn build(time_sheet: &TimeSheet, day: NaiveDate, config: &Config, now: DateTime<Utc>) -> DayProfile {
+        let hour_start = |hour: u32| match NaiveTime::from_hms_opt(hour, 0, 0) {
+            Some(time) => logic::naive_to_utc(day.and_time(time), config.zone()),
+            None => logic::naive_to_utc(day.succ_opt().unwrap_or(day).and_time(NaiveTime::MIN), config.zone()),
+        };
+        let range = Period::range(hour_start(0), hour_start(24));
+        let active = time_sheet.active_period_start.filter(|start| *start < now).map(|start| Period::range(start, now));
+        let periods: Vec<&Period> = time_sheet.periods_in(&range).chain(active.as_ref()).collect();
+
+        let mut profile = DayProfile { total: Duration::zero(), busy_hours: [false; 24], first_hour: None, last_hour: None };
+        for hour in 0..24 {
+            let slot = Period::range(hour_start(hour as u32), hour_start(hour as u32 + 1));
+            let tracked: Duration = periods.iter().map(|p| p.overlap(&slot)).sum();
+            profile.total += tracked;
+            if tracked > Duration::zero() {
+                profile.first_hour.get_or_insert(hour);
+                profile.last_hour = Some(hour);
+            }
+            profile.busy_hours[hour] = tracked >= Duration::minutes(30);
+        }
+        profile
+    }
+}
+
+// Something unusual about a day, found by `anomalies`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    // Much more tracked than usual, e.g. after a forgotten stop.
+    Long { usual: Duration, deviation: Duration },
+    // Much less tracked than usual, e.g. after a missing import.
+    Short { usual: Duration, deviation: Duration },
+    // Untracked hours, from `start` to `end`, that are usually busy.
+    Gap { start: usize, end: usize },
+}
+
+// Unusual days among the `days` days up to `today`, each compared with the
+// tracked days of the four weeks before it: a total more than `sigma` standard
+// deviations from their mean (and at least half an hour), or an untracked
+// stretch with at least two hours that are busy on most of them. Days without tracked time are left to `report missing`, and days
+// with an absence are not called short.
+pub fn anomalies(
+    time_sheet: &TimeSheet,
+    config: &Config,
+    days: u32,
+    sigma: f64,
+    today: NaiveDate,
+    now: DateTime<Utc>,
+) -> Vec<(NaiveDate, Duration, Anomaly)> {
+    let first = today - Duration::days(days.saturating_sub(1) as i64);
+    let mut profiles: BTreeMap<NaiveDate, DayProfile> = BTreeMap::new();
+    for day in (first - Duration::days(BASELINE_DAYS)).iter_days().take_while(|day| *day <= today) {
+        profiles.insert(day, DayProfile::build(time_sheet, day, config, now));
+    }
+
+    let mut found = Vec::new();
+    for (day, profile) in profiles.range(first..) {
+        if profile.total.is_zero() {
+            continue;
+        }
+        let baseline: Vec<&DayProfile> = profiles
+            .range(*day - Duration::days(BASELINE_DAYS)..*day)
+            .map(|(_, p)| p)
+            .filter(|p| !p.total.is_zero())
+            .collect();
+        if baseline.len() < MIN_BASELINE_SAMPLES {
+            continue;
+        }
+        let totals: Vec<f64> = baseline.iter().map(|p| p.total.num_seconds() as f64).collect();
+        let mean = totals.iter().sum::<f64>() / totals.len() as f64;
+        let deviation = (totals.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / totals.len() as f64).sqrt();
+        let usual = Duration::seconds(mean.round() as i64);
+        let spread = Duration::seconds(deviation.round() as i64);
+        let total = profile.total.num_seconds() as f64;
+        let deviation = deviation.max((MIN_DEVIATION_MINUTES * 60) as f64 / sigma.max(f64::EPSILON));
+        // Today is not over, so it can only be too long yet.
+        if total > mean + sigma * deviation {
+            found.push((*day, profile.total, Anomaly::Long { usual, deviation: spread }));
+        } else if *day < today
+            && total < mean - sigma * deviation
+            && !time_sheet.absences.iter().any(|a| a.date == *day)
+        {
+            found.push((*day, profile.total, Anomaly::Short { usual, deviation: spread }));
+        }
+
+        let (Some(first_hour), Some(last_hour)) = (profile.first_hour, profile.last_hour) else { continue };
+        let usually_busy: Vec<bool> = (0..24)
+            .map(|hour| baseline.iter().filter(|p| p.busy_hours[hour]).count() as f64 >= BUSY_HOUR_SHARE * baseline.len() as f64)
+            .collect();
+        // Untracked stretches between the first and last tracked hour, flagged if
+        // enough of their hours are usually busy.
+        let mut hour = first_hour;
+        while hour <= last_hour {
+            let start = hour;
+            while hour <= last_hour && !profile.busy_hours[hour] {
+                hour += 1;
+            }
+            if (start..hour).filter(|h| usually_busy[*h]).count() >= MIN_GAP_HOURS {
+                found.push((*day, profile.total, Anomaly::Gap { start, end: hour }));
+            }
+            hour += 1;
+        }
+    }
+    debug!(days, found = found.len(), "looked for anomalies");
+    found
+}
+
+// Handles `stats anomalies`.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn show_anomalies(time_sheet: &TimeSheet, config: &Config, days: u32, sigma: f64, formatter: &TimeFormatter, clock: &dyn Clock) {
+    let now = clock.now();
+    let found = anomalies(time_sheet, config, days, sigma, now.with_timezone(&Local).date_naive(), now);
+    if found.is_empty() {
+        println!("No unusual days in the last {} days.", days);
+        return;
+    }
+    println!("Unusual days in the last {} days, compared with the {} days before each:", days, BASELINE_DAYS);
+    for (day, total, anomaly) in &found {
+        let what = match anomaly {
+            Anomaly::Long { usual, deviation } => format!(
+                "long day, usually {} ± {}. Forgotten stop?",
+                formatter.duration(*usual),
+                formatter.duration(*deviation)
+            ),
+            Anomaly::Short { usual, deviation } => format!(
+                "short day, usually {} ± {}. Missing entries?",
+                formatter.duration(*usual),
+                formatter.duration(*deviation)
+            ),
+            Anomaly::Gap { start, end } => format!("nothing tracked {:02}:00-{:02}:00, usually busy hours", start, end),
+        };
+        println!("  {}  {:>10}  {}", describe(*day, formatter), formatter.duration(*total), what);
+    }
+}
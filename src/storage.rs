@@ -0,0 +1,394 @@
+use crate::config::{self, Config, StorageLayout};
+use crate::{journal, recovery, shards, strict, Period, TimeSheet};
+use chrono::{DateTime, Utc};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use tracing::{debug, info, instrument, warn};
+
+// On-disk formats of the timesheet, chosen by the file extension: `.msgpack` is
+// MessagePack (with the `binary` feature), everything else is JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageFormat {
+    Json,
+    MessagePack,
+}
+
+impl StorageFormat {
+    pub fn for_path(path: &Path) -> StorageFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("msgpack") => StorageFormat::MessagePack,
+            _ => StorageFormat::Json,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageFormat::Json => "JSON",
+            StorageFormat::MessagePack => "MessagePack",
+        }
+    }
+}
+
+// How the data file is loaded. By default, files edited by hand or by other tools
+// are tidied up on load; strict loading refuses them instead (see `strict`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// Load strictly, as of this time: periods must not end after it.
+    pub strict_at: Option<DateTime<Utc>>,
+}
+
+impl LoadOptions {
+    /// Strict loading, with `now` as the current time.
+    pub fn strict(now: DateTime<Utc>) -> LoadOptions {
+        LoadOptions { strict_at: Some(now) }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict_at.is_some()
+    }
+}
+
+// Whether the path uses the monthly layout: an existing directory, or a path
+// written with a trailing slash.
+pub fn is_monthly(path: &Path) -> bool {
+    path.is_dir() || path.as_os_str().to_string_lossy().ends_with(std::path::is_separator)
+}
+
+// Reads a timesheet in the format given by the extension, or a monthly directory.
+// Unlike loading the data file, no recovery is attempted.
+pub fn read(path: &Path) -> io::Result<TimeSheet> {
+    if is_monthly(path) {
+        return shards::read_all(path, LoadOptions::default());
+    }
+    match StorageFormat::for_path(path) {
+        StorageFormat::Json => {
+            let contents = fs::read_to_string(path)?;
+            serde_json::from_str(&contents).map_err(|e| invalid_data(path, e))
+        }
+        StorageFormat::MessagePack => read_binary(path),
+    }
+}
+
+// Writes a timesheet in the format given by the extension, or as a monthly directory.
+pub fn write(path: &Path, time_sheet: &TimeSheet) -> io::Result<()> {
+    if is_monthly(path) {
+        return shards::write(path, time_sheet).map(|_| ());
+    }
+    let temporary = write_temporary(path, time_sheet)?;
+    fs::rename(&temporary, path)
+}
+
+// The file a new version of the data file is written to before it replaces it.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+// Writes the timesheet next to the data file and flushes it to disk, so that the
+// rename over the data file either keeps the old version or gives the whole new
+// one, even if the machine goes down in between.
+fn write_temporary(path: &Path, time_sheet: &TimeSheet) -> io::Result<PathBuf> {
+    let temporary = temporary_path(path);
+    let file = OpenOptions::new().write(true).truncate(true).create(true).open(&temporary)?;
+    let mut writer = BufWriter::new(file);
+    match StorageFormat::for_path(path) {
+        StorageFormat::Json => serde_json::to_writer_pretty(&mut writer, time_sheet).map_err(io::Error::other)?,
+        StorageFormat::MessagePack => writer.write_all(&encode_binary(path, time_sheet)?)?,
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(temporary)
+}
+
+#[cfg(feature = "binary")]
+pub fn read_binary(path: &Path) -> io::Result<TimeSheet> {
+    let bytes = fs::read(path)?;
+    rmp_serde::from_slice(&bytes).map_err(|e| invalid_data(path, e))
+}
+
+#[cfg(feature = "binary")]
+fn encode_binary(_path: &Path, time_sheet: &TimeSheet) -> io::Result<Vec<u8>> {
+    // Named fields keep the format self-describing, which the flattened period
+    // metadata and the optional fields need.
+    rmp_serde::to_vec_named(time_sheet).map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "binary"))]
+pub fn read_binary(path: &Path) -> io::Result<TimeSheet> {
+    Err(binary_unsupported(path))
+}
+
+#[cfg(not(feature = "binary"))]
+fn encode_binary(path: &Path, _time_sheet: &TimeSheet) -> io::Result<Vec<u8>> {
+    Err(binary_unsupported(path))
+}
+
+#[cfg(not(feature = "binary"))]
+fn binary_unsupported(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is a MessagePack file, but this build has no 'binary' feature.", path.display()),
+    )
+}
+
+fn invalid_data(path: &Path, error: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Could not read {}: {}", path.display(), error))
+}
+
+// Handles `convert <input> <output>`: rewrites a timesheet in the format of the
+// output file's extension, or split by month when the output ends in a slash. An
+// existing output is not overwritten.
+#[instrument(level = "debug")]
+pub fn convert(input: &Path, output: &Path) -> io::Result<()> {
+    if output.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists.", output.display()),
+        ));
+    }
+    let time_sheet = read(input)?;
+    write(output, &time_sheet)?;
+
+    let (from, before) = describe(input)?;
+    let (to, after) = describe(output)?;
+    debug!(from, to, before, after, "converted timesheet");
+    info!(periods = time_sheet.periods.len(), "converted timesheet");
+    println!(
+        "Converted {} ({}, {} bytes) to {} ({}, {} bytes).",
+        input.display(),
+        from,
+        before,
+        output.display(),
+        to,
+        after
+    );
+    if is_monthly(output) {
+        println!("Set data_file to the directory and storage_layout = \"monthly\" in the config to use it.");
+    } else {
+        println!("Set data_file in the config to use the new file.");
+    }
+    Ok(())
+}
+
+// Bytes used by the timesheet, including its journal.
+pub fn size_on_disk(path: &Path) -> io::Result<u64> {
+    let data = if is_monthly(path) {
+        shards::size(path)?
+    } else {
+        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+    };
+    let journal = fs::metadata(journal::journal_path(path)).map(|m| m.len()).unwrap_or(0);
+    Ok(data + journal)
+}
+
+// The layout or format name and size on disk of a converted timesheet.
+fn describe(path: &Path) -> io::Result<(&'static str, u64)> {
+    if is_monthly(path) {
+        return Ok(("monthly", shards::size(path)?));
+    }
+    Ok((StorageFormat::for_path(path).name(), fs::metadata(path)?.len()))
+}
+
+// Gets the path to the timesheet data file, from the config or in the home directory.
+// With the monthly layout, this is a directory, which is created if missing.
+pub fn get_data_file_path(config: &Config) -> io::Result<PathBuf> {
+    let default_name = match config.storage_layout {
+        StorageLayout::Single => ".work_time_tracker.json",
+        StorageLayout::Monthly => ".work_time_tracker",
+    };
+    let path = match &config.data_file {
+        Some(path) => config::expand_home(path),
+        None => match dirs::home_dir() {
+            Some(home) => home.join(default_name),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "Could not find home directory.",
+                ));
+            }
+        },
+    };
+    if config.storage_layout == StorageLayout::Monthly {
+        if path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} is a file, but storage_layout is monthly. Split it with 'convert {} <directory>/'.",
+                    path.display(),
+                    path.display()
+                ),
+            ));
+        }
+        fs::create_dir_all(&path)?;
+        // Without a trailing slash, the journal sits next to the directory.
+        return Ok(path.components().collect());
+    }
+    Ok(path)
+}
+
+// Loads the TimeSheet from the data file and replays the journal on top of it.
+#[instrument(level = "debug")]
+pub fn load_or_create_timesheet(path: &Path, options: LoadOptions) -> io::Result<TimeSheet> {
+    finish_interrupted_save(path)?;
+    let mut time_sheet = load_data_file(path, options)?;
+    strict::check_sorted(path, &time_sheet, options)?;
+    journal::replay(path, &mut time_sheet)?;
+    strict::check(path, &time_sheet, options)?;
+    report_overlaps(path, &time_sheet.check_periods());
+
+    // Older files have no period IDs. Persist the new IDs right away so they stay stable.
+    let assigned = time_sheet.assign_missing_ids();
+    if assigned > 0 {
+        info!(assigned, "assigned IDs to periods without one");
+        save_timesheet(path, &time_sheet)?;
+    }
+
+    Ok(time_sheet)
+}
+
+// Loads what a report on `range` needs. With the monthly layout, only the months
+// around the range are read, so the result must not be saved.
+#[instrument(level = "debug")]
+pub fn load_range(path: &Path, range: &Period, options: LoadOptions) -> io::Result<TimeSheet> {
+    if !is_monthly(path) {
+        return load_or_create_timesheet(path, options);
+    }
+    let mut time_sheet = shards::read_range(path, range, options)?;
+    strict::check_sorted(path, &time_sheet, options)?;
+    journal::replay(path, &mut time_sheet)?;
+    strict::check(path, &time_sheet, options)?;
+    report_overlaps(path, &time_sheet.check_periods());
+    Ok(time_sheet)
+}
+
+// Tells about overlapping periods, which reports count twice for the time they
+// share. Strict loading refuses them instead.
+fn report_overlaps(path: &Path, overlaps: &[(Uuid, Uuid)]) {
+    let Some((first, second)) = overlaps.first() else { return };
+    eprintln!(
+        "Warning: {} pair(s) of periods in {} overlap, e.g. {} and {}; their common time is counted twice. 'doctor' lists them.",
+        overlaps.len(),
+        path.display(),
+        first,
+        second
+    );
+}
+
+// Reads the data file itself, without the journal.
+fn load_data_file(path: &Path, options: LoadOptions) -> io::Result<TimeSheet> {
+    if is_monthly(path) {
+        return shards::read_all(path, options);
+    }
+    if !path.exists() {
+        debug!(path = %path.display(), "data file does not exist, starting empty");
+        return Ok(TimeSheet::default());
+    }
+
+    if StorageFormat::for_path(path) == StorageFormat::MessagePack {
+        // Binary files are written in one go and have no partial content to salvage.
+        let time_sheet = read_binary(path)?;
+        debug!(path = %path.display(), periods = time_sheet.periods.len(), "loaded binary timesheet");
+        return Ok(time_sheet);
+    }
+
+    let contents = fs::read_to_string(path)?;
+
+    let time_sheet = match strict::from_json::<TimeSheet>(path, &contents, options) {
+        Ok(time_sheet) => {
+            debug!(
+                path = %path.display(),
+                periods = time_sheet.periods.len(),
+                active = time_sheet.active_period_start.is_some(),
+                "loaded timesheet"
+            );
+            time_sheet
+        }
+        Err(e) if e.is_eof() && contents.trim().is_empty() => {
+            warn!(path = %path.display(), "data file is empty, starting empty");
+            return Ok(TimeSheet::default());
+        }
+        Err(e) if options.is_strict() => return Err(strict::refuse(path, vec![e.to_string()])),
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "failed to parse data file");
+            recovery::recover_data_file(path, &contents, &e)?
+        }
+    };
+    Ok(time_sheet)
+}
+
+// Saves the TimeSheet data to the data file, as JSON or MessagePack depending on the
+// extension. The journal is folded in, so it is removed afterwards.
+#[instrument(level = "debug", skip(time_sheet), fields(periods = time_sheet.periods.len()))]
+pub fn save_timesheet(path: &Path, time_sheet: &TimeSheet) -> io::Result<()> {
+    if is_monthly(path) {
+        // Each month file is replaced on its own, so a crash can leave some
+        // months saved and others not, but never a damaged one.
+        shards::write(path, time_sheet)?;
+        journal::clear(path)?;
+        debug!(path = %path.display(), "saved timesheet");
+        return Ok(());
+    }
+    // The journal is set aside while the data file is replaced, and only removed
+    // once it has been. `finish_interrupted_save` settles a crash in between.
+    let temporary = write_temporary(path, time_sheet)?;
+    let set_aside = journal::set_aside(path)?;
+    if let Err(e) = fs::rename(&temporary, path) {
+        if set_aside {
+            journal::put_back(path)?;
+        }
+        let _ = fs::remove_file(&temporary);
+        return Err(e);
+    }
+    journal::discard_set_aside(path)?;
+    debug!(path = %path.display(), "saved timesheet");
+    Ok(())
+}
+
+// Completes a save that was interrupted, before the data file is read. If the
+// new version was not yet in place, the old file and its journal are kept;
+// otherwise the journal it folded in is removed, so it is not applied twice.
+fn finish_interrupted_save(path: &Path) -> io::Result<()> {
+    if is_monthly(path) {
+        return Ok(());
+    }
+    let temporary = temporary_path(path);
+    if journal::is_set_aside(path) {
+        if temporary.exists() {
+            warn!(path = %path.display(), "save was interrupted, keeping the previous data file");
+            journal::put_back(path)?;
+        } else {
+            info!(path = %path.display(), "finishing an interrupted save");
+            journal::discard_set_aside(path)?;
+        }
+    }
+    match fs::remove_file(&temporary) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Where a whole timesheet is kept. The data file is one store; the web UI keeps
+// its timesheet in the browser instead (see `wasm::BrowserStore`).
+pub trait TimesheetStore {
+    fn load(&self) -> io::Result<TimeSheet>;
+    fn save(&self, time_sheet: &TimeSheet) -> io::Result<()>;
+}
+
+// The data file with its journal, as used by the command-line program.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    pub path: PathBuf,
+    pub options: LoadOptions,
+}
+
+impl TimesheetStore for FileStore {
+    fn load(&self) -> io::Result<TimeSheet> {
+        load_or_create_timesheet(&self.path, self.options)
+    }
+
+    fn save(&self, time_sheet: &TimeSheet) -> io::Result<()> {
+        save_timesheet(&self.path, time_sheet)
+    }
+}
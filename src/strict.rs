@@ -0,0 +1,129 @@
+// Strict loading, with `--strict` or `strict = true` in the config: the data file
+// must be exactly as this program writes it, or loading fails with a list of what
+// is wrong. Without it, files edited by hand or by other tools are tidied up on
+// load instead: periods are sorted, damaged files are salvaged and unknown fields
+// dropped, and overlaps only show in `doctor`. For teams that sync the file,
+// failing early is better than reports computed from a file someone broke.
+//
+// Strict loading refuses
+// - fields this version does not know, in the JSON data file or month files
+// - periods not sorted by start, in the data file itself
+// - periods that overlap, after the journal is applied
+// - periods that end, and a running session that starts, in the future
+// - a data file that can only be salvaged in part
+
+use crate::storage::LoadOptions;
+use crate::TimeSheet;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::io;
+use std::path::Path;
+use tracing::debug;
+
+// At most this many problems are listed in the error.
+const MAX_LISTED: usize = 10;
+
+// Reads JSON, failing on fields `T` does not have when strict. Those are the
+// fields that are lost when `T` is written back, apart from empty ones, which are
+// not written.
+pub fn from_json<T: DeserializeOwned + Serialize>(path: &Path, contents: &str, options: LoadOptions) -> serde_json::Result<T> {
+    let value: T = serde_json::from_str(contents)?;
+    if !options.is_strict() {
+        return Ok(value);
+    }
+    let mut unknown = Vec::new();
+    unknown_fields(&serde_json::from_str(contents)?, &serde_json::to_value(&value)?, "", &mut unknown);
+    if !unknown.is_empty() {
+        debug!(path = %path.display(), ?unknown, "unknown fields");
+        return Err(serde::de::Error::custom(format!("unknown field(s) {}", list(&unknown))));
+    }
+    Ok(value)
+}
+
+// Collects the paths of the fields in `read` that are missing from `written`.
+fn unknown_fields(read: &Value, written: &Value, path: &str, unknown: &mut Vec<String>) {
+    match (read, written) {
+        (Value::Object(read), Value::Object(written)) => {
+            for (key, value) in read {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match written.get(key) {
+                    Some(written) => unknown_fields(value, written, &path, unknown),
+                    None if !is_empty(value) => unknown.push(path),
+                    None => {}
+                }
+            }
+        }
+        (Value::Array(read), Value::Array(written)) => {
+            for (index, (value, written)) in read.iter().zip(written).enumerate() {
+                unknown_fields(value, written, &format!("{}[{}]", path, index), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => true,
+        Value::String(text) => text.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false,
+    }
+}
+
+// Fails if the periods of the data file, as read before the journal is applied,
+// are not sorted.
+pub fn check_sorted(path: &Path, time_sheet: &TimeSheet, options: LoadOptions) -> io::Result<()> {
+    if !options.is_strict() {
+        return Ok(());
+    }
+    match time_sheet.periods.windows(2).find(|pair| pair[0].start > pair[1].start) {
+        Some(pair) => Err(refuse(
+            path,
+            vec![format!("period {} starts before period {}, which comes first; 'compact' sorts them", pair[1].id, pair[0].id)],
+        )),
+        None => Ok(()),
+    }
+}
+
+// Fails on overlapping periods and on times after the time the options were
+// made for.
+pub fn check(path: &Path, time_sheet: &TimeSheet, options: LoadOptions) -> io::Result<()> {
+    let Some(now) = options.strict_at else {
+        return Ok(());
+    };
+    let mut problems = Vec::new();
+    let mut sorted: Vec<_> = time_sheet.periods.iter().collect();
+    sorted.sort_by_key(|p| p.start);
+    for pair in sorted.windows(2).filter(|pair| pair[0].end > pair[1].start) {
+        problems.push(format!("periods {} and {} overlap", pair[0].id, pair[1].id));
+    }
+    for period in time_sheet.periods.iter().filter(|p| p.end > now) {
+        problems.push(format!("period {} ends in the future, at {}", period.id, period.end));
+    }
+    if let Some(start) = time_sheet.active_period_start.filter(|start| *start > now) {
+        problems.push(format!("the running session starts in the future, at {}", start));
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(refuse(path, problems))
+}
+
+pub fn refuse(path: &Path, problems: Vec<String>) -> io::Error {
+    debug!(path = %path.display(), problems = problems.len(), "refusing to load");
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Strict mode: {} was not loaded: {}.", path.display(), list(&problems)),
+    )
+}
+
+fn list(items: &[String]) -> String {
+    let mut listed = items.iter().take(MAX_LISTED).cloned().collect::<Vec<_>>().join("; ");
+    if items.len() > MAX_LISTED {
+        listed.push_str(&format!("; and {} more", items.len() - MAX_LISTED));
+    }
+    listed
+}
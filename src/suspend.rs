@@ -0,0 +1,116 @@
+// Time the computer slept during a session, so that a laptop suspended overnight
+// does not record the night as work.
+//
+// Nothing runs while a session does, so the sleep is measured from two readings
+// of the kernel's clocks: CLOCK_BOOTTIME counts the time in suspend and
+// hibernation, CLOCK_MONOTONIC does not, and their difference is the time slept
+// since boot. `start` keeps that difference with the session, and `stop` compares
+// it with the current one. Readings from another boot cannot be compared, and
+// other systems have no such clocks, so there nothing changes.
+//
+// The readings tell how long the computer slept, not when. Leaving the sleep out
+// therefore ends the session earlier by that much: the usual case is a session
+// left running when the lid was closed, where that is the time it really ended.
+
+use crate::clock::Clock;
+use crate::config::{Config, SuspendPolicy};
+use crate::prompt;
+use crate::timefmt::TimeFormatter;
+use crate::TimeSheet;
+use chrono::{DateTime, Duration, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal};
+use tracing::{debug, info, instrument, warn};
+
+// Sleep shorter than this is left alone; the two clocks drift apart by a little
+// even without one.
+const MIN_SLEEP_SECONDS: i64 = 60;
+
+// The time slept since boot, as read when a session started.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SuspendMark {
+    /// The kernel's ID of the boot the reading belongs to.
+    pub boot_id: String,
+    /// Milliseconds slept since that boot.
+    pub slept_ms: u64,
+}
+
+// The current reading, if the system has the clocks.
+#[cfg(target_os = "linux")]
+pub fn mark() -> Option<SuspendMark> {
+    fn read(clock: libc::clockid_t) -> Option<u64> {
+        let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        // SAFETY: `time` is a valid timespec for the call to write to.
+        if unsafe { libc::clock_gettime(clock, &mut time) } != 0 {
+            return None;
+        }
+        Some(time.tv_sec as u64 * 1000 + time.tv_nsec as u64 / 1_000_000)
+    }
+    let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?.trim().to_string();
+    let boot = read(libc::CLOCK_BOOTTIME)?;
+    let monotonic = read(libc::CLOCK_MONOTONIC)?;
+    Some(SuspendMark { boot_id, slept_ms: boot.saturating_sub(monotonic) })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn mark() -> Option<SuspendMark> {
+    None
+}
+
+// How long the computer slept between the two readings, or none if they cannot
+// be compared.
+pub fn slept_between(before: &SuspendMark, after: &SuspendMark) -> Option<Duration> {
+    if before.boot_id != after.boot_id {
+        return None;
+    }
+    Some(Duration::milliseconds(after.slept_ms.saturating_sub(before.slept_ms) as i64))
+}
+
+// The time `stop` should end the running session at, if the computer slept during
+// it and the sleep is left out as `suspend_policy` says: asked about on a
+// terminal with `prompt`, which leaves it out elsewhere.
+#[instrument(level = "debug", skip(time_sheet, config, formatter, clock))]
+pub fn stop_time(
+    time_sheet: &TimeSheet,
+    config: &Config,
+    formatter: &TimeFormatter,
+    clock: &dyn Clock,
+) -> io::Result<Option<DateTime<Utc>>> {
+    let (Some(start), Some(before)) = (time_sheet.active_period_start, &time_sheet.active_suspend) else {
+        return Ok(None);
+    };
+    if config.suspend_policy == SuspendPolicy::Keep {
+        return Ok(None);
+    }
+    let Some(after) = clock.suspend_mark() else {
+        return Ok(None);
+    };
+    let Some(slept) = slept_between(before, &after) else {
+        warn!("the computer was restarted during the session; cannot tell how long it slept");
+        return Ok(None);
+    };
+    debug!(slept = slept.num_seconds(), "slept during the session");
+    let now = clock.now();
+    if slept < Duration::seconds(MIN_SLEEP_SECONDS) || now - slept <= start {
+        return Ok(None);
+    }
+
+    let end = now - slept;
+    if config.suspend_policy == SuspendPolicy::Prompt && io::stdin().is_terminal() {
+        let question = format!(
+            "The computer slept for {} during this session. Leave that out, ending the session at {}?",
+            formatter.duration(slept),
+            formatter.date_time(end.with_timezone(&Local))
+        );
+        if !prompt::confirm(&question)? {
+            return Ok(None);
+        }
+    }
+    info!(slept = slept.num_seconds(), end = %end, "left out the time slept");
+    println!(
+        "Left out {} the computer slept: the session ends at {}.",
+        formatter.duration(slept),
+        formatter.date_time(end.with_timezone(&Local))
+    );
+    Ok(Some(end))
+}
@@ -0,0 +1,388 @@
+// Protection against lost updates when the data file is synced by Dropbox or a
+// similar client, which may replace it while a command is running.
+//
+// A `Snapshot` records the files and the timesheet as loaded. Before saving,
+// `reconcile` checks whether the files changed since. If they did, the changes
+// made on disk and those made by the command are merged against the snapshot,
+// period by period. If both sides changed the same thing, the conflict is settled
+// as the `ConflictStrategy` says: asked about on a terminal, or resolved for one
+// side. Unresolved conflicts mean nothing is saved. Every resolution is printed,
+// since billing depends on these periods.
+
+use crate::diff::{self, Version};
+use crate::index::{self, Stamp};
+use crate::logic::naive_to_utc;
+use crate::prompt::ask;
+use crate::storage::LoadOptions;
+use crate::timefmt::TimeFormatter;
+use crate::zone::Zone;
+use crate::{storage, Period, TimeSheet};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
+
+// Top-level fields of the timesheet that are merged as a whole, with a name for
+// conflict messages. The running session is one unit across its fields.
+const FIELDS: [(&[&str], &str); 6] = [
+    (&["active_period_start", "active_metadata", "active_laps", "active_remind_after", "active_suspend"], "the running session"),
+    (&["closed_months"], "closed months"),
+    (&["absences"], "absences"),
+    (&["breaks"], "marked breaks"),
+    (&["reviewed_days"], "reviewed days"),
+    (&["plan"], "the weekly plans"),
+];
+
+// The data files and timesheet as they were when the command started.
+pub struct Snapshot {
+    stamp: Stamp,
+    base: TimeSheet,
+}
+
+impl Snapshot {
+    pub fn take(data_path: &Path, time_sheet: &TimeSheet) -> io::Result<Snapshot> {
+        Ok(Snapshot { stamp: index::stamp(data_path)?, base: time_sheet.clone() })
+    }
+}
+
+// How conflicts between the command's changes and those on disk are settled.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    /// Ask about each conflict if standard input is a terminal; otherwise fail.
+    #[default]
+    Ask,
+    /// Save nothing.
+    Fail,
+    /// Keep the changes of this command.
+    Mine,
+    /// Keep the changes on disk.
+    Theirs,
+}
+
+// Something both sides changed differently. Periods are their JSON values.
+#[derive(Debug)]
+enum Conflict {
+    Field(&'static str),
+    Period { ours: Option<Value>, theirs: Option<Value> },
+    Overlap { ours: Value, theirs: Value },
+}
+
+impl Conflict {
+    // Our period, if there is one that could be edited.
+    fn editable(&self) -> Option<&Value> {
+        match self {
+            Conflict::Field(_) => None,
+            Conflict::Period { ours, .. } => ours.as_ref(),
+            Conflict::Overlap { ours, .. } => Some(ours),
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Field(name) => write!(f, "{}", name),
+            Conflict::Period { ours, theirs } => {
+                write!(f, "a period, mine {}, theirs {}", describe(ours.as_ref()), describe(theirs.as_ref()))
+            }
+            Conflict::Overlap { ours, theirs } => {
+                write!(f, "my period {} overlapping theirs {}", describe(Some(ours)), describe(Some(theirs)))
+            }
+        }
+    }
+}
+
+// How a conflict is settled. An edited period replaces ours.
+#[derive(Debug)]
+enum Resolution {
+    Mine,
+    Theirs,
+    Edit(Value),
+}
+
+// Which side changed a merged period, for finding overlaps between the two.
+// Periods given up to settle an overlap are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Both,
+    Ours,
+    Theirs,
+    Dropped,
+}
+
+// Makes `time_sheet` safe to save over the data file. If the files are as in the
+// snapshot, nothing is done. Otherwise the timesheet on disk is read and merged
+// with the changes in `time_sheet`; `time_sheet` then holds the merged result and
+// must be saved in full. Times edited to settle a conflict are read in `zone`.
+// Returns whether a merge happened, or an error listing the conflicts that were
+// left unresolved.
+#[instrument(level = "debug", skip(snapshot, time_sheet))]
+pub fn reconcile(
+    data_path: &Path,
+    snapshot: &Snapshot,
+    time_sheet: &mut TimeSheet,
+    strategy: ConflictStrategy,
+    options: LoadOptions,
+    zone: Zone,
+) -> io::Result<bool> {
+    if index::stamp(data_path)? == snapshot.stamp {
+        return Ok(false);
+    }
+    warn!(path = %data_path.display(), "data file changed since it was read");
+    let theirs = storage::load_or_create_timesheet(data_path, options)?;
+    let interactive = strategy == ConflictStrategy::Ask && io::stdin().is_terminal();
+    if interactive {
+        eprintln!("{} was changed by another program, such as a sync client, while this command ran.", data_path.display());
+    }
+    let mut resolve = |conflict: &Conflict| -> io::Result<Option<Resolution>> {
+        let resolution = match strategy {
+            ConflictStrategy::Mine => Some(Resolution::Mine),
+            ConflictStrategy::Theirs => Some(Resolution::Theirs),
+            ConflictStrategy::Ask if interactive => ask_resolution(conflict, zone)?,
+            ConflictStrategy::Ask | ConflictStrategy::Fail => None,
+        };
+        match &resolution {
+            Some(Resolution::Mine) => println!("Conflict on {}: kept mine.", conflict),
+            Some(Resolution::Theirs) => println!("Conflict on {}: kept theirs.", conflict),
+            Some(Resolution::Edit(period)) => println!("Conflict on {}: changed mine to {}.", conflict, describe(Some(period))),
+            None => {}
+        }
+        Ok(resolution)
+    };
+    let merged = merge(&snapshot.base, time_sheet, &theirs, &mut resolve)?.map_err(|conflicts| {
+        io::Error::other(format!(
+            "{} was changed by another program, such as a sync client, while this command ran. \
+             Both changed {}, so nothing was saved. Run the command again to apply it to the new data, \
+             or choose a side with --conflicts mine or --conflicts theirs.",
+            data_path.display(),
+            conflicts.join(", ")
+        ))
+    })?;
+    *time_sheet = merged;
+    time_sheet.check_periods();
+    info!("merged concurrent changes to the data file");
+    println!("{} was changed by another program meanwhile; merged both changes.", data_path.display());
+    Ok(true)
+}
+
+// Three-way merge of the timesheets. Conflicts are passed to `resolve`; fails with
+// a description of each one it leaves unresolved.
+fn merge(
+    base: &TimeSheet,
+    ours: &TimeSheet,
+    theirs: &TimeSheet,
+    resolve: &mut dyn FnMut(&Conflict) -> io::Result<Option<Resolution>>,
+) -> io::Result<Result<TimeSheet, Vec<String>>> {
+    let (base, ours, theirs) = (to_object(base), to_object(ours), to_object(theirs));
+    let mut conflicts = Vec::new();
+    let mut merged = Map::new();
+
+    for (keys, name) in FIELDS {
+        let pick = |side: &Map<String, Value>| keys.iter().map(|key| side.get(*key).cloned()).collect::<Vec<_>>();
+        let (b, o, t) = (pick(&base), pick(&ours), pick(&theirs));
+        let chosen = match merge_value(&b, &o, &t) {
+            Some(side) => side,
+            None => match resolve(&Conflict::Field(name))? {
+                Some(Resolution::Theirs) => &t,
+                Some(_) => &o,
+                None => {
+                    conflicts.push(name.to_string());
+                    &o
+                }
+            },
+        };
+        for (key, value) in keys.iter().zip(chosen) {
+            if let Some(value) = value {
+                merged.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+
+    let (b, o, t) = (periods(&base), periods(&ours), periods(&theirs));
+    let ids: BTreeSet<Uuid> = o.iter().chain(t.iter()).map(|(id, _)| *id).collect();
+    let mut kept: Vec<(Value, Side)> = Vec::new();
+    for id in ids {
+        let find = |side: &[(Uuid, Value)]| side.iter().find(|(other, _)| *other == id).map(|(_, p)| p.clone());
+        let (pb, po, pt) = (find(&b), find(&o), find(&t));
+        let (period, side) = match (po == pb, pt == pb) {
+            (true, true) => (pb, Side::Both),
+            (true, false) => (pt, Side::Theirs),
+            (false, true) => (po, Side::Ours),
+            (false, false) if po == pt => (po, Side::Both),
+            (false, false) => match resolve(&Conflict::Period { ours: po.clone(), theirs: pt.clone() })? {
+                Some(Resolution::Mine) => (po, Side::Ours),
+                Some(Resolution::Theirs) => (pt, Side::Theirs),
+                Some(Resolution::Edit(period)) => (Some(period), Side::Ours),
+                None => {
+                    conflicts.push(format!("period {}", id));
+                    continue;
+                }
+            },
+        };
+        if let Some(period) = period {
+            kept.push((period, side));
+        }
+    }
+    // An edited period may overlap again, so the overlaps are looked for anew
+    // after each resolution.
+    let mut unresolved = Vec::new();
+    while let Some((o, t)) = next_overlap(&kept, &unresolved) {
+        let conflict = Conflict::Overlap { ours: kept[o].0.clone(), theirs: kept[t].0.clone() };
+        match resolve(&conflict)? {
+            Some(Resolution::Mine) => kept[t].1 = Side::Dropped,
+            Some(Resolution::Theirs) => kept[o].1 = Side::Dropped,
+            Some(Resolution::Edit(period)) => kept[o].0 = period,
+            None => {
+                conflicts.push(describe_overlap(&kept[o].0, &kept[t].0));
+                unresolved.push((o, t));
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        debug!(?conflicts, "merge failed");
+        return Ok(Err(conflicts));
+    }
+
+    let mut periods: Vec<Value> =
+        kept.into_iter().filter(|(_, side)| *side != Side::Dropped).map(|(period, _)| period).collect();
+    periods.sort_by_key(|period| times(period).map(|(start, _)| start));
+    merged.insert("periods".to_string(), Value::Array(periods));
+    Ok(serde_json::from_value(Value::Object(merged)).map_err(|e| vec![format!("the merged data ({})", e)]))
+}
+
+// The side whose value to keep, or none if both changed it differently.
+fn merge_value<'a, T: PartialEq>(base: &'a T, ours: &'a T, theirs: &'a T) -> Option<&'a T> {
+    if ours == base || ours == theirs {
+        Some(theirs)
+    } else if theirs == base {
+        Some(ours)
+    } else {
+        None
+    }
+}
+
+// The first period added or changed on our side that overlaps one of the other
+// side, as indices into `kept`, skipping the pairs left unresolved.
+fn next_overlap(kept: &[(Value, Side)], unresolved: &[(usize, usize)]) -> Option<(usize, usize)> {
+    let of = |wanted: Side| kept.iter().enumerate().filter(move |(_, (_, side))| *side == wanted);
+    of(Side::Ours).find_map(|(o, (ours, _))| {
+        of(Side::Theirs)
+            .filter(|(t, _)| !unresolved.contains(&(o, *t)))
+            .find(|(_, (theirs, _))| {
+                matches!((times(ours), times(theirs)), (Some((a_start, a_end)), Some((b_start, b_end)))
+                    if a_start < b_end && b_start < a_end)
+            })
+            .map(|(t, _)| (o, t))
+    })
+}
+
+fn describe_overlap(ours: &Value, theirs: &Value) -> String {
+    let (Some((a_start, a_end)), Some((b_start, b_end))) = (times(ours), times(theirs)) else {
+        return "overlapping periods".to_string();
+    };
+    format!(
+        "the time from {} to {}",
+        a_start.max(b_start).with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+        a_end.min(b_end).with_timezone(&Local).format("%H:%M")
+    )
+}
+
+// A period for conflict messages: its local times and project, or "deleted".
+fn describe(period: Option<&Value>) -> String {
+    let Some(period) = period else { return "deleted".to_string() };
+    let Some((start, end)) = times(period) else { return "unreadable".to_string() };
+    let mut text = format!(
+        "{} to {}",
+        start.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+        end.with_timezone(&Local).format("%H:%M")
+    );
+    if let Some(project) = period["project"].as_str() {
+        text.push_str(&format!(" ({})", project));
+    }
+    text
+}
+
+// The two versions of the periods in conflict as a diff, theirs as removed and
+// ours as added.
+fn conflict_diff(conflict: &Conflict) -> Option<String> {
+    let (ours, theirs) = match conflict {
+        Conflict::Field(_) => return None,
+        Conflict::Period { ours, theirs } => (ours.as_ref(), theirs.as_ref()),
+        Conflict::Overlap { ours, theirs } => (Some(ours), Some(theirs)),
+    };
+    let parse = |value: Option<&Value>| value.and_then(|value| serde_json::from_value::<Period>(value.clone()).ok());
+    let (ours, theirs) = (parse(ours), parse(theirs));
+    let id = ours.as_ref().or(theirs.as_ref())?.id;
+    let title = match conflict {
+        Conflict::Overlap { .. } => "overlapping periods, - theirs, + mine".to_string(),
+        _ => format!("{}, - theirs, + mine", id),
+    };
+    let color = diff::use_color(io::stderr().is_terminal());
+    Some(diff::render(&title, theirs.as_ref().map(Version::from), ours.as_ref().map(Version::from), &TimeFormatter::default(), color))
+}
+
+// Asks on the terminal how to settle a conflict. `None` gives up on it.
+fn ask_resolution(conflict: &Conflict, zone: Zone) -> io::Result<Option<Resolution>> {
+    eprintln!("Conflict on {}.", conflict);
+    if let Some(diff) = conflict_diff(conflict) {
+        eprint!("{}", diff);
+    }
+    let choices = if conflict.editable().is_some() { "[m]ine, [t]heirs, [e]dit or [a]bort" } else { "[m]ine, [t]heirs or [a]bort" };
+    loop {
+        match ask(&format!("Keep {}?", choices), "a")?.to_lowercase().as_str() {
+            "m" | "mine" => return Ok(Some(Resolution::Mine)),
+            "t" | "theirs" => return Ok(Some(Resolution::Theirs)),
+            "a" | "abort" => return Ok(None),
+            "e" | "edit" if let Some(period) = conflict.editable() => match edit_period(period, zone) {
+                Ok(period) => return Ok(Some(Resolution::Edit(period))),
+                Err(e) => eprintln!("{}", e),
+            },
+            _ => eprintln!("Please answer with one of {}.", choices),
+        }
+    }
+}
+
+// Asks for new start and end times of our period, as local times in `zone`.
+fn edit_period(period: &Value, zone: Zone) -> io::Result<Value> {
+    const FORMAT: &str = "%Y-%m-%d %H:%M";
+    let (start, end) = times(period).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The period has no valid times."))?;
+    let read = |question: &str, default: DateTime<Utc>| -> io::Result<DateTime<Utc>> {
+        let answer = ask(question, &zone.local_time(default).format(FORMAT).to_string())?;
+        let naive = NaiveDateTime::parse_from_str(&answer, FORMAT).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid time '{}'. Expected YYYY-MM-DD HH:MM.", answer))
+        })?;
+        Ok(naive_to_utc(naive, zone))
+    };
+    let (start, end) = (read("Start", start)?, read("End", end)?);
+    if end <= start {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The end must be after the start."));
+    }
+    let mut period = period.clone();
+    period["start"] = serde_json::to_value(start)?;
+    period["end"] = serde_json::to_value(end)?;
+    Ok(period)
+}
+
+fn times(period: &Value) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    Some((period["start"].as_str()?.parse().ok()?, period["end"].as_str()?.parse().ok()?))
+}
+
+fn to_object(time_sheet: &TimeSheet) -> Map<String, Value> {
+    match serde_json::to_value(time_sheet) {
+        Ok(Value::Object(object)) => object,
+        _ => Map::new(),
+    }
+}
+
+fn periods(object: &Map<String, Value>) -> Vec<(Uuid, Value)> {
+    let Some(Value::Array(periods)) = object.get("periods") else { return Vec::new() };
+    periods
+        .iter()
+        .filter_map(|period| Some((period["id"].as_str()?.parse().ok()?, period.clone())))
+        .collect()
+}
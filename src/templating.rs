@@ -0,0 +1,252 @@
+// Rendering of reports through user-defined templates.
+//
+// Templates use the TinyTemplate syntax (`{value}`, `{{ for x in list }}`,
+// `{{ if value }}`). Output is not HTML-escaped. The context passed to a template
+// is a `ReportContext`; `report <period> --dump-context` prints it as JSON, which
+// is the easiest way to see what is available:
+//
+// ```text
+// {
+//   "title": "week",                      name of the reporting period
+//   "rolling": false,                     true for `report rolling`, whose window
+//                                          ends now
+//   "start": "2024-05-06 00:00",          local start of the period
+//   "end": "2024-05-13 00:00",            local end (exclusive)
+//   "time_zone": "Europe/Berlin",         the zone of --tz, empty for the local zone
+//   "generated_at": "2024-05-08 17:02",
+//   "total": "12:30:00",                  formatted with the chosen hours format
+//   "total_seconds": 45000,
+//   "projects": [ { "name", "total", "total_seconds" } ],   sorted by name
+//   "tags": [ { "name", "total", "total_seconds" } ],       sorted by name
+//   "fields": [ { "key", "values": [ { "name", "total", "total_seconds" } ] } ],
+//                                          time per custom field value, "(none)"
+//                                          for the time without the field
+//   "groups": [ { "key", "values": [ ... ] } ],             one per --group-by, in order;
+//                                          the built-in template shows them instead of fields
+//   "categories": [ { "name", "total", "total_seconds" } ], regular/evening/night/
+//                                          weekend/holiday, empty without [schedule]
+//   "days": [ { "name", "total", "total_seconds" } ],       one per day with time, named by date;
+//                                          sessions across midnight count on both days
+//   "absences": [ { "date", "kind", "note" } ],             sick or vacation days
+//   "outside_window": { "name", "total", "total_seconds" }, time outside
+//                                          [tracking_window], null if none
+//   "periods": [ { "id", "date", "start", "end", "duration", "duration_seconds",
+//                  "project", "tags", "note", "category", "fields", "active" } ]
+//                                          fields is an object, e.g. {period.fields.ticket}
+// }
+// ```
+//
+// Dates and times are written as set with `date_format` and `clock_format`.
+// Durations of periods that cross the report boundaries only count the part
+// inside the reporting period. Missing project, note or category values are
+// empty strings.
+
+use crate::report::{Grouping, Report};
+use crate::timefmt::TimeFormatter;
+use chrono::Duration;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tinytemplate::TinyTemplate;
+use tracing::instrument;
+
+// Used when no --template is given.
+const DEFAULT_TEMPLATE: &str = "Report for {{ if rolling }}the{{ else }}this{{ endif }} {title}: {start} - {end}{{ if time_zone }} ({time_zone}){{ endif }}
+Total: {total}
+{{- if projects }}
+
+By project:
+{{ for project in projects }}  {project.name}: {project.total}
+{{ endfor }}
+{{- endif }}
+{{- if tags }}
+By tag:
+{{ for tag in tags }}  {tag.name}: {tag.total}
+{{ endfor }}
+{{- endif }}
+{{- if groups }}
+{{- for group in groups }}
+By {group.key}:
+{{ for value in group.values }}  {value.name}: {value.total}
+{{ endfor }}
+{{- endfor }}
+{{- else }}
+{{- for field in fields }}
+By {field.key}:
+{{ for value in field.values }}  {value.name}: {value.total}
+{{ endfor }}
+{{- endfor }}
+{{- endif }}
+{{- if absences }}
+Absences:
+{{ for absence in absences }}  {absence.date}: {absence.kind}
+{{ endfor }}
+{{- endif }}
+{{- if categories }}
+By category:
+{{ for category in categories }}  {category.name}: {category.total}
+{{ endfor }}
+{{- endif }}
+{{- if outside_window }}
+Outside tracking hours: {outside_window.total}
+{{ endif }}
+";
+
+#[derive(Serialize, Debug)]
+pub struct ReportContext {
+    pub title: String,
+    pub rolling: bool,
+    pub start: String,
+    pub end: String,
+    pub time_zone: String,
+    pub generated_at: String,
+    pub total: String,
+    pub total_seconds: i64,
+    pub projects: Vec<TotalRow>,
+    pub tags: Vec<TotalRow>,
+    pub fields: Vec<Group>,
+    pub groups: Vec<Group>,
+    pub categories: Vec<TotalRow>,
+    pub days: Vec<TotalRow>,
+    pub absences: Vec<AbsenceRow>,
+    pub outside_window: Option<TotalRow>,
+    pub periods: Vec<PeriodRow>,
+}
+
+// A named total, used for the project, tag, category and day breakdowns.
+#[derive(Serialize, Debug)]
+pub struct TotalRow {
+    pub name: String,
+    pub total: String,
+    pub total_seconds: i64,
+}
+
+// The totals per value of one grouping, such as a custom field.
+#[derive(Serialize, Debug)]
+pub struct Group {
+    pub key: String,
+    pub values: Vec<TotalRow>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AbsenceRow {
+    pub date: String,
+    pub kind: String,
+    pub note: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PeriodRow {
+    pub id: String,
+    pub date: String,
+    pub start: String,
+    pub end: String,
+    pub duration: String,
+    pub duration_seconds: i64,
+    pub project: String,
+    pub tags: Vec<String>,
+    pub note: String,
+    pub category: String,
+    pub fields: BTreeMap<String, String>,
+    pub active: bool,
+}
+
+// Builds the template context from the report, with a group for each of `groupings`.
+#[instrument(level = "debug", skip_all, fields(title = %report.title))]
+pub fn build_context(report: &Report, groupings: &[Grouping], formatter: &TimeFormatter) -> ReportContext {
+    let row = |name: String, total: Duration| TotalRow { name, total: formatter.duration(total), total_seconds: total.num_seconds() };
+    let group = |grouping: &Grouping| {
+        let unnamed = match grouping {
+            Grouping::Project => "(no project)",
+            Grouping::Tag => "(no tag)",
+            Grouping::Category => "(no category)",
+            Grouping::Field(_) | Grouping::Weekday => "(none)",
+        };
+        Group {
+            key: grouping.name().to_string(),
+            values: report
+                .group(grouping)
+                .into_iter()
+                .map(|g| row(g.name.unwrap_or_else(|| unnamed.to_string()), g.total))
+                .collect(),
+        }
+    };
+    let periods = report
+        .periods
+        .iter()
+        .map(|row| {
+            let period = &row.period;
+            let start = report.zone.local_time(period.start);
+            PeriodRow {
+                id: if row.active { String::new() } else { period.id.to_string() },
+                date: formatter.date(start.date_naive()),
+                start: formatter.time(start),
+                end: if row.active { String::new() } else { formatter.time(report.zone.local_time(period.end)) },
+                duration: formatter.duration(row.duration),
+                duration_seconds: row.duration.num_seconds(),
+                project: period.metadata.project.clone().unwrap_or_default(),
+                tags: period.metadata.tags.clone(),
+                note: period.metadata.note.clone().unwrap_or_default(),
+                category: period.metadata.category.clone().unwrap_or_default(),
+                fields: period.metadata.fields.clone(),
+                active: row.active,
+            }
+        })
+        .collect();
+
+    ReportContext {
+        title: report.title.clone(),
+        rolling: report.rolling,
+        start: formatter.date_time(report.zone.local_time(report.range.start)),
+        end: formatter.date_time(report.zone.local_time(report.range.end)),
+        time_zone: report.zone.named().map(|zone| zone.to_string()).unwrap_or_default(),
+        generated_at: formatter.date_time(report.zone.local_time(report.generated_at)),
+        total: formatter.duration(report.total),
+        total_seconds: report.total.num_seconds(),
+        projects: report
+            .projects
+            .iter()
+            .map(|p| row(p.project.clone().unwrap_or_else(|| "(no project)".to_string()), p.total))
+            .collect(),
+        tags: report.tags.iter().map(|t| row(t.tag.clone(), t.total)).collect(),
+        fields: report.field_keys().into_iter().map(|key| group(&Grouping::Field(key.to_string()))).collect(),
+        groups: groupings.iter().map(group).collect(),
+        categories: report.categories.iter().map(|c| row(c.category.name().to_string(), c.total)).collect(),
+        days: report
+            .days
+            .iter()
+            .filter(|d| d.total > Duration::zero())
+            .map(|d| row(formatter.date(d.date), d.total))
+            .collect(),
+        absences: report
+            .absences
+            .iter()
+            .map(|a| AbsenceRow {
+                date: formatter.date(a.date),
+                kind: a.kind.name().to_string(),
+                note: a.note.clone().unwrap_or_default(),
+            })
+            .collect(),
+        outside_window: report.outside_window.as_ref().map(|o| row("outside tracking hours".to_string(), o.total)),
+        periods,
+    }
+}
+
+// Renders the context with the template file, or with the built-in template.
+pub fn render(context: &ReportContext, template_path: Option<&Path>) -> io::Result<String> {
+    let source = match template_path {
+        Some(path) => fs::read_to_string(path)?,
+        None => DEFAULT_TEMPLATE.to_string(),
+    };
+
+    let mut template = TinyTemplate::new();
+    template.set_default_formatter(&tinytemplate::format_unescaped);
+    template.add_template("report", &source).map_err(template_error)?;
+    template.render("report", context).map_err(template_error)
+}
+
+fn template_error(error: tinytemplate::error::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Template error: {}", error))
+}
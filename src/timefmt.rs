@@ -0,0 +1,292 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+// How durations are rendered in reports and status messages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HoursFormat {
+    /// Decimal hours, e.g. `7.50 h`. This is what payroll usually expects.
+    Decimal,
+    /// Clock style, e.g. `07:30:00`.
+    #[default]
+    Hms,
+    /// Spelled out, e.g. `7 hours 30 minutes`.
+    Verbose,
+}
+
+impl FromStr for HoursFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(HoursFormat::Decimal),
+            "hms" => Ok(HoursFormat::Hms),
+            "verbose" => Ok(HoursFormat::Verbose),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid hours format '{}'. Expected decimal, hms or verbose.", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HoursFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HoursFormat::Decimal => "decimal",
+            HoursFormat::Hms => "hms",
+            HoursFormat::Verbose => "verbose",
+        };
+        f.write_str(name)
+    }
+}
+
+// How finely durations are shown. Only affects display; stored periods and any
+// billing rounding keep their exact length.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Precision {
+    #[default]
+    Seconds,
+    /// Rounded to the nearest minute.
+    Minutes,
+    /// Rounded to the nearest 15 minutes.
+    QuarterHours,
+}
+
+impl Precision {
+    // The unit durations are rounded to, in seconds.
+    fn unit(self) -> i64 {
+        match self {
+            Precision::Seconds => 1,
+            Precision::Minutes => 60,
+            Precision::QuarterHours => 15 * 60,
+        }
+    }
+
+    // Rounds to the nearest unit; halves round up.
+    pub fn round(self, seconds: i64) -> i64 {
+        let unit = self.unit();
+        (seconds + unit / 2) / unit * unit
+    }
+}
+
+impl FromStr for Precision {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seconds" => Ok(Precision::Seconds),
+            "minutes" => Ok(Precision::Minutes),
+            "quarter-hours" => Ok(Precision::QuarterHours),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid precision '{}'. Expected seconds, minutes or quarter-hours.", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Precision::Seconds => "seconds",
+            Precision::Minutes => "minutes",
+            Precision::QuarterHours => "quarter-hours",
+        };
+        f.write_str(name)
+    }
+}
+
+// How dates are written, as a strftime pattern such as "%d.%m.%Y" or "%m/%d/%Y".
+// Checked when the config is read, since chrono cannot render a broken pattern.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct DateFormat(String);
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat("%Y-%m-%d".to_string())
+    }
+}
+
+impl TryFrom<String> for DateFormat {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        if pattern.is_empty() || StrftimeItems::new(&pattern).any(|item| matches!(item, Item::Error)) {
+            return Err(format!("invalid date format '{}', expected a strftime pattern such as \"%d.%m.%Y\"", pattern));
+        }
+        Ok(DateFormat(pattern))
+    }
+}
+
+impl From<DateFormat> for String {
+    fn from(format: DateFormat) -> String {
+        format.0
+    }
+}
+
+// How times of day are written.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockFormat {
+    /// `14:05`.
+    #[default]
+    #[serde(rename = "24h")]
+    TwentyFour,
+    /// `2:05 PM`.
+    #[serde(rename = "12h")]
+    Twelve,
+}
+
+/// Central place for turning durations, dates and times into text. Every renderer
+/// goes through this so that the chosen format is applied consistently.
+#[derive(Debug, Clone, Default)]
+pub struct TimeFormatter {
+    pub hours_format: HoursFormat,
+    pub precision: Precision,
+    pub date_format: DateFormat,
+    pub clock: ClockFormat,
+}
+
+impl TimeFormatter {
+    pub fn new(hours_format: HoursFormat) -> Self {
+        TimeFormatter { hours_format, ..TimeFormatter::default() }
+    }
+
+    pub fn with_precision(self, precision: Precision) -> Self {
+        TimeFormatter { precision, ..self }
+    }
+
+    pub fn with_dates(self, date_format: DateFormat, clock: ClockFormat) -> Self {
+        TimeFormatter { date_format, clock, ..self }
+    }
+
+    /// A date in the configured date format.
+    pub fn date(&self, date: NaiveDate) -> String {
+        date.format(&self.date_format.0).to_string()
+    }
+
+    /// A time of day with the configured clock. Local times are shown as given, so
+    /// convert times stored in UTC first.
+    pub fn time(&self, time: impl Timelike) -> String {
+        match self.clock {
+            ClockFormat::TwentyFour => format!("{:02}:{:02}", time.hour(), time.minute()),
+            ClockFormat::Twelve => {
+                let (pm, hour) = time.hour12();
+                format!("{}:{:02} {}", hour, time.minute(), if pm { "PM" } else { "AM" })
+            }
+        }
+    }
+
+    /// Date and time of a local point in time.
+    pub fn date_time<Tz: TimeZone>(&self, time: DateTime<Tz>) -> String {
+        format!("{} {}", self.date(time.date_naive()), self.time(time))
+    }
+
+    /// Formats a duration according to the configured hours format, rounded to
+    /// the configured precision. Negative durations are shown as zero.
+    pub fn duration(&self, duration: Duration) -> String {
+        let seconds = if duration < Duration::zero() { 0 } else { duration.num_seconds() };
+        let seconds = self.precision.round(seconds);
+        match self.hours_format {
+            HoursFormat::Decimal => format_decimal(seconds),
+            HoursFormat::Hms => format_hms(seconds, self.precision == Precision::Seconds),
+            HoursFormat::Verbose => format_verbose(seconds),
+        }
+    }
+}
+
+// Parses a duration as typed on the command line: units as in "2h30m", "45m",
+// "1.5h" or "90s", clock style "2:30", or a plain number of minutes.
+pub fn parse_duration(text: &str) -> io::Result<Duration> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid duration '{}'. Expected e.g. 2h30m, 45m, 1.5h or 2:30.", text),
+        )
+    };
+    let text = text.trim().to_lowercase();
+    let seconds = if let Some((hours, minutes)) = text.split_once(':') {
+        let hours: i64 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i64 = minutes.parse().map_err(|_| invalid())?;
+        if minutes >= 60 {
+            return Err(invalid());
+        }
+        hours * 3600 + minutes * 60
+    } else if let Ok(minutes) = text.parse::<f64>() {
+        (minutes * 60.0).round() as i64
+    } else {
+        let mut total = 0.0;
+        let mut number = String::new();
+        for c in text.chars() {
+            let unit = match c {
+                '0'..='9' | '.' => {
+                    number.push(c);
+                    continue;
+                }
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                ' ' => continue,
+                _ => return Err(invalid()),
+            };
+            total += number.parse::<f64>().map_err(|_| invalid())? * unit;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return Err(invalid());
+        }
+        total.round() as i64
+    };
+    if seconds <= 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The duration '{}' is not positive.", text)));
+    }
+    Ok(Duration::seconds(seconds))
+}
+
+// Formats seconds as HH:MM:SS, or HH:MM when seconds are not shown.
+fn format_hms(seconds: i64, with_seconds: bool) -> String {
+    let hours = seconds / 3600;
+    let minutes = (seconds % 3600) / 60;
+    if !with_seconds {
+        return format!("{:02}:{:02}", hours, minutes);
+    }
+    let seconds = seconds % 60;
+    format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+}
+
+// Formats seconds as decimal hours with two places, e.g. "7.50 h".
+fn format_decimal(seconds: i64) -> String {
+    format!("{:.2} h", seconds as f64 / 3600.0)
+}
+
+// Formats seconds as words, leaving out zero components.
+fn format_verbose(seconds: i64) -> String {
+    let parts = [
+        (seconds / 3600, "hour"),
+        ((seconds % 3600) / 60, "minute"),
+        (seconds % 60, "second"),
+    ];
+    let words: Vec<String> = parts
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| {
+            if *value == 1 {
+                format!("{} {}", value, unit)
+            } else {
+                format!("{} {}s", value, unit)
+            }
+        })
+        .collect();
+
+    if words.is_empty() {
+        "0 minutes".to_string()
+    } else {
+        words.join(" ")
+    }
+}
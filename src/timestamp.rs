@@ -0,0 +1,105 @@
+// Reading the timestamps of the data file leniently, so that files written by
+// older versions, edited by hand or converted from other trackers load.
+//
+// Timestamps are written as RFC 3339 in UTC. Besides that, these are read:
+// - seconds since the Unix epoch, as a number or a string of digits, with a
+//   fraction if any; numbers too large for seconds are taken as milliseconds,
+//   as JavaScript writes them
+// - RFC 2822, e.g. "Wed, 1 May 2024 09:00:00 +0200"
+// - date and time separated by a space, with an offset or "UTC" after them, as
+//   chrono's `Display` writes them
+// - date and time without a time zone, with or without seconds, taken as UTC
+//   since that is what the file holds
+//
+// Everything is normalized to UTC on load, so the next save writes RFC 3339.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use std::fmt;
+
+// Epoch values above this are milliseconds: as seconds, they would lie after
+// the year 5000.
+const MAX_EPOCH_SECONDS: f64 = 1e11;
+
+const OFFSET_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S%.f %z", "%Y-%m-%d %H:%M:%S%.f%:z"];
+const NAIVE_FORMATS: [&str; 4] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"];
+
+// Reads a timestamp in any of the formats above.
+pub fn parse(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(seconds) = text.parse::<f64>() {
+        return from_epoch(seconds);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc2822(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let (text, utc) = match text.strip_suffix(" UTC") {
+        Some(rest) => (rest, true),
+        None => (text, false),
+    };
+    if !utc
+        && let Some(time) = OFFSET_FORMATS.iter().find_map(|format| DateTime::parse_from_str(text, format).ok())
+    {
+        return Some(time.with_timezone(&Utc));
+    }
+    NAIVE_FORMATS.iter().find_map(|format| NaiveDateTime::parse_from_str(text, format).ok()).map(|naive| naive.and_utc())
+}
+
+fn from_epoch(value: f64) -> Option<DateTime<Utc>> {
+    if !value.is_finite() {
+        return None;
+    }
+    let millis = if value.abs() > MAX_EPOCH_SECONDS { value } else { value * 1000.0 };
+    DateTime::from_timestamp_millis(millis.round() as i64)
+}
+
+struct TimestampVisitor;
+
+impl Visitor<'_> for TimestampVisitor {
+    type Value = DateTime<Utc>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a timestamp, e.g. \"2024-05-01T09:00:00Z\" or seconds since 1970")
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<Self::Value, E> {
+        parse(text).ok_or_else(|| E::invalid_value(de::Unexpected::Str(text), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        from_epoch(value as f64).ok_or_else(|| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        from_epoch(value as f64).ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        from_epoch(value).ok_or_else(|| E::invalid_value(de::Unexpected::Float(value), &self))
+    }
+}
+
+// For `#[serde(deserialize_with = "timestamp::deserialize")]`.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    deserializer.deserialize_any(TimestampVisitor)
+}
+
+// The same for optional timestamps; `null` is none.
+pub fn deserialize_option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    Ok(Option::<Lenient>::deserialize(deserializer)?.map(|time| time.0))
+}
+
+// A timestamp read leniently, for places where `deserialize_with` cannot be
+// used, such as inside tuples.
+#[derive(Debug, Clone, Copy)]
+pub struct Lenient(pub DateTime<Utc>);
+
+impl<'de> Deserialize<'de> for Lenient {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(Lenient)
+    }
+}
@@ -0,0 +1,156 @@
+// Conversion to and from Timewarrior's JSON interchange format, as written by
+// `timew export` and read by `timew import`:
+//
+//   [{"id":1,"start":"20240502T080000Z","end":"20240502T120000Z",
+//     "tags":["project:acme","billable"],"annotation":"Sprint review"}]
+//
+// Timewarrior only has tags, so the project travels as a `project:<name>` tag and
+// the note as the annotation.
+
+use crate::config::NormalizeConfig;
+use crate::import::ImportSummary;
+use crate::logic;
+use crate::{Metadata, Overlaps, Period, TimeSheet};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::{info, instrument};
+
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const PROJECT_PREFIX: &str = "project:";
+
+// One interval. `id` is written by `timew export` and ignored here.
+#[derive(Serialize, Deserialize, Debug)]
+struct Interval {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    start: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    annotation: Option<String>,
+}
+
+fn parse_time(text: &str) -> io::Result<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(text, TIME_FORMAT).map(|t| t.and_utc()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid Timewarrior time '{}'.", text))
+    })
+}
+
+fn metadata_from_tags(tags: &[String], annotation: Option<&str>) -> Metadata {
+    let mut metadata = Metadata { note: annotation.filter(|a| !a.is_empty()).map(str::to_string), ..Metadata::default() };
+    for tag in tags {
+        match tag.strip_prefix(PROJECT_PREFIX) {
+            Some(project) if metadata.project.is_none() => metadata.project = Some(project.to_string()),
+            _ => metadata.tags.push(tag.clone()),
+        }
+    }
+    metadata
+}
+
+fn tags_from_metadata(metadata: &Metadata) -> Vec<String> {
+    metadata
+        .project
+        .iter()
+        .map(|project| format!("{}{}", PROJECT_PREFIX, project))
+        .chain(metadata.tags.iter().cloned())
+        .collect()
+}
+
+// Handles `import timew <file>`. Intervals already in the timesheet, overlapping
+// recorded periods, in closed months or still running are skipped; `defaults` fills in what an interval does
+// not have. With `dry_run` the summary says what would be imported; the caller
+// works on a copy.
+#[instrument(level = "debug", skip(time_sheet, defaults))]
+pub fn import_timew(
+    time_sheet: &mut TimeSheet,
+    path: &Path,
+    defaults: Metadata,
+    rules: &NormalizeConfig,
+    dry_run: bool,
+) -> io::Result<ImportSummary> {
+    let contents = fs::read_to_string(path)?;
+    let intervals: Vec<Interval> = serde_json::from_str(&contents).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a Timewarrior export: {}", path.display(), e))
+    })?;
+
+    let (mut imported, mut duplicates, mut locked, mut overlapping, mut running, mut empty) = (0, 0, 0, 0, 0, 0);
+    let mut candidates = Vec::new();
+    for interval in &intervals {
+        let Some(end) = &interval.end else {
+            running += 1;
+            continue;
+        };
+        let Ok(period) = Period::new(parse_time(&interval.start)?, parse_time(end)?) else {
+            empty += 1;
+            continue;
+        };
+        let metadata = metadata_from_tags(&interval.tags, interval.annotation.as_deref()).or(&defaults);
+        candidates.push(period.with_metadata(metadata));
+    }
+
+    // Duplicates are found after normalizing, so a re-import merges the same way.
+    let normalized = logic::normalize(&mut candidates, rules);
+    for period in candidates {
+        // Timewarrior keeps whole seconds, so compare at that precision.
+        let same = |p: &Period| p.start.timestamp() == period.start.timestamp() && p.end.timestamp() == period.end.timestamp();
+        if time_sheet.periods.iter().any(same) {
+            duplicates += 1;
+            continue;
+        }
+        if time_sheet.closed_month_at(period.start).is_some() || time_sheet.closed_month_at(period.end).is_some() {
+            locked += 1;
+            continue;
+        }
+        if time_sheet.insert_period(period, Overlaps::Reject).is_err() {
+            overlapping += 1;
+            continue;
+        }
+        imported += 1;
+    }
+
+    info!(imported, duplicates, locked, overlapping, running, empty, merged = normalized.merged, dropped = normalized.dropped, "imported Timewarrior intervals");
+    println!("{} {} of {} interval(s).", if dry_run { "Would import" } else { "Imported" }, imported, intervals.len());
+    if duplicates > 0 {
+        println!("Skipped {} interval(s) that were already imported.", duplicates);
+    }
+    if locked > 0 {
+        println!("Skipped {} interval(s) in closed months.", locked);
+    }
+    if overlapping > 0 {
+        println!("Skipped {} interval(s) that overlap recorded periods.", overlapping);
+    }
+    if running > 0 {
+        println!("Skipped {} running interval(s).", running);
+    }
+    if empty > 0 {
+        println!("Skipped {} interval(s) without a positive duration.", empty);
+    }
+    normalized.print("interval(s)", rules);
+    Ok(ImportSummary { imported, duplicates, conflicts: locked + overlapping })
+}
+
+// Handles `export timew`: all periods, and the running session as an open
+// interval, in the format `timew import` reads.
+pub fn export_timew(time_sheet: &TimeSheet) -> io::Result<String> {
+    let finished = time_sheet.periods.iter().map(|period| Interval {
+        id: None,
+        start: period.start.format(TIME_FORMAT).to_string(),
+        end: Some(period.end.format(TIME_FORMAT).to_string()),
+        tags: tags_from_metadata(&period.metadata),
+        annotation: period.metadata.note.clone(),
+    });
+    let running = time_sheet.active_period_start.map(|start| Interval {
+        id: None,
+        start: start.format(TIME_FORMAT).to_string(),
+        end: None,
+        tags: tags_from_metadata(&time_sheet.active_metadata),
+        annotation: time_sheet.active_metadata.note.clone(),
+    });
+    let intervals: Vec<Interval> = finished.chain(running).collect();
+    serde_json::to_string_pretty(&intervals).map_err(io::Error::other)
+}
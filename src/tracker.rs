@@ -0,0 +1,329 @@
+use crate::clock::{Clock, SystemClock};
+use crate::journal::{self, JournalEntry};
+use crate::storage::LoadOptions;
+use crate::{logic, storage, Metadata, Overlaps, Period, TimeSheet};
+use chrono::{DateTime, Duration, Utc};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{debug, info, instrument};
+
+// Thread-safe facade over a timesheet file, for programs that embed the tracker.
+//
+// All methods take `&self`; the timesheet is kept behind a mutex, and every change
+// is written to the data file's journal before the lock is released. Share it
+// between threads with an `Arc<Tracker>`. The current time comes from the clock
+// given to `with_clock`, never from the system directly. Unlike the command-line
+// handlers, nothing is printed. Several changes that must be saved together, or
+// not at all, go through `transaction`.
+pub struct Tracker {
+    data_path: PathBuf,
+    clock: Arc<dyn Clock>,
+    options: LoadOptions,
+    time_sheet: Mutex<TimeSheet>,
+}
+
+// A tracker is meant to be shared between threads.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Tracker>();
+};
+
+impl Tracker {
+    /// Opens the timesheet at `data_path` using the system clock.
+    pub fn open(data_path: &Path) -> io::Result<Tracker> {
+        Tracker::with_clock(data_path, Arc::new(SystemClock))
+    }
+
+    /// Opens the timesheet at `data_path` with the given clock.
+    pub fn with_clock(data_path: &Path, clock: Arc<dyn Clock>) -> io::Result<Tracker> {
+        Tracker::with_options(data_path, clock, LoadOptions::default())
+    }
+
+    /// Opens the timesheet at `data_path` with the given clock, loading it, now
+    /// and on `reload`, with `options`.
+    pub fn with_options(data_path: &Path, clock: Arc<dyn Clock>, options: LoadOptions) -> io::Result<Tracker> {
+        let time_sheet = storage::load_or_create_timesheet(data_path, options)?;
+        Ok(Tracker { data_path: data_path.to_path_buf(), clock, options, time_sheet: Mutex::new(time_sheet) })
+    }
+
+    /// Starts a session now. Fails if one is already running or the month is closed.
+    #[instrument(level = "debug", skip(self))]
+    pub fn start(&self, metadata: Metadata) -> io::Result<DateTime<Utc>> {
+        let mut time_sheet = self.lock()?;
+        let now = self.clock.now();
+        check_start(&time_sheet, now)?;
+        journal::append(&self.data_path, &JournalEntry::Start { start: now, metadata: metadata.clone(), remind_after: None, suspend: None })?;
+        begin_session(&mut time_sheet, metadata, now);
+        Ok(now)
+    }
+
+    /// Stops the running session and returns the recorded period.
+    #[instrument(level = "debug", skip(self))]
+    pub fn stop(&self) -> io::Result<Period> {
+        let mut time_sheet = self.lock()?;
+        let period = finished_session(&time_sheet, self.clock.now())?;
+        journal::append(&self.data_path, &JournalEntry::Stop { period: Some(period.clone()) })?;
+        end_session(&mut time_sheet, period.clone())?;
+        Ok(period)
+    }
+
+    /// Stops the running session and starts one with `metadata` at the same
+    /// instant. Returns the recorded period, or none if the session had only just
+    /// started and was relabelled instead.
+    #[instrument(level = "debug", skip(self))]
+    pub fn switch(&self, metadata: Metadata) -> io::Result<Option<Period>> {
+        let mut time_sheet = self.lock()?;
+        let now = self.clock.now();
+        let period = switched_session(&time_sheet, now)?;
+        let entry = JournalEntry::Switch { period: period.clone(), start: now, metadata: metadata.clone(), suspend: None };
+        journal::append(&self.data_path, &entry)?;
+        if let Some(period) = &period {
+            end_session(&mut time_sheet, period.clone())?;
+        }
+        begin_session(&mut time_sheet, metadata, now);
+        Ok(period)
+    }
+
+    /// Applies several changes at once. `change` works on a copy of the timesheet
+    /// as it is on disk, read again under the lock, so that changes made meanwhile
+    /// by another process, such as `start` on the command line, are kept. If
+    /// `change` succeeds, the copy is saved in full and becomes the current
+    /// timesheet. If `change` or the save fails, nothing changes, in memory or on
+    /// disk: the data file is replaced by renaming a complete new version over it,
+    /// so the previous one survives a failed write or a crash.
+    #[instrument(level = "debug", skip_all)]
+    pub fn transaction<T>(&self, change: impl FnOnce(&mut Transaction<'_>) -> io::Result<T>) -> io::Result<T> {
+        let mut time_sheet = self.lock()?;
+        let mut working = storage::load_or_create_timesheet(&self.data_path, self.options)?;
+        let result = change(&mut Transaction { time_sheet: &mut working, clock: self.clock.as_ref() })?;
+        storage::save_timesheet(&self.data_path, &working)?;
+        *time_sheet = working;
+        info!("committed transaction");
+        Ok(result)
+    }
+
+    /// Runs `change` on a copy of the timesheet and returns its result with the
+    /// changed copy, without saving anything or changing the current timesheet.
+    /// For showing what a change would do before making it.
+    pub fn preview<T>(&self, change: impl FnOnce(&mut Transaction<'_>) -> io::Result<T>) -> io::Result<(T, TimeSheet)> {
+        let time_sheet = self.lock()?;
+        Transaction::preview(&time_sheet, self.clock.as_ref(), change)
+    }
+
+    /// Start of the running session, if any.
+    pub fn active_since(&self) -> io::Result<Option<DateTime<Utc>>> {
+        Ok(self.lock()?.active_period_start)
+    }
+
+    /// Tracked time within `range`, including the running session up to now.
+    pub fn tracked_in(&self, range: &Period) -> io::Result<Duration> {
+        let time_sheet = self.lock()?;
+        Ok(logic::calculate_tracked_time_in_period(&time_sheet, range, self.clock.now()))
+    }
+
+    /// A copy of the current timesheet.
+    pub fn snapshot(&self) -> io::Result<TimeSheet> {
+        Ok(self.lock()?.clone())
+    }
+
+    /// Writes the full timesheet and removes the journal. The data file and
+    /// journal are read again first, so changes by other processes are kept.
+    pub fn compact(&self) -> io::Result<()> {
+        let mut time_sheet = self.lock()?;
+        let current = storage::load_or_create_timesheet(&self.data_path, self.options)?;
+        storage::save_timesheet(&self.data_path, &current)?;
+        *time_sheet = current;
+        Ok(())
+    }
+
+    /// Reads the data file again, e.g. after another process changed it.
+    pub fn reload(&self) -> io::Result<()> {
+        let mut time_sheet = self.lock()?;
+        *time_sheet = storage::load_or_create_timesheet(&self.data_path, self.options)?;
+        Ok(())
+    }
+
+    // A panic while holding the lock leaves the timesheet in an unknown state, so
+    // it is reported instead of carrying on with it.
+    fn lock(&self) -> io::Result<MutexGuard<'_, TimeSheet>> {
+        self.time_sheet
+            .lock()
+            .map_err(|_| io::Error::other("The timesheet lock was poisoned by a panic in another thread."))
+    }
+}
+
+// The changes of a `Tracker::transaction`, made to a working copy of the
+// timesheet. The library's other functions that change a `TimeSheet`, such as
+// `retag::retag` or `review::review_week`, can work on `time_sheet_mut`.
+pub struct Transaction<'a> {
+    time_sheet: &'a mut TimeSheet,
+    clock: &'a dyn Clock,
+}
+
+impl Transaction<'_> {
+    /// Runs `change` on a copy of `time_sheet`, as `Tracker::preview` does, for
+    /// callers that hold a timesheet themselves.
+    pub fn preview<T>(
+        time_sheet: &TimeSheet,
+        clock: &dyn Clock,
+        change: impl FnOnce(&mut Transaction<'_>) -> io::Result<T>,
+    ) -> io::Result<(T, TimeSheet)> {
+        let mut working = time_sheet.clone();
+        let result = change(&mut Transaction { time_sheet: &mut working, clock })?;
+        debug!("previewed change");
+        Ok((result, working))
+    }
+
+    /// Starts a session now, as `Tracker::start` does.
+    pub fn start(&mut self, metadata: Metadata) -> io::Result<DateTime<Utc>> {
+        let now = self.clock.now();
+        check_start(self.time_sheet, now)?;
+        begin_session(self.time_sheet, metadata, now);
+        Ok(now)
+    }
+
+    /// Stops the running session, as `Tracker::stop` does.
+    pub fn stop(&mut self) -> io::Result<Period> {
+        let period = finished_session(self.time_sheet, self.clock.now())?;
+        end_session(self.time_sheet, period.clone())?;
+        Ok(period)
+    }
+
+    /// Switches to a new session, as `Tracker::switch` does.
+    pub fn switch(&mut self, metadata: Metadata) -> io::Result<Option<Period>> {
+        let now = self.clock.now();
+        let period = switched_session(self.time_sheet, now)?;
+        if let Some(period) = &period {
+            end_session(self.time_sheet, period.clone())?;
+        }
+        begin_session(self.time_sheet, metadata, now);
+        Ok(period)
+    }
+
+    /// Records a finished period. Fails if it overlaps another or lies in a closed month.
+    pub fn add(&mut self, period: Period) -> io::Result<()> {
+        if self.time_sheet.is_locked(&period) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("The period from {} to {} is in a closed month.", period.start, period.end),
+            ));
+        }
+        self.time_sheet.insert_period(period, Overlaps::Reject)
+    }
+
+    /// The timesheet with the changes made so far.
+    pub fn time_sheet(&self) -> &TimeSheet {
+        self.time_sheet
+    }
+
+    /// The working copy, for changes the transaction has no method for.
+    pub fn time_sheet_mut(&mut self) -> &mut TimeSheet {
+        self.time_sheet
+    }
+}
+
+// Fails if a session cannot be started at `now`.
+fn check_start(time_sheet: &TimeSheet, now: DateTime<Utc>) -> io::Result<()> {
+    if let Some(start) = time_sheet.active_period_start {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Already tracking time since {}.", start),
+        ));
+    }
+    if let Some(closed) = time_sheet.closed_month_at(now) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Month {} is closed; no new sessions can be started in it.", closed.month),
+        ));
+    }
+    Ok(())
+}
+
+fn begin_session(time_sheet: &mut TimeSheet, metadata: Metadata, now: DateTime<Utc>) {
+    time_sheet.active_period_start = Some(now);
+    time_sheet.active_metadata = metadata;
+    time_sheet.active_laps.clear();
+    time_sheet.active_remind_after = None;
+    time_sheet.active_suspend = None;
+    info!(start = %now, "started active period");
+}
+
+// The period the running session becomes if it is stopped at `now`.
+fn finished_session(time_sheet: &TimeSheet, now: DateTime<Utc>) -> io::Result<Period> {
+    let Some(start) = time_sheet.active_period_start else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No active time tracking period to stop."));
+    };
+    let mut period = Period::new(start, now)?.with_metadata(time_sheet.active_metadata.clone());
+    period.laps = time_sheet.active_laps.clone();
+    Ok(period)
+}
+
+// The period the running session becomes when switching away from it at `now`,
+// none if it started at `now`. Fails if nothing is running or the month is closed.
+fn switched_session(time_sheet: &TimeSheet, now: DateTime<Utc>) -> io::Result<Option<Period>> {
+    let Some(start) = time_sheet.active_period_start else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No active time tracking period to switch from."));
+    };
+    if let Some(closed) = time_sheet.closed_month_at(now) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Month {} is closed; no new sessions can be started in it.", closed.month),
+        ));
+    }
+    if now <= start {
+        return Ok(None);
+    }
+    finished_session(time_sheet, now).map(Some)
+}
+
+fn end_session(time_sheet: &mut TimeSheet, period: Period) -> io::Result<()> {
+    time_sheet.active_period_start = None;
+    time_sheet.active_metadata = Metadata::default();
+    time_sheet.active_laps.clear();
+    time_sheet.active_remind_after = None;
+    time_sheet.active_suspend = None;
+    info!(start = %period.start, end = %period.end, "stopped active period");
+    time_sheet.insert_period(period, Overlaps::Allow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use std::fs;
+
+    // A data file in a fresh directory under the temp dir.
+    fn data_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("time-tracker-tracker-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("data.json")
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    fn clock(text: &str) -> Arc<dyn Clock> {
+        Arc::new(FixedClock::new(utc(text)))
+    }
+
+    #[test]
+    fn transactions_keep_changes_by_other_processes() {
+        let path = data_file("transaction");
+        let tracker = Tracker::with_clock(&path, clock("2024-03-04T12:00:00Z")).unwrap();
+        // Another process starts a session after the tracker loaded the file.
+        Tracker::with_clock(&path, clock("2024-03-04T09:00:00Z")).unwrap().start(Metadata::default()).unwrap();
+
+        let period = Period::new(utc("2024-03-01T09:00:00Z"), utc("2024-03-01T10:00:00Z")).unwrap();
+        tracker.transaction(|t| t.add(period)).unwrap();
+        tracker.compact().unwrap();
+
+        let saved = storage::load_or_create_timesheet(&path, LoadOptions::default()).unwrap();
+        assert_eq!(saved.active_period_start, Some(utc("2024-03-04T09:00:00Z")));
+        assert_eq!(saved.periods.len(), 1);
+        assert_eq!(tracker.active_since().unwrap(), saved.active_period_start);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+// Bindings for a web UI, with the `wasm` feature. For the browser, build with
+//
+//   cargo rustc --release --lib --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//   wasm-bindgen --target web target/wasm32-unknown-unknown/release/time_tracker.wasm --out-dir pkg
+//
+// The calculations are the ones of `logic`, so the web UI shows the same totals
+// and rejects the same overlapping periods as the command-line program. The
+// timesheet is passed around as the JSON of the data file and kept in the
+// browser's local storage by `BrowserStore`. Local times are the browser's: with
+// this feature, chrono takes the time zone and the current time from JavaScript.
+// The integrations and `remind` are not available in the browser.
+
+use crate::clock::SystemClock;
+use crate::config::Config;
+use crate::storage::TimesheetStore;
+use crate::timefmt::{HoursFormat, TimeFormatter};
+use crate::{logic, Metadata, TimeSheet};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::io;
+use wasm_bindgen::prelude::*;
+
+// A timesheet kept under one key of the browser's local storage.
+#[wasm_bindgen]
+pub struct BrowserStore {
+    key: String,
+}
+
+#[wasm_bindgen]
+impl BrowserStore {
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &str) -> BrowserStore {
+        BrowserStore { key: key.to_string() }
+    }
+
+    /// The stored timesheet as JSON; an empty one if nothing is stored yet.
+    #[wasm_bindgen(js_name = loadJson)]
+    pub fn load_json(&self) -> Result<String, JsError> {
+        Ok(serde_json::to_string(&self.load().map_err(js_error)?)?)
+    }
+
+    #[wasm_bindgen(js_name = saveJson)]
+    pub fn save_json(&self, timesheet: &str) -> Result<(), JsError> {
+        self.save(&parse_timesheet(timesheet)?).map_err(js_error)
+    }
+}
+
+impl TimesheetStore for BrowserStore {
+    fn load(&self) -> io::Result<TimeSheet> {
+        match local_storage()?.get_item(&self.key).map_err(storage_error)? {
+            Some(json) => serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            None => Ok(TimeSheet::default()),
+        }
+    }
+
+    fn save(&self, time_sheet: &TimeSheet) -> io::Result<()> {
+        let json = serde_json::to_string(time_sheet)?;
+        local_storage()?.set_item(&self.key, &json).map_err(storage_error)
+    }
+}
+
+fn local_storage() -> io::Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "The browser's local storage is not available."))
+}
+
+fn storage_error(error: JsValue) -> io::Error {
+    io::Error::other(format!("Local storage failed: {:?}", error))
+}
+
+fn js_error(error: io::Error) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+fn parse_timesheet(json: &str) -> Result<TimeSheet, JsError> {
+    let mut time_sheet: TimeSheet = serde_json::from_str(json)?;
+    time_sheet.check_periods();
+    Ok(time_sheet)
+}
+
+// The config is the TOML of the config file; an empty string is the default config.
+fn parse_config(toml: &str) -> Result<Config, JsError> {
+    Ok(toml::from_str(toml)?)
+}
+
+fn parse_time(text: &str) -> Result<DateTime<Utc>, JsError> {
+    Ok(DateTime::parse_from_rfc3339(text)?.to_utc())
+}
+
+/// The report on `today`, `week`, `month` or a `[periods]` alias as JSON, with
+/// seconds for durations.
+#[wasm_bindgen]
+pub fn report(timesheet: &str, config: &str, period: &str) -> Result<String, JsError> {
+    let (time_sheet, config) = (parse_timesheet(timesheet)?, parse_config(config)?);
+    let report = logic::named_report(&time_sheet, period, &config, &SystemClock).map_err(js_error)?;
+    let report = json!({
+        "title": report.title,
+        "start": report.range.start(),
+        "end": report.range.end(),
+        "total_seconds": report.total.num_seconds(),
+        "active_since": report.active.as_ref().map(|active| active.start),
+        "projects": report.projects.iter().map(|row| json!({
+            "project": row.project,
+            "seconds": row.total.num_seconds(),
+        })).collect::<Vec<_>>(),
+        "days": report.days.iter().map(|day| json!({
+            "date": day.date,
+            "seconds": day.total.num_seconds(),
+        })).collect::<Vec<_>>(),
+    });
+    Ok(report.to_string())
+}
+
+/// Adds a finished period given as RFC 3339 times and returns the new timesheet
+/// JSON. Fails like `add` does, e.g. when the period overlaps another one.
+#[wasm_bindgen(js_name = addPeriod)]
+pub fn add_period(timesheet: &str, start: &str, end: &str, project: Option<String>) -> Result<String, JsError> {
+    let mut time_sheet = parse_timesheet(timesheet)?;
+    let metadata = Metadata { project, ..Metadata::default() };
+    let formatter = TimeFormatter::new(HoursFormat::default());
+    logic::add_manual_period(&mut time_sheet, parse_time(start)?, parse_time(end)?, metadata, &formatter, &SystemClock)
+        .map_err(js_error)?;
+    Ok(serde_json::to_string(&time_sheet)?)
+}
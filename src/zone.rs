@@ -0,0 +1,72 @@
+// The time zone reports count days, weeks and months in and show times in.
+//
+// That is the machine's local zone, unless `report --tz` names another one from
+// the IANA database, e.g. to report for people in Berlin from a server running
+// in UTC. The zone data comes with the program, so this works on systems
+// without a zoneinfo directory as well.
+//
+// The zone is a value passed to what needs it, usually from `Config::zone`, so
+// that a program embedding the library can report in several zones at once.
+
+use crate::config::DstPolicy;
+use chrono::{DateTime, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::io;
+
+// A zone, and which of the two instants an ambiguous local time in it means.
+// The default is the local zone with the default policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Zone {
+    named: Option<Tz>,
+    /// How times that occur twice when clocks go back are read.
+    pub policy: DstPolicy,
+}
+
+// Reads a zone name such as "Europe/Berlin" or "UTC".
+pub fn parse(name: &str) -> io::Result<Tz> {
+    name.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown time zone '{}'. Expected a name from the IANA database, e.g. Europe/Berlin.", name.trim()),
+        )
+    })
+}
+
+impl Zone {
+    /// The machine's local zone.
+    pub fn local(policy: DstPolicy) -> Zone {
+        Zone { named: None, policy }
+    }
+
+    /// A zone from the IANA database, or the local zone without one.
+    pub fn new(named: Option<Tz>, policy: DstPolicy) -> Zone {
+        Zone { named, policy }
+    }
+
+    /// The zone's name, if it is not the local zone.
+    pub fn named(&self) -> Option<Tz> {
+        self.named
+    }
+
+    /// `time` as seen in the zone.
+    pub fn local_time(&self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match &self.named {
+            Some(zone) => time.with_timezone(zone).fixed_offset(),
+            None => time.with_timezone(&Local).fixed_offset(),
+        }
+    }
+
+    /// The date of `time` in the zone.
+    pub fn date(&self, time: DateTime<Utc>) -> NaiveDate {
+        self.local_time(time).date_naive()
+    }
+
+    /// The points in time a wall-clock time of the zone stands for: none in a gap
+    /// when clocks go forward, two when they go back.
+    pub fn from_local(&self, naive: &NaiveDateTime) -> LocalResult<DateTime<Utc>> {
+        match &self.named {
+            Some(zone) => zone.from_local_datetime(naive).map(|time| time.to_utc()),
+            None => Local.from_local_datetime(naive).map(|time| time.to_utc()),
+        }
+    }
+}